@@ -0,0 +1,124 @@
+//!Procedural macros for `rocket_read_db_pools`, re-exported by that crate
+use proc_macro::TokenStream;
+use quote::quote;
+use quote::format_ident;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, FnArg, ItemFn, LitStr, PathArguments, Type};
+
+///Makes a route's database connection guards use the read pool. See
+///`rocket_read_db_pools::read_only`.
+#[proc_macro_attribute]
+pub fn read_only(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return syn::Error::new_spanned(attr, "`#[read_only]` takes no arguments").into_compile_error().into();
+    }
+    let mut function = parse_macro_input!(item as ItemFn);
+    for input in &mut function.sig.inputs {
+        if let FnArg::Typed(arg) = input {
+            to_read_connection(&mut arg.ty);
+        }
+    }
+    quote!(#function).into()
+}
+
+///Replaces `Connection<..>`, `RwConnection<..>` and `AutoConnection<..>` with `ReadConnection<..>`
+fn to_read_connection(ty: &mut Type) {
+    let Type::Path(path) = ty else {return};
+    if path.qself.is_some() {
+        return;
+    }
+    let Some(last) = path.path.segments.last() else {return};
+    if !matches!(last.ident.to_string().as_str(), "Connection" | "RwConnection" | "AutoConnection") {
+        return;
+    }
+    let PathArguments::AngleBracketed(ref args) = last.arguments else {return};
+    *ty = parse_quote!(::rocket_read_db_pools::ReadConnection #args);
+}
+
+///Implements `Database` for a read pool wrapper along with guard aliases. See
+///`rocket_read_db_pools::ReadDatabase`.
+#[proc_macro_derive(ReadDatabase, attributes(database))]
+pub fn read_database(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_read_database(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
+fn expand_read_database(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let pool_type = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => return Err(syn::Error::new_spanned(input, "struct must have exactly one unnamed field")),
+        },
+        _ => return Err(syn::Error::new_spanned(input, "`ReadDatabase` can only be derived for tuple structs")),
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "`ReadDatabase` can't be derived for generic structs"));
+    }
+    let attr = input.attrs.iter().find(|a| a.path().is_ident("database"))
+        .ok_or_else(|| syn::Error::new_spanned(input, "missing `#[database(\"name\")]` attribute"))?;
+    let name = attr.parse_args::<LitStr>()?.value();
+    let fairing_name = format!("'{}' Database Pool", name);
+    let (vis, ident) = (&input.vis, &input.ident);
+    let read_alias = format_ident!("{}Read", ident);
+    let rw_alias = format_ident!("{}Rw", ident);
+    let read_doc = format!("A read connection to the `{}` database", name);
+    let rw_doc = format!("A read-write connection to the `{}` database", name);
+    Ok(quote! {
+        #[::rocket::async_trait]
+        impl ::rocket_db_pools::Database for #ident {
+            type Pool = #pool_type;
+
+            const NAME: &'static str = #name;
+
+            fn init() -> ::rocket_db_pools::Initializer<Self> {
+                ::rocket_db_pools::Initializer::with_name(#fairing_name)
+            }
+        }
+
+        impl ::std::convert::From<#pool_type> for #ident {
+            fn from(pool: #pool_type) -> Self {
+                Self(pool)
+            }
+        }
+
+        impl ::std::ops::Deref for #ident {
+            type Target = #pool_type;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl ::std::ops::DerefMut for #ident {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+
+        #[::rocket::async_trait]
+        impl<'r> ::rocket::request::FromRequest<'r> for &'r #ident {
+            type Error = ();
+
+            async fn from_request(req: &'r ::rocket::request::Request<'_>) -> ::rocket::request::Outcome<Self, Self::Error> {
+                match <#ident as ::rocket_db_pools::Database>::fetch(req.rocket()) {
+                    Some(db) => ::rocket::outcome::Outcome::Success(db),
+                    None => ::rocket::outcome::Outcome::Error((::rocket::http::Status::InternalServerError, ())),
+                }
+            }
+        }
+
+        impl ::rocket::Sentinel for &#ident {
+            fn abort(rocket: &::rocket::Rocket<::rocket::Ignite>) -> bool {
+                <#ident as ::rocket_db_pools::Database>::fetch(rocket).is_none()
+            }
+        }
+
+        #[doc = #read_doc]
+        #vis type #read_alias = ::rocket_read_db_pools::ReadConnection<#ident>;
+        #[doc = #rw_doc]
+        #vis type #rw_alias = ::rocket_read_db_pools::RwConnection<#ident>;
+    })
+}
@@ -0,0 +1,18 @@
+//!Measures the per-request overhead of this crate's guards against `rocket_db_pools::Connection`,
+//!using pools which hand out connections instantly.
+//!
+//!Run with `cargo run --release --features bench --example acquisition_overhead [iterations]`.
+use rocket_read_db_pools::bench;
+
+#[rocket::main]
+async fn main() {
+    let iterations = std::env::args().nth(1).and_then(|n| n.parse().ok()).unwrap_or(100_000);
+    let pool = bench::mock_read_pool().await;
+    println!("{}", bench::read_acquisition(&pool, iterations).await);
+    println!("{}", bench::write_acquisition(&pool, iterations).await);
+    let results = bench::guard_overhead(iterations).await;
+    let baseline = results[0].per_iteration();
+    for result in &results {
+        println!("{} ({:?} over no guard)", result, result.per_iteration().saturating_sub(baseline));
+    }
+}
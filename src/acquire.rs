@@ -0,0 +1,265 @@
+//!Connection acquisition with a per-call timeout, retries, or without waiting
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::tokio::time::{sleep, timeout};
+use rocket_db_pools::Pool;
+use crate::{budget, failure, prefer};
+use crate::logging::db_log;
+use crate::{FailureKind, LogConfig, PoolRole, ReadCapablePool, ReadDbConfig, ReadPool};
+
+rocket::tokio::task_local! {
+    ///Set while `ReadPool::get_read_with_timeout` acquires a connection, to receive the role of
+    ///the pool it's waiting on
+    static WAITING: Arc<Mutex<PoolRole>>;
+}
+
+///Default of `acquire_backoff_ms`
+const DEFAULT_BACKOFF_MS: u64 = 50;
+
+///Retrying of failed acquisitions, from `acquire_retries` and `acquire_backoff_ms`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retry {
+    retries: u32,
+    backoff: Duration,
+}
+impl Retry {
+    pub fn configured(config: &ReadDbConfig) -> Option<Self> {
+        let retries = config.acquire_retries.filter(|&r| r > 0)?;
+        Some(Retry{retries, backoff: Duration::from_millis(config.acquire_backoff_ms.unwrap_or(DEFAULT_BACKOFF_MS))})
+    }
+
+    ///Runs `acquire` until it succeeds or the retries run out, doubling the wait between
+    ///attempts each time
+    pub async fn run<T, E, F, Fut>(retry: Option<Self>, log: &LogConfig, label: &str, mut acquire: F) -> Result<T, E>
+        where E: fmt::Display, F: FnMut() -> Fut, Fut: Future<Output = Result<T, E>>
+    {
+        let Some(retry) = retry else {return acquire().await};
+        let mut attempt = 0;
+        loop {
+            match acquire().await {
+                Err(e) if attempt < retry.retries && budget::take() => {
+                    db_log!(log, General, Debug, "`{}` pool acquisition failed, retrying: {}", label, e);
+                }
+                result => return result,
+            }
+            sleep(retry.backoff.saturating_mul(1 << attempt.min(16))).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Runs `acquire` for the pool `label` with the configured retries, logging it if slow
+    pub(crate) async fn acquire_from<T, E, F, Fut>(&self, label: &str, acquire: F) -> Result<T, E>
+        where E: fmt::Display, F: FnMut() -> Fut, Fut: Future<Output = Result<T, E>>
+    {
+        let start = Instant::now();
+        let result = Retry::run(self.retry, &self.log, label, acquire).await;
+        self.log.acquired(label, start);
+        result
+    }
+
+    ///Runs `acquire` as `acquire_from` does, then tests the connection if the pool with the role
+    ///`role` has `test_before_acquire` set, replacing it from `acquire` while it fails
+    pub(crate) async fn acquire_tested<T, E, F, Fut>(&self, role: PoolRole, label: &str, mut acquire: F) -> Result<T, E>
+        where T: Send + 'static, E: fmt::Display, F: FnMut() -> Fut, Fut: Future<Output = Result<T, E>>
+    {
+        let _ = WAITING.try_with(|waiting| *waiting.lock().unwrap_or_else(|e| e.into_inner()) = role);
+        let conn = self.acquire_from(label, &mut acquire).await?;
+        match *self.tests_before_acquire.get(role) {
+            true => self.hooks.tested(label, conn, acquire, &self.log).await,
+            false => Ok(conn),
+        }
+    }
+}
+
+///Error from [`ReadPool::get_with_timeout`], [`ReadPool::get_read_with_timeout`], the
+///[`WithTimeout`] guard and the `tower` service adapter.
+///
+///The pool's own error is kept as the [`source`](std::error::Error::source), so it can still
+///be downcast. Code matching on the driver's error type can convert the whole error into it
+///with [`AcquireError::unify`]:
+///```rust
+/// # use rocket_read_db_pools::AcquireError;
+/// use rocket_read_db_pools::testing::MockError;
+///
+/// fn to_pool_error(e: AcquireError<MockError>) -> MockError {
+///     e.unify(|_| MockError::Exhausted("main".to_string()), |role| MockError::Unavailable(format!("{:?}", role)))
+/// }
+///```
+#[derive(Debug)]
+pub enum AcquireError<E> {
+    ///No connection was acquired within the given timeout
+    Timeout(Duration),
+    ///The pool failed to provide a connection
+    Pool(E),
+    ///No pool with that role is configured
+    Unconfigured(PoolRole),
+}
+impl<E: fmt::Display> fmt::Display for AcquireError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcquireError::Timeout(t) => write!(f, "timed out acquiring a connection after {:?}", t),
+            AcquireError::Pool(e) => e.fmt(f),
+            AcquireError::Unconfigured(role) => write!(f, "no {:?} pool is configured", role),
+        }
+    }
+}
+impl<E: std::error::Error + 'static> std::error::Error for AcquireError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AcquireError::Pool(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl<E> AcquireError<E> {
+    ///Whether no connection was acquired in time
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, AcquireError::Timeout(_))
+    }
+
+    ///The pool's own error, if it failed
+    pub fn pool_error(&self) -> Option<&E> {
+        match self {
+            AcquireError::Pool(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    ///Converts into the pool's error type, building timeouts and unconfigured pools with the
+    ///given functions, e.g. as `sqlx::Error::PoolTimedOut` and `sqlx::Error::Configuration`
+    pub fn unify<T, U>(self, timed_out: T, unconfigured: U) -> E
+        where T: FnOnce(Duration) -> E, U: FnOnce(PoolRole) -> E
+    {
+        match self {
+            AcquireError::Timeout(t) => timed_out(t),
+            AcquireError::Pool(e) => e,
+            AcquireError::Unconfigured(role) => unconfigured(role),
+        }
+    }
+}
+
+///A request guard wrapping a connection guard (`WithTimeout<ReadConnection<Db>, 200>`,
+///`WithTimeout<RwConnection<Db>, 50>`, ...) which fails with
+///[`AcquireError::Timeout`] and `503 Service Unavailable` if the wrapped guard takes longer
+///than `MS` milliseconds, so latency-sensitive routes fail fast rather than queue.
+///
+///The pools' own `connect_timeout` still applies, so this can only shorten the wait. Timed out
+///acquisitions aren't recorded in [`RequestRouting`](crate::RequestRouting).
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::{ReadConnection, WithTimeout};
+///
+/// #[rocket::get("/search")]
+/// async fn search(db: WithTimeout<ReadConnection<Db>, 100>) -> &'static str {
+///     # let _ = db;
+///     "..."
+/// }
+///```
+pub struct WithTimeout<G, const MS: u64>(pub G);
+impl<G, const MS: u64> WithTimeout<G, MS> {
+    ///Gets the wrapped guard
+    pub fn into_inner(self) -> G {
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, G: FromRequest<'r>, const MS: u64> FromRequest<'r> for WithTimeout<G, MS> {
+    type Error = AcquireError<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let patience = Duration::from_millis(MS);
+        match timeout(patience, G::from_request(req)).await {
+            Ok(Outcome::Success(guard)) => Outcome::Success(WithTimeout(guard)),
+            Ok(Outcome::Error((status, e))) => Outcome::Error((status, AcquireError::Pool(e))),
+            Ok(Outcome::Forward(status)) => Outcome::Forward(status),
+            Err(_) => {
+                let message = format!("timed out acquiring a connection after {:?}", patience);
+                let status = failure::respond(req, None, FailureKind::Timeout, Status::ServiceUnavailable, &message);
+                Outcome::Error((status, AcquireError::Timeout(patience)))
+            }
+        }
+    }
+}
+impl<G: Deref, const MS: u64> Deref for WithTimeout<G, MS> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<G: DerefMut, const MS: u64> DerefMut for WithTimeout<G, MS> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+///Non-blocking acquisition of an idle connection, for [`ReadPool::try_get_read`].
+///
+///Implement it for the pool types in use, e.g. with sqlx's `Pool::try_acquire`.
+pub trait TryAcquire: Pool {
+    ///Returns an idle connection, or `None` if none is available right now
+    fn try_get(&self) -> Option<Self::Connection>;
+}
+
+impl<P, R> ReadPool<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, R::Connection: Into<P::Connection> + Send, P::Connection: Send
+{
+    ///Gets a connection from the main pool, waiting at most `patience`.
+    ///
+    ///The pool's own `connect_timeout` still applies, so this can only shorten the wait.
+    pub async fn get_with_timeout(&self, patience: Duration) -> Result<P::Connection, AcquireError<P::Error>> {
+        match timeout(patience, prefer::writing(self.get())).await {
+            Ok(result) => result.map_err(AcquireError::Pool),
+            Err(_) => Err(AcquireError::Timeout(patience)),
+        }
+    }
+
+    ///Gets a connection as [`ReadConnection`](crate::ReadConnection) would, waiting at most
+    ///`patience`, along with the pool which served it, or on timeout the pool it was waiting on,
+    ///e.g. the main pool after a replica failed and the read fell back.
+    ///
+    ///The pools' own `connect_timeout` still applies, so this can only shorten the wait.
+    pub async fn get_read_with_timeout(&self, patience: Duration)
+        -> (PoolRole, Result<P::Connection, AcquireError<P::Error>>)
+    {
+        let waiting = Arc::new(Mutex::new(PoolRole::Read));
+        let acquisition = WAITING.scope(waiting.clone(), ReadCapablePool::<P::Connection>::get_read(self));
+        match timeout(patience, acquisition).await {
+            Ok((role, result)) => (role, result.map_err(AcquireError::Pool)),
+            Err(_) => (*waiting.lock().unwrap_or_else(|e| e.into_inner()), Err(AcquireError::Timeout(patience))),
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R>
+    where P: TryAcquire, R: TryAcquire, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, R::Connection: Into<P::Connection>
+{
+    ///Gets an idle connection from the main pool without waiting
+    pub fn try_get(&self) -> Option<P::Connection> {
+        self.route(PoolRole::Main);
+        self.primary().try_get()
+    }
+
+    ///Gets an idle connection from the pool [`ReadConnection`](crate::ReadConnection) would use,
+    ///without waiting, along with that pool. The pool is chosen as for any read, so
+    ///`read.primary_read_ratio`, brownouts and the circuit breaker apply. Returns `None` rather
+    ///than falling back to another pool, so best-effort work can be skipped under load.
+    pub fn try_get_read(&self) -> Option<(PoolRole, P::Connection)> {
+        let set = self.replica_set();
+        match self.read_target(&set) {
+            Ok(i) => set.pools[i].try_get().map(|conn| (PoolRole::Read, conn.into())),
+            Err(_) => self.primary().try_get().map(|conn| (PoolRole::Main, conn)),
+        }
+    }
+}
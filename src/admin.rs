@@ -0,0 +1,179 @@
+//!Routes listing and operating a database's read replicas as JSON, with the `json` feature
+use std::marker::PhantomData;
+use rocket::{Build, Data, Request, Rocket, Route};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::route::{self, Handler};
+use rocket::serde::Serialize;
+use rocket::serde::json::Json;
+use rocket_db_pools::Database;
+use crate::{HealthRegistry, HealthState, PoolRole, PoolStats, PoolUsage, ReadPool};
+
+///One read replica, from [`ReadPool::replica_info`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaInfo {
+    ///Name of the replica in `read.replicas`, see [`Replicas`](crate::Replicas)
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    ///The replica's `weight` for the balancer
+    pub weight: u32,
+    pub state: HealthState,
+    ///Whether reads may use the replica, which they don't in any state but healthy
+    pub available: bool,
+    pub stats: PoolStats,
+    ///Connection acquisitions attempted from the replica
+    pub acquisitions: u64,
+    ///Acquisitions from the replica which failed
+    pub errors: u64,
+}
+
+///The read replicas of a database, from [`ReadPool::replica_info`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaSetInfo {
+    pub database: String,
+    ///The configured label of the read pool
+    pub label: String,
+    ///Each read replica, in order
+    pub replicas: Vec<ReplicaInfo>,
+}
+
+impl<P, R: PoolUsage> ReadPool<P, R> {
+    ///A snapshot of each read replica's configuration, state and counters, e.g. for an
+    ///operator's dashboard
+    pub fn replica_info(&self, database: &str) -> ReplicaSetInfo {
+        let set = self.replica_set();
+        let replicas = set.replicas.iter().zip(&set.pools).zip(&set.weights).map(|((replica, pool), &weight)| {
+            let (acquisitions, errors) = replica.usage.totals();
+            ReplicaInfo{
+                name: replica.name.to_string(),
+                zone: replica.zone.as_deref().map(str::to_string),
+                weight,
+                state: replica.health.state(),
+                available: replica.health.available(),
+                stats: PoolStats::of(pool),
+                acquisitions,
+                errors,
+            }
+        }).collect();
+        ReplicaSetInfo{database: database.to_string(), label: self.label(PoolRole::Read).to_string(), replicas}
+    }
+}
+
+///A fairing which mounts routes listing the read replicas of the database `D` and taking them
+///in and out of rotation, for operating them without a restart. Every route requires the
+///request guard `G` to succeed first, so it should authenticate an operator:
+///
+/// * `GET <path>` responds with [`ReadPool::replica_info`]
+/// * `POST <path>/<name>/drain` calls [`ReadPool::drain_replica`]
+/// * `POST <path>/<name>/enable` calls [`ReadPool::enable_replica`]
+///
+///Both `POST` routes respond with the replicas as `GET` does, or `404 Not Found` if there's no
+///replica named `name`. Changes are published to the managed [`HealthRegistry`], if any.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::http::Status;
+/// use rocket::request::{FromRequest, Outcome, Request};
+/// use rocket_read_db_pools::ReplicaAdmin;
+///
+/// struct Operator;
+/// #[rocket::async_trait]
+/// impl<'r> FromRequest<'r> for Operator {
+///     type Error = ();
+///
+///     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+///         match req.headers().get_one("X-Admin-Token") == Some("secret") {
+///             true => Outcome::Success(Operator),
+///             false => Outcome::Error((Status::Forbidden, ())),
+///         }
+///     }
+/// }
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReplicaAdmin::<Db, Operator>::new("/admin/db"))
+/// # }
+///```
+pub struct ReplicaAdmin<D, G> {
+    path: String,
+    _marker: PhantomData<fn() -> (D, G)>,
+}
+impl<D, G> ReplicaAdmin<D, G> {
+    ///Serves the routes under `path`
+    pub fn new<T: Into<String>>(path: T) -> Self {
+        ReplicaAdmin{path: path.into(), _marker: PhantomData}
+    }
+}
+
+///What a [`ReplicaAdmin`] route does
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    List,
+    Drain,
+    Enable,
+}
+
+struct AdminHandler<D, G> {
+    action: Action,
+    _marker: PhantomData<fn() -> (D, G)>,
+}
+impl<D, G> Clone for AdminHandler<D, G> {
+    fn clone(&self) -> Self {
+        AdminHandler{action: self.action, _marker: PhantomData}
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R, G> Handler for AdminHandler<D, G>
+    where D: Database<Pool = ReadPool<P, R>>, P: Send + Sync + 'static, R: PoolUsage, G: for<'r> FromRequest<'r> + 'static
+{
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match G::from_request(req).await {
+            Outcome::Success(_) => {}
+            Outcome::Error((status, _)) => return route::Outcome::Error(status),
+            Outcome::Forward(status) => return route::Outcome::Forward((data, status)),
+        }
+        let Some(db) = D::fetch(req.rocket()) else {return route::Outcome::Error(Status::ServiceUnavailable)};
+        let name = req.param::<&str>(0).and_then(Result::ok).unwrap_or_default();
+        let found = match self.action {
+            Action::List => true,
+            Action::Drain => db.drain_replica(name),
+            Action::Enable => db.enable_replica(name),
+        };
+        if !found {
+            return route::Outcome::Error(Status::NotFound);
+        }
+        if let Some(registry) = req.rocket().state::<HealthRegistry>() {
+            registry.update(D::NAME, db);
+        }
+        route::Outcome::from(req, Json(db.replica_info(D::NAME)))
+    }
+}
+
+#[rocket::async_trait]
+impl<D, P, R, G> Fairing for ReplicaAdmin<D, G>
+    where D: Database<Pool = ReadPool<P, R>>, P: Send + Sync + 'static, R: PoolUsage, G: for<'r> FromRequest<'r> + 'static
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Admin",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let handler = |action| AdminHandler::<D, G>{action, _marker: PhantomData};
+        let routes = vec![
+            Route::new(Method::Get, "/", handler(Action::List)),
+            Route::new(Method::Post, "/<name>/drain", handler(Action::Drain)),
+            Route::new(Method::Post, "/<name>/enable", handler(Action::Enable)),
+        ];
+        Ok(rocket.mount(self.path.as_str(), routes))
+    }
+}
@@ -0,0 +1,162 @@
+//!Concurrent initialization of several databases
+use std::any::{type_name, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::futures::future::{join_all, BoxFuture};
+use rocket_db_pools::{Database, Pool};
+
+///The config of the database `name` within Rocket's `figment`, with the same defaults as
+///rocket_db_pools' initializer, as `Pool::init` receives it
+pub(crate) fn database_figment(figment: &Figment, name: &str) -> Figment {
+    let workers: usize = figment
+        .extract_inner(rocket::Config::WORKERS)
+        .unwrap_or_else(|_| rocket::Config::default().workers);
+    figment
+        .focus(&format!("databases.{}", name))
+        .join(Serialized::default("max_connections", workers * 4))
+        .join(Serialized::default("connect_timeout", 5))
+}
+
+type Manage = Box<dyn FnOnce(Rocket<Build>) -> Rocket<Build> + Send>;
+
+trait Init: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn database(&self) -> (TypeId, &'static str);
+    fn is_managed(&self, rocket: &Rocket<Build>) -> bool;
+    fn init(&self, figment: Figment) -> BoxFuture<'static, Result<Manage, String>>;
+    fn close<'a>(&self, rocket: &'a Rocket<Orbit>) -> BoxFuture<'a, ()>;
+}
+
+struct DatabaseInit<D>(PhantomData<fn() -> D>);
+impl<D: Database> Init for DatabaseInit<D> {
+    fn name(&self) -> &'static str {
+        D::NAME
+    }
+
+    fn database(&self) -> (TypeId, &'static str) {
+        (TypeId::of::<D>(), type_name::<D>())
+    }
+
+    fn is_managed(&self, rocket: &Rocket<Build>) -> bool {
+        D::fetch(rocket).is_some()
+    }
+
+    fn init(&self, figment: Figment) -> BoxFuture<'static, Result<Manage, String>> {
+        Box::pin(async move {
+            match <D::Pool>::init(&figment).await {
+                Ok(pool) => Ok(Box::new(move |rocket: Rocket<Build>| rocket.manage(D::from(pool))) as Manage),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+    }
+
+    fn close<'a>(&self, rocket: &'a Rocket<Orbit>) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let Some(db) = D::fetch(rocket) {
+                db.close().await;
+            }
+        })
+    }
+}
+
+///A fairing which initializes several databases concurrently, in place of attaching each
+///database's `Database::init()` fairing.
+///
+///Every database is initialized before launch continues, and all failures are reported
+///rather than just the first. Usually created with [`attach_read_dbs!`](crate::attach_read_dbs).
+///
+///A database added more than once is only initialized once, and one whose pools are already
+///managed at ignite (e.g. through an earlier `Db::init()` fairing) keeps them rather than opening
+///a second set of connections. Ignite fails if two different database types use the same name.
+///Attaching `Db::init()` *after* this fairing still makes Rocket abort, as the database would
+///be managed twice.
+#[derive(Default)]
+pub struct ReadDatabases {
+    databases: Vec<Box<dyn Init>>,
+}
+impl ReadDatabases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Adds the database `D`, unless it has already been added
+    pub fn add<D: Database>(mut self) -> Self {
+        if self.databases.iter().any(|db| db.database().0 == TypeId::of::<D>()) {
+            return self;
+        }
+        self.databases.push(Box::new(DatabaseInit::<D>(PhantomData)));
+        self
+    }
+}
+#[rocket::async_trait]
+impl Fairing for ReadDatabases {
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Databases",
+            kind: Kind::Ignite | Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let mut names = HashMap::new();
+        let mut failed = false;
+        for db in &self.databases {
+            if let Some(other) = names.insert(db.name(), db.database().1) {
+                rocket::error!("`{}` and `{}` are both attached as database `{}`, which would open its connections twice",
+                    other, db.database().1, db.name());
+                failed = true;
+            }
+        }
+        if failed {
+            return Err(rocket);
+        }
+        let pending: Vec<_> = self.databases.iter().filter(|db| {
+            let managed = db.is_managed(&rocket);
+            if managed {
+                rocket::warn!("database `{}` is already attached, reusing its pools", db.name());
+            }
+            !managed
+        }).collect();
+        let results = join_all(pending.iter().map(|db| db.init(database_figment(rocket.figment(), db.name())))).await;
+        let mut rocket = rocket;
+        for (db, result) in pending.into_iter().zip(results) {
+            match result {
+                Ok(manage) => rocket = manage(rocket),
+                Err(e) => {
+                    rocket::error!("failed to initialize database `{}`: {}", db.name(), e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {Err(rocket)} else {Ok(rocket)}
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        join_all(self.databases.iter().map(|db| db.close(rocket))).await;
+    }
+}
+
+///Attaches several databases to a Rocket, initializing them concurrently with [`ReadDatabases`].
+///
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct MainDb(ReadPool<MockPool>);
+/// # #[derive(Database)] #[database("reporting")] struct ReportingDb(ReadPool<MockPool>);
+/// use rocket_read_db_pools::attach_read_dbs;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// attach_read_dbs!(rocket::build(), MainDb, ReportingDb)
+/// # }
+///```
+#[macro_export]
+macro_rules! attach_read_dbs {
+    ($rocket:expr, $($db:ty),+ $(,)?) => {
+        $rocket.attach($crate::ReadDatabases::new()$(.add::<$db>())+)
+    };
+}
@@ -0,0 +1,107 @@
+//!Startup audit that the read pool's database role can't write
+use std::marker::PhantomData;
+use rocket::{Build, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{HealthProbe, ReadPool};
+
+///Result of auditing the read pool's role
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoleAudit {
+    ///The read pool's role can't write
+    ReadOnly,
+    ///The read pool's role was able to write
+    Writable,
+    ///There is no read pool to audit
+    NoReadPool,
+    ///A connection couldn't be acquired or the probe failed
+    Failed(String),
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, R::Connection: HealthProbe {
+    ///Checks that the read pool's database role can't write, as defense in depth beyond
+    ///session read-only settings. With several replicas, the first which isn't read-only is
+    ///reported.
+    pub async fn audit_read_role(&self) -> RoleAudit {
+        let set = self.replica_set();
+        if set.is_empty() {
+            return RoleAudit::NoReadPool;
+        }
+        for read in &set.pools {
+            let mut conn = match read.get().await {
+                Ok(conn) => conn,
+                Err(e) => return RoleAudit::Failed(e.to_string()),
+            };
+            match conn.can_write().await {
+                Ok(true) => return RoleAudit::Writable,
+                Ok(false) => {}
+                Err(e) => return RoleAudit::Failed(e.to_string()),
+            }
+        }
+        RoleAudit::ReadOnly
+    }
+}
+
+///A fairing which audits the read pool's role for the database `D` at ignite and logs the result.
+///
+///Attach it after `D::init()`. By default launch continues whatever the result; use
+///[`ReadRoleAudit::required`] to abort launch unless the role is confirmed read-only.
+pub struct ReadRoleAudit<D>{
+    required: bool,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ReadRoleAudit<D> {
+    pub fn new() -> Self {
+        ReadRoleAudit{required: false, _db: PhantomData}
+    }
+
+    ///Aborts launch unless the read role is confirmed read-only
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+impl<D> Default for ReadRoleAudit<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadRoleAudit<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool, R::Connection: HealthProbe
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Role Audit",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let Some(db) = D::fetch(&rocket) else {
+            rocket::error!("`ReadRoleAudit` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        };
+        let label = db.label(crate::PoolRole::Read).to_string();
+        let ok = match db.audit_read_role().await {
+            RoleAudit::ReadOnly => {
+                db_log!(db.log, General, Info, "database `{}`: `{}` role is read-only", D::NAME, label);
+                true
+            }
+            RoleAudit::Writable => {
+                db_log!(db.log, General, Error, "database `{}`: `{}` role is able to write", D::NAME, label);
+                false
+            }
+            RoleAudit::NoReadPool => {
+                db_log!(db.log, General, Warn, "database `{}`: no read pool to audit", D::NAME);
+                false
+            }
+            RoleAudit::Failed(e) => {
+                db_log!(db.log, General, Error, "database `{}`: failed to audit `{}` role: {}", D::NAME, label, e);
+                false
+            }
+        };
+        if ok || !self.required {Ok(rocket)} else {Err(rocket)}
+    }
+}
@@ -0,0 +1,84 @@
+//!Request guard choosing the pool by HTTP method
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use rocket::{Ignite, Rocket, Sentinel};
+use rocket::http::Method;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::{Database, Pool};
+use crate::hooks::Release;
+use crate::{ConnectionSource, ReadCapablePool, ReadConnection, ReadPoolError, RwConnection, StreamConnection};
+
+/// A request guard which retrieves a read connection for `GET` and `HEAD` requests, as
+/// [`ReadConnection`] does, and a main pool connection for any other method, as
+/// [`RwConnection`] does.
+///
+/// Handlers using it follow the read/write split without choosing a guard each. Routes whose
+/// safe methods write, or whose mutating methods must read from a replica, should use the
+/// explicit guards instead.
+pub struct AutoConnection<D: Database>(<D::Pool as Pool>::Connection, bool, PhantomData<fn() -> D>, ConnectionSource, Option<Release>);
+impl<D: Database> AutoConnection<D> {
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        drop(self.4);
+        self.0
+    }
+
+    ///Whether the connection was acquired for reading, though it may still have been served by
+    ///the main pool
+    pub fn is_read(&self) -> bool {
+        self.1
+    }
+
+    ///The pool which served the connection, and the replica if one did
+    pub fn source(&self) -> &ConnectionSource {
+        &self.3
+    }
+
+    ///Converts into a connection a streamed response can own, returned to its pool when the
+    ///stream is dropped, see [`StreamConnection`]
+    pub fn into_stream_guard(self) -> StreamConnection<<D::Pool as Pool>::Connection> {
+        StreamConnection::new(self.0, self.4, self.3)
+    }
+
+    ///Whether a read replica served the connection, rather than the main pool
+    pub fn is_replica(&self) -> bool {
+        self.3.is_replica()
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for AutoConnection<D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.method() {
+            Method::Get | Method::Head => ReadConnection::<D>::from_request(req).await
+                .map(|conn| {
+                    let source = conn.source().clone();
+                    let (conn, release) = conn.into_parts();
+                    AutoConnection(conn, true, PhantomData, source, release)
+                }),
+            _ => RwConnection::<D>::from_request(req).await
+                .map(|conn| {
+                    let (conn, release) = conn.into_parts();
+                    AutoConnection(conn, false, PhantomData, ConnectionSource::main(), release)
+                }),
+        }
+    }
+}
+impl<D: Database> Sentinel for AutoConnection<D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for AutoConnection<D> {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<D: Database> DerefMut for AutoConnection<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
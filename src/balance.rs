@@ -0,0 +1,308 @@
+//!Choice of read replica for each read acquisition
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::Pool;
+use crate::ReadPool;
+#[cfg(feature = "strategy-least-connections")]
+use crate::PoolUsage;
+
+///Chooses which of several read replicas serves the next read, for [`ReadPool::set_balancer`].
+///
+///`weights` has an entry for each replica, from its `weight` option (1 if absent).
+pub trait ReadBalancer<R>: Send + Sync {
+    ///Returns the index of the replica to use. Out of range indices wrap around.
+    fn pick(&self, replicas: &[R], weights: &[u32]) -> usize;
+    ///Returns the index of the replica to use among those `available` admits, e.g. those which
+    ///aren't quarantined. Defaults to `pick`, whose choice is replaced by the next available
+    ///replica if it's unavailable; balancers which would keep choosing an unavailable replica,
+    ///e.g. as it's idle, should choose among the available ones instead.
+    fn pick_available(&self, replicas: &[R], weights: &[u32], available: &dyn Fn(usize) -> bool) -> usize {
+        let _ = available;
+        self.pick(replicas, weights)
+    }
+    ///Name of the balancer in [`ReadPool::info`]. Defaults to its type name.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+///The built-in balancers which can be chosen with the `read.balancer` option. `random` and
+///`weighted` need the `strategy-random` and `strategy-weighted` features; without them, naming
+///one fails the pool's initialization as an unknown variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    ///[`RoundRobin`]
+    #[default]
+    RoundRobin,
+    ///[`Random`]
+    #[cfg(feature = "strategy-random")]
+    Random,
+    ///[`Weighted`]
+    #[cfg(feature = "strategy-weighted")]
+    Weighted,
+    ///[`Preferred`]
+    Preferred,
+}
+impl BalanceStrategy {
+    pub(crate) fn balancer<R>(self) -> Arc<dyn ReadBalancer<R>> {
+        match self {
+            BalanceStrategy::RoundRobin => Arc::new(RoundRobin::default()),
+            #[cfg(feature = "strategy-random")]
+            BalanceStrategy::Random => Arc::new(Random::default()),
+            #[cfg(feature = "strategy-weighted")]
+            BalanceStrategy::Weighted => Arc::new(Weighted::default()),
+            BalanceStrategy::Preferred => Arc::new(Preferred),
+        }
+    }
+}
+
+///Always picks the first replica, so the others are only used while it's quarantined, in the
+///order they're listed
+#[derive(Debug, Default)]
+pub struct Preferred;
+impl<R> ReadBalancer<R> for Preferred {
+    fn pick(&self, _replicas: &[R], _weights: &[u32]) -> usize {
+        0
+    }
+
+    fn name(&self) -> &'static str {
+        "preferred"
+    }
+}
+
+///Takes turns between the replicas, ignoring weights
+#[derive(Debug, Default)]
+pub struct RoundRobin(AtomicUsize);
+impl<R> ReadBalancer<R> for RoundRobin {
+    fn pick(&self, _replicas: &[R], _weights: &[u32]) -> usize {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn name(&self) -> &'static str {
+        "round_robin"
+    }
+}
+
+///Picks a replica at random, in proportion to its weight, with the `strategy-random` feature
+#[derive(Debug)]
+pub struct Random(AtomicU64);
+impl Default for Random {
+    fn default() -> Self {
+        Self::seeded(RandomState::new().hash_one(0u64))
+    }
+}
+impl Random {
+    ///A generator whose sequence is fixed by `seed`
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Random(AtomicU64::new(seed | 1))
+    }
+
+    pub(crate) fn next(&self) -> u64 {
+        //xorshift; races between threads only make it more random
+        let mut x = self.0.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.store(x, Ordering::Relaxed);
+        x
+    }
+}
+#[cfg(feature = "strategy-random")]
+impl<R> ReadBalancer<R> for Random {
+    fn pick(&self, _replicas: &[R], weights: &[u32]) -> usize {
+        let total: u64 = weights.iter().map(|&w| w as u64).sum();
+        if total == 0 {
+            return self.next() as usize;
+        }
+        by_weight(weights, self.next() % total)
+    }
+
+    fn name(&self) -> &'static str {
+        "random"
+    }
+}
+
+///Takes turns between the replicas, giving each as many turns in a row as its weight
+#[cfg(feature = "strategy-weighted")]
+#[derive(Debug, Default)]
+pub struct Weighted(AtomicU64);
+#[cfg(feature = "strategy-weighted")]
+impl<R> ReadBalancer<R> for Weighted {
+    fn pick(&self, _replicas: &[R], weights: &[u32]) -> usize {
+        let turn = self.0.fetch_add(1, Ordering::Relaxed);
+        let total: u64 = weights.iter().map(|&w| w as u64).sum();
+        if total == 0 {
+            return turn as usize;
+        }
+        by_weight(weights, turn % total)
+    }
+
+    fn name(&self) -> &'static str {
+        "weighted"
+    }
+}
+
+///The replica `balancer` picks among `replicas`, or the next after it which `admits`, or `None`
+///if none does
+pub(crate) fn pick_admitted<R>(balancer: &dyn ReadBalancer<R>, replicas: &[R], weights: &[u32], admits: impl Fn(usize) -> bool) -> Option<usize> {
+    let first = match replicas.len() {
+        0 => return None,
+        1 => 0,
+        n => balancer.pick_available(replicas, weights, &admits) % n,
+    };
+    (0..replicas.len())
+        .map(|i| (first + i) % replicas.len())
+        .find(|&i| admits(i))
+}
+
+///The replica whose share of the total weight contains `n`
+#[cfg(any(feature = "strategy-random", feature = "strategy-weighted"))]
+fn by_weight(weights: &[u32], mut n: u64) -> usize {
+    for (i, &weight) in weights.iter().enumerate() {
+        if n < weight as u64 {
+            return i;
+        }
+        n -= weight as u64;
+    }
+    0
+}
+
+///Picks the replica with the fewest connections in use relative to its size and weight.
+///
+///It needs [`PoolUsage`], so it can't be chosen from config; set it with
+///[`ReadPool::set_balancer`].
+#[cfg(feature = "strategy-least-connections")]
+#[derive(Debug, Default)]
+pub struct LeastConnections;
+#[cfg(feature = "strategy-least-connections")]
+impl<R: PoolUsage> ReadBalancer<R> for LeastConnections {
+    fn pick(&self, replicas: &[R], weights: &[u32]) -> usize {
+        self.pick_available(replicas, weights, &|_| true)
+    }
+
+    fn pick_available(&self, replicas: &[R], weights: &[u32], available: &dyn Fn(usize) -> bool) -> usize {
+        let load = |(i, replica): (usize, &R)| {
+            let capacity = replica.max_connections() as f32 * weights.get(i).copied().unwrap_or(1) as f32;
+            if capacity == 0.0 {f32::INFINITY} else {replica.in_use() as f32 / capacity}
+        };
+        replicas.iter().enumerate()
+            .filter(|&(i, _)| available(i))
+            .map(|replica| (replica.0, load(replica)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn name(&self) -> &'static str {
+        "least_connections"
+    }
+}
+
+impl<P: Pool, R: Pool> ReadPool<P, R> {
+    ///Replaces the balancer choosing between read replicas, e.g. with `LeastConnections`.
+    ///Clones of the pool share the change.
+    pub fn set_balancer(&self, balancer: impl ReadBalancer<R> + 'static) {
+        *self.balancer.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(balancer);
+    }
+
+    ///The weight of each read replica, from the `weight` option
+    pub fn read_weights(&self) -> Vec<u32> {
+        self.replica_set().weights.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Usage(u32, u32);
+    #[rocket::async_trait]
+    impl Pool for Usage {
+        type Connection = ();
+        type Error = std::io::Error;
+
+        async fn init(_figment: &rocket::figment::Figment) -> Result<Self, Self::Error> {
+            Ok(Usage(0, 1))
+        }
+
+        async fn get(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(())
+        }
+
+        async fn close(&self) {}
+    }
+    impl PoolUsage for Usage {
+        fn in_use(&self) -> u32 {
+            self.0
+        }
+
+        fn max_connections(&self) -> u32 {
+            self.1
+        }
+    }
+
+    fn picks<B: ReadBalancer<()>>(balancer: &B, weights: &[u32], n: usize) -> Vec<usize> {
+        let replicas = vec![(); weights.len()];
+        (0..n).map(|_| balancer.pick(&replicas, weights) % weights.len()).collect()
+    }
+
+    #[test]
+    fn round_robin_ignores_weights() {
+        assert_eq!(picks(&RoundRobin::default(), &[3, 1, 1], 6), [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn weighted_gives_turns_in_a_row() {
+        assert_eq!(picks(&Weighted::default(), &[2, 1, 3], 12), [0, 0, 1, 2, 2, 2, 0, 0, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn random_follows_weights() {
+        let counts = picks(&Random::seeded(7), &[1, 0, 3], 4000).into_iter().fold([0; 3], |mut counts, i| {
+            counts[i] += 1;
+            counts
+        });
+        assert_eq!(counts[1], 0);
+        assert!((2700..3300).contains(&counts[2]), "{:?}", counts);
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        assert_eq!(picks(&Random::seeded(42), &[1, 1, 1], 20), picks(&Random::seeded(42), &[1, 1, 1], 20));
+    }
+
+    #[test]
+    fn preferred_picks_the_first() {
+        assert_eq!(picks(&Preferred, &[1, 5], 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn least_connections_weighs_capacity() {
+        let replicas = [Usage(5, 10), Usage(2, 10), Usage(3, 10)];
+        assert_eq!(LeastConnections.pick(&replicas, &[1, 1, 1]), 1);
+        assert_eq!(LeastConnections.pick(&replicas, &[1, 1, 2]), 2);
+        assert_eq!(LeastConnections.pick_available(&replicas, &[1, 1, 1], &|i| i != 1), 2);
+        assert_eq!(LeastConnections.pick(&[Usage(0, 0), Usage(9, 10)], &[1, 1]), 1);
+    }
+
+    #[test]
+    fn pick_admitted_skips_unadmitted() {
+        let replicas = [(), (), ()];
+        assert_eq!(pick_admitted(&Preferred, &replicas, &[1, 1, 1], |i| i != 0), Some(1));
+        assert_eq!(pick_admitted(&Preferred, &replicas, &[1, 1, 1], |i| i == 0), Some(0));
+        assert_eq!(pick_admitted(&Preferred, &replicas, &[1, 1, 1], |_| false), None);
+        assert_eq!(pick_admitted(&Preferred, &[] as &[()], &[], |_| true), None);
+        let round_robin = RoundRobin(AtomicUsize::new(5));
+        assert_eq!(pick_admitted(&round_robin, &replicas, &[1, 1, 1], |i| i != 2), Some(0));
+    }
+
+    #[test]
+    fn strategies_name_their_balancers() {
+        let strategies = [BalanceStrategy::RoundRobin, BalanceStrategy::Random, BalanceStrategy::Weighted, BalanceStrategy::Preferred];
+        let names: Vec<_> = strategies.into_iter().map(|strategy| strategy.balancer::<()>().name()).collect();
+        assert_eq!(names, ["round_robin", "random", "weighted", "preferred"]);
+    }
+}
@@ -0,0 +1,157 @@
+//!Reusable benchmarks of acquisition and routing overhead against mock pools, enabled by the
+//!`bench` feature.
+//!
+//!The harness times plain loops rather than depending on a benchmarking framework, so results
+//!can be printed from an example or fed to any framework:
+//!```rust
+//! # async fn _inner() {
+//! use rocket_read_db_pools::bench;
+//!
+//! let pool = bench::mock_read_pool().await;
+//! let result = bench::read_acquisition(&pool, 10_000).await;
+//! println!("{}", result);
+//! # }
+//!```
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::local::asynchronous::Client;
+use rocket_db_pools::{Connection, Database, Pool};
+use crate::{ReadCapablePool, ReadConnection, ReadPool, RwConnection};
+
+///A pool which hands out connections instantly, or after a fixed `latency_us` set in its config
+pub struct MockPool {
+    latency: Duration,
+}
+
+///Error of [`MockPool`], which only fails on an invalid configuration
+#[derive(Debug)]
+pub struct MockError;
+impl From<rocket::figment::Error> for MockError {
+    fn from(_: rocket::figment::Error) -> Self {
+        MockError
+    }
+}
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("mock pool error")
+    }
+}
+impl std::error::Error for MockError {}
+
+#[rocket::async_trait]
+impl Pool for MockPool {
+    type Connection = ();
+    type Error = MockError;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let latency = figment.extract_inner("latency_us").map(Duration::from_micros).unwrap_or_default();
+        Ok(MockPool{latency})
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        if !self.latency.is_zero() {
+            rocket::tokio::time::sleep(self.latency).await;
+        }
+        Ok(())
+    }
+
+    async fn close(&self) {}
+}
+
+///A `ReadPool` of instant mock pools with a read pool configured
+pub async fn mock_read_pool() -> ReadPool<MockPool> {
+    let figment = Figment::from(Serialized::default("url", "mock")).merge(Serialized::default("read.url", "mock"));
+    ReadPool::<MockPool>::init(&figment).await.expect("mock pools never fail")
+}
+
+///Timing of one benchmark
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchResult {
+    pub name: String,
+    pub iterations: u32,
+    pub total: Duration,
+}
+impl BenchResult {
+    ///Mean time per iteration
+    pub fn per_iteration(&self) -> Duration {
+        self.total / self.iterations.max(1)
+    }
+}
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<28} {:?}/iter ({} iterations)", self.name, self.per_iteration(), self.iterations)
+    }
+}
+
+///Times `iterations` sequential runs of `f`
+pub async fn run<F, Fut>(name: &str, iterations: u32, mut f: F) -> BenchResult
+    where F: FnMut() -> Fut, Fut: Future
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f().await;
+    }
+    BenchResult{name: name.to_string(), iterations, total: start.elapsed()}
+}
+
+///Times read acquisition through `pool`'s routing, as used by `ReadConnection`
+pub async fn read_acquisition<P, R>(pool: &ReadPool<P, R>, iterations: u32) -> BenchResult
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, R::Connection: Into<P::Connection> + Send, P::Connection: Send
+{
+    run("read acquisition", iterations, || async {
+        let _ = ReadCapablePool::<P::Connection>::get_read(pool).await;
+    }).await
+}
+
+///Times write acquisition through `pool`, as used by `RwConnection`
+pub async fn write_acquisition<P, R>(pool: &ReadPool<P, R>, iterations: u32) -> BenchResult
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, P::Connection: Send, R::Connection: Send
+{
+    run("write acquisition", iterations, || async {
+        let _ = pool.get().await;
+    }).await
+}
+
+#[derive(Database)]
+#[database("direct")]
+struct Direct(MockPool);
+
+#[derive(Database)]
+#[database("routed")]
+struct Routed(ReadPool<MockPool>);
+
+#[rocket::get("/none")]
+fn no_guard() {}
+
+#[rocket::get("/direct")]
+fn direct(_conn: Connection<Direct>) {}
+
+#[rocket::get("/read")]
+fn read(_conn: ReadConnection<Routed>) {}
+
+#[rocket::get("/rw")]
+fn rw(_conn: RwConnection<Routed>) {}
+
+///Times requests through a local client to routes taking no guard, `rocket_db_pools`'
+///`Connection`, `ReadConnection` and `RwConnection`, all over instant mock pools, to measure
+///the per-request overhead of this crate's guards
+pub async fn guard_overhead(iterations: u32) -> Vec<BenchResult> {
+    let figment = rocket::Config::figment()
+        .merge(("log_level", "off"))
+        .merge(("databases.direct.url", "mock"))
+        .merge(("databases.routed.url", "mock"))
+        .merge(("databases.routed.read.url", "mock"));
+    let rocket = rocket::custom(figment)
+        .attach(Direct::init())
+        .attach(Routed::init())
+        .mount("/", rocket::routes![no_guard, direct, read, rw]);
+    let client = Client::untracked(rocket).await.expect("valid rocket");
+    let mut results = Vec::new();
+    for (name, uri) in [("no guard", "/none"), ("Connection", "/direct"), ("ReadConnection", "/read"), ("RwConnection", "/rw")] {
+        results.push(run(name, iterations, || client.get(uri).dispatch()).await);
+    }
+    results
+}
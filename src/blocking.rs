@@ -0,0 +1,134 @@
+//!Read/write splitting for blocking pools, such as Diesel's r2d2 pools
+use std::sync::Arc;
+use rocket::figment::Figment;
+use rocket::tokio::task::{spawn_blocking, JoinError};
+use rocket_db_pools::Pool;
+use crate::ReadPool;
+
+///A pool whose connections block, such as an r2d2 pool of Diesel connections, made usable as
+///the main and read pools of a [`ReadPool`] by wrapping it in [`Blocking`].
+///
+///Implement it for the pool type. Both methods are called on a blocking thread.
+pub trait SyncPool: Sized + Send + Sync + 'static {
+    type Connection: Send + 'static;
+    type Error: std::error::Error + Send + 'static;
+
+    ///Builds the pool from its config, the `databases.<name>` table or one of the tables it's
+    ///derived from for the read pools, as `Pool::init` does
+    fn init(figment: &Figment) -> Result<Self, Self::Error>;
+
+    ///Gets a connection, blocking until one is available
+    fn get(&self) -> Result<Self::Connection, Self::Error>;
+}
+
+///A [`ReadPool`] of blocking pools. Its guards deref to a [`SyncConnection`], whose
+///[`run`](SyncConnection::run) method runs closures on a blocking thread, so `ReadConnection`
+///and `RwConnection` serve the same purpose as the connection guards of `rocket_sync_db_pools`
+///while routing reads to the replicas.
+///```rust
+/// use rocket::figment::Figment;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{ReadConnection, RwConnection, SyncPool, SyncReadPool, TryAcquire};
+/// use rocket_read_db_pools::testing::{MockError, MockPool, MockPoolConnection};
+///
+/// //Stands in for e.g. an r2d2 pool of Diesel connections
+/// pub struct BlockingPool(MockPool);
+/// impl SyncPool for BlockingPool {
+///     type Connection = MockPoolConnection;
+///     type Error = MockError;
+///
+///     fn init(figment: &Figment) -> Result<Self, Self::Error> {
+///         Ok(BlockingPool(MockPool::new(&figment.extract_inner::<String>("url")?)))
+///     }
+///
+///     fn get(&self) -> Result<Self::Connection, Self::Error> {
+///         self.0.try_get().ok_or_else(|| MockError::Exhausted(self.0.url().to_string()))
+///     }
+/// }
+///
+/// #[derive(Database)]
+/// #[database("main")]
+/// struct Db(SyncReadPool<BlockingPool>);
+///
+/// #[rocket::get("/read")]
+/// async fn read(mut conn: ReadConnection<Db>) -> String {
+///     conn.run(|c| c.url().to_string()).await
+/// }
+///
+/// #[rocket::get("/write")]
+/// async fn write(mut conn: RwConnection<Db>) -> String {
+///     conn.run(|c| c.url().to_string()).await
+/// }
+///```
+pub type SyncReadPool<S, R = S> = ReadPool<Blocking<S>, Blocking<R>>;
+
+///Adapts a [`SyncPool`] to `rocket_db_pools`' async [`Pool`] trait, running its blocking calls
+///on blocking threads
+pub struct Blocking<S>(Arc<S>);
+impl<S> Blocking<S> {
+    ///Gets the wrapped pool
+    pub fn inner(&self) -> &S {
+        &self.0
+    }
+}
+impl<S> Clone for Blocking<S> {
+    fn clone(&self) -> Self {
+        Blocking(self.0.clone())
+    }
+}
+
+///Resumes a panic of a blocking task, which can't otherwise fail
+fn joined<T>(result: Result<T, JoinError>) -> T {
+    result.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+#[rocket::async_trait]
+impl<S: SyncPool> Pool for Blocking<S> {
+    type Connection = SyncConnection<S::Connection>;
+    type Error = S::Error;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let figment = figment.clone();
+        joined(spawn_blocking(move || S::init(&figment)).await).map(|pool| Blocking(Arc::new(pool)))
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        let pool = self.0.clone();
+        joined(spawn_blocking(move || pool.get()).await).map(|conn| SyncConnection(Some(conn)))
+    }
+
+    async fn close(&self) {}
+}
+
+///A connection from a [`Blocking`] pool, used by running closures on a blocking thread with
+///[`SyncConnection::run`]
+pub struct SyncConnection<C: Send + 'static>(Option<C>);
+impl<C: Send + 'static> SyncConnection<C> {
+    ///Runs `f` with the connection on a blocking thread, returning its result.
+    ///
+    ///If `f` panics, the panic is resumed and the connection is lost.
+    pub async fn run<F, T>(&mut self, f: F) -> T
+        where F: FnOnce(&mut C) -> T + Send + 'static, T: Send + 'static
+    {
+        let mut conn = self.0.take().expect("connection lost by a panic in `run`");
+        let (conn, result) = joined(spawn_blocking(move || {
+            let result = f(&mut conn);
+            (conn, result)
+        }).await);
+        self.0 = Some(conn);
+        result
+    }
+
+    ///Gets the internal connection value
+    pub fn into_inner(mut self) -> C {
+        self.0.take().expect("connection lost by a panic in `run`")
+    }
+}
+impl<C: Send + 'static> Drop for SyncConnection<C> {
+    fn drop(&mut self) {
+        //Returning a connection to its pool may block
+        if let (Some(conn), Ok(handle)) = (self.0.take(), rocket::tokio::runtime::Handle::try_current()) {
+            handle.spawn_blocking(move || drop(conn));
+        }
+    }
+}
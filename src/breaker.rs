@@ -0,0 +1,147 @@
+//!Circuit breaker routing reads to the main pool while the replicas keep failing
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rocket::serde::{Deserialize, Serialize};
+use crate::logging::db_log;
+use crate::{PoolRole, ReadPool};
+
+///Configuration of the read circuit breaker: the `databases.<name>.read.circuit_breaker` table.
+///
+///After `failure_threshold` read acquisitions fail in a row, the circuit opens and reads go
+///straight to the main pool. Once `cooldown_ms` has passed a single read tries the replicas
+///again: if it succeeds the circuit closes, otherwise it stays open for another cooldown.
+///```toml
+///[default.databases.main.read.circuit_breaker]
+///failure_threshold = 5
+///cooldown_ms = 30000
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CircuitBreakerConfig {
+    ///`failure_threshold`: consecutive failed read acquisitions which open the circuit.
+    ///Defaults to 5.
+    pub failure_threshold: u32,
+    ///`cooldown_ms`: time the circuit stays open before a read tries the replicas again.
+    ///Defaults to 30000.
+    pub cooldown_ms: u64,
+}
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig{failure_threshold: 5, cooldown_ms: 30000}
+    }
+}
+
+pub(crate) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    created: Instant,
+    failures: AtomicU32,
+    ///When the circuit opened, in microseconds since `created` plus one, or 0 if closed
+    opened: AtomicU64,
+    ///Whether a half-open trial read is in flight
+    trial: AtomicBool,
+}
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker{config, created: Instant::now(), failures: AtomicU32::new(0), opened: AtomicU64::new(0), trial: AtomicBool::new(false)}
+    }
+
+    fn now(&self) -> u64 {
+        self.created.elapsed().as_micros().min(u64::MAX as u128 - 1) as u64 + 1
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.opened.load(Ordering::Relaxed) != 0
+    }
+
+    ///Whether the next read may use the replicas. After the cooldown, only one read at a time
+    ///is let through until its result is recorded.
+    pub fn allows(&self) -> bool {
+        let opened = self.opened.load(Ordering::Relaxed);
+        if opened == 0 {
+            return true;
+        }
+        let cooldown = Duration::from_millis(self.config.cooldown_ms).as_micros() as u64;
+        self.now().saturating_sub(opened) >= cooldown && !self.trial.swap(true, Ordering::AcqRel)
+    }
+
+    ///Records the result of a read acquisition, returning the new state of the circuit (`true`
+    ///if open) if it changed
+    pub fn record(&self, ok: bool) -> Option<bool> {
+        let trial = self.trial.swap(false, Ordering::AcqRel);
+        if ok {
+            self.failures.store(0, Ordering::Relaxed);
+            return (self.opened.swap(0, Ordering::Relaxed) != 0).then_some(false);
+        }
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if trial {
+            self.opened.store(self.now(), Ordering::Relaxed);
+            None
+        } else if failures >= self.config.failure_threshold.max(1) && !self.is_open() {
+            self.opened.store(self.now(), Ordering::Relaxed);
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Whether the circuit breaker lets the next read use the replicas
+    pub(crate) fn breaker_allows(&self) -> bool {
+        self.breaker.as_ref().is_none_or(|b| b.allows())
+    }
+
+    ///Records the result of a read acquisition with the circuit breaker
+    pub(crate) fn breaker_record(&self, ok: bool) {
+        match self.breaker.as_ref().and_then(|b| b.record(ok)) {
+            Some(true) => db_log!(self.log, Routing, Warn, "`{}` pool circuit opened, reading from `{}`", self.label(PoolRole::Read), self.label(PoolRole::Main)),
+            Some(false) => db_log!(self.log, Routing, Info, "`{}` pool circuit closed", self.label(PoolRole::Read)),
+            None => {}
+        }
+    }
+
+    ///Whether the read circuit breaker is currently open, sending reads to the main pool
+    pub fn circuit_open(&self) -> bool {
+        self.breaker.as_ref().is_some_and(|b| b.is_open())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig{failure_threshold: 3, cooldown_ms: 60000});
+        assert_eq!(breaker.record(false), None);
+        assert_eq!(breaker.record(false), None);
+        assert!(breaker.allows());
+        assert_eq!(breaker.record(false), Some(true));
+        assert!(breaker.is_open());
+        assert!(!breaker.allows());
+        assert_eq!(breaker.record(false), None);
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig{failure_threshold: 2, cooldown_ms: 60000});
+        assert_eq!(breaker.record(false), None);
+        assert_eq!(breaker.record(true), None);
+        assert_eq!(breaker.record(false), None);
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn one_trial_after_cooldown() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig{failure_threshold: 1, cooldown_ms: 0});
+        assert_eq!(breaker.record(false), Some(true));
+        assert!(breaker.allows());
+        assert!(!breaker.allows(), "a second read was let through while the trial was in flight");
+        assert_eq!(breaker.record(false), None);
+        assert!(breaker.is_open());
+        assert!(breaker.allows());
+        assert_eq!(breaker.record(true), Some(false));
+        assert!(!breaker.is_open());
+        assert!(breaker.allows());
+    }
+}
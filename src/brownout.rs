@@ -0,0 +1,182 @@
+//!Gradual shedding of read traffic from a slow replica to the main pool
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use rocket::serde::{Deserialize, Serialize};
+use crate::ReadPool;
+
+///Weight of each new latency sample in the moving average, in thousandths
+const SAMPLE_WEIGHT: u64 = 100;
+///Highest fraction of reads which can be shed, so the replica keeps reporting latency
+const SHED_LIMIT: f64 = 0.95;
+
+///Configuration of brownout mode: the `databases.<name>.read.brownout` table.
+///
+///While the replica's average read latency is above `target_latency_ms`, a fraction of reads
+///rising linearly to `max_shed` at `max_latency_ms` is routed to the main pool instead.
+///```toml
+///[default.databases.main.read.brownout]
+///target_latency_ms = 20
+///max_latency_ms = 200
+///max_shed = 0.8
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BrownoutConfig {
+    ///`target_latency_ms`: latency above which reads start being shed
+    pub target_latency_ms: u64,
+    ///`max_latency_ms`: latency at which `max_shed` is reached
+    pub max_latency_ms: u64,
+    ///`max_shed`: the highest fraction of reads shed, at most 0.95. Defaults to 0.9.
+    #[serde(default = "default_max_shed")]
+    pub max_shed: f64,
+}
+fn default_max_shed() -> f64 {
+    0.9
+}
+
+pub(crate) struct Brownout {
+    config: BrownoutConfig,
+    ///Moving average of read latency in microseconds
+    latency: AtomicU64,
+    ///Accumulated fraction of reads owed to the main pool, in millionths
+    debt: AtomicU64,
+}
+impl Brownout {
+    pub fn new(config: BrownoutConfig) -> Self {
+        Brownout{config, latency: AtomicU64::new(0), debt: AtomicU64::new(0)}
+    }
+
+    pub fn observe(&self, latency: Duration) {
+        let sample = latency.as_micros().min(u64::MAX as u128) as u64;
+        let _ = self.latency.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+            Some((average * (1000 - SAMPLE_WEIGHT) + sample * SAMPLE_WEIGHT) / 1000)
+        });
+    }
+
+    pub fn shed_fraction(&self) -> f64 {
+        let latency = self.latency.load(Ordering::Relaxed) as f64 / 1000.0;
+        let target = self.config.target_latency_ms as f64;
+        if latency <= target {
+            return 0.0;
+        }
+        let span = (self.config.max_latency_ms as f64 - target).max(f64::EPSILON);
+        let max_shed = self.config.max_shed.clamp(0.0, SHED_LIMIT);
+        max_shed * ((latency - target) / span).min(1.0)
+    }
+
+    ///Whether the next read should be shed. Shed reads are spread evenly at the current
+    ///fraction rather than drawn at random.
+    pub fn shed(&self) -> bool {
+        spread(&self.debt, self.shed_fraction())
+    }
+}
+
+///A fixed fraction of reads served by the main pool under `read.primary_read_ratio`
+pub(crate) struct PrimaryShare {
+    ratio: f64,
+    ///Accumulated fraction of reads owed to the main pool, in millionths
+    debt: AtomicU64,
+}
+impl PrimaryShare {
+    ///A share of `ratio`, or none if it's 0 or less
+    pub fn new(ratio: f64) -> Option<Self> {
+        (ratio > 0.0).then(|| PrimaryShare{ratio: ratio.min(1.0), debt: AtomicU64::new(0)})
+    }
+
+    ///Whether the next read goes to the main pool
+    pub fn take(&self) -> bool {
+        spread(&self.debt, self.ratio)
+    }
+}
+
+///Whether the next of a stream of reads, `fraction` of which are owed elsewhere, is. Reads are
+///spread evenly by accumulating `debt` rather than drawn at random.
+pub(crate) fn spread(debt: &AtomicU64, fraction: f64) -> bool {
+    let fraction = (fraction * 1_000_000.0) as u64;
+    if fraction == 0 {
+        return false;
+    }
+    let mut owed = false;
+    let _ = debt.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |debt| {
+        let debt = debt + fraction;
+        owed = debt >= 1_000_000;
+        Some(if owed {debt - 1_000_000} else {debt})
+    });
+    owed
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Reports the latency of a read query on the read pool, for brownout mode. Read connection
+    ///acquisition times are reported automatically.
+    pub fn observe_read_latency(&self, latency: Duration) {
+        if let Some(ref brownout) = self.brownout {
+            brownout.observe(latency);
+        }
+    }
+
+    ///The fraction of reads currently being shed to the main pool by brownout mode
+    pub fn brownout_shed(&self) -> f64 {
+        self.brownout.as_ref().map_or(0.0, |b| b.shed_fraction())
+    }
+
+    ///The fraction of reads deliberately served by the main pool, from
+    ///`read.primary_read_ratio`
+    pub fn primary_read_ratio(&self) -> f64 {
+        self.primary_share.as_ref().map_or(0.0, |s| s.ratio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brownout(latency_ms: u64) -> Brownout {
+        let brownout = Brownout::new(BrownoutConfig{target_latency_ms: 20, max_latency_ms: 120, max_shed: 0.8});
+        brownout.latency.store(latency_ms * 1000, Ordering::Relaxed);
+        brownout
+    }
+
+    #[test]
+    fn sheds_in_proportion_to_latency() {
+        assert_eq!(brownout(10).shed_fraction(), 0.0);
+        assert_eq!(brownout(20).shed_fraction(), 0.0);
+        assert!((brownout(70).shed_fraction() - 0.4).abs() < 1e-9);
+        assert!((brownout(120).shed_fraction() - 0.8).abs() < 1e-9);
+        assert!((brownout(1000).shed_fraction() - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn max_shed_is_limited() {
+        let brownout = Brownout::new(BrownoutConfig{target_latency_ms: 0, max_latency_ms: 1, max_shed: 1.0});
+        brownout.latency.store(5000, Ordering::Relaxed);
+        assert_eq!(brownout.shed_fraction(), SHED_LIMIT);
+    }
+
+    #[test]
+    fn average_moves_towards_samples() {
+        let brownout = brownout(0);
+        brownout.observe(Duration::from_millis(100));
+        assert_eq!(brownout.latency.load(Ordering::Relaxed), 10_000);
+        for _ in 0..100 {
+            brownout.observe(Duration::from_millis(100));
+        }
+        assert!(brownout.latency.load(Ordering::Relaxed) > 99_000);
+    }
+
+    #[test]
+    fn spreads_reads_evenly() {
+        let debt = AtomicU64::new(0);
+        let owed: Vec<bool> = (0..8).map(|_| spread(&debt, 0.25)).collect();
+        assert_eq!(owed, [false, false, false, true, false, false, false, true]);
+        assert!(!(0..100).any(|_| spread(&debt, 0.0)));
+    }
+
+    #[test]
+    fn primary_share() {
+        assert!(PrimaryShare::new(0.0).is_none());
+        let share = PrimaryShare::new(2.0).unwrap();
+        assert!((0..10).all(|_| share.take()));
+        let share = PrimaryShare::new(0.5).unwrap();
+        assert_eq!((0..10).filter(|_| share.take()).count(), 5);
+    }
+}
@@ -0,0 +1,81 @@
+//!A per-request budget shared by the retries and fallbacks this crate makes
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use rocket::{Orbit, Request, Rocket};
+use crate::RequestRouting;
+
+rocket::tokio::task_local! {
+    ///The budget of the request whose guard is acquiring a connection
+    static BUDGET: Arc<Spent>;
+}
+
+///Managed state limiting the retries this crate makes during one request, across every
+///database and guard, so layered retry mechanisms can't multiply into retry storms.
+///
+///Each acquisition retry from `acquire_retries`, each extra attempt of
+///`read.on_error = "retry_then_fallback"` and each fallback to the main pool after a read pool
+///failure takes a token. Once they're gone, failures are returned as they are. Routing to the
+///main pool for other reasons, such as quarantined replicas, is free.
+///```rust
+/// use rocket_read_db_pools::RetryBudget;
+///
+/// let rocket = rocket::build().manage(RetryBudget::new(3));
+///```
+///Tokens taken are reported by [`RequestRouting::retries`] whether or not a budget is managed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBudget {
+    tokens: u32,
+}
+impl RetryBudget {
+    ///A budget of `tokens` retries per request
+    pub fn new(tokens: u32) -> Self {
+        RetryBudget{tokens}
+    }
+
+    pub fn tokens(&self) -> u32 {
+        self.tokens
+    }
+}
+
+///Tokens taken during a request, and how many it may take
+#[derive(Debug)]
+pub(crate) struct Spent {
+    used: AtomicU32,
+    limit: AtomicU32,
+}
+impl Default for Spent {
+    fn default() -> Self {
+        Spent{used: AtomicU32::new(0), limit: AtomicU32::new(u32::MAX)}
+    }
+}
+impl Spent {
+    pub fn used(&self) -> u32 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn take(&self) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| (used < limit).then_some(used + 1)).is_ok()
+    }
+}
+
+///Runs a guard's acquisition `fut` against the budget of `req`
+pub(crate) async fn scoped<F: Future>(req: &Request<'_>, fut: F) -> F::Output {
+    scoped_in(req.rocket(), RequestRouting::of(req), fut).await
+}
+
+///Runs an acquisition `fut` against the budget of the request with the routing record `routing`
+pub(crate) async fn scoped_in<F: Future>(rocket: &Rocket<Orbit>, routing: &RequestRouting, fut: F) -> F::Output {
+    let spent = routing.spent();
+    if let Some(budget) = rocket.state::<RetryBudget>() {
+        spent.limit.store(budget.tokens, Ordering::Relaxed);
+    }
+    BUDGET.scope(spent, fut).await
+}
+
+///Takes a token for a retry or fallback, returning whether it may go ahead. Acquisitions outside
+///a request guard have no budget.
+pub(crate) fn take() -> bool {
+    BUDGET.try_with(|spent| spent.take()).unwrap_or(true)
+}
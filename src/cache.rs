@@ -0,0 +1,301 @@
+//!Caching of read query results, with the `cache` feature
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rocket::{Ignite, Rocket, Sentinel};
+use rocket::futures::future::BoxFuture;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{Database, Pool};
+use crate::{failure, FallbackReason, ReadConnection, ReadPool, ReadPoolError, RequestRouting};
+
+///Configuration of the read result cache: the `databases.<name>.read.cache` table
+///```toml
+///[default.databases.main.read.cache]
+///ttl_ms = 5000
+///max_entries = 10000
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    ///`ttl_ms`: time a result is served from the cache after it's fetched. Defaults to 1000.
+    pub ttl_ms: u64,
+    ///`max_entries`: most results kept at once, evicting those expiring soonest. Defaults to
+    ///1000.
+    pub max_entries: usize,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig{ttl_ms: 1000, max_entries: 1000}
+    }
+}
+
+///Hits and misses of the read result cache, from [`ReadPool::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheStats {
+    ///Results served from the cache
+    pub hits: u64,
+    ///Results fetched because none was cached, or it had expired
+    pub misses: u64,
+    ///Results currently cached, including expired ones not yet evicted
+    pub entries: usize,
+}
+
+///A cached result of one statement and parameters, by result type
+type Key = (Box<str>, u64, TypeId);
+
+struct Entry {
+    expires: Instant,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+///The results cached for a [`ReadPool`], shared by its clones
+pub(crate) struct ResultCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<Key, Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+impl ResultCache {
+    pub fn new(config: CacheConfig) -> Self {
+        ResultCache{
+            ttl: Duration::from_millis(config.ttl_ms),
+            max_entries: config.max_entries,
+            entries: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get<T: Clone + 'static>(&self, key: &Key) -> Option<T> {
+        let value = self.entries().get(key)
+            .filter(|e| e.expires > Instant::now())
+            .and_then(|e| e.value.downcast_ref::<T>().cloned());
+        let counter = if value.is_some() {&self.hits} else {&self.misses};
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    fn insert<T: Send + Sync + 'static>(&self, key: Key, value: T) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, e| e.expires > now);
+            if entries.len() >= self.max_entries {
+                let soonest = entries.iter().min_by_key(|(_, e)| e.expires).map(|(k, _)| k.clone());
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        entries.insert(key, Entry{expires: now + self.ttl, value: Arc::new(value)});
+    }
+
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats{
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries().len(),
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Drops every cached read result, e.g. after a write that must be visible at once
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+}
+
+///A request guard which retrieves a read connection as [`ReadConnection`] does, and can serve
+///the results of its queries from a cache shared by the database's requests with
+///[`CachedRead::cached`], so hot read endpoints don't run the same query on the replicas for
+///every request. Results are kept for `read.cache.ttl_ms`, see [`CacheConfig`].
+///
+///Requests whose reads are kept on the main pool by [`RoutingFlags`](crate::RoutingFlags),
+///[`ReadYourWrites`](crate::ReadYourWrites), `read.pin_after_write` or a
+///[`RoutingPolicy`](crate::RoutingPolicy) neither use nor fill the cache, so they see their own
+///writes.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool};
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::response::Debug;
+/// use rocket_read_db_pools::CachedRead;
+///
+/// #[rocket::get("/plans?<region>")]
+/// async fn plans(mut db: CachedRead<Db>, region: &str) -> Result<String, Debug<MockError>> {
+///     let plans: Vec<String> = db.cached("SELECT name FROM plans WHERE region = $1", region, |conn, sql| Box::pin(
+///         conn.query(sql).bind(region).fetch_all()
+///     )).await?;
+///     Ok(plans.join("\n"))
+/// }
+///```
+pub struct CachedRead<D: Database> {
+    conn: ReadConnection<D>,
+    cache: Arc<ResultCache>,
+    bypass: bool,
+}
+impl<D: Database> CachedRead<D> {
+    ///Gets the result of `sql` with the parameters `params` from the cache, or runs `query`
+    ///with the connection and `sql` and caches its result if it succeeds. `params` must cover
+    ///every value `query` binds.
+    ///
+    ///Results are cached by statement, parameters and type, so queries of one statement
+    ///decoded into different types don't share entries.
+    pub async fn cached<'a, T, E, F>(&'a mut self, sql: &'a str, params: impl Hash, query: F) -> Result<T, E>
+        where T: Clone + Send + Sync + 'static,
+              F: FnOnce(&'a mut <D::Pool as Pool>::Connection, &'a str) -> BoxFuture<'a, Result<T, E>>
+    {
+        if self.bypass {
+            return query(&mut self.conn, sql).await;
+        }
+        let mut hasher = DefaultHasher::new();
+        params.hash(&mut hasher);
+        let key = (Box::from(sql), hasher.finish(), TypeId::of::<T>());
+        let cache = self.cache.clone();
+        if let Some(value) = cache.get::<T>(&key) {
+            return Ok(value);
+        }
+        let value = query(&mut self.conn, sql).await?;
+        cache.insert(key, value.clone());
+        Ok(value)
+    }
+
+    ///Whether [`CachedRead::cached`] bypasses the cache for this request
+    pub fn bypassed(&self) -> bool {
+        self.bypass
+    }
+
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        self.conn.into_inner()
+    }
+}
+#[rocket::async_trait]
+impl<'r, D, P, R> FromRequest<'r> for CachedRead<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>,
+        R::Connection: Into<P::Connection> + Send, P::Connection: Send + 'static
+{
+    type Error = ReadPoolError<P::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let conn = match ReadConnection::<D>::from_request(req).await {
+            Outcome::Success(conn) => conn,
+            Outcome::Error(failure) => return Outcome::Error(failure),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        let Some(db) = D::fetch(req.rocket()) else {
+            return failure::outcome(req, D::NAME, ReadPoolError::DatabaseNotAttached);
+        };
+        let bypass = RequestRouting::of(req).last().is_some_and(|a| matches!(
+            a.fallback, Some(FallbackReason::RoutingFlags | FallbackReason::ReadYourWrites | FallbackReason::AfterWrite | FallbackReason::Policy)
+        ));
+        Outcome::Success(CachedRead{conn, cache: db.cache.clone(), bypass})
+    }
+}
+impl<D: Database> Sentinel for CachedRead<D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for CachedRead<D> {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl<D: Database> DerefMut for CachedRead<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::asynchronous::Client;
+    use crate::testing::{pair_rocket_figment, MockError, MockPool};
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    const COUNT: &str = "SELECT count(*) FROM plans";
+
+    fn key(sql: &str) -> Key {
+        (Box::from(sql), 0, TypeId::of::<u32>())
+    }
+
+    async fn guard(client: &Client, bypass: bool) -> CachedRead<Db> {
+        let conn = ReadConnection::<Db>::from_rocket(client.rocket()).await.unwrap();
+        CachedRead{conn, cache: Db::fetch(client.rocket()).unwrap().cache.clone(), bypass}
+    }
+
+    async fn count<T: std::str::FromStr + Clone + Send + Sync + 'static>(db: &mut CachedRead<Db>) -> T {
+        db.cached(COUNT, (), |conn, sql| Box::pin(conn.query(sql).fetch_one::<T>())).await.unwrap_or_else(|e: MockError| panic!("{}", e))
+    }
+
+    #[rocket::async_test]
+    async fn results_are_served_from_the_cache_until_cleared() {
+        let rocket = rocket::custom(pair_rocket_figment("db", "main", "read")).attach(Db::init());
+        let client = Client::tracked(rocket).await.unwrap();
+        let pool = Db::fetch(client.rocket()).unwrap();
+        let replica = pool.replica_set().pools[0].clone();
+        replica.respond(COUNT, 3);
+
+        let mut db = guard(&client, false).await;
+        assert_eq!(count::<u32>(&mut db).await, 3);
+        replica.respond(COUNT, 4);
+        assert_eq!(count::<u32>(&mut guard(&client, false).await).await, 3);
+        assert_eq!(count::<String>(&mut db).await, "4");
+        assert_eq!(replica.statements().len(), 2);
+        assert_eq!(pool.stats().cache, CacheStats{hits: 1, misses: 2, entries: 2});
+
+        let mut bypassing = guard(&client, true).await;
+        assert!(bypassing.bypassed());
+        assert_eq!(count::<u32>(&mut bypassing).await, 4);
+        pool.clear_cache();
+        assert_eq!(count::<u32>(&mut db).await, 4);
+        assert_eq!(replica.statements().len(), 4);
+    }
+
+    #[test]
+    fn results_expire_and_the_soonest_expiring_are_evicted() {
+        let expired = ResultCache::new(CacheConfig{ttl_ms: 0, max_entries: 10});
+        expired.insert(key("a"), 1u32);
+        assert_eq!(expired.get::<u32>(&key("a")), None);
+
+        let cache = ResultCache::new(CacheConfig{ttl_ms: 60000, max_entries: 2});
+        for sql in ["a", "b", "c"] {
+            cache.insert(key(sql), 1u32);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.get::<u32>(&key("a")), None);
+        assert_eq!(cache.get::<u32>(&key("c")), Some(1));
+        assert_eq!(cache.stats(), CacheStats{hits: 1, misses: 1, entries: 2});
+
+        let disabled = ResultCache::new(CacheConfig{ttl_ms: 60000, max_entries: 0});
+        disabled.insert(key("a"), 1u32);
+        assert_eq!(disabled.stats().entries, 0);
+    }
+}
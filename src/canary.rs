@@ -0,0 +1,195 @@
+//!Canary queries checking that read replicas return sensible results, degrading broken ones
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::future::join_all;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{interval, timeout, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{HealthProbe, HealthRegistry, PoolRole, ReadPool};
+
+///The result of a canary query, from [`HealthProbe::canary`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CanaryResult {
+    ///Number of rows the query returned, checked against `min_rows`
+    pub rows: u64,
+    ///Timestamp the query returned, checked against `max_age_ms`
+    pub timestamp: Option<SystemTime>,
+}
+
+///A canary query and the invariants its result must satisfy, from `read.canary.queries`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Canary {
+    ///`name`: name of the canary in logs. Defaults to its query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    ///`sql`: the query
+    pub sql: String,
+    ///`min_rows`: fewest rows the query may return
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_rows: Option<u64>,
+    ///`max_age_ms`: greatest age of the timestamp the query returns. A query returning none
+    ///fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_ms: Option<u64>,
+}
+impl Canary {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.sql)
+    }
+
+    ///Why `result` breaks the invariants, if it does
+    fn violation(&self, result: &CanaryResult) -> Option<String> {
+        if let Some(min) = self.min_rows.filter(|&min| result.rows < min) {
+            return Some(format!("returned {} rows, expected at least {}", result.rows, min));
+        }
+        let max_age = Duration::from_millis(self.max_age_ms?);
+        match result.timestamp {
+            None => Some("returned no timestamp".to_string()),
+            Some(timestamp) => {
+                let age = SystemTime::now().duration_since(timestamp).unwrap_or_default();
+                (age > max_age).then(|| format!("returned a timestamp {:?} old, expected at most {:?}", age, max_age))
+            }
+        }
+    }
+}
+
+///Configuration of read replica canaries: the `databases.<name>.read.canary` table.
+///
+///Each replica runs every query in `queries` periodically. If any fails or breaks its
+///invariants, the replica is degraded and skipped by reads as if quarantined, even though it
+///still accepts connections, until a later run passes. If every replica is unavailable, reads
+///use the main pool.
+///```toml
+///[default.databases.main.read.canary]
+///interval_ms = 30000
+///queries = [
+///    {name = "plans", sql = "SELECT id FROM plans", min_rows = 1},
+///    {name = "orders", sql = "SELECT max(created_at) FROM orders", max_age_ms = 60000},
+///]
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CanaryConfig {
+    ///`interval_ms`: time between runs on each replica. Defaults to 30000.
+    pub interval_ms: u64,
+    ///`timeout_ms`: time a run, including acquiring its connection, may take before it counts
+    ///as failed. Defaults to 5000.
+    pub timeout_ms: u64,
+    ///`queries`: the canaries
+    pub queries: Vec<Canary>,
+}
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        CanaryConfig{interval_ms: 30000, timeout_ms: 5000, queries: Vec::new()}
+    }
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, R::Connection: HealthProbe {
+    ///Runs the `read.canary` queries on each read replica once, concurrently, degrading
+    ///replicas whose results break their invariants and restoring those which pass again.
+    ///[`ReadCanary`] calls this periodically.
+    pub async fn run_canaries(&self) {
+        let Some(config) = self.canary.as_ref() else {return};
+        let label = self.label(PoolRole::Read);
+        let set = self.replica_set();
+        let patience = Duration::from_millis(config.timeout_ms);
+        let runs = set.pools.iter().map(|pool| async move {
+            let run = async {
+                let mut conn = pool.get().await.map_err(|e| format!("failed to connect: {}", e))?;
+                for canary in &config.queries {
+                    let result = conn.canary(&canary.sql).await.map_err(|e| format!("canary `{}` failed: {}", canary.name(), e))?;
+                    if let Some(violation) = canary.violation(&result) {
+                        return Err(format!("canary `{}` {}", canary.name(), violation));
+                    }
+                }
+                Ok(())
+            };
+            match timeout(patience, run).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("canaries timed out after {:?}", patience)),
+            }
+        });
+        for (replica, result) in set.replicas.iter().zip(join_all(runs).await) {
+            match (replica.health.degraded.swap(result.is_err(), Ordering::Relaxed), result) {
+                (false, Err(e)) => db_log!(self.log, General, Warn, "`{}` replica `{}` degraded: {}", label, replica.name, e),
+                (true, Ok(())) => {
+                    db_log!(self.log, General, Info, "`{}` replica `{}` passed its canaries again", label, replica.name);
+                    replica.health.rejoin();
+                }
+                (true, Err(e)) => db_log!(self.log, General, Debug, "`{}` replica `{}` still failing canaries: {}", label, replica.name, e),
+                (false, Ok(())) => {}
+            }
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Whether each read replica is currently degraded by failing canaries
+    pub fn degraded(&self) -> Vec<bool> {
+        self.replica_set().replicas.iter().map(|r| r.health.degraded()).collect()
+    }
+}
+
+///A fairing which runs [`ReadPool::run_canaries`] for the database `D` every
+///`read.canary.interval_ms` from launch until shutdown, if `read.canary` is configured.
+///Changes are published to the managed [`HealthRegistry`], if any.
+///
+///Attach it after `D::init()`. Like [`ReadHealthCheck`](crate::ReadHealthCheck), both pool types
+///must be `Clone`.
+pub struct ReadCanary<D>(PhantomData<fn() -> D>);
+impl<D> ReadCanary<D> {
+    pub fn new() -> Self {
+        ReadCanary(PhantomData)
+    }
+}
+impl<D> Default for ReadCanary<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadCanary<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone, R::Connection: HealthProbe
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Canaries",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadCanary` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let Some(every) = pool.canary.as_ref().map(|c| Duration::from_millis(c.interval_ms)) else {return};
+        let registry = rocket.state::<HealthRegistry>().cloned();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(every.max(Duration::from_millis(1)));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                pool.run_canaries().await;
+                if let Some(ref registry) = registry {
+                    registry.update(D::NAME, &pool);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,150 @@
+//!Resetting connection session state before connections return to their pool
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use rocket::Request;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::{Deserialize, Serialize};
+use crate::{BoxError, PoolRole, ReadDbConfig, RequestRouting};
+
+///How a connection's session state is reset before it returns to its pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ResetStrategy {
+    ///Discard all session state, including prepared statements and temporary tables
+    ///(`DISCARD ALL` on Postgres)
+    DiscardAll,
+    ///Reset session variables only (`RESET ALL` on Postgres)
+    Reset,
+    ///Leave the session as it is
+    None,
+}
+
+///Resets the session state of a connection, for [`WithCleanup`].
+///
+///Implement it for your connection type, e.g. with `DISCARD ALL` / `RESET ALL` on Postgres.
+#[rocket::async_trait]
+pub trait SessionReset: Send {
+    ///Resets the session with `strategy`, which is never [`ResetStrategy::None`]
+    async fn reset_session(&mut self, strategy: ResetStrategy) -> Result<(), BoxError>;
+}
+
+///Managed state giving the [`ResetStrategy`] used by [`WithCleanup`] per database and pool.
+///
+///Strategies can be configured per pool with `reset_on_return`, e.g. `"reset"` for direct
+///connections and `"discard_all"` behind a transaction pooler:
+///```toml
+///[default.databases.main]
+///reset_on_return = "discard_all"
+///[default.databases.main.read]
+///reset_on_return = "reset"
+///```
+///```rust
+/// # fn _inner(figment: &rocket::figment::Figment) -> Result<(), Box<rocket::figment::Error>> {
+/// use rocket_read_db_pools::{PoolRole, ReadDbConfig, ResetStrategy, SessionCleanup};
+///
+/// let config = ReadDbConfig::extract(&figment.focus("databases.main"))?;
+/// let cleanup = SessionCleanup::new(ResetStrategy::Reset)
+///     .strategy("reporting", PoolRole::Read, ResetStrategy::None)
+///     .configured("main", &config);
+/// let rocket = rocket::build().manage(cleanup);
+/// # Ok(()) }
+///```
+pub struct SessionCleanup {
+    default: ResetStrategy,
+    strategies: HashMap<(String, PoolRole), ResetStrategy>,
+}
+impl SessionCleanup {
+    ///Creates a cleanup which uses `default` for every pool
+    pub fn new(default: ResetStrategy) -> Self {
+        SessionCleanup{default, strategies: HashMap::new()}
+    }
+
+    ///Uses `strategy` for connections from the `pool` of database `database`
+    pub fn strategy(mut self, database: &str, pool: PoolRole, strategy: ResetStrategy) -> Self {
+        self.strategies.insert((database.to_string(), pool), strategy);
+        self
+    }
+
+    ///Adds the strategies configured for the database `database`
+    pub fn configured(mut self, database: &str, config: &ReadDbConfig) -> Self {
+        let read = config.read.as_ref();
+        for (pool, strategy) in [
+            (PoolRole::Main, config.reset_on_return),
+            (PoolRole::Read, read.and_then(|r| r.reset_on_return)),
+            (PoolRole::Delayed, read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.reset_on_return)),
+        ] {
+            if let Some(strategy) = strategy {
+                self = self.strategy(database, pool, strategy);
+            }
+        }
+        self
+    }
+
+    fn strategy_for(&self, database: &str, pool: PoolRole) -> ResetStrategy {
+        self.strategies.get(&(database.to_string(), pool)).copied().unwrap_or(self.default)
+    }
+}
+
+///A request guard wrapping a connection guard (`WithCleanup<ReadConnection<Db>>`,
+///`WithCleanup<WithContext<RwConnection<Db>>>`, ...) which resets the connection's session with
+///the strategy the managed [`SessionCleanup`] gives for the serving pool when the guard is
+///dropped, so state set during one request never leaks to the next.
+///
+///The reset needs an async task, so happens in the background on the current Tokio runtime; the
+///connection returns to its pool once it completes. If no `SessionCleanup` is managed nothing is
+///reset.
+pub struct WithCleanup<G> where G: DerefMut + Send + 'static, G::Target: SessionReset {
+    guard: Option<G>,
+    strategy: ResetStrategy,
+}
+impl<G> WithCleanup<G> where G: DerefMut + Send + 'static, G::Target: SessionReset {
+    ///The strategy the session will be reset with
+    pub fn strategy(&self) -> ResetStrategy {
+        self.strategy
+    }
+}
+#[rocket::async_trait]
+impl<'r, G> FromRequest<'r> for WithCleanup<G>
+    where G: FromRequest<'r> + DerefMut + Send + 'static, G::Target: SessionReset
+{
+    type Error = G::Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let guard = match G::from_request(req).await {
+            Outcome::Success(guard) => guard,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        let strategy = req.rocket().state::<SessionCleanup>().zip(RequestRouting::of(req).last())
+            .map_or(ResetStrategy::None, |(cleanup, acquisition)| cleanup.strategy_for(acquisition.database, acquisition.role));
+        Outcome::Success(WithCleanup{guard: Some(guard), strategy})
+    }
+}
+impl<G> Drop for WithCleanup<G> where G: DerefMut + Send + 'static, G::Target: SessionReset {
+    fn drop(&mut self) {
+        let strategy = self.strategy;
+        let Some(mut guard) = self.guard.take().filter(|_| strategy != ResetStrategy::None) else {return};
+        match rocket::tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = guard.reset_session(strategy).await {
+                        rocket::error!("failed to reset connection session: {}", e);
+                    }
+                });
+            }
+            Err(_) => rocket::error!("no async runtime to reset connection session on"),
+        }
+    }
+}
+impl<G> Deref for WithCleanup<G> where G: DerefMut + Send + 'static, G::Target: SessionReset {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().expect("guard present until dropped")
+    }
+}
+impl<G> DerefMut for WithCleanup<G> where G: DerefMut + Send + 'static, G::Target: SessionReset {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().expect("guard present until dropped")
+    }
+}
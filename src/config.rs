@@ -0,0 +1,819 @@
+//!Typed configuration for a `ReadPool` database
+use rocket::figment::{self, Figment};
+use rocket::figment::providers::Serialized;
+use rocket::figment::value::{Dict, Value};
+use rocket::serde::{Deserialize, Deserializer, Serialize};
+use rocket::serde::de::Error as _;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::{BalanceStrategy, BrownoutConfig, CanaryConfig, CircuitBreakerConfig, CoordinationConfig, DiscoveryConfig, MaintenanceWindow, HealthCheckConfig, IpPreference, PoolRole, LogConfig, ReadOnlyModeConfig, ResetStrategy, Resolve, SecretsConfig, TlsConfig, WarmupPolicy};
+
+///Configuration of a `ReadPool` database: the `databases.<name>` table.
+///
+///Options this crate doesn't recognise are passed through to the underlying pools, which
+///read their own keys (e.g. `url` and `max_connections` for `rocket_db_pools` pools).
+///
+///A config can be built programmatically and turned into a figment for `Pool::init` with
+///[`ReadDbConfig::figment`], or for Rocket with [`ReadDbConfig::figment_for`]. The key names are
+///also available as constants in [`keys`](crate::keys).
+///
+///The `read` table follows Rocket's profiles like any other configuration, with the tables of
+///the default and selected profiles merged key by key, so development can run against one
+///local database while release builds read from a replica. Either leave the `read` table out
+///of the default profile, or turn off its replica (or `read_urls`) in another profile with
+///`read = false`:
+///```toml
+///[default.databases.main]
+///url = "postgresql://user@primary.example/dbname"
+///[default.databases.main.read]
+///url = "postgresql://user@replica.example/dbname"
+///
+///[debug.databases.main]
+///url = "postgresql://user@localhost/dbname"
+///read = false
+///```
+///
+///When replicas only differ from the main pool by their url, `read_urls` stands in for the
+///`read` table:
+///```toml
+///[default.databases.main]
+///url = "postgresql://user@primary.example/dbname"
+///max_connections = 10
+///read_urls = ["postgresql://user@replica-1.example/dbname", "postgresql://user@replica-2.example/dbname"]
+///```
+///
+///A warm standby primary can be configured under `failover`. Its pool is kept open (with
+///`min_connections` defaulting to 1) and [`ReadPool::fail_over`](crate::ReadPool::fail_over)
+///switches the main pool to it:
+///```toml
+///[default.databases.main.failover]
+///url = "postgresql://user@standby.example/dbname"
+///```
+///
+///The options for acquiring connections apply to every pool of the database. Failed
+///acquisitions are retried `acquire_retries` times, waiting `acquire_backoff_ms` and then twice
+///as long before each next retry, so a brief failover doesn't fail requests, up to the cap of
+///a managed [`RetryBudget`](crate::RetryBudget). `lease_warn_ms` and `log_slow_hold_ms` tell
+///handlers hogging connections, e.g. across slow external calls, from an undersized pool,
+///which `log_slow_acquire_ms` shows:
+///```toml
+///[default.databases.main]
+///acquire_retries = 3
+///acquire_backoff_ms = 100
+///lease_warn_ms = 2000
+///log_slow_acquire_ms = 250
+///```
+///Guards' connections converted with `into_stream_guard` into a
+///[`StreamConnection`](crate::StreamConnection) are exempt from both warnings.
+///
+///Each pool can be given a `label`, used to identify it in logs and reports instead of its
+///role, and a distinct `application_name`, telling their connections apart in Postgres'
+///`pg_stat_activity` and slow query logs:
+///```toml
+///[default.databases.main]
+///application_name = "api"
+///read = {url = "postgresql://user@replica.example/dbname", application_name = "api-read"}
+///```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ReadDbConfig {
+    ///Options for the main pool, passed through to its `Pool::init`
+    #[serde(flatten)]
+    pub main: Dict,
+    ///`label`: name of the main pool in logs and reports. Defaults to `"main"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    ///`roles`: database role per user class for the main pool, see `RoleSwitch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<BTreeMap<String, String>>,
+    ///`statement_timeout_ms`: the main pool's default statement timeout, if it has one.
+    ///`ReadConnection::with_statement_timeout` never loosens beyond it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_timeout_ms: Option<u64>,
+    ///`resolve`: when the host in the main pool's `url` is resolved. Defaults to `"connect"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<Resolve>,
+    ///`ip_preference`: address family the main pool connects over: `"ipv4"`, `"ipv6"` or
+    ///`"happy_eyeballs"` (the default, leaving it to the driver)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_preference: Option<IpPreference>,
+    ///`tls`: certificates and verification of the main pool's connections, see `TlsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    ///`secrets`: where the main pool's url or password is read from, see `SecretsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretsConfig>,
+    ///`application_name`: the name the main pool's connections report to the server, e.g. in
+    ///Postgres' `pg_stat_activity`. Only applied to Postgres urls.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    ///`reset_on_return`: how `WithCleanup` resets the main pool's sessions: `"discard_all"`,
+    ///`"reset"` or `"none"`. Uses the `SessionCleanup` default if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_on_return: Option<ResetStrategy>,
+    ///`drain_timeout_ms`: time closing the main pool at shutdown waits for its connections in
+    ///use to be returned before abandoning them, see `ReadDrain`. Defaults to waiting for all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain_timeout_ms: Option<u64>,
+    ///`test_before_acquire`: whether connections from the main pool are checked with the
+    ///`ReadPool::test_with` tests before being handed out. Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_before_acquire: Option<bool>,
+    ///`acquire_retries`: times a failed acquisition from any of the pools is retried. Defaults to
+    ///none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acquire_retries: Option<u32>,
+    ///`acquire_backoff_ms`: wait before the first retry, doubling for each further one.
+    ///Defaults to 50.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acquire_backoff_ms: Option<u64>,
+    ///`lease_warn_ms`: time a connection from this crate's request guards may be held before a
+    ///warning naming its route is logged and it's counted by `ReadPool::long_held`. Defaults
+    ///to no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_warn_ms: Option<u64>,
+    ///`warmup`: whether `ReadWarmup` opens `min_connections` on each pool at ignite, logging
+    ///failures (`"log"`) or failing launch (`"fail"`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<WarmupPolicy>,
+    ///`log`: verbosity of this crate's output about the database, see `LogConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<LogConfig>,
+    ///`log_slow_acquire_ms`: shorthand for `log.slow_acquire_ms`, which takes precedence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_slow_acquire_ms: Option<u64>,
+    ///`log_slow_hold_ms`: shorthand for `log.slow_hold_ms`, which takes precedence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_slow_hold_ms: Option<u64>,
+    ///`primaries`: the writable primaries of a `MultiPrimaryPool` main pool, each given as pool
+    ///options overriding those of this table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primaries: Option<Vec<Dict>>,
+    ///`primary_balancer`: how a `MultiPrimaryPool` chooses between `primaries`, as
+    ///`read.balancer` does between replicas. Defaults to `"round_robin"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_balancer: Option<BalanceStrategy>,
+    ///`primary_health_check`: tuning of `MultiPrimaryPool::check_health`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_health_check: Option<HealthCheckConfig>,
+    ///`shards`: the shards of a `ShardedReadPool` by name, each given as options overriding
+    ///those of this table, other than its `url`, `read`, `read_urls` and `failover`, which each
+    ///shard sets itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shards: Option<BTreeMap<String, Dict>>,
+    ///`tenants`: the shards of a `ShardedReadPool` as per-tenant options, by tenant identifier,
+    ///if there are no `shards`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenants: Option<BTreeMap<String, Dict>>,
+    ///`default_shard`: the shard a `ShardedReadPool` uses when none is resolved. Without it,
+    ///requests resolving none fail.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_shard: Option<String>,
+    ///`prefer`: the pool a plain `Pool::get`, and so `rocket_db_pools::Connection`, uses:
+    ///`"main"` (the default) or `"read"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefer: Option<Prefer>,
+    ///`read_only_mode`: serving reads only after repeated main pool failures, see
+    ///`ReadOnlyModeConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only_mode: Option<ReadOnlyModeConfig>,
+    ///Options for a warm standby primary pool, from the `failover` table, passed through to its
+    ///`Pool::init`. See `ReadPool::fail_over`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover: Option<Dict>,
+    ///`read_urls`: shorthand for read replicas differing from the main pool only in their
+    ///`url`, one per url, each with the main pool's other options. Ignored if there's a `read`
+    ///table.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_urls: Option<Vec<String>>,
+    ///The read replica, from the `read` table. Reads use the main pool if absent, or if set to
+    ///`false`, which turns off a replica configured by another profile.
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "replica_or_off")]
+    pub read: Option<ReplicaConfig>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+///Deserializes `read`, which is either a table or `false`
+fn replica_or_off<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ReplicaConfig>, D::Error> {
+    match <Value as Deserialize>::deserialize(deserializer)? {
+        Value::Bool(_, false) => Ok(None),
+        Value::Bool(..) => Err(D::Error::custom("expected a `read` table or `false`")),
+        value => value.deserialize().map(Some).map_err(D::Error::custom),
+    }
+}
+
+///Configuration of a read replica: the `databases.<name>.read` table.
+///
+///Pool options the `read` table doesn't set, such as `max_connections` and `connect_timeout`,
+///default to the main pool's, except its `url`. Set `inherit_main = false` to have them
+///default as for any pool instead.
+///
+///Several replicas can be listed under `replicas`, each entry overriding the `read` table's
+///pool options, see [`Replicas`]. Read acquisitions then take turns between them, or are spread
+///by `balancer`, see [`BalanceStrategy`](crate::BalanceStrategy):
+///```toml
+///[default.databases.main.read]
+///max_connections = 10
+///balancer = "weighted"
+///replicas = [
+///    {url = "postgresql://user@replica-1.example/dbname"},
+///    {url = "postgresql://user@replica-2.example/dbname"},
+///    {url = "postgresql://user@replica-3.example/dbname", max_connections = 20, weight = 2},
+///]
+///```
+///Other strategies, such as `LeastConnections` with the `strategy-least-connections` feature,
+///can be set with [`ReadPool::set_balancer`](crate::ReadPool::set_balancer). Replicas can be
+///added and removed while running, e.g. as an orchestrator scales them, with
+///[`ReadPool::add_replica`](crate::ReadPool::add_replica) and
+///[`ReadPool::remove_replica`](crate::ReadPool::remove_replica).
+///
+///Giving replicas a `zone` and setting `prefer_zone` (or `prefer_zone_env` to the name of a
+///variable holding it) keeps reads on the replicas in that zone, only spilling to the others
+///while none of them is available, or once they're saturated with
+///[`ReadPool::spill_at`](crate::ReadPool::spill_at):
+///```toml
+///[default.databases.main.read]
+///prefer_zone_env = "AVAILABILITY_ZONE"
+///replicas = [
+///    {url = "postgresql://user@replica-a.example/dbname", zone = "eu-west-1a"},
+///    {url = "postgresql://user@replica-b.example/dbname", zone = "eu-west-1b"},
+///]
+///```
+///
+///Several options keep reads off the replicas, or ease them on:
+///- `max_wait_ms` smooths over momentary exhaustion of the replicas' pools, without falling
+///  back when they fail, as `on_error` does
+///- `slow_start_ms` ramps up a replica's share of reads as its cold caches warm, the reads it
+///  passes over going to the next replica, or the main pool if none is left
+///- `primary_read_ratio` is lowered as confidence in the replicas grows, e.g. from `0.9`
+///- `min_healthy` keeps a single surviving replica from being crushed, see [`BelowMinHealthy`]
+///```toml
+///[default.databases.main.read]
+///max_wait_ms = 50
+///slow_start_ms = 30000
+///primary_read_ratio = 0.5
+///```
+///
+///If the replica only carries some of the tables (e.g. a logical replication subscriber), list
+///them in `replicated_tables` and use
+///[`ReadPool::check_read_query`](crate::ReadPool::check_read_query) to flag read-path queries
+///which touch anything else:
+///```toml
+///[default.databases.main.read]
+///url = "postgresql://user@subscriber.example/dbname"
+///replicated_tables = ["users", "public.orders"]
+///check_replicated_tables = true
+///```
+///
+///Before reads are moved to the replicas, `shadow_ratio` and the experimental `verify` mode
+///compare their results with the main pool's, through [`ReadPool::shadow`](crate::ReadPool::shadow)
+///and [`ReadPool::verify`](crate::ReadPool::verify), and `record` records the read queries
+///passed to [`ReadPool::record_read`](crate::ReadPool::record_read) for later replay, see
+///[`record`](crate::record).
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ReplicaConfig {
+    ///Options for the read pool, passed through to its `Pool::init`
+    #[serde(flatten)]
+    pub pool: Dict,
+    ///`label`: name of the read pool in logs and reports. Defaults to `"read"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    ///`roles`: database role per user class for the read pool, see `RoleSwitch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<BTreeMap<String, String>>,
+    ///`statement_timeout_ms`: the read pool's default statement timeout, if it has one.
+    ///`ReadConnection::with_statement_timeout` never loosens beyond it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_timeout_ms: Option<u64>,
+    ///`search_path`: schema search path set on the read pool's connections by `WithContext`,
+    ///see `SessionContext::configured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_path: Option<String>,
+    ///`enforce_read_only`: whether `WithContext` makes the read pool's sessions read-only, so
+    ///writes through `ReadConnection` fail, see `SessionContext::configured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enforce_read_only: Option<bool>,
+    ///`resolve`: when the host in the read pool's `url` is resolved. Defaults to `"connect"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<Resolve>,
+    ///`ip_preference`: address family the read pool connects over: `"ipv4"`, `"ipv6"` or
+    ///`"happy_eyeballs"` (the default, leaving it to the driver)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_preference: Option<IpPreference>,
+    ///`tls`: certificates and verification of the read pool's connections, see `TlsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    ///`secrets`: where the read pool's url or password is read from, see `SecretsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretsConfig>,
+    ///`application_name`: the name the read pool's connections report to the server, inherited by the replicas, e.g. in
+    ///Postgres' `pg_stat_activity`. Only applied to Postgres urls.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    ///`reset_on_return`: how `WithCleanup` resets the read pool's sessions: `"discard_all"`,
+    ///`"reset"` or `"none"`. Uses the `SessionCleanup` default if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_on_return: Option<ResetStrategy>,
+    ///`drain_timeout_ms`: time closing the read pool at shutdown waits for its connections in
+    ///use to be returned before abandoning them, see `ReadDrain`. Defaults to waiting for all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain_timeout_ms: Option<u64>,
+    ///`test_before_acquire`: whether connections from the read pool are checked with the
+    ///`ReadPool::test_with` tests before being handed out. Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_before_acquire: Option<bool>,
+    ///`inherit_main`: whether pool options absent from this table, such as `max_connections`
+    ///and `connect_timeout`, default to the main pool's, `url` excepted. Defaults to true; with
+    ///false they default as for any pool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inherit_main: Option<bool>,
+    ///`replicas`: several read replicas taking turns, each given as pool options overriding
+    ///those of this table, either as a list or as a table of named replicas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<Replicas>,
+    ///`balancer`: how reads are spread between `replicas`: `"round_robin"`, `"random"`,
+    ///`"weighted"` or `"preferred"`. Defaults to `"round_robin"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balancer: Option<BalanceStrategy>,
+    ///`weight`: relative share of reads for the replica with the `"random"` and `"weighted"`
+    ///balancers, usually given per `replicas` entry. Defaults to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    ///`zone`: availability zone or region of the replica, usually given per `replicas` entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    ///`prefer_zone`: zone whose replicas serve reads while any of them is available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefer_zone: Option<String>,
+    ///`prefer_zone_env`: environment variable holding `prefer_zone`, e.g. one set by the
+    ///orchestrator, used if `prefer_zone` isn't set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefer_zone_env: Option<String>,
+    ///`replicated_tables`: tables present on the replica, if it only carries some of them.
+    ///All tables are assumed replicated if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replicated_tables: Option<Vec<String>>,
+    ///`check_replicated_tables`: whether `ReadPool::check_read_query` checks queries against
+    ///`replicated_tables`. Defaults to on in debug builds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_replicated_tables: Option<bool>,
+    ///`fallback`: whether reads are retried on the main pool when the read pool fails to
+    ///provide a connection, e.g. because the replica is down or `connect_timeout` elapsed. The
+    ///same as `on_error = "fallback"`; ignored if `on_error` is set.
+    #[serde(skip_serializing_if = "is_false")]
+    pub fallback: bool,
+    ///`on_error`: what reads do when the read pool fails to provide a connection: `"error"`,
+    ///`"fallback"` or `"retry_then_fallback"`. Defaults to `"error"`, or `"fallback"` with
+    ///`fallback = true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<OnError>,
+    ///`max_wait_ms`: time a read waits for a replica connection before trying the main pool
+    ///instead, e.g. while the replicas' pools are momentarily exhausted. Defaults to waiting
+    ///as long as the replica's `connect_timeout`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_wait_ms: Option<u64>,
+    ///`verify`: enables the experimental `ReadPool::verify` mode
+    #[serde(skip_serializing_if = "is_false")]
+    pub verify: bool,
+    ///`shadow_ratio`: fraction of reads, from 0 to 1, run through `ReadPool::shadow` which are
+    ///also run on a replica and compared with the main pool's results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow_ratio: Option<f64>,
+    ///`pin_after_write`: once a request has acquired an `RwConnection`, its later
+    ///`ReadConnection`s use the main pool, so it reads its own writes
+    #[serde(skip_serializing_if = "is_false")]
+    pub pin_after_write: bool,
+    ///`lazy`: creates the replicas' pools on the first read, or `ReadPool::connect_read`,
+    ///instead of at launch, so the application can start before its replicas are reachable.
+    ///Reads while they can't be created fail or fall back as `on_error` says.
+    #[serde(skip_serializing_if = "is_false")]
+    pub lazy: bool,
+    ///`degraded_start`: if the replicas' pools can't be created at launch, serves reads from the
+    ///main pool and keeps retrying in the background instead of failing to launch
+    #[serde(skip_serializing_if = "is_false")]
+    pub degraded_start: bool,
+    ///`init_retry_ms`: time between retries under `degraded_start`. Defaults to 5000.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_retry_ms: Option<u64>,
+    ///`record`: path of a file to record read query fingerprints to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<String>,
+    ///`warmup`: number of read connections `ReadPool::warm_up` opens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<u32>,
+    ///`warmup_concurrency`: most connections opened at once during warm-up. Defaults to 4.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup_concurrency: Option<usize>,
+    ///`brownout`: gradual shedding of reads to the main pool while the replica is slow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brownout: Option<BrownoutConfig>,
+    ///`primary_read_ratio`: fraction of reads, from 0 to 1, served by the main pool even
+    ///while the replicas are available, e.g. to roll reads out to the replicas gradually or
+    ///keep the main pool's cache warm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_read_ratio: Option<f64>,
+    ///`min_healthy`: fewest replicas which may serve reads, i.e. aren't quarantined, degraded,
+    ///in maintenance or drained, for the replicas to take full read traffic. With fewer,
+    ///`below_min_healthy` decides which reads they still serve, so a single surviving replica
+    ///isn't crushed by the load of all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_healthy: Option<usize>,
+    ///`below_min_healthy`: what reads do while fewer than `min_healthy` replicas are healthy,
+    ///`"main"` or `"shed"`. Defaults to `"main"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below_min_healthy: Option<BelowMinHealthy>,
+    ///`slow_start_ms`: time over which a replica's share of reads ramps up from nothing after
+    ///it's readmitted by health checks, leaves maintenance or is added at runtime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_start_ms: Option<u64>,
+    ///`maintenance`: recurring windows during which reads avoid the replica, usually given per
+    ///`replicas` entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    ///`circuit_breaker`: routing of reads to the main pool after repeated replica failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    ///`health_check`: background probing of the replicas by `ReadHealthCheck`, quarantining
+    ///failing ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+    ///`canary`: queries run periodically on the replicas by `ReadCanary`, degrading those whose
+    ///results break their invariants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanaryConfig>,
+    ///`discovery`: the SRV record or hook `ReadDiscovery` finds the replicas with, and how often
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<DiscoveryConfig>,
+    ///`coordination`: the budget of connections to each replica `ReadCoordination` divides
+    ///between the application's instances
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coordination: Option<CoordinationConfig>,
+    ///`cache`: expiry and size of the results cached by `CachedRead`, with the `cache` feature
+    #[cfg(feature = "cache")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<crate::CacheConfig>,
+    ///`delayed`: a deliberately delayed replica pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delayed: Option<DelayedConfig>,
+}
+
+///The `read.replicas` option: several read replicas, each given as pool options overriding
+///those of the `read` table.
+///
+///As a list, replicas are named by their index. As a table, by their key, which shows up in logs,
+///metrics and [`ReplicaStatus`](crate::ReplicaStatus), and they're ordered by name:
+///```toml
+///[default.databases.main.read.replicas.eu1]
+///url = "postgresql://user@replica-eu1.example/dbname"
+///
+///[default.databases.main.read.replicas.us1]
+///url = "postgresql://user@replica-us1.example/dbname"
+///max_connections = 20
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum Replicas {
+    List(Vec<Dict>),
+    Named(BTreeMap<String, Dict>),
+}
+impl Replicas {
+    ///The name and options of each replica, in order
+    pub fn named(&self) -> Vec<(String, Dict)> {
+        match self {
+            Replicas::List(replicas) => replicas.iter().cloned().enumerate().map(|(i, r)| (i.to_string(), r)).collect(),
+            Replicas::Named(replicas) => replicas.clone().into_iter().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Replicas::List(replicas) => replicas.len(),
+            Replicas::Named(replicas) => replicas.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+///What a read does when the read pool fails to provide a connection: the `read.on_error` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum OnError {
+    ///Fail the read with the read pool's error
+    #[default]
+    Error,
+    ///Read from the main pool instead
+    Fallback,
+    ///Try another available replica once, then read from the main pool. Without another
+    ///replica, reads from the main pool straight away.
+    #[serde(alias = "retry-then-fallback")]
+    RetryThenFallback,
+}
+
+///What reads do while fewer than `read.min_healthy` replicas are healthy: the
+///`read.below_min_healthy` option
+///```toml
+///[default.databases.main.read]
+///replicas = [
+///    {url = "postgresql://user@replica-1.example/dbname"},
+///    {url = "postgresql://user@replica-2.example/dbname"},
+///    {url = "postgresql://user@replica-3.example/dbname"},
+///]
+///min_healthy = 2
+///below_min_healthy = "shed"
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum BelowMinHealthy {
+    ///Read from the main pool, as [`FallbackReason::MinHealthy`](crate::FallbackReason::MinHealthy)
+    #[default]
+    Main,
+    ///Fail the `ReadConnection` guards of low-priority requests with
+    ///[`ReadPoolError::Shed`](crate::ReadPoolError::Shed), marked so by
+    ///[`RequestRouting::set_low_priority`](crate::RequestRouting::set_low_priority), while the
+    ///remaining replicas serve the others
+    Shed,
+}
+
+///Which pool a plain `Pool::get` uses: the `prefer` option.
+///
+///With `"read"`, a reporting service can move all its traffic to the replicas without changing
+///its handlers. It's ignored unless both pools hand out the same connection type.
+///```toml
+///[default.databases.reports]
+///url = "postgresql://user@primary.example/dbname"
+///prefer = "read"
+///read = {url = "postgresql://user@replica.example/dbname"}
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Prefer {
+    ///The main pool, as `RwConnection` does
+    #[default]
+    Main,
+    ///The read replicas, as `ReadConnection` does, so `rocket_db_pools::Connection` reads from
+    ///them too. `RwConnection` still uses the main pool.
+    Read,
+}
+
+///Configuration of a delayed replica: the `databases.<name>.read.delayed` table.
+///
+///A deliberately delayed replica serves "as of an hour ago" style tooling through the
+///[`DelayedReadConnection`](crate::DelayedReadConnection) guard, and is never used by other
+///reads:
+///```toml
+///[default.databases.main.read.delayed]
+///url = "postgresql://user@delayed-replica.example/dbname"
+///```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DelayedConfig {
+    ///Options for the delayed pool, passed through to its `Pool::init`
+    #[serde(flatten)]
+    pub pool: Dict,
+    ///`label`: name of the delayed pool in logs and reports. Defaults to `"delayed"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    ///`roles`: database role per user class for the delayed pool, see `RoleSwitch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<BTreeMap<String, String>>,
+    ///`statement_timeout_ms`: the delayed pool's default statement timeout, if it has one.
+    ///`ReadConnection::with_statement_timeout` never loosens beyond it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_timeout_ms: Option<u64>,
+    ///`search_path`: schema search path set on the delayed pool's connections by `WithContext`,
+    ///see `SessionContext::configured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_path: Option<String>,
+    ///`resolve`: when the host in the delayed pool's `url` is resolved. Defaults to `"connect"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<Resolve>,
+    ///`ip_preference`: address family the delayed pool connects over: `"ipv4"`, `"ipv6"` or
+    ///`"happy_eyeballs"` (the default, leaving it to the driver)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_preference: Option<IpPreference>,
+    ///`tls`: certificates and verification of the delayed pool's connections, see `TlsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    ///`secrets`: where the delayed pool's url or password is read from, see `SecretsConfig`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretsConfig>,
+    ///`application_name`: the name the delayed pool's connections report to the server, e.g. in
+    ///Postgres' `pg_stat_activity`. Only applied to Postgres urls.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<String>,
+    ///`reset_on_return`: how `WithCleanup` resets the delayed pool's sessions: `"discard_all"`,
+    ///`"reset"` or `"none"`. Uses the `SessionCleanup` default if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_on_return: Option<ResetStrategy>,
+    ///`drain_timeout_ms`: time closing the delayed pool at shutdown waits for its connections in
+    ///use to be returned before abandoning them, see `ReadDrain`. Defaults to waiting for all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain_timeout_ms: Option<u64>,
+    ///`test_before_acquire`: whether connections from the delayed pool are checked with the
+    ///`ReadPool::test_with` tests before being handed out. Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_before_acquire: Option<bool>,
+}
+
+impl ReadDbConfig {
+    ///Extracts the config from a database's figment, as passed to `Pool::init`
+    pub fn extract(figment: &Figment) -> Result<Self, Box<figment::Error>> {
+        Ok(figment.extract()?)
+    }
+
+    ///A figment of this config, as passed to `Pool::init`
+    pub fn figment(&self) -> Figment {
+        Figment::from(Serialized::defaults(self))
+    }
+
+    ///A figment of this config as the `databases.<name>` table, to merge into Rocket's
+    ///configuration
+    pub fn figment_for(&self, name: &str) -> Figment {
+        Figment::from(Serialized::defaults(self).key(&format!("databases.{}", name)))
+    }
+}
+
+impl ReadDbConfig {
+    ///The `log` table, with the `log_slow_acquire_ms` and `log_slow_hold_ms` shorthands applied
+    pub(crate) fn log_config(&self) -> LogConfig {
+        let mut log = self.log.clone().unwrap_or_default();
+        log.slow_acquire_ms = log.slow_acquire_ms.or(self.log_slow_acquire_ms);
+        log.slow_hold_ms = log.slow_hold_ms.or(self.log_slow_hold_ms);
+        log
+    }
+
+    ///The label of each pool
+    pub(crate) fn labels(&self) -> PerRole<Arc<str>> {
+        let read = self.read.as_ref();
+        PerRole{
+            main: self.label.as_deref().unwrap_or("main").into(),
+            read: read.and_then(|r| r.label.as_deref()).unwrap_or("read").into(),
+            delayed: read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.label.as_deref()).unwrap_or("delayed").into(),
+        }
+    }
+
+    ///The configured default statement timeout of each pool
+    pub(crate) fn statement_timeouts(&self) -> PerRole<Option<Duration>> {
+        let read = self.read.as_ref();
+        PerRole{
+            main: self.statement_timeout_ms.map(Duration::from_millis),
+            read: read.and_then(|r| r.statement_timeout_ms).map(Duration::from_millis),
+            delayed: read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.statement_timeout_ms).map(Duration::from_millis),
+        }
+    }
+
+    ///Whether each pool tests its connections before handing them out
+    pub(crate) fn tests_before_acquire(&self) -> PerRole<bool> {
+        let read = self.read.as_ref();
+        PerRole{
+            main: self.test_before_acquire.unwrap_or(false),
+            read: read.and_then(|r| r.test_before_acquire).unwrap_or(false),
+            delayed: read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.test_before_acquire).unwrap_or(false),
+        }
+    }
+
+    pub(crate) fn drain_timeouts(&self) -> PerRole<Option<Duration>> {
+        let read = self.read.as_ref();
+        PerRole{
+            main: self.drain_timeout_ms.map(Duration::from_millis),
+            read: read.and_then(|r| r.drain_timeout_ms).map(Duration::from_millis),
+            delayed: read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.drain_timeout_ms).map(Duration::from_millis),
+        }
+    }
+}
+
+///A value for each of a `ReadPool`'s pools
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PerRole<T> {
+    pub main: T,
+    pub read: T,
+    pub delayed: T,
+}
+impl<T> PerRole<T> {
+    pub fn get(&self, role: PoolRole) -> &T {
+        match role {
+            PoolRole::Main => &self.main,
+            PoolRole::Read => &self.read,
+            PoolRole::Delayed => &self.delayed,
+        }
+    }
+
+    pub fn get_mut(&mut self, role: PoolRole) -> &mut T {
+        match role {
+            PoolRole::Main => &mut self.main,
+            PoolRole::Read => &mut self.read,
+            PoolRole::Delayed => &mut self.delayed,
+        }
+    }
+}
+
+impl ReplicaConfig {
+    ///What reads do when the read pool fails, from `on_error` or else `fallback`
+    pub fn on_error(&self) -> OnError {
+        match self.on_error {
+            Some(on_error) => on_error,
+            None if self.fallback => OnError::Fallback,
+            None => OnError::Error,
+        }
+    }
+
+    ///Whether read queries are checked against `replicated_tables`
+    pub fn check_replicated_tables(&self) -> bool {
+        self.check_replicated_tables.unwrap_or(cfg!(debug_assertions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Format, Toml};
+    use super::*;
+
+    fn config(toml: &str) -> ReadDbConfig {
+        ReadDbConfig::extract(&Figment::from(Toml::string(toml))).unwrap()
+    }
+
+    #[test]
+    fn keeps_main_pool_options() {
+        let config = config(r#"
+            url = "postgresql://user@primary/db"
+            max_connections = 8
+            label = "primary"
+            [read]
+            url = "postgresql://user@replica/db"
+        "#);
+        assert_eq!(config.main.get("max_connections").and_then(|v| v.to_i128()), Some(8));
+        assert_eq!(config.read.as_ref().unwrap().pool.get("url").and_then(|v| v.as_str()), Some("postgresql://user@replica/db"));
+        let labels = config.labels();
+        assert_eq!((&*labels.main, &*labels.read, &*labels.delayed), ("primary", "read", "delayed"));
+    }
+
+    #[test]
+    fn read_can_be_turned_off() {
+        assert!(config("url = \"db\"\nread = false").read.is_none());
+        assert!(config("url = \"db\"").read.is_none());
+        assert!(ReadDbConfig::extract(&Figment::from(Toml::string("url = \"db\"\nread = true"))).is_err());
+    }
+
+    #[test]
+    fn replicas_as_list_or_table() {
+        let list = config("[read]\nreplicas = [{url = \"a\"}, {url = \"b\"}]").read.unwrap().replicas.unwrap();
+        let names: Vec<String> = list.named().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["0", "1"]);
+        let table = config("[read.replicas.us1]\nurl = \"b\"\n[read.replicas.eu1]\nurl = \"a\"").read.unwrap().replicas.unwrap();
+        let named = table.named();
+        assert_eq!(named.len(), 2);
+        assert_eq!(named[0].0, "eu1");
+        assert_eq!(named[0].1.get("url").and_then(|v| v.as_str()), Some("a"));
+    }
+
+    #[test]
+    fn on_error_defaults_from_fallback() {
+        assert_eq!(config("[read]\nurl = \"r\"").read.unwrap().on_error(), OnError::Error);
+        assert_eq!(config("[read]\nfallback = true").read.unwrap().on_error(), OnError::Fallback);
+        let read = config("[read]\nfallback = true\non_error = \"retry_then_fallback\"").read.unwrap();
+        assert_eq!(read.on_error(), OnError::RetryThenFallback);
+    }
+
+    #[test]
+    fn log_shorthands_apply_unless_set() {
+        let config = config("log_slow_acquire_ms = 50\nlog_slow_hold_ms = 900\n[log]\nslow_acquire_ms = 10");
+        let log = config.log_config();
+        assert_eq!(log.slow_acquire_ms, Some(10));
+        assert_eq!(log.slow_hold_ms, Some(900));
+    }
+
+    #[test]
+    fn per_role_options() {
+        let config = config(r#"
+            statement_timeout_ms = 1000
+            [read]
+            test_before_acquire = true
+            [read.delayed]
+            url = "d"
+            label = "reporting"
+            drain_timeout_ms = 5
+        "#);
+        let timeouts = config.statement_timeouts();
+        assert_eq!((timeouts.main, timeouts.read), (Some(Duration::from_millis(1000)), None));
+        let tests = config.tests_before_acquire();
+        assert_eq!((tests.main, tests.read, tests.delayed), (false, true, false));
+        assert_eq!(*config.drain_timeouts().get(PoolRole::Delayed), Some(Duration::from_millis(5)));
+        assert_eq!(&**config.labels().get(PoolRole::Delayed), "reporting");
+    }
+
+    #[test]
+    fn round_trips_through_figment() {
+        let original = config("url = \"m\"\n[read]\nurl = \"r\"\nweight = 3\nmin_healthy = 1");
+        assert_eq!(ReadDbConfig::extract(&original.figment()).unwrap(), original);
+        let rocket_figment = original.figment_for("main");
+        assert_eq!(rocket_figment.extract_inner::<u32>("databases.main.read.weight").unwrap(), 3);
+    }
+}
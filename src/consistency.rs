@@ -0,0 +1,450 @@
+//!Read-your-writes consistency across a client's requests
+use std::any::Any;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rocket::{Build, Data, Request, Response, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::future::BoxFuture;
+use rocket::http::{Cookie, Header, RawStr};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{BoxError, FallbackReason, PoolRole, ReadCapablePool, ReadPool, RequestRouting, RoutingContext, RoutingFlagRegistry, RoutingPolicies, RoutingPolicy};
+
+///How often replicas are polled while waiting for them to catch up
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+///The longest token accepted from a client, as it's sent to the database unauthenticated
+const MAX_TOKEN_LEN: usize = 4096;
+
+///Reads and compares replication positions, for [`ReadYourWrites`].
+///
+///Implement it for the connection types of both pools, e.g. with `pg_current_wal_lsn()` and
+///`pg_last_wal_replay_lsn()` on Postgres, or `@@global.gtid_executed` and
+///`WAIT_FOR_EXECUTED_GTID_SET` on MySQL:
+///```rust
+/// # use std::time::Duration;
+/// # use rocket_read_db_pools::{BoxError, ReplicationPosition};
+/// # use rocket_read_db_pools::testing::MockPoolConnection;
+/// # struct MySqlConnection(MockPoolConnection);
+/// #[rocket::async_trait]
+/// impl ReplicationPosition for MySqlConnection {
+///     async fn write_position(&mut self) -> Result<String, BoxError> {
+///         Ok(self.0.query("SELECT @@global.gtid_executed").fetch_one().await?)
+///     }
+///
+///     async fn has_replayed(&mut self, position: &str) -> Result<bool, BoxError> {
+///         self.wait_for_replay(position, Duration::ZERO).await
+///     }
+///
+///     async fn wait_for_replay(&mut self, position: &str, timeout: Duration) -> Result<bool, BoxError> {
+///         let timed_out: i64 = self.0.query("SELECT WAIT_FOR_EXECUTED_GTID_SET(?, ?)")
+///             .bind(position).bind(timeout.as_secs_f64())
+///             .fetch_one().await?;
+///         Ok(timed_out == 0)
+///     }
+/// }
+///```
+#[rocket::async_trait]
+pub trait ReplicationPosition: Send {
+    ///The primary's current replication position
+    async fn write_position(&mut self) -> Result<String, BoxError>;
+    ///Whether this replica has replayed up to `position`, as returned by `write_position`
+    async fn has_replayed(&mut self, position: &str) -> Result<bool, BoxError>;
+    ///Waits up to `timeout` for this replica to replay up to `position`, returning whether it
+    ///did. Defaults to polling `has_replayed`; override it where the server can wait itself,
+    ///such as with `WAIT_FOR_EXECUTED_GTID_SET` on MySQL.
+    async fn wait_for_replay(&mut self, position: &str, timeout: Duration) -> Result<bool, BoxError> {
+        let deadline = Instant::now() + timeout;
+        while !self.has_replayed(position).await? {
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            rocket::tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(true)
+    }
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, R::Connection: ReplicationPosition {
+    ///Whether every read replica which isn't quarantined or in maintenance has replayed up to `position`, polling
+    ///for up to `wait`
+    pub async fn wait_for_replay(&self, position: &str, wait: Duration) -> Result<bool, BoxError> {
+        let deadline = Instant::now() + wait;
+        let set = self.replica_set();
+        for (read, replica) in set.pools.iter().zip(&set.replicas) {
+            if !replica.health.available() {
+                continue;
+            }
+            let mut conn = read.get().await.map_err(|e| e.to_string())?;
+            if !conn.wait_for_replay(position, deadline.saturating_duration_since(Instant::now())).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+///Databases whose reads are tracked by [`ReadYourWrites`] during a request, and whether they're
+///pinned to the main pool, or for [`ReadYourWrites::causal`] the position their replica
+///connections wait for and until when, as the request's guards share the wait
+#[derive(Debug, Default)]
+struct CausalReads {
+    pinned: Mutex<HashMap<&'static str, bool>>,
+    positions: Mutex<HashMap<&'static str, String>>,
+    deadlines: Mutex<HashMap<&'static str, Instant>>,
+}
+impl CausalReads {
+    fn of<'r>(req: &'r Request<'_>) -> &'r CausalReads {
+        req.local_cache(CausalReads::default)
+    }
+}
+
+///Whether reads of `D` must use the main pool to see the client's writes: either the replicas
+///hadn't caught up with its last request's writes, or this request has already written
+fn pinned(req: &Request<'_>, database: &str) -> bool {
+    match CausalReads::of(req).pinned.lock().unwrap_or_else(|e| e.into_inner()).get(database) {
+        Some(&pinned) => pinned || wrote(req, database),
+        None => false,
+    }
+}
+
+///Whether this request has acquired an `RwConnection` to the database named `database`
+fn wrote(req: &Request<'_>, database: &str) -> bool {
+    RequestRouting::of(req).acquisitions().iter().any(|a| a.database == database && a.read_write() && a.success)
+}
+
+///The [`RoutingPolicy`] keeping a client's reads on the main pool while [`ReadYourWrites`] has
+///them wait for the replicas, unless its [`RoutingFlags`](crate::RoutingFlags) turn it off
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadYourWritesPolicy;
+impl RoutingPolicy for ReadYourWritesPolicy {
+    fn route(&self, req: &Request<'_>, cx: &RoutingContext) -> Option<FallbackReason> {
+        (cx.flags.read_your_writes && pinned(req, cx.database)).then_some(FallbackReason::ReadYourWrites)
+    }
+}
+
+///The [`RoutingPolicy`] of `read.pin_after_write`, keeping a request's reads on the main pool
+///once it has acquired an `RwConnection`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PinAfterWritePolicy;
+impl RoutingPolicy for PinAfterWritePolicy {
+    fn route(&self, req: &Request<'_>, cx: &RoutingContext) -> Option<FallbackReason> {
+        (cx.pin_after_write && wrote(req, cx.database)).then_some(FallbackReason::AfterWrite)
+    }
+}
+
+type Waiter = dyn for<'c> Fn(&'c mut dyn Any, &'c str, Duration) -> Option<BoxFuture<'c, Result<bool, BoxError>>> + Send + Sync;
+
+///How replica connections of each database with [`ReadYourWrites::causal`] wait for a position,
+///and for how long
+#[derive(Default)]
+struct CausalWaits(Mutex<HashMap<&'static str, (Arc<Waiter>, Duration)>>);
+
+///Has the replica connection `conn` of `D` wait for the client's last write if
+///[`ReadYourWrites::causal`] requires it, returning whether it's safe to read from. The wait is
+///shared by the request's guards, and a connection whose type can't wait isn't safe.
+async fn caught_up<D: Database, C: 'static>(req: &Request<'_>, conn: &mut C) -> bool {
+    let reads = CausalReads::of(req);
+    let Some(position) = reads.positions.lock().unwrap_or_else(|e| e.into_inner()).get(D::NAME).cloned() else {return true};
+    let Some((waiter, wait)) = req.rocket().state::<CausalWaits>()
+        .and_then(|w| w.0.lock().unwrap_or_else(|e| e.into_inner()).get(D::NAME).cloned()) else {return true};
+    let deadline = *reads.deadlines.lock().unwrap_or_else(|e| e.into_inner()).entry(D::NAME).or_insert_with(|| Instant::now() + wait);
+    let Some(replayed) = waiter(conn, &position, deadline.saturating_duration_since(Instant::now())) else {
+        rocket::warn!("database `{}`: `{}` connections can't wait for a replication position", D::NAME, std::any::type_name::<C>());
+        return false;
+    };
+    match replayed.await {
+        Ok(replayed) => replayed,
+        Err(e) => {
+            rocket::warn!("database `{}`: failed to wait for replication position: {}", D::NAME, e);
+            false
+        }
+    }
+}
+
+///Gets a read connection for the `ReadConnection` guard, from the main pool if the request's
+///[`RoutingPolicies`](crate::RoutingPolicies) require it
+pub(crate) async fn get_read<D, C>(req: &Request<'_>, db: &D::Pool) -> (PoolRole, Option<FallbackReason>, Result<C, <D::Pool as Pool>::Error>)
+    where D: Database, D::Pool: ReadCapablePool<C>, C: Send + 'static
+{
+    let flags = RoutingFlagRegistry::flags::<D>(req);
+    let (replicas, available_replicas) = db.replica_counts().unzip();
+    let cx = RoutingContext{database: D::NAME, flags, replicas, available_replicas, pin_after_write: db.pins_after_write()};
+    if let Some(reason) = RoutingPolicies::route(req, &cx) {
+        if let Some(result) = db.get_read_main_for(reason).await {
+            return (PoolRole::Main, Some(reason), result);
+        }
+    }
+    let mut conn = match db.get_read_explained().await {
+        (PoolRole::Read, reason, Ok(conn)) if flags.read_your_writes => (reason, conn),
+        explained => return explained,
+    };
+    if caught_up::<D, C>(req, &mut conn.1).await {
+        return (PoolRole::Read, conn.0, Ok(conn.1));
+    }
+    drop(conn);
+    if let Some(result) = db.get_read_main_for(FallbackReason::ReadYourWrites).await {
+        return (PoolRole::Main, Some(FallbackReason::ReadYourWrites), result);
+    }
+    db.get_read_explained().await
+}
+
+///A fairing which gives clients of the database `D` read-your-writes consistency.
+///
+///After a request writes through an `RwConnection`, the primary's replication position (e.g.
+///an LSN or GTID set) is sent to the client as a consistency token, in a cookie or with
+///[`ReadYourWrites::header`] a response header. On the client's next requests, which send it
+///back, `ReadConnection` guards use the main pool until every replica has replayed up to that
+///position, after which the cookie is removed. Within the request which wrote, later
+///`ReadConnection` guards also use the main pool.
+///
+///Recording the position takes an extra main pool connection after each writing request, and
+///checking it a read connection to each replica at the start of the client's next requests.
+///Both pools' connection types must implement [`ReplicationPosition`].
+///
+///Tokens aren't authenticated, so a client can send any position, but only its own reads wait
+///for it: for up to `wait` per request however many replicas or guards wait, before using the
+///main pool. Tokens longer than 4096 bytes are ignored.
+///
+///With [`ReadYourWrites::causal`], the position is instead waited for on each replica
+///connection handed to a `ReadConnection` guard, as MySQL's `WAIT_FOR_EXECUTED_GTID_SET` does:
+///```rust
+/// # use std::time::Duration;
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{ReadPool, ReadYourWrites};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// # fn _f() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadYourWrites::<Db>::new().causal().wait(Duration::from_millis(200)))
+/// # }
+///```
+pub struct ReadYourWrites<D> {
+    wait: Duration,
+    causal: bool,
+    header: Option<String>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D: Database> ReadYourWrites<D> {
+    pub fn new() -> Self {
+        ReadYourWrites{wait: Duration::ZERO, causal: false, header: None, _db: PhantomData}
+    }
+
+    ///Sends the token in the response header `name` instead of a cookie, and reads it from the
+    ///request header of the same name, for clients which don't keep cookies such as other
+    ///services. Clients send back the last token they were given, which they should treat as
+    ///opaque. Nothing tells them when the replicas have caught up, so they keep sending it
+    ///until their next write replaces it.
+    ///```rust
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::testing::MockPool;
+    /// # use rocket_read_db_pools::{ReadPool, ReadYourWrites};
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// # fn _f() -> rocket::Rocket<rocket::Build> {
+    /// rocket::build()
+    ///     .attach(Db::init())
+    ///     .attach(ReadYourWrites::<Db>::new().header("X-Consistency-Token"))
+    /// # }
+    ///```
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = Some(name.to_string());
+        self
+    }
+
+    ///Waits up to `wait` for the replicas to catch up before using the main pool instead.
+    ///Defaults to not waiting.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    ///Has each replica connection a `ReadConnection` guard acquires wait up to `wait` to replay
+    ///the client's last write with [`ReplicationPosition::wait_for_replay`] before handing it
+    ///out, using the main pool instead if it doesn't, rather than checking every replica when
+    ///the request arrives. The position is kept until the client writes again, so each read
+    ///costs the wait's round trip, which returns at once for a replica which has caught up.
+    ///Connections acquired other than by `ReadConnection`, e.g. by `Pool::get`, don't wait.
+    pub fn causal(mut self) -> Self {
+        self.causal = true;
+        self
+    }
+
+    fn cookie_name() -> String {
+        format!("db_position_{}", D::NAME)
+    }
+
+    ///The position in the client's token, if it sent one which isn't too long
+    fn position(&self, req: &Request<'_>) -> Option<String> {
+        let token = match &self.header {
+            Some(header) => req.headers().get_one(header).map(str::to_string),
+            None => req.cookies().get(&Self::cookie_name()).map(|c| c.value().to_string()),
+        }?;
+        if token.len() > MAX_TOKEN_LEN {
+            rocket::debug!("database `{}`: ignoring a consistency token of {} bytes", D::NAME, token.len());
+            return None;
+        }
+        RawStr::new(&token).percent_decode().ok().map(|position| position.into_owned())
+    }
+}
+
+///The token of a replication position: positions such as MySQL's GTID sets can hold commas and
+///newlines, so anything else than letters, digits and `-_.:` is percent-encoded
+fn token(position: &str) -> String {
+    position.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b':' => (b as char).to_string(),
+        b => format!("%{:02X}", b),
+    }).collect()
+}
+
+impl<D: Database> Default for ReadYourWrites<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadYourWrites<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool,
+        P::Connection: ReplicationPosition, R::Connection: ReplicationPosition
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Your Writes",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if !self.causal {
+            return Ok(rocket);
+        }
+        let rocket = match rocket.state::<CausalWaits>() {
+            Some(_) => rocket,
+            None => rocket.manage(CausalWaits::default()),
+        };
+        let waiter: Arc<Waiter> = Arc::new(|conn, position, wait| {
+            let conn: &mut dyn ReplicationPosition = match conn.is::<P::Connection>() {
+                true => conn.downcast_mut::<P::Connection>()?,
+                false => conn.downcast_mut::<R::Connection>()?,
+            };
+            Some(conn.wait_for_replay(position, wait))
+        });
+        if let Some(waits) = rocket.state::<CausalWaits>() {
+            waits.0.lock().unwrap_or_else(|e| e.into_inner()).insert(D::NAME, (waiter, self.wait));
+        }
+        Ok(rocket)
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        let Some(db) = D::fetch(req.rocket()) else {return};
+        if self.causal {
+            if let Some(position) = self.position(req) {
+                CausalReads::of(req).positions.lock().unwrap_or_else(|e| e.into_inner()).insert(D::NAME, position);
+            }
+            CausalReads::of(req).pinned.lock().unwrap_or_else(|e| e.into_inner()).insert(D::NAME, false);
+            return;
+        }
+        let pinned = match self.position(req) {
+            Some(position) => match db.wait_for_replay(&position, self.wait).await {
+                Ok(true) => {
+                    if self.header.is_none() {
+                        req.cookies().remove(Cookie::build(Self::cookie_name()).path("/"));
+                    }
+                    false
+                }
+                Ok(false) => true,
+                Err(e) => {
+                    db_log!(db.log, General, Warn, "database `{}`: failed to check replication position, reading from `{}`: {}",
+                        D::NAME, db.label(PoolRole::Main), e);
+                    true
+                }
+            },
+            None => false,
+        };
+        CausalReads::of(req).pinned.lock().unwrap_or_else(|e| e.into_inner()).insert(D::NAME, pinned);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let wrote = RequestRouting::of(req).acquisitions().iter()
+            .any(|a| a.database == D::NAME && a.read_write() && a.success)
+            && RoutingFlagRegistry::flags::<D>(req).read_your_writes;
+        let Some(db) = D::fetch(req.rocket()).filter(|_| wrote) else {return};
+        let position = match db.get_primary().await.map_err(|e| e.to_string()) {
+            Ok(mut conn) => conn.write_position().await,
+            Err(e) => Err(e.into()),
+        };
+        match (position, &self.header) {
+            (Ok(position), Some(header)) => {
+                res.set_header(Header::new(header.clone(), token(&position)));
+            }
+            //The request's cookie jar has already been applied to the response
+            (Ok(position), None) => res.adjoin_header(Cookie::build((Self::cookie_name(), token(&position))).path("/").http_only(true).build()),
+            (Err(e), _) => db_log!(db.log, General, Warn, "database `{}`: failed to record replication position: {}", D::NAME, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::asynchronous::Client;
+    use crate::testing::MockPool;
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    ///A client whose requests to `Db` wait for the position `"0/1"` with `waiter`
+    async fn client(waiter: Arc<Waiter>) -> Client {
+        let waits = CausalWaits::default();
+        waits.0.lock().unwrap().insert(Db::NAME, (waiter, Duration::from_secs(1)));
+        Client::tracked(rocket::build().manage(waits)).await.unwrap()
+    }
+
+    fn waiting<'a, 'r>(req: &'a Request<'r>) -> &'a Request<'r> {
+        CausalReads::of(req).positions.lock().unwrap().insert(Db::NAME, "0/1".to_string());
+        req
+    }
+
+    #[test]
+    fn tokens_encode_positions() {
+        let position = "3E11FA47-71CA-11E1-9E33-C80AA9429562:1-5,\nuuid:1-2";
+        assert_eq!(token(position), "3E11FA47-71CA-11E1-9E33-C80AA9429562:1-5%2C%0Auuid:1-2");
+        assert_eq!(RawStr::new(&token(position)).percent_decode().unwrap(), position);
+    }
+
+    #[rocket::async_test]
+    async fn connections_which_cant_wait_arent_caught_up() {
+        let client = client(Arc::new(|_, _, _| None)).await;
+        let req = client.get("/");
+        assert!(!caught_up::<Db, u32>(waiting(&req), &mut 0).await);
+    }
+
+    #[rocket::async_test]
+    async fn guards_share_the_wait() {
+        let waits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = waits.clone();
+        let client = client(Arc::new(move |_, _, wait| {
+            recorded.lock().unwrap().push(wait);
+            Some(Box::pin(async {Ok(true)}))
+        })).await;
+        let req = client.get("/");
+        assert!(caught_up::<Db, u32>(waiting(&req), &mut 0).await);
+        rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(caught_up::<Db, u32>(&req, &mut 0).await);
+        let waits = waits.lock().unwrap();
+        assert!(waits[1] <= waits[0] - Duration::from_millis(20), "{:?}", waits);
+    }
+
+    #[rocket::async_test]
+    async fn long_tokens_are_ignored() {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let rw = ReadYourWrites::<Db>::new().header("X-Consistency-Token");
+        let req = client.get("/").header(Header::new("X-Consistency-Token", "0%2F1"));
+        assert_eq!(rw.position(&req).as_deref(), Some("0/1"));
+        let req = client.get("/").header(Header::new("X-Consistency-Token", "0".repeat(MAX_TOKEN_LEN + 1)));
+        assert_eq!(rw.position(&req), None);
+    }
+}
@@ -0,0 +1,252 @@
+//!Division of a cluster-wide read connection budget among the instances of an application
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{interval, timeout, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{keys, BoxError, ReadPool, ResizePool};
+
+///Tracks the live instances of an application sharing a read connection budget, for
+///[`ReadCoordination`], e.g. with Redis keys expiring after `ttl`, or rows of a Postgres table
+///whose heartbeats are counted while they're recent.
+///```rust
+/// use std::time::Duration;
+/// use rocket_read_db_pools::{BoxError, CoordinationBackend};
+///
+/// ///Instances counted by an orchestrator, e.g. from a deployment's replica count
+/// struct Fixed(u32);
+/// #[rocket::async_trait]
+/// impl CoordinationBackend for Fixed {
+///     async fn heartbeat(&self, _instance: &str, _ttl: Duration) -> Result<u32, BoxError> {
+///         Ok(self.0)
+///     }
+/// }
+///```
+#[rocket::async_trait]
+pub trait CoordinationBackend: Send + Sync + 'static {
+    ///Registers `instance` as live for `ttl`, or renews it, and returns how many instances are
+    ///live, including it
+    async fn heartbeat(&self, instance: &str, ttl: Duration) -> Result<u32, BoxError>;
+    ///Deregisters `instance` at shutdown, so the others' shares grow without waiting for its
+    ///registration to expire. Defaults to doing nothing.
+    async fn leave(&self, instance: &str) -> Result<(), BoxError> {
+        let _ = instance;
+        Ok(())
+    }
+}
+
+///Configuration of coordination: the `databases.<name>.read.coordination` table.
+///```toml
+///[default.databases.main.read.coordination]
+///max_connections = 200
+///interval_ms = 10000
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CoordinationConfig {
+    ///`max_connections`: connections each read replica accepts from all the instances together.
+    ///Coordination is off without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    ///`min_connections`: fewest connections each instance keeps to each replica, however many
+    ///instances there are. Defaults to 1.
+    pub min_connections: u32,
+    ///`interval_ms`: time between heartbeats. Defaults to 10000.
+    pub interval_ms: u64,
+    ///`ttl_ms`: time an instance stays registered without a heartbeat, and a heartbeat may take
+    ///before it's abandoned until the next. Defaults to 30000.
+    pub ttl_ms: u64,
+    ///`instance`: name of this instance with the backend. Defaults to `$HOSTNAME` followed by the
+    ///process id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+impl Default for CoordinationConfig {
+    fn default() -> Self {
+        CoordinationConfig{max_connections: None, min_connections: 1, interval_ms: 10000, ttl_ms: 30000, instance: None}
+    }
+}
+impl CoordinationConfig {
+    fn instance(&self) -> String {
+        self.instance.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+            format!("{}-{}", host, std::process::id())
+        })
+    }
+
+    ///Each of `live` instances' share of `max_connections`
+    fn share(&self, max_connections: u32, live: u32) -> u32 {
+        (max_connections / live.max(1)).max(self.min_connections)
+    }
+}
+
+impl<P, R: ResizePool + Clone> ReadPool<P, R> {
+    ///Renews `instance` with `backend` and resizes the read replicas to its share of the budget
+    ///if it isn't `current`, returning the share
+    async fn take_share(&self, backend: &dyn CoordinationBackend, instance: &str, config: &CoordinationConfig, max_connections: u32, current: Option<u32>) -> Result<u32, BoxError> {
+        let ttl = Duration::from_millis(config.ttl_ms);
+        let live = timeout(ttl, backend.heartbeat(instance, ttl)).await
+            .map_err(|_| format!("heartbeat timed out after {:?}", ttl))??;
+        let share = config.share(max_connections, live);
+        if current != Some(share) {
+            db_log!(self.log, General, Info, "`{}` pool: {} instances live, taking {} of {} connections per replica", self.labels.read, live, share, max_connections);
+            self.resize_read(share).await.map_err(|e| e.to_string())?;
+        }
+        Ok(share)
+    }
+}
+
+///A fairing which divides `read.coordination.max_connections` between the instances of an
+///application using the database `D`, so that together they stay within each replica's
+///connection limit as instances come and go. Every `read.coordination.interval_ms` from launch
+///until shutdown, it renews this instance with a [`CoordinationBackend`] and resizes the read
+///replicas with [`ReadPool::resize_read`] to an equal share of the budget. See
+///[`CoordinationConfig`].
+///
+///Shares are only as fresh as the heartbeats, so instances starting together may briefly hold
+///more than the budget between them; leave headroom below the database's own limit. A failed
+///heartbeat keeps the current share.
+///
+///Attach it after `D::init()`. The read pool type must implement [`ResizePool`] and `Clone`.
+///```rust
+/// # use std::time::Duration;
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{BoxError, CoordinationBackend, ReadPool};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// # struct RedisInstances;
+/// # #[rocket::async_trait]
+/// # impl CoordinationBackend for RedisInstances {
+/// #     async fn heartbeat(&self, _instance: &str, _ttl: Duration) -> Result<u32, BoxError> {Ok(1)}
+/// # }
+/// use rocket_read_db_pools::ReadCoordination;
+///
+/// # fn _f() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadCoordination::<Db>::new(RedisInstances))
+/// # }
+///```
+pub struct ReadCoordination<D> {
+    backend: Arc<dyn CoordinationBackend>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ReadCoordination<D> {
+    pub fn new<T: CoordinationBackend>(backend: T) -> Self {
+        ReadCoordination{backend: Arc::new(backend), _db: PhantomData}
+    }
+}
+
+fn config<D: Database>(rocket: &Rocket<Orbit>) -> CoordinationConfig {
+    rocket.figment().focus(&format!("databases.{}", D::NAME))
+        .extract_inner::<CoordinationConfig>(keys::READ_COORDINATION)
+        .unwrap_or_default()
+}
+
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadCoordination<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: ResizePool + Clone
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Connection Coordination",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadCoordination` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let config = config::<D>(rocket);
+        let Some(max_connections) = config.max_connections else {
+            return db_log!(pool.log, General, Warn, "database `{}`: `{}.max_connections` not set, coordination off", D::NAME, keys::READ_COORDINATION);
+        };
+        let backend = self.backend.clone();
+        let instance = config.instance();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(Duration::from_millis(config.interval_ms).max(Duration::from_millis(1)));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut current = None;
+            loop {
+                rocket::tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                match pool.take_share(&*backend, &instance, &config, max_connections, current).await {
+                    Ok(share) => current = Some(share),
+                    Err(e) => db_log!(pool.log, General, Warn, "database `{}`: coordination failed, keeping the current share: {}", D::NAME, e),
+                }
+            }
+        });
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let config = config::<D>(rocket);
+        if config.max_connections.is_none() {
+            return;
+        }
+        if let Err(e) = self.backend.leave(&config.instance()).await {
+            db_log!(db.log, General, Warn, "database `{}`: failed to leave coordination: {}", D::NAME, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use rocket::figment::Figment;
+    use crate::testing::{pair_figment, MockPool};
+    use super::*;
+
+    ///Reports a set number of live instances, recording the heartbeats
+    #[derive(Default)]
+    struct Instances(AtomicU32, Mutex<Vec<String>>);
+    #[rocket::async_trait]
+    impl CoordinationBackend for Instances {
+        async fn heartbeat(&self, instance: &str, _ttl: Duration) -> Result<u32, BoxError> {
+            self.1.lock().unwrap().push(instance.to_string());
+            match self.0.load(Ordering::Relaxed) {
+                0 => Err("backend down".into()),
+                live => Ok(live),
+            }
+        }
+    }
+
+    fn read_max(pool: &ReadPool<MockPool>) -> u32 {
+        pool.stats().read[0].1.max_connections
+    }
+
+    #[rocket::async_test]
+    async fn replicas_are_resized_to_the_instances_share() {
+        let pool: ReadPool<MockPool> = Pool::init(&pair_figment("main", "read")).await.unwrap();
+        let config: CoordinationConfig = Figment::from(("instance", "web-1")).merge(("min_connections", 4)).extract().unwrap();
+        let backend = Instances::default();
+
+        backend.0.store(4, Ordering::Relaxed);
+        assert_eq!(pool.take_share(&backend, "web-1", &config, 100, None).await.unwrap(), 25);
+        assert_eq!(read_max(&pool), 25);
+        backend.0.store(50, Ordering::Relaxed);
+        assert_eq!(pool.take_share(&backend, "web-1", &config, 100, Some(25)).await.unwrap(), 4);
+        assert_eq!(read_max(&pool), 4);
+
+        backend.0.store(0, Ordering::Relaxed);
+        assert!(pool.take_share(&backend, "web-1", &config, 100, Some(4)).await.is_err());
+        assert_eq!(read_max(&pool), 4);
+        assert_eq!(*backend.1.lock().unwrap(), ["web-1"; 3]);
+    }
+}
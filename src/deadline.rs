@@ -0,0 +1,110 @@
+//!Request deadlines capping how long connection guards wait
+use std::future::Future;
+use std::time::{Duration, Instant};
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::tokio::time::timeout_at;
+use crate::RequestRouting;
+
+///Default header giving a request's remaining budget
+const DEFAULT_HEADER: &str = "X-Request-Deadline";
+
+///A fairing which caps how long this crate's connection guards wait for a connection to the
+///time left in each request's budget, so requests don't queue behind a saturated pool after
+///their client has given up on them.
+///
+///Rocket doesn't track deadlines itself, so the budget is read from a header, by default
+///`X-Request-Deadline`, giving the milliseconds the client or proxy will wait from when the
+///request arrives. Requests without it get the budget of [`RequestDeadline::default_budget`]
+///if set, otherwise no deadline.
+///
+///A guard still waiting at the deadline fails with
+///[`ReadPoolError::Timeout`](crate::ReadPoolError::Timeout) and `503 Service Unavailable`, and
+///the response gets a `Retry-After` header. The pools' own `connect_timeout` still applies, and
+///[`RequestRouting::missed_deadline`] tells whether the deadline was hit.
+///```rust
+/// use std::time::Duration;
+/// use rocket_read_db_pools::RequestDeadline;
+///
+/// let rocket = rocket::build()
+///     .attach(RequestDeadline::new().header("X-Timeout-Ms").default_budget(Duration::from_secs(5)));
+///```
+#[derive(Debug, Clone)]
+pub struct RequestDeadline {
+    header: String,
+    default_budget: Option<Duration>,
+    retry_after: u64,
+}
+impl RequestDeadline {
+    pub fn new() -> Self {
+        RequestDeadline{header: DEFAULT_HEADER.to_string(), default_budget: None, retry_after: 1}
+    }
+
+    ///Reads the budget from the header `name` instead of `X-Request-Deadline`
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = name.to_string();
+        self
+    }
+
+    ///Gives requests without a valid budget header a budget of `budget`
+    pub fn default_budget(mut self, budget: Duration) -> Self {
+        self.default_budget = Some(budget);
+        self
+    }
+
+    ///Sets the `Retry-After` sent with responses which missed their deadline, rounded up to
+    ///whole seconds. Defaults to 1 second.
+    pub fn retry_after(mut self, wait: Duration) -> Self {
+        self.retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        self
+    }
+
+    ///The budget of `req`, from its header or the default
+    fn budget(&self, req: &Request<'_>) -> Option<Duration> {
+        req.headers().get_one(&self.header)
+            .and_then(|ms| ms.trim().parse().ok())
+            .map(Duration::from_millis)
+            .or(self.default_budget)
+    }
+}
+impl Default for RequestDeadline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl Fairing for RequestDeadline {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Deadline",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        if let Some(budget) = self.budget(req) {
+            RequestRouting::of(req).set_deadline(Instant::now() + budget);
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.status() == Status::ServiceUnavailable && RequestRouting::of(req).missed_deadline() {
+            res.set_header(Header::new("Retry-After", self.retry_after.to_string()));
+        }
+    }
+}
+
+///Runs a guard's acquisition `fut` until the deadline of the request with the routing record
+///`routing`, if it has one. Past it, gives up with how long the guard waited.
+pub(crate) async fn within<F: Future>(routing: &RequestRouting, fut: F) -> Result<F::Output, Duration> {
+    let Some(deadline) = routing.deadline() else {return Ok(fut.await)};
+    let start = Instant::now();
+    match timeout_at(deadline.into(), fut).await {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            routing.miss_deadline();
+            Err(start.elapsed())
+        }
+    }
+}
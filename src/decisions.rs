@@ -0,0 +1,135 @@
+//!Persistent, append-only log of which pool served each request, e.g. to prove that report
+//!endpoints never touched the primary
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rocket::{Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::serde::{Deserialize, Serialize};
+use crate::{BoxError, FallbackReason, PoolRole, RequestRouting};
+use crate::record::escape;
+
+///One connection acquisition made while serving a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RoutingDecision {
+    ///When the request's response was produced
+    pub timestamp: SystemTime,
+    ///HTTP method of the request
+    pub method: String,
+    ///Path of the request
+    pub path: String,
+    ///Name of the route which handled the request, if it matched a named route
+    pub route: Option<String>,
+    ///`Database::NAME` of the database
+    pub database: String,
+    ///The pool which served (or failed to serve) the connection
+    pub role: PoolRole,
+    ///The configured label of that pool, if known
+    pub label: Option<String>,
+    ///Whether a connection was acquired
+    pub success: bool,
+    ///Why a read was served by the main pool, if it was
+    #[serde(default)]
+    pub fallback: Option<FallbackReason>,
+}
+
+///Destination for [`RoutingDecision`]s written by [`RoutingLog`].
+///
+///[`FileRoutingSink`] appends to a local file; to store decisions in a database, implement this
+///for a type holding a pool (ideally one not subject to the routing being audited).
+#[rocket::async_trait]
+pub trait RoutingSink: Send + Sync + 'static {
+    ///Persists the decisions made for one request
+    async fn write(&self, decisions: &[RoutingDecision]) -> Result<(), BoxError>;
+}
+
+///Appends decisions to a file as tab separated lines of
+///`unix_micros method path route database role label success fallback`, with `-` for absent
+///values.
+///Each request's decisions are flushed before the response is sent.
+pub struct FileRoutingSink {
+    out: Mutex<File>,
+}
+impl FileRoutingSink {
+    ///Opens `path` for appending, creating it if necessary. Existing records are never truncated.
+    pub fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(FileRoutingSink{out: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)})
+    }
+}
+#[rocket::async_trait]
+impl RoutingSink for FileRoutingSink {
+    async fn write(&self, decisions: &[RoutingDecision]) -> Result<(), BoxError> {
+        let mut lines = String::new();
+        for d in decisions {
+            let micros = d.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros();
+            let role = match d.role {
+                PoolRole::Main => "main",
+                PoolRole::Read => "read",
+                PoolRole::Delayed => "delayed",
+            };
+            let fallback = d.fallback.map_or("-", FallbackReason::name);
+            lines += &format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n", micros, escape(&d.method), escape(&d.path),
+                d.route.as_deref().map_or("-".into(), escape), escape(&d.database), role,
+                d.label.as_deref().map_or("-".into(), escape), d.success, fallback);
+        }
+        //One write per request so concurrent requests' lines don't interleave
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        out.write_all(lines.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+///A fairing which writes the [`RequestRouting`] of every request to a [`RoutingSink`].
+///
+///Requests which acquired no connection from this crate's guards aren't logged. Failures to
+///write are logged as errors; the response is still sent.
+///```rust,no_run
+/// use rocket_read_db_pools::{FileRoutingSink, RoutingLog};
+///
+/// let sink = FileRoutingSink::open("routing.log").expect("open routing log");
+/// let rocket = rocket::build().attach(RoutingLog::new(sink));
+///```
+pub struct RoutingLog {
+    sink: Arc<dyn RoutingSink>,
+}
+impl RoutingLog {
+    pub fn new<S: RoutingSink>(sink: S) -> Self {
+        RoutingLog{sink: Arc::new(sink)}
+    }
+}
+#[rocket::async_trait]
+impl Fairing for RoutingLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Routing Log",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, _res: &mut Response<'r>) {
+        let acquisitions = RequestRouting::of(req).acquisitions();
+        if acquisitions.is_empty() {
+            return;
+        }
+        let timestamp = SystemTime::now();
+        let route = req.route().and_then(|r| r.name.as_deref()).map(str::to_string);
+        let decisions: Vec<_> = acquisitions.into_iter().map(|a| RoutingDecision{
+            timestamp,
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            route: route.clone(),
+            database: a.database.to_string(),
+            role: a.role,
+            label: a.label.map(|l| l.to_string()),
+            success: a.success,
+            fallback: a.fallback,
+        }).collect();
+        if let Err(e) = self.sink.write(&decisions).await {
+            rocket::error!("failed to write routing decisions for {} {}: {}", req.method(), req.uri(), e);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//!An ordered pipeline of decorators applied to connections from each pool
+use std::ops::{Deref, DerefMut};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use crate::{BoxError, ContextError, PoolRole, RequestRouting, RoleSwitch, SessionContext, SessionRole, SessionVariables};
+
+///A step of a [`ConnectionPipeline`], preparing a connection of type `C` for a request before
+///the handler runs, e.g. setting session state or tagging it for instrumentation.
+///
+///[`SessionContext`], [`RoleSwitch`] and [`ReadOnly`] are decorators, so their features can be
+///combined in one pipeline in a chosen order.
+#[rocket::async_trait]
+pub trait ConnectionDecorator<C: Send + ?Sized>: Send + Sync {
+    ///Decorates `conn`, just acquired for `req`. [`RequestRouting::last`] tells which pool it
+    ///came from.
+    async fn decorate(&self, req: &Request<'_>, conn: &mut C) -> Result<(), BoxError>;
+}
+
+#[rocket::async_trait]
+impl<C: SessionVariables + ?Sized> ConnectionDecorator<C> for SessionContext {
+    async fn decorate(&self, req: &Request<'_>, conn: &mut C) -> Result<(), BoxError> {
+        self.apply(req, conn).await
+    }
+}
+
+///Switches the connection to the request's role, or resets it if there is none. Unlike
+///[`WithRole`](crate::WithRole), the role isn't reset when the connection is returned, so every
+///pipeline using the pool should include the switch.
+#[rocket::async_trait]
+impl<C: SessionRole + ?Sized> ConnectionDecorator<C> for RoleSwitch {
+    async fn decorate(&self, req: &Request<'_>, conn: &mut C) -> Result<(), BoxError> {
+        let role = RequestRouting::of(req).last().and_then(|a| self.role_for(req, a.database, a.role));
+        match role {
+            Some(role) => conn.set_role(role).await,
+            None => conn.reset_role().await,
+        }
+    }
+}
+
+///A decorator making the session read-only with [`SessionVariables::set_read_only`], usually
+///added for [`PoolRole::Read`] alone with [`ConnectionPipeline::then_on`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOnly;
+#[rocket::async_trait]
+impl<C: SessionVariables + ?Sized> ConnectionDecorator<C> for ReadOnly {
+    async fn decorate(&self, _req: &Request<'_>, conn: &mut C) -> Result<(), BoxError> {
+        conn.set_read_only().await
+    }
+}
+
+///Managed state listing the [`ConnectionDecorator`]s applied in order by the [`Decorated`]
+///guard to connections of type `C`, each to every pool or to one pool role.
+///```rust
+/// use rocket::Request;
+/// use rocket_read_db_pools::{BoxError, ConnectionDecorator, ConnectionPipeline, PoolRole, ReadOnly, SessionContext};
+/// # use rocket_read_db_pools::testing::MockPoolConnection;
+///
+/// type Conn = MockPoolConnection;
+///
+/// struct ApplicationName;
+/// #[rocket::async_trait]
+/// impl ConnectionDecorator<Conn> for ApplicationName {
+///     async fn decorate(&self, req: &Request<'_>, conn: &mut Conn) -> Result<(), BoxError> {
+///         conn.query("SELECT set_config('application_name', $1, false)")
+///             .bind(req.uri().path().as_str()).execute().await?;
+///         Ok(())
+///     }
+/// }
+///
+/// # fn _f() {
+/// let pipeline = ConnectionPipeline::<Conn>::new()
+///     .then(SessionContext::new().variable("app.tenant_id", |req| req.headers().get_one("X-Tenant").map(str::to_string)))
+///     .then_on(PoolRole::Read, ReadOnly)
+///     .then(ApplicationName);
+/// let rocket = rocket::build().manage(pipeline);
+/// # }
+///```
+pub struct ConnectionPipeline<C: Send + ?Sized + 'static> {
+    stages: Vec<(Option<PoolRole>, Box<dyn ConnectionDecorator<C>>)>,
+}
+impl<C: Send + ?Sized + 'static> Default for ConnectionPipeline<C> {
+    fn default() -> Self {
+        ConnectionPipeline{stages: Vec::new()}
+    }
+}
+impl<C: Send + ?Sized + 'static> ConnectionPipeline<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Appends `decorator`, applied to connections from every pool
+    pub fn then(mut self, decorator: impl ConnectionDecorator<C> + 'static) -> Self {
+        self.stages.push((None, Box::new(decorator)));
+        self
+    }
+
+    ///Appends `decorator`, applied only to connections from pools with the role `pool`
+    pub fn then_on(mut self, pool: PoolRole, decorator: impl ConnectionDecorator<C> + 'static) -> Self {
+        self.stages.push((Some(pool), Box::new(decorator)));
+        self
+    }
+
+    ///Applies each decorator in order to `conn`, whose pool is taken from the request's latest
+    ///acquisition. Decorators of one pool role are skipped if that's unknown. Stops at the first
+    ///error.
+    pub async fn apply(&self, req: &Request<'_>, conn: &mut C) -> Result<(), BoxError> {
+        let role = RequestRouting::of(req).last().map(|a| a.role);
+        for (pool, decorator) in &self.stages {
+            if pool.is_none_or(|pool| Some(pool) == role) {
+                decorator.decorate(req, conn).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///A request guard wrapping a connection guard (`Decorated<ReadConnection<Db>>`,
+///`Decorated<RwConnection<Db>>`, ...) which runs the managed [`ConnectionPipeline`] for its
+///connection type before the handler runs.
+///
+///If no such pipeline is managed the connection is passed through unchanged.
+pub struct Decorated<G>(pub G);
+impl<G> Decorated<G> {
+    ///Gets the wrapped guard
+    pub fn into_inner(self) -> G {
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, G> FromRequest<'r> for Decorated<G>
+    where G: FromRequest<'r> + DerefMut + Send, G::Target: Send + 'static
+{
+    type Error = ContextError<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut guard = match G::from_request(req).await {
+            Outcome::Success(guard) => guard,
+            Outcome::Error((status, e)) => return Outcome::Error((status, ContextError::Guard(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        if let Some(pipeline) = req.rocket().state::<ConnectionPipeline<G::Target>>() {
+            if let Err(e) = pipeline.apply(req, &mut *guard).await {
+                return Outcome::Error((Status::InternalServerError, ContextError::Apply(e)));
+            }
+        }
+        Outcome::Success(Decorated(guard))
+    }
+}
+impl<G: Deref> Deref for Decorated<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<G: DerefMut> DerefMut for Decorated<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,226 @@
+//!Discovery of the read replicas from DNS SRV records or an application's hook
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{interval, timeout, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{dns, keys, plan, BoxError, HealthRegistry, PoolRole, ReadPool};
+#[cfg(feature = "discovery-dns")]
+use crate::SrvDiscovery;
+
+///A read replica found by a [`ReplicaDiscovery`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DiscoveredReplica {
+    ///Name of the replica in logs and [`ReplicaStatus`](crate::ReplicaStatus), which must stay
+    ///the same while the replica does
+    pub name: String,
+    ///Host replacing the one in the `read` table's `url`
+    pub host: String,
+    ///Port replacing the one in the `read` table's `url`
+    pub port: u16,
+    ///Share of reads the replica takes, as its `weight` option
+    pub weight: u32,
+}
+
+///Finds the current read replicas, for [`ReadDiscovery`], e.g. from a cloud provider's API such
+///as RDS's `DescribeDBClusters`. With the `discovery-dns` feature, `SrvDiscovery` looks them up
+///in DNS.
+///```rust
+/// use rocket_read_db_pools::{BoxError, DiscoveredReplica, ReplicaDiscovery};
+///
+/// struct ClusterReaders;
+/// #[rocket::async_trait]
+/// impl ReplicaDiscovery for ClusterReaders {
+///     async fn discover(&self) -> Result<Vec<DiscoveredReplica>, BoxError> {
+///         // e.g. the endpoints of the cluster's members which aren't its writer
+///         let endpoints = [("reader-1", "reader-1.abc.eu-west-1.rds.amazonaws.com")];
+///         Ok(endpoints.iter().map(|(name, host)| DiscoveredReplica{
+///             name: name.to_string(),
+///             host: host.to_string(),
+///             port: 5432,
+///             weight: 1,
+///         }).collect())
+///     }
+/// }
+///```
+#[rocket::async_trait]
+pub trait ReplicaDiscovery: Send + Sync + 'static {
+    ///The read replicas there are now
+    async fn discover(&self) -> Result<Vec<DiscoveredReplica>, BoxError>;
+}
+
+///Configuration of replica discovery: the `databases.<name>.read.discovery` table.
+///```toml
+///[default.databases.main.read]
+///url = "postgresql://app@replicas.example/dbname"
+///discovery = {srv = "_postgresql._tcp.replicas.example", interval_ms = 30000}
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct DiscoveryConfig {
+    ///`srv`: name of the SRV record listing the replicas, looked up by [`ReadDiscovery::new`]
+    ///with the `discovery-dns` feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub srv: Option<String>,
+    ///`nameserver`: address of the DNS server to query, as `ip` or `ip:port`. Defaults to the
+    ///first in `/etc/resolv.conf`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nameserver: Option<String>,
+    ///`interval_ms`: time between discoveries. Defaults to 30000.
+    pub interval_ms: u64,
+    ///`timeout_ms`: time a discovery may take before it's abandoned until the next. Defaults
+    ///to 5000.
+    pub timeout_ms: u64,
+}
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig{srv: None, nameserver: None, interval_ms: 30000, timeout_ms: 5000}
+    }
+}
+
+impl<P, R: Pool + Clone> ReadPool<P, R> {
+    ///Makes the read replicas those of `discovered`, each created from `template` with its url's
+    ///host and port replaced, adding new ones, replacing those whose weight changed and removing
+    ///the rest. An empty discovery is ignored rather than removing every replica.
+    async fn apply_discovered(&self, discovered: Vec<DiscoveredReplica>, template: &Figment) {
+        let label = self.label(PoolRole::Read);
+        if discovered.is_empty() {
+            db_log!(self.log, General, Warn, "`{}` pool: no replicas discovered, keeping the current ones", label);
+            return;
+        }
+        let Ok(url) = template.extract_inner::<String>(keys::URL) else {
+            db_log!(self.log, General, Error, "`{}` pool: discovery needs a `read.url` to substitute replicas into", label);
+            return;
+        };
+        let set = self.replica_set();
+        for replica in &discovered {
+            let current = set.position(&replica.name).map(|i| set.weights[i]);
+            if current == Some(replica.weight) {
+                continue;
+            }
+            let Some(url) = dns::with_host(&url, &replica.host, replica.port) else {
+                db_log!(self.log, General, Error, "`{}` pool: discovery needs a single host in `read.url`", label);
+                return;
+            };
+            let figment = template.clone().merge((keys::URL, url)).merge(("weight", replica.weight));
+            if let Err(e) = self.add_replica(&replica.name, &figment).await {
+                db_log!(self.log, General, Error, "`{}` pool: failed to add discovered replica `{}`: {}", label, replica.name, e);
+            }
+        }
+        let names: HashSet<&str> = discovered.iter().map(|r| r.name.as_str()).collect();
+        for gone in set.replicas.iter().filter(|r| !names.contains(&*r.name)) {
+            self.remove_replica(&gone.name).await;
+        }
+    }
+}
+
+///A fairing which keeps the read replicas of the database `D` in line with a
+///[`ReplicaDiscovery`] every `read.discovery.interval_ms` from launch until shutdown, adding
+///and removing pools as replicas appear and disappear. See [`DiscoveryConfig`].
+///
+///Discovered replicas are created from the `read` table, with the host and port of its `url`
+///replaced by theirs. Replicas configured statically, including the `read` table's own, are
+///removed by the first discovery unless they're found by it, so the `read` table's url can
+///point at a reader endpoint serving until then. A failed or empty discovery keeps the
+///replicas as they are.
+///
+///Attach it after `D::init()`. The read pool type must be `Clone`, and replica states are
+///published to the managed [`HealthRegistry`], if any.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{ReadDiscovery, ReadPool};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// # fn _f() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadDiscovery::<Db>::new())
+/// # }
+///```
+pub struct ReadDiscovery<D> {
+    discovery: Option<Arc<dyn ReplicaDiscovery>>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ReadDiscovery<D> {
+    ///Discovers the replicas from the SRV record named by `read.discovery.srv`
+    pub fn new() -> Self {
+        ReadDiscovery{discovery: None, _db: PhantomData}
+    }
+
+    ///Discovers the replicas with `discovery` instead of `read.discovery.srv`
+    pub fn with<T: ReplicaDiscovery>(discovery: T) -> Self {
+        ReadDiscovery{discovery: Some(Arc::new(discovery)), _db: PhantomData}
+    }
+}
+impl<D> Default for ReadDiscovery<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadDiscovery<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Discovery",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadDiscovery` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let figment = plan::with_read_urls(&rocket.figment().focus(&format!("databases.{}", D::NAME)));
+        let config = figment.extract_inner::<DiscoveryConfig>(keys::READ_DISCOVERY).unwrap_or_default();
+        let discovery = match (&self.discovery, &config.srv) {
+            (Some(discovery), _) => discovery.clone(),
+            #[cfg(feature = "discovery-dns")]
+            (None, Some(srv)) => match SrvDiscovery::from_config(srv, &config) {
+                Ok(discovery) => Arc::new(discovery),
+                Err(e) => return db_log!(pool.log, General, Error, "database `{}`: replica discovery off: {}", D::NAME, e),
+            },
+            #[cfg(not(feature = "discovery-dns"))]
+            (None, Some(_)) => return db_log!(pool.log, General, Error, "database `{}`: `{}.srv` needs the `discovery-dns` feature, replica discovery off", D::NAME, keys::READ_DISCOVERY),
+            (None, None) => return db_log!(pool.log, General, Warn, "database `{}`: `{}.srv` not set, replica discovery off", D::NAME, keys::READ_DISCOVERY),
+        };
+        let Some(template) = plan::read_figment(&figment) else {
+            return db_log!(pool.log, General, Warn, "database `{}`: no `read` table, replica discovery off", D::NAME);
+        };
+        let registry = rocket.state::<HealthRegistry>().cloned();
+        let patience = Duration::from_millis(config.timeout_ms);
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(Duration::from_millis(config.interval_ms).max(Duration::from_millis(1)));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                match timeout(patience, discovery.discover()).await {
+                    Ok(Ok(discovered)) => pool.apply_discovered(discovered, &template).await,
+                    Ok(Err(e)) => db_log!(pool.log, General, Warn, "database `{}`: replica discovery failed: {}", D::NAME, e),
+                    Err(_) => db_log!(pool.log, General, Warn, "database `{}`: replica discovery timed out after {:?}", D::NAME, patience),
+                }
+                if let Some(ref registry) = registry {
+                    registry.update(D::NAME, &pool);
+                }
+            }
+        });
+    }
+}
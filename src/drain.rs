@@ -0,0 +1,244 @@
+//!Closing a `ReadPool`'s pools at shutdown within per-pool deadlines, after a grace period
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::future::join_all;
+use rocket::tokio::join;
+use rocket::tokio::time::{sleep_until, timeout, Instant};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::record::Recorder;
+use crate::{PoolRole, PoolUsage, ReadPool};
+
+///Shutdown of a `ReadPool` and its clones, begun by [`ReadShutdown`]
+#[derive(Debug, Default)]
+pub(crate) struct Stopping {
+    begun: AtomicBool,
+    ///When the grace period ends, until which closing the pools waits
+    grace_until: Mutex<Option<Instant>>,
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Whether shutdown has begun, which [`DbHealthRoute`](crate::DbHealthRoute) reports as not
+    ///ready. The pools stay usable until they're closed.
+    pub fn shutting_down(&self) -> bool {
+        self.stopping.begun.load(Ordering::Relaxed)
+    }
+
+    ///Begins shutdown, delaying the pools' closing until `grace` has passed
+    fn begin_shutdown(&self, grace: Duration) {
+        *self.stopping.grace_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + grace);
+        self.stopping.begun.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<P: Pool, R: Pool> ReadPool<P, R> {
+    ///Closes every pool concurrently, each waiting up to its `drain_timeout_ms` for the
+    ///connections in use to be returned, once any grace period begun by [`ReadShutdown`] has
+    ///passed. `main_in_use` and `read_in_use` count those abandoned, if known.
+    pub(crate) async fn close_within(&self, main_in_use: impl Fn(&P) -> Option<u32>, read_in_use: impl Fn(&R) -> Option<u32>) {
+        let grace_until = *self.stopping.grace_until.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(grace_until) = grace_until {
+            sleep_until(grace_until).await;
+        }
+        if let Some(pending) = self.pending.as_ref() {
+            pending.stop();
+        }
+        let set = self.replica_set();
+        let main = join_all([Some(&self.main), self.standby.as_ref()].into_iter().flatten()
+            .map(|pool| self.close_pool(pool, &self.labels.main, self.drain_timeouts.main, &main_in_use)));
+        let labels: Vec<_> = (0..set.len()).map(|i| set.label(&self.labels.read, i)).collect();
+        let read = join_all(set.pools.iter().zip(&labels)
+            .map(|(pool, label)| self.close_pool(pool, label, self.drain_timeouts.read, &read_in_use)));
+        let delayed = join_all(self.delayed.iter()
+            .map(|pool| self.close_pool(pool, &self.labels.delayed, self.drain_timeouts.delayed, &read_in_use)));
+        join!(main, read, delayed);
+        if let Some(Err(e)) = self.recorder.as_deref().map(Recorder::flush) {
+            rocket::error!("failed to flush read query recording: {}", e);
+        }
+    }
+
+    ///Closes `pool`, abandoning its connections still in use after `deadline`
+    async fn close_pool<T: Pool>(&self, pool: &T, label: &str, deadline: Option<Duration>, in_use: impl Fn(&T) -> Option<u32>) {
+        let Some(deadline) = deadline else {return pool.close().await};
+        if timeout(deadline, pool.close()).await.is_err() {
+            match in_use(pool) {
+                Some(n) => db_log!(self.log, General, Warn, "`{}` pool closed with {} connections still in use after {:?}", label, n, deadline),
+                None => db_log!(self.log, General, Warn, "`{}` pool closed with connections still in use after {:?}", label, deadline),
+            }
+        }
+    }
+}
+
+impl<P: PoolUsage, R: PoolUsage> ReadPool<P, R> {
+    ///Closes the pools as `Pool::close` does, each waiting up to its `drain_timeout_ms` for the
+    ///connections in use to be returned, and logs how many each waits for and abandons.
+    ///[`ReadDrain`] calls this at shutdown.
+    pub async fn drain(&self) {
+        let set = self.replica_set();
+        let waiting = [
+            (self.label(PoolRole::Main), self.primary().in_use()),
+            (self.label(PoolRole::Read), set.pools.iter().map(PoolUsage::in_use).sum()),
+        ].into_iter().chain(self.delayed.as_ref().map(|d| (self.label(PoolRole::Delayed), d.in_use())));
+        for (label, in_use) in waiting.filter(|&(_, in_use)| in_use > 0) {
+            db_log!(self.log, General, Info, "`{}` pool draining {} connections in use", label, in_use);
+        }
+        self.close_within(|p| Some(p.in_use()), |r| Some(r.in_use())).await;
+    }
+}
+
+///A fairing which drains the pools of the database `D` when shutdown begins with
+///[`ReadPool::drain`]: new acquisitions fail as the pools close, while connections in use get
+///until each pool's `drain_timeout_ms` to be returned before they're abandoned.
+///
+///`rocket_db_pools` closes the pools at shutdown too, within the same deadlines; this adds
+///logging of the connections each pool waits for and abandons, so long-running queries cut
+///short on deploy show up.
+///```toml
+///[default.databases.main]
+///drain_timeout_ms = 5000
+///[default.databases.main.read]
+///drain_timeout_ms = 30000
+///```
+///Attach it after `D::init()`.
+pub struct ReadDrain<D>(PhantomData<fn() -> D>);
+impl<D> ReadDrain<D> {
+    pub fn new() -> Self {
+        ReadDrain(PhantomData)
+    }
+}
+impl<D> Default for ReadDrain<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadDrain<D> where D: Database<Pool = ReadPool<P, R>>, P: PoolUsage, R: PoolUsage {
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Pool Drain",
+            kind: Kind::Ignite | Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadDrain` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        if let Some(db) = D::fetch(rocket) {
+            db.drain().await;
+        }
+    }
+}
+
+///A fairing which shuts the database `D` down gracefully when Rocket's shutdown begins, e.g.
+///on `SIGTERM`: [`ReadPool::shutting_down`] turns true at once, so
+///[`DbHealthRoute`](crate::DbHealthRoute) responds `503 Service Unavailable` and load balancers
+///stop sending traffic, while the pools stay usable for requests in flight until the grace
+///period ends. The pools are then closed as [`ReadDrain`] closes them.
+///
+///The grace period defaults to Rocket's `shutdown.grace`, as Rocket stops waiting for requests
+///in flight after it anyway. `rocket_db_pools` closing the pools at shutdown waits for it too.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use std::time::Duration;
+/// use rocket_read_db_pools::ReadShutdown;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadShutdown::<Db>::new().grace(Duration::from_secs(10)))
+/// # }
+///```
+///Attach it after `D::init()`.
+pub struct ReadShutdown<D> {
+    grace: Option<Duration>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ReadShutdown<D> {
+    pub fn new() -> Self {
+        ReadShutdown{grace: None, _db: PhantomData}
+    }
+
+    ///Time the pools stay usable after shutdown begins, instead of Rocket's `shutdown.grace`
+    pub fn grace(mut self, grace: Duration) -> Self {
+        self.grace = Some(grace);
+        self
+    }
+}
+impl<D> Default for ReadShutdown<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadShutdown<D> where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool {
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Pool Shutdown",
+            kind: Kind::Ignite | Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadShutdown` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let grace = self.grace.unwrap_or_else(|| Duration::from_secs(rocket.config().shutdown.grace as u64));
+        db_log!(db.log, General, Info, "database `{}` shutting down, closing its pools in {:?}", D::NAME, grace);
+        db.begin_shutdown(grace);
+        db.close_within(|_| None, |_| None).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::time::sleep;
+    use crate::testing::{pair_rocket_figment, MockPool};
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    #[rocket::async_test]
+    async fn shutdown_reports_not_ready_and_closes_after_the_grace_period() {
+        let grace = Duration::from_millis(200);
+        let rocket = rocket::custom(pair_rocket_figment("db", "main", "read"))
+            .attach(Db::init())
+            .attach(ReadShutdown::<Db>::new().grace(grace));
+        let client = Client::tracked(rocket).await.unwrap();
+        let pool = (**Db::fetch(client.rocket()).unwrap()).clone();
+        assert!(!pool.shutting_down());
+        assert!(pool.health("db", Duration::from_secs(1)).await.ready);
+
+        let start = Instant::now();
+        let terminated = rocket::tokio::spawn(client.terminate());
+        sleep(Duration::from_millis(50)).await;
+        assert!(pool.shutting_down());
+        let health = pool.health("db", Duration::from_secs(1)).await;
+        assert!(health.shutting_down && !health.ready);
+        assert!(pool.get().await.is_ok());
+        assert!(!terminated.is_finished());
+        terminated.await.unwrap();
+        assert!(start.elapsed() >= grace);
+    }
+}
@@ -0,0 +1,100 @@
+//!Figment provider for conventional database environment variables
+use rocket::figment::{Error, Metadata, Profile, Provider};
+use rocket::figment::value::{Dict, Map, Value};
+use crate::keys;
+
+///Maps the conventional `DATABASE_URL`, `DATABASE_READ_URL` and `DATABASE_READ_URLS`
+///environment variables onto a database's config, for 12-factor style deployments.
+///
+///Values are global, so override any profile in Rocket.toml. `DATABASE_READ_URLS` is a
+///comma-separated list of replicas, set as `read_urls` when `DATABASE_READ_URL` isn't set; as
+///with `read_urls`, a `read` table in Rocket.toml takes precedence over it.
+///```rust
+/// use rocket_read_db_pools::ConventionalEnv;
+///
+/// let figment = rocket::Config::figment().merge(ConventionalEnv::new("main"));
+/// let rocket = rocket::custom(figment);
+///```
+#[derive(Debug, Clone)]
+pub struct ConventionalEnv {
+    database: String,
+    prefix: String,
+}
+impl ConventionalEnv {
+    ///Maps the variables onto `databases.<database>`
+    pub fn new(database: &str) -> Self {
+        ConventionalEnv{database: database.to_string(), prefix: "DATABASE".to_string()}
+    }
+
+    ///Uses `<prefix>_URL` etc. instead of `DATABASE_URL`, e.g. for apps with several databases
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    fn var(&self, suffix: &str) -> Option<String> {
+        std::env::var(format!("{}_{}", self.prefix, suffix)).ok().filter(|v| !v.is_empty())
+    }
+
+    fn read_urls(&self) -> Option<Vec<String>> {
+        let urls = self.var("READ_URLS")?;
+        let urls: Vec<String> = urls.split(',').map(str::trim).filter(|u| !u.is_empty()).map(str::to_string).collect();
+        Some(urls).filter(|urls| !urls.is_empty())
+    }
+}
+impl Provider for ConventionalEnv {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(format!("`{}_*` environment variables", self.prefix))
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        let mut database = Dict::new();
+        if let Some(url) = self.var("URL") {
+            database.insert("url".into(), url.into());
+        }
+        if let Some(url) = self.var("READ_URL") {
+            let read = Dict::from([("url".to_string(), Value::from(url))]);
+            database.insert("read".into(), read.into());
+        } else if let Some(urls) = self.read_urls() {
+            database.insert(keys::READ_URLS.into(), urls.into());
+        }
+        let mut dict = Dict::new();
+        if !database.is_empty() {
+            let databases = Dict::from([(self.database.clone(), Value::from(database))]);
+            dict.insert("databases".into(), databases.into());
+        }
+        Ok(Map::from([(Profile::Global, dict)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::Figment;
+    use rocket_db_pools::Pool;
+    use crate::testing::MockPool;
+    use crate::ReadPool;
+    use super::*;
+
+    #[rocket::async_test]
+    async fn read_urls_lists_every_replica() {
+        std::env::set_var("ENV_TEST_URL", "main");
+        std::env::set_var("ENV_TEST_READ_URLS", "replica-1, replica-2,,replica-3");
+        let figment = Figment::from(ConventionalEnv::new("db").prefix("ENV_TEST"));
+        let urls: Vec<String> = figment.extract_inner("databases.db.read_urls").unwrap();
+        assert_eq!(urls, ["replica-1", "replica-2", "replica-3"]);
+        assert!(!figment.contains("databases.db.read"));
+
+        let pool: ReadPool<MockPool> = Pool::init(&figment.focus("databases.db")).await.unwrap();
+        let read: Vec<_> = pool.stats().read.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(read.len(), 3);
+    }
+
+    #[test]
+    fn read_url_takes_precedence() {
+        std::env::set_var("ENV_BOTH_READ_URL", "replica");
+        std::env::set_var("ENV_BOTH_READ_URLS", "replica-1,replica-2");
+        let figment = Figment::from(ConventionalEnv::new("db").prefix("ENV_BOTH"));
+        assert_eq!(figment.extract_inner::<String>("databases.db.read.url").unwrap(), "replica");
+        assert!(!figment.contains("databases.db.read_urls"));
+    }
+}
@@ -0,0 +1,152 @@
+//!The error of this crate's connection guards, telling which pool failed
+use std::fmt;
+use std::time::Duration;
+use rocket::http::Status;
+use crate::{AcquireError, FailureKind, PoolRole};
+
+///Why a connection guard such as [`ReadConnection`](crate::ReadConnection) or
+///[`RwConnection`](crate::RwConnection) failed, with the pool's error `E` if it provided one.
+///
+///Catchers only see the status, and the failure recorded in [`RequestRouting::failure`](crate::RequestRouting::failure),
+///so handlers taking `Result<ReadConnection<Db>, ReadPoolError<_>>` can tell a failing replica
+///from a failing main pool:
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool};
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::{ReadConnection, ReadPoolError};
+///
+/// #[rocket::get("/")]
+/// fn index(conn: Result<ReadConnection<Db>, ReadPoolError<MockError>>) -> String {
+///     match conn {
+///         Ok(_) => "ok".to_string(),
+///         Err(ReadPoolError::ReplicaUnavailable{replica, ..}) => format!("replica {} is down", replica),
+///         Err(e) => e.to_string(),
+///     }
+/// }
+///```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReadPoolError<E> {
+    ///The main pool failed to provide a connection
+    MainUnavailable(E),
+    ///A read replica, or the delayed replica, failed to provide a connection
+    ReplicaUnavailable {
+        ///The label of the pool which failed
+        replica: String,
+        error: E,
+    },
+    ///The database's fairing isn't attached
+    DatabaseNotAttached,
+    ///No connection was acquired within the time allowed, by a
+    ///[`WithTimeout`](crate::WithTimeout) guard (see [`ReadPoolError::from`]) or the request's
+    ///[`RequestDeadline`](crate::RequestDeadline)
+    Timeout(Duration),
+    ///The guard needs a pool with that role and none is configured
+    Unconfigured(PoolRole),
+    ///The database is in read-only mode and refused a write, see
+    ///[`ReadOnlySurvival`](crate::ReadOnlySurvival)
+    ReadOnly,
+    ///A failure of the pool with that role was injected by the `testing` feature's
+    ///`FailureInjector`
+    #[cfg(feature = "testing")]
+    Injected(PoolRole),
+    ///The request named a shard of a [`ShardedReadPool`](crate::ShardedReadPool) which isn't
+    ///configured
+    UnknownShard(String),
+    ///The request named no shard of a sharded [`ShardedReadPool`](crate::ShardedReadPool), such
+    ///as no tenant, and it has no `default_shard`
+    MissingShard,
+    ///The request's low-priority read was shed while too few replicas were healthy, see
+    ///[`BelowMinHealthy::Shed`](crate::BelowMinHealthy::Shed)
+    Shed,
+}
+impl<E> ReadPoolError<E> {
+    ///The error of a failed acquisition from the pool with the role `role` and label `label`
+    pub(crate) fn acquisition(role: PoolRole, label: &str, error: E) -> Self {
+        match role {
+            PoolRole::Main => ReadPoolError::MainUnavailable(error),
+            PoolRole::Read | PoolRole::Delayed => ReadPoolError::ReplicaUnavailable{replica: label.to_string(), error},
+        }
+    }
+
+    ///The status a guard fails with unless [`FailureResponses`](crate::FailureResponses) sets
+    ///another: `500 Internal Server Error` for misconfiguration, `404 Not Found` for an unknown
+    ///shard, `400 Bad Request` for a missing one, otherwise `503 Service Unavailable`
+    pub fn status(&self) -> Status {
+        match self {
+            ReadPoolError::DatabaseNotAttached | ReadPoolError::Unconfigured(_) => Status::InternalServerError,
+            ReadPoolError::UnknownShard(_) => Status::NotFound,
+            ReadPoolError::MissingShard => Status::BadRequest,
+            _ => Status::ServiceUnavailable,
+        }
+    }
+
+    ///What made the guard fail, by which [`FailureResponses`](crate::FailureResponses) sets its
+    ///status
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            ReadPoolError::MainUnavailable(_) => FailureKind::MainUnavailable,
+            ReadPoolError::ReplicaUnavailable{..} => FailureKind::ReplicaUnavailable,
+            #[cfg(feature = "testing")]
+            ReadPoolError::Injected(PoolRole::Main) => FailureKind::MainUnavailable,
+            #[cfg(feature = "testing")]
+            ReadPoolError::Injected(_) => FailureKind::ReplicaUnavailable,
+            ReadPoolError::DatabaseNotAttached | ReadPoolError::Unconfigured(_) => FailureKind::Misconfigured,
+            ReadPoolError::Timeout(_) => FailureKind::Timeout,
+            ReadPoolError::ReadOnly => FailureKind::ReadOnly,
+            ReadPoolError::UnknownShard(_) => FailureKind::UnknownShard,
+            ReadPoolError::MissingShard => FailureKind::MissingShard,
+            ReadPoolError::Shed => FailureKind::Shed,
+        }
+    }
+
+    ///The pool's own error, if it failed
+    pub fn pool_error(&self) -> Option<&E> {
+        match self {
+            ReadPoolError::MainUnavailable(e) | ReadPoolError::ReplicaUnavailable{error: e, ..} => Some(e),
+            _ => None,
+        }
+    }
+
+    ///Converts into the pool's own error, if it failed, as guards reported errors before
+    pub fn into_pool_error(self) -> Option<E> {
+        match self {
+            ReadPoolError::MainUnavailable(e) | ReadPoolError::ReplicaUnavailable{error: e, ..} => Some(e),
+            _ => None,
+        }
+    }
+}
+impl<E: fmt::Display> fmt::Display for ReadPoolError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadPoolError::MainUnavailable(e) => write!(f, "main pool unavailable: {}", e),
+            ReadPoolError::ReplicaUnavailable{replica, error} => write!(f, "`{}` pool unavailable: {}", replica, error),
+            ReadPoolError::DatabaseNotAttached => f.write_str("database not attached"),
+            ReadPoolError::Timeout(t) => write!(f, "timed out acquiring a connection after {:?}", t),
+            ReadPoolError::Unconfigured(role) => write!(f, "no {:?} pool is configured", role),
+            ReadPoolError::ReadOnly => f.write_str("database is in read-only mode"),
+            #[cfg(feature = "testing")]
+            ReadPoolError::Injected(role) => write!(f, "failure injected into the {:?} pool", role),
+            ReadPoolError::UnknownShard(shard) => write!(f, "unknown shard `{}`", shard),
+            ReadPoolError::MissingShard => f.write_str("no shard named by the request, and no default shard"),
+            ReadPoolError::Shed => f.write_str("low-priority read shed while too few replicas are healthy"),
+        }
+    }
+}
+impl<E: std::error::Error + 'static> std::error::Error for ReadPoolError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.pool_error().map(|e| e as _)
+    }
+}
+///Flattens the error of a [`WithTimeout`](crate::WithTimeout) guard around a connection guard
+impl<E> From<AcquireError<ReadPoolError<E>>> for ReadPoolError<E> {
+    fn from(e: AcquireError<ReadPoolError<E>>) -> Self {
+        match e {
+            AcquireError::Timeout(t) => ReadPoolError::Timeout(t),
+            AcquireError::Pool(e) => e,
+            AcquireError::Unconfigured(role) => ReadPoolError::Unconfigured(role),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//!Switching the main pool to a warm standby primary
+use std::sync::atomic::Ordering;
+use crate::logging::db_log;
+use crate::ReadPool;
+
+impl<P, R> ReadPool<P, R> {
+    ///Switches the main pool to the warm standby configured under `failover`, so writes (and
+    ///reads falling back to the main pool) go to the new primary without waiting for cold
+    ///connections. Returns `false` if no standby is configured.
+    ///
+    ///The switch lasts until the pool is re-initialized, and applies to all clones of the pool.
+    pub fn fail_over(&self) -> bool {
+        if self.standby.is_none() {
+            return false;
+        }
+        if !self.failed_over.swap(true, Ordering::SeqCst) {
+            db_log!(self.log, General, Warn, "`{}` pool failed over to standby primary", self.label(crate::PoolRole::Main));
+        }
+        true
+    }
+
+    ///Whether [`ReadPool::fail_over`] has switched the main pool to the standby
+    pub fn failed_over(&self) -> bool {
+        self.failed_over.load(Ordering::SeqCst)
+    }
+
+    ///The pool currently acting as the main pool: the standby once failed over. For writes
+    ///outside a request, e.g. from a background job or fairing.
+    pub fn main(&self) -> &P {
+        self.primary()
+    }
+
+    ///The pool currently acting as the main pool
+    pub(crate) fn primary(&self) -> &P {
+        match self.standby {
+            Some(ref standby) if self.failed_over() => standby,
+            _ => &self.main,
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//!Configurable responses of connection guards which fail
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use rocket::{Build, Request, Response, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::Outcome;
+use rocket::serde::{Deserialize, Serialize};
+use crate::{ReadPoolError, RequestRouting};
+
+///What made a connection guard fail, from [`ReadPoolError::kind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum FailureKind {
+    ///The main pool failed to provide a connection, e.g. because it's exhausted or down
+    MainUnavailable,
+    ///A read replica, or the delayed replica, failed to provide a connection, e.g. because
+    ///it's exhausted, quarantined or down and reads don't fall back to the main pool
+    ReplicaUnavailable,
+    ///No connection was acquired within the time allowed by a [`WithTimeout`](crate::WithTimeout)
+    ///guard or the request's [`RequestDeadline`](crate::RequestDeadline)
+    Timeout,
+    ///The database isn't attached, or the guard needs a pool which isn't configured
+    Misconfigured,
+    ///The database is in read-only mode and refused a write
+    ReadOnly,
+    ///The request named a shard which isn't configured
+    UnknownShard,
+    ///The request named no shard, such as no tenant, and there's no default shard
+    MissingShard,
+    ///The request's low-priority read was shed while too few replicas were healthy, see
+    ///[`BelowMinHealthy::Shed`](crate::BelowMinHealthy::Shed)
+    Shed,
+}
+impl FailureKind {
+    ///Every kind, in declaration order
+    pub const ALL: [FailureKind; 8] = [
+        FailureKind::MainUnavailable,
+        FailureKind::ReplicaUnavailable,
+        FailureKind::Timeout,
+        FailureKind::Misconfigured,
+        FailureKind::ReadOnly,
+        FailureKind::UnknownShard,
+        FailureKind::MissingShard,
+        FailureKind::Shed,
+    ];
+
+    ///The kind's name, as serialized, e.g. `"main_unavailable"`
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::MainUnavailable => "main_unavailable",
+            FailureKind::ReplicaUnavailable => "replica_unavailable",
+            FailureKind::Timeout => "timeout",
+            FailureKind::Misconfigured => "misconfigured",
+            FailureKind::ReadOnly => "read_only",
+            FailureKind::UnknownShard => "unknown_shard",
+            FailureKind::MissingShard => "missing_shard",
+            FailureKind::Shed => "shed",
+        }
+    }
+}
+
+///A failure of one of this crate's connection guards during a request, from
+///[`RequestRouting::failure`], e.g. for a catcher to render, which otherwise only sees the
+///status:
+///```rust
+/// use rocket::{catch, Request};
+/// use rocket_read_db_pools::RequestRouting;
+///
+/// #[catch(503)]
+/// fn unavailable(req: &Request<'_>) -> String {
+///     match RequestRouting::of(req).failure() {
+///         Some(failure) => format!("{} is unavailable ({}), retry in {:?}s",
+///             failure.database.unwrap_or("the database"), failure.kind.name(), failure.retry_after),
+///         None => "service unavailable".to_string(),
+///     }
+/// }
+///```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardFailure {
+    ///`Database::NAME` of the guard's database, if it was known
+    pub database: Option<&'static str>,
+    ///What made the guard fail
+    pub kind: FailureKind,
+    ///The status the guard failed with
+    pub status: Status,
+    ///Seconds sent in a `Retry-After` header by [`FailureResponses`], if any
+    pub retry_after: Option<u64>,
+    ///The guard's error, as displayed
+    pub message: String,
+}
+
+///A fairing setting the status connection guards fail with, and a `Retry-After` header for
+///responses with that status, by [`FailureKind`], e.g. to tell clients when to come back while
+///the main pool is exhausted or a replica is quarantined.
+///
+///Kinds left alone fail with [`ReadPoolError::status`]: `500 Internal Server Error` for
+///misconfiguration, `404 Not Found` for an unknown shard, otherwise `503 Service Unavailable`.
+///The failure is also recorded in [`RequestRouting::failure`] for catchers.
+///```rust
+/// use std::time::Duration;
+/// use rocket::http::Status;
+/// use rocket_read_db_pools::{FailureKind, FailureResponses};
+///
+/// let rocket = rocket::build()
+///     .attach(FailureResponses::new()
+///         .retry_after(FailureKind::MainUnavailable, Duration::from_secs(5))
+///         .retry_after(FailureKind::ReplicaUnavailable, Duration::from_secs(30))
+///         .status(FailureKind::Timeout, Status::GatewayTimeout));
+///```
+#[derive(Debug, Clone, Default)]
+pub struct FailureResponses {
+    statuses: HashMap<FailureKind, Status>,
+    retry_after: HashMap<FailureKind, u64>,
+}
+impl FailureResponses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Fails guards with `status` on failures of `kind`
+    pub fn status(mut self, kind: FailureKind, status: Status) -> Self {
+        self.statuses.insert(kind, status);
+        self
+    }
+
+    ///Sends `Retry-After: <wait>` with responses to failures of `kind`, rounded up to whole
+    ///seconds
+    pub fn retry_after(mut self, kind: FailureKind, wait: Duration) -> Self {
+        self.retry_after.insert(kind, wait.as_secs() + u64::from(wait.subsec_nanos() > 0));
+        self
+    }
+}
+#[rocket::async_trait]
+impl Fairing for FailureResponses {
+    fn info(&self) -> Info {
+        Info {
+            name: "Connection Failure Responses",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if rocket.state::<FailureResponses>().is_some() {
+            rocket::error!("`FailureResponses` is attached more than once");
+            return Err(rocket);
+        }
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(failure) = RequestRouting::of(req).failure() else {return};
+        if let Some(seconds) = failure.retry_after.filter(|_| res.status() == failure.status) {
+            if !res.headers().contains("Retry-After") {
+                res.set_header(Header::new("Retry-After", seconds.to_string()));
+            }
+        }
+    }
+}
+
+///Records the failure of a guard of the database `database` with `error` of the kind `kind`,
+///returning the status to fail with: the one [`FailureResponses`] sets, or else `status`
+pub(crate) fn respond(req: &Request<'_>, database: Option<&'static str>, kind: FailureKind, status: Status, error: &dyn fmt::Display) -> Status {
+    let responses = req.rocket().state::<FailureResponses>();
+    let status = responses.and_then(|r| r.statuses.get(&kind).copied()).unwrap_or(status);
+    let retry_after = responses.and_then(|r| r.retry_after.get(&kind).copied());
+    RequestRouting::of(req).fail(GuardFailure{database, kind, status, retry_after, message: error.to_string()});
+    status
+}
+
+///Fails a guard of the database `database` with `error`
+pub(crate) fn outcome<S, E: fmt::Display>(req: &Request<'_>, database: &'static str, error: ReadPoolError<E>) -> Outcome<S, ReadPoolError<E>> {
+    let status = respond(req, Some(database), error.kind(), error.status(), &error);
+    Outcome::Error((status, error))
+}
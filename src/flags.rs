@@ -0,0 +1,116 @@
+//!Routing driven by feature flags, evaluated per request
+use std::collections::HashMap;
+use rocket::Request;
+use rocket::figment::{self, Figment};
+use rocket::figment::value::Dict;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::Database;
+use crate::{keys, FallbackReason, Random, RoutingContext, RoutingPolicy};
+
+///Routing settings for the reads of one database during one request, from a
+///[`RoutingFlagProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RoutingFlags {
+    ///`replica`: whether reads may use the read replicas at all
+    pub replica: bool,
+    ///`read_percentage`: percentage of reads sent to the read replicas, the rest using the main
+    ///pool
+    pub read_percentage: u8,
+    ///`read_your_writes`: whether [`ReadYourWrites`](crate::ReadYourWrites) keeps reads on the
+    ///main pool after a write
+    pub read_your_writes: bool,
+}
+impl Default for RoutingFlags {
+    fn default() -> Self {
+        RoutingFlags{replica: true, read_percentage: 100, read_your_writes: true}
+    }
+}
+
+///Decides [`RoutingFlags`] per request, e.g. from a LaunchDarkly or Unleash client, so the
+///read/write split can be rolled out or switched off without a deploy.
+///
+///Register a provider by managing a [`RoutingFlagRegistry`]; without one, every flag is on. It
+///is consulted on each read acquisition by the request guards, so should answer from a local
+///cache rather than over the network.
+///```rust
+/// use rocket::Request;
+/// use rocket_read_db_pools::{RoutingFlagProvider, RoutingFlagRegistry, RoutingFlags};
+///
+/// struct BetaReplicas;
+/// impl RoutingFlagProvider for BetaReplicas {
+///     fn flags(&self, req: &Request<'_>, _database: &'static str) -> RoutingFlags {
+///         let beta = req.headers().get_one("X-Beta").is_some();
+///         RoutingFlags{replica: beta, ..Default::default()}
+///     }
+/// }
+///
+/// let rocket = rocket::build().manage(RoutingFlagRegistry::new(BetaReplicas));
+///```
+pub trait RoutingFlagProvider: Send + Sync + 'static {
+    ///The flags for reads of the database named `database` while serving `req`
+    fn flags(&self, req: &Request<'_>, database: &'static str) -> RoutingFlags;
+}
+
+///Flags fixed in configuration, as the `routing_flags` table of each database. Databases
+///without one use the default flags.
+///```toml
+///[default.databases.main.routing_flags]
+///read_percentage = 10
+///```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StaticRoutingFlags {
+    pub default: RoutingFlags,
+    pub databases: HashMap<String, RoutingFlags>,
+}
+impl StaticRoutingFlags {
+    ///Reads each database's `routing_flags` table from Rocket's configuration
+    pub fn from_figment(figment: &Figment) -> Result<Self, Box<figment::Error>> {
+        let mut databases = HashMap::new();
+        for name in figment.extract_inner::<Dict>("databases").unwrap_or_default().into_keys() {
+            let key = keys::database(&name, keys::ROUTING_FLAGS);
+            if figment.contains(&key) {
+                let flags = figment.extract_inner(&key)?;
+                databases.insert(name, flags);
+            }
+        }
+        Ok(StaticRoutingFlags{default: RoutingFlags::default(), databases})
+    }
+}
+impl RoutingFlagProvider for StaticRoutingFlags {
+    fn flags(&self, _req: &Request<'_>, database: &'static str) -> RoutingFlags {
+        self.databases.get(database).copied().unwrap_or(self.default)
+    }
+}
+
+///Managed state registering the application's [`RoutingFlagProvider`]
+pub struct RoutingFlagRegistry {
+    provider: Box<dyn RoutingFlagProvider>,
+    spread: Random,
+}
+impl RoutingFlagRegistry {
+    pub fn new<F: RoutingFlagProvider>(provider: F) -> Self {
+        RoutingFlagRegistry{provider: Box::new(provider), spread: Random::default()}
+    }
+
+    ///The flags for reads of `D` while serving `req`
+    pub(crate) fn flags<D: Database>(req: &Request<'_>) -> RoutingFlags {
+        req.rocket().state::<Self>().map_or_else(RoutingFlags::default, |r| r.provider.flags(req, D::NAME))
+    }
+
+    ///Whether a read with `flags` while serving `req` may use the replicas, spreading
+    ///`read_percentage` over the reads
+    fn replica(req: &Request<'_>, flags: &RoutingFlags) -> bool {
+        flags.replica && (flags.read_percentage >= 100
+            || req.rocket().state::<Self>().is_none_or(|r| r.spread.next() % 100 < flags.read_percentage as u64))
+    }
+}
+
+///The [`RoutingPolicy`] keeping reads on the main pool as the request's [`RoutingFlags`] say
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoutingFlagPolicy;
+impl RoutingPolicy for RoutingFlagPolicy {
+    fn route(&self, req: &Request<'_>, cx: &RoutingContext) -> Option<FallbackReason> {
+        (!RoutingFlagRegistry::replica(req, &cx.flags)).then_some(FallbackReason::RoutingFlags)
+    }
+}
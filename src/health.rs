@@ -0,0 +1,582 @@
+//!Background health checks of read replicas, quarantining failing ones
+use std::collections::{BTreeMap, VecDeque};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::future::join_all;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::sync::broadcast;
+use rocket::tokio::time::{interval, timeout, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{BoxError, CanaryResult, LogConfig, PoolRole, Random, ReadPool};
+
+///Capacity of the [`HealthRegistry`] change channel; slower subscribers miss older changes
+const CHANGE_CAPACITY: usize = 64;
+
+///Parts of the error messages of connections which were accepted and then reset or closed
+const RESET_MARKERS: [&str; 7] = [
+    "connection reset", "reset by peer", "broken pipe", "unexpected eof", "unexpected end of file",
+    "connection closed", "closed the connection",
+];
+
+///Longest a failed connection attempt may take to count as accepted then reset, rather than
+///stuck behind a timeout
+const RESET_WITHIN: Duration = Duration::from_millis(500);
+
+///Probes a connection's server, for [`ReadPool::check_health`] and the other checks of a
+///replica's state.
+///
+///Implement it for the read pool's connection type, e.g. by running `SELECT 1` in `probe`. The
+///other probes are only needed by the features which use them, and fail unless implemented:
+///- `lag` by `DbHealthRoute::with_lag`, with the `json` feature, e.g. with
+///  `now() - pg_last_xact_replay_timestamp()` on Postgres or `Seconds_Behind_Source` on MySQL
+///- `can_write` by [`ReadPool::audit_read_role`], e.g. by attempting an `INSERT` into a scratch
+///  table inside a transaction which is always rolled back, or by inspecting the role's
+///  attributes and grants
+///- `canary` by [`ReadPool::run_canaries`], e.g. by fetching all rows of the query with sqlx and
+///  decoding the first column of the first as a timestamp
+#[rocket::async_trait]
+pub trait HealthProbe: Send {
+    ///Returns `Ok(())` if the connection could run a trivial query
+    async fn probe(&mut self) -> Result<(), BoxError>;
+
+    ///Returns the server's replication lag
+    async fn lag(&mut self) -> Result<Duration, BoxError> {
+        Err("replication lag isn't measured by this connection type".into())
+    }
+
+    ///Returns `Ok(true)` if the connection could write
+    async fn can_write(&mut self) -> Result<bool, BoxError> {
+        Err("writability isn't probed by this connection type".into())
+    }
+
+    ///Runs `sql`, returning what the [`Canary`](crate::Canary) invariants are checked against
+    async fn canary(&mut self, _sql: &str) -> Result<CanaryResult, BoxError> {
+        Err("canary queries aren't run by this connection type".into())
+    }
+}
+
+///Configuration of read replica health checks: the `databases.<name>.read.health_check` table.
+///
+///A replica failing `quarantine_after` probes in a row is skipped by reads until it passes
+///`readmit_after` probes in a row. If every replica is quarantined, reads use the main pool.
+///
+///With `max_backoff_ms` set, a quarantined replica is probed less often while it keeps failing:
+///each failed probe doubles the wait before the next, up to `max_backoff_ms`, and the wait is
+///jittered so that many processes don't probe a recovering replica at once. A passing probe
+///returns to probing every `interval_ms`, to readmit it promptly.
+///```toml
+///[default.databases.main.read.health_check]
+///interval_ms = 2000
+///quarantine_after = 3
+///max_backoff_ms = 60000
+///```
+///When `read.url` points at a load balancer, an overloaded replica behind it may show up as
+///connections which are accepted and then reset at once, as the balancer's surge queue
+///overflows, rather than as failed probes. With `surge_resets` set, that many such failures of
+///reads and probes within `surge_window_ms` quarantine the replica at once, until it passes
+///`readmit_after` probes. Failures count as resets by their error messages, e.g. "connection
+///reset by peer" or "unexpected EOF", when they come within 500ms of the attempt.
+///```toml
+///[default.databases.main.read.health_check]
+///surge_resets = 5
+///surge_window_ms = 1000
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HealthCheckConfig {
+    ///`interval_ms`: time between probes of each replica. Defaults to 5000.
+    pub interval_ms: u64,
+    ///`timeout_ms`: time a probe, including acquiring its connection, may take before it counts
+    ///as failed. Defaults to 1000.
+    pub timeout_ms: u64,
+    ///`quarantine_after`: consecutive failed probes which quarantine a replica. Defaults to 2.
+    pub quarantine_after: u32,
+    ///`readmit_after`: consecutive successful probes which readmit a quarantined replica.
+    ///Defaults to 3.
+    pub readmit_after: u32,
+    ///`max_backoff_ms`: longest wait between probes of a quarantined replica which keeps
+    ///failing them. Unset, quarantined replicas are probed every `interval_ms`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_backoff_ms: Option<u64>,
+    ///`surge_resets`: connections accepted then reset within `surge_window_ms` which quarantine
+    ///a replica at once. Unset, resets count only as failed probes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub surge_resets: Option<u32>,
+    ///`surge_window_ms`: the window of `surge_resets`. Defaults to 1000.
+    pub surge_window_ms: u64,
+}
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig{
+            interval_ms: 5000,
+            timeout_ms: 1000,
+            quarantine_after: 2,
+            readmit_after: 3,
+            max_backoff_ms: None,
+            surge_resets: None,
+            surge_window_ms: 1000,
+        }
+    }
+}
+
+///Health of one read replica
+#[derive(Debug, Default)]
+pub(crate) struct ReplicaHealth {
+    quarantined: AtomicBool,
+    ///Consecutive probe results contrary to the replica's current state
+    streak: AtomicU32,
+    ///Whether one of the replica's maintenance windows is open
+    pub(crate) maintenance: AtomicBool,
+    ///Whether the replica's last canary run failed
+    pub(crate) degraded: AtomicBool,
+    ///Whether the replica was drained with `ReadPool::drain_replica`
+    pub(crate) drained: AtomicBool,
+    ///When the replica last rejoined rotation, while it may still be ramping up under
+    ///`read.slow_start_ms`
+    rejoined: Mutex<Option<Instant>>,
+    ///Accumulated share of reads owed to the replica during slow start, in millionths
+    credit: AtomicU64,
+    ///Consecutive probes failed while quarantined, for `max_backoff_ms`
+    failures: AtomicU32,
+    ///When a quarantined replica backing off is next probed
+    next_probe: Mutex<Option<Instant>>,
+    ///Times of recent connections accepted then reset, for `surge_resets`
+    resets: Mutex<VecDeque<Instant>>,
+}
+impl ReplicaHealth {
+    pub fn quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn drained(&self) -> bool {
+        self.drained.load(Ordering::Relaxed)
+    }
+
+    pub fn state(&self) -> HealthState {
+        if self.drained() {
+            HealthState::Drained
+        } else if self.quarantined() {
+            HealthState::Quarantined
+        } else if self.degraded() {
+            HealthState::Degraded
+        } else if self.in_maintenance() {
+            HealthState::Maintenance
+        } else {
+            HealthState::Healthy
+        }
+    }
+
+    ///Whether the replica may serve reads: neither drained, quarantined, degraded nor in
+    ///maintenance
+    pub fn available(&self) -> bool {
+        !self.drained() && !self.quarantined() && !self.degraded() && !self.in_maintenance()
+    }
+
+    ///Marks the replica as having just rejoined rotation, starting its slow start
+    pub(crate) fn rejoin(&self) {
+        *self.rejoined.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        self.credit.store(0, Ordering::Relaxed);
+    }
+
+    ///Whether the replica takes the next read it's chosen for while ramping up over `window`
+    ///since it rejoined. Its share grows linearly, with the reads it takes spread evenly.
+    pub(crate) fn admits(&self, window: Duration) -> bool {
+        let mut rejoined = self.rejoined.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(since) = *rejoined else {return true};
+        let elapsed = since.elapsed();
+        if elapsed >= window {
+            *rejoined = None;
+            return true;
+        }
+        let share = (elapsed.as_secs_f64() / window.as_secs_f64() * 1_000_000.0) as u64;
+        let mut admitted = false;
+        let _ = self.credit.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |credit| {
+            let credit = credit + share;
+            admitted = credit >= 1_000_000;
+            Some(if admitted {credit - 1_000_000} else {credit})
+        });
+        admitted
+    }
+
+    ///Records a probe result, returning the new quarantine state if it changed
+    fn observe(&self, healthy: bool, config: &HealthCheckConfig) -> Option<bool> {
+        let quarantined = self.quarantined();
+        if healthy != quarantined {
+            self.streak.store(0, Ordering::Relaxed);
+            return None;
+        }
+        let needed = if quarantined {config.readmit_after} else {config.quarantine_after};
+        if self.streak.fetch_add(1, Ordering::Relaxed) + 1 < needed.max(1) {
+            return None;
+        }
+        self.streak.store(0, Ordering::Relaxed);
+        self.quarantined.store(!quarantined, Ordering::Relaxed);
+        if quarantined {
+            self.rejoin();
+        }
+        Some(!quarantined)
+    }
+
+    ///Records a failed connection attempt which took `elapsed`, quarantining the replica if it
+    ///makes `surge_resets` resets within `surge_window_ms`. Returns whether it did.
+    pub(crate) fn observe_failure(&self, error: &str, elapsed: Duration, config: &HealthCheckConfig) -> bool {
+        let Some(limit) = config.surge_resets else {return false};
+        let error = error.to_lowercase();
+        if elapsed > RESET_WITHIN || !RESET_MARKERS.iter().any(|marker| error.contains(marker)) || self.quarantined() {
+            return false;
+        }
+        let now = Instant::now();
+        let window = Duration::from_millis(config.surge_window_ms);
+        let mut resets = self.resets.lock().unwrap_or_else(|e| e.into_inner());
+        while resets.front().is_some_and(|&reset| now.duration_since(reset) > window) {
+            resets.pop_front();
+        }
+        resets.push_back(now);
+        if resets.len() < limit.max(1) as usize {
+            return false;
+        }
+        resets.clear();
+        self.streak.store(0, Ordering::Relaxed);
+        !self.quarantined.swap(true, Ordering::Relaxed)
+    }
+
+    ///Whether the replica is due a probe, which it isn't while backing off in quarantine
+    fn due(&self) -> bool {
+        self.next_probe.lock().unwrap_or_else(|e| e.into_inner()).is_none_or(|next| Instant::now() >= next)
+    }
+
+    ///Schedules the replica's next probe after one which passed if `healthy`, returning the wait
+    ///if it's backing off
+    fn back_off(&self, healthy: bool, config: &HealthCheckConfig, jitter: &Random) -> Option<Duration> {
+        let mut next_probe = self.next_probe.lock().unwrap_or_else(|e| e.into_inner());
+        let max = match config.max_backoff_ms {
+            Some(max) if !healthy && self.quarantined() => max,
+            _ => {
+                self.failures.store(0, Ordering::Relaxed);
+                *next_probe = None;
+                return None;
+            }
+        };
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let wait = config.interval_ms.saturating_mul(1 << failures.min(16)).min(max.max(config.interval_ms));
+        //Equal jitter: at least half the wait, so backing off still backs off
+        let wait = Duration::from_millis(wait / 2 + jitter.next() % (wait / 2 + 1));
+        *next_probe = Some(Instant::now() + wait);
+        Some(wait)
+    }
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, R::Connection: HealthProbe {
+    ///Probes each read replica once, concurrently, quarantining or readmitting them according
+    ///to `read.health_check`. Quarantined replicas backing off under `max_backoff_ms` are
+    ///skipped until they're due. [`ReadHealthCheck`] calls this periodically.
+    pub async fn check_health(&self) {
+        let config = self.health_check.clone().unwrap_or_default();
+        let set = self.replica_set();
+        let names: Vec<_> = set.replicas.iter().map(|r| r.name.clone()).collect();
+        let health: Vec<_> = set.replicas.iter().map(|r| &r.health).collect();
+        probe_all(&set.pools, &names, &health, &config, self.label(crate::PoolRole::Read), &self.log).await;
+    }
+}
+
+///Probes each of `pools` once, concurrently, quarantining or readmitting them according to
+///`config`, and logging changes with their `names`
+pub(crate) async fn probe_all<T, N>(pools: &[T], names: &[N], health: &[&ReplicaHealth], config: &HealthCheckConfig, label: &str, log: &LogConfig)
+    where T: Pool, T::Connection: HealthProbe, N: std::fmt::Display
+{
+    let patience = Duration::from_millis(config.timeout_ms);
+    let probes = pools.iter().zip(health).map(|(pool, health)| async move {
+        if !health.due() {
+            return None;
+        }
+        let start = Instant::now();
+        let probe = async {
+            let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+            conn.probe().await.map_err(|e| e.to_string())
+        };
+        Some(match timeout(patience, probe).await {
+            Ok(result) => (result, start.elapsed()),
+            Err(_) => (Err(format!("timed out after {:?}", patience)), patience),
+        })
+    });
+    let jitter = Random::default();
+    for (i, result) in join_all(probes).await.into_iter().enumerate() {
+        let Some((result, elapsed)) = result else {continue};
+        if let Err(e) = &result {
+            if health[i].observe_failure(e, elapsed, config) {
+                db_log!(log, General, Warn, "`{}` replica `{}` quarantined as a surge queue, accepting then resetting connections: {}", label, names[i], e);
+                continue;
+            }
+        }
+        let change = health[i].observe(result.is_ok(), config);
+        let backoff = health[i].back_off(result.is_ok(), config, &jitter);
+        match (change, result) {
+            (Some(true), Err(e)) => db_log!(log, General, Warn, "`{}` replica `{}` quarantined: {}", label, names[i], e),
+            (Some(false), _) => db_log!(log, General, Info, "`{}` replica `{}` readmitted", label, names[i]),
+            (None, Err(e)) => match backoff {
+                Some(wait) => db_log!(log, General, Debug, "`{}` replica `{}` failed health check, next in {:?}: {}", label, names[i], wait, e),
+                None => db_log!(log, General, Debug, "`{}` replica `{}` failed health check: {}", label, names[i], e),
+            },
+            _ => {}
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Whether each read replica is currently quarantined by health checks
+    pub fn quarantined(&self) -> Vec<bool> {
+        self.replica_set().replicas.iter().map(|r| r.health.quarantined()).collect()
+    }
+}
+
+///Health of a read replica as seen by the health checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum HealthState {
+    ///Serving reads
+    Healthy,
+    ///Skipped by reads after failing health checks
+    Quarantined,
+    ///Skipped by reads after failing canary queries, see [`ReadCanary`](crate::ReadCanary)
+    Degraded,
+    ///Skipped by reads during a maintenance window
+    Maintenance,
+    ///Skipped by reads after being drained, see [`ReadPool::drain_replica`]
+    Drained,
+}
+
+///The health of one read replica, from [`HealthRegistry`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaStatus {
+    ///`Database::NAME` of the database
+    pub database: String,
+    ///The configured label of its read pool
+    pub label: String,
+    ///Index of the replica in `read.replicas`, or 0 for a single replica
+    pub replica: usize,
+    ///Name of the replica in `read.replicas`, see [`Replicas`](crate::Replicas)
+    pub name: String,
+    pub state: HealthState,
+}
+
+///Managed state holding the [`HealthState`] of every read replica checked by a
+///[`ReadHealthCheck`] fairing, for bridging health into monitoring this crate doesn't support.
+///
+///It's managed by the first `ReadHealthCheck` attached, or can be managed beforehand. Clones
+///share their state.
+///```rust
+/// # async fn _inner(rocket: &rocket::Rocket<rocket::Orbit>) {
+/// use rocket_read_db_pools::HealthRegistry;
+///
+/// if let Some(registry) = rocket.state::<HealthRegistry>() {
+///     let mut changes = registry.subscribe();
+///     rocket::tokio::spawn(async move {
+///         while let Ok(change) = changes.recv().await {
+///             println!("{} replica {} is now {:?}", change.label, change.name, change.state);
+///         }
+///     });
+/// }
+/// # }
+///```
+#[derive(Clone)]
+pub struct HealthRegistry {
+    states: Arc<Mutex<BTreeMap<(String, usize), ReplicaStatus>>>,
+    changes: broadcast::Sender<ReplicaStatus>,
+}
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        HealthRegistry{states: Default::default(), changes: broadcast::channel(CHANGE_CAPACITY).0}
+    }
+}
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///The current state of every known replica, ordered by database and replica
+    pub fn statuses(&self) -> Vec<ReplicaStatus> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+
+    ///The current state of a replica of the database `database`, if known
+    pub fn state(&self, database: &str, replica: usize) -> Option<HealthState> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner()).get(&(database.to_string(), replica)).map(|s| s.state)
+    }
+
+    ///Receives every change of state from now on, including replicas becoming known
+    pub fn subscribe(&self) -> broadcast::Receiver<ReplicaStatus> {
+        self.changes.subscribe()
+    }
+
+    ///Records the states of the read replicas of `pool`, notifying subscribers of changes
+    pub(crate) fn update<P, R>(&self, database: &str, pool: &ReadPool<P, R>) {
+        let label = pool.label(PoolRole::Read);
+        let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        let set = pool.replica_set();
+        states.retain(|(db, replica), _| db != database || *replica < set.len());
+        for (replica, state) in set.replicas.iter().enumerate() {
+            let state = state.health.state();
+            let name = set.replicas[replica].name.to_string();
+            let status = ReplicaStatus{database: database.to_string(), label: label.to_string(), replica, name, state};
+            if states.get(&(status.database.clone(), replica)) != Some(&status) {
+                states.insert((status.database.clone(), replica), status.clone());
+                //Only fails without subscribers
+                let _ = self.changes.send(status);
+            }
+        }
+    }
+}
+
+///A fairing which runs [`ReadPool::check_health`] for the database `D` every
+///`read.health_check.interval_ms` from launch until shutdown.
+///
+///Attach it after `D::init()`. The pool is shared with the health check task by cloning it, so
+///both pool types must be `Clone`. Replica states are published to the managed
+///[`HealthRegistry`].
+pub struct ReadHealthCheck<D>(PhantomData<fn() -> D>);
+impl<D> ReadHealthCheck<D> {
+    pub fn new() -> Self {
+        ReadHealthCheck(PhantomData)
+    }
+}
+impl<D> Default for ReadHealthCheck<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadHealthCheck<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone, R::Connection: HealthProbe
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Health Check",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadHealthCheck` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        match rocket.state::<HealthRegistry>() {
+            Some(_) => Ok(rocket),
+            None => Ok(rocket.manage(HealthRegistry::new())),
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let registry = rocket.state::<HealthRegistry>().cloned().unwrap_or_default();
+        registry.update(D::NAME, &pool);
+        let every = Duration::from_millis(pool.health_check.as_ref().map_or(HealthCheckConfig::default().interval_ms, |c| c.interval_ms));
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(every.max(Duration::from_millis(1)));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                pool.check_health().await;
+                registry.update(D::NAME, &pool);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HealthCheckConfig {
+        HealthCheckConfig{quarantine_after: 2, readmit_after: 3, ..HealthCheckConfig::default()}
+    }
+
+    #[test]
+    fn quarantines_and_readmits_after_streaks() {
+        let health = ReplicaHealth::default();
+        let config = config();
+        assert_eq!(health.observe(false, &config), None);
+        assert_eq!(health.observe(true, &config), None);
+        assert_eq!(health.observe(false, &config), None);
+        assert_eq!(health.observe(false, &config), Some(true));
+        assert_eq!(health.state(), HealthState::Quarantined);
+        assert!(!health.available());
+        assert_eq!(health.observe(true, &config), None);
+        assert_eq!(health.observe(true, &config), None);
+        assert_eq!(health.observe(true, &config), Some(false));
+        assert!(health.available());
+    }
+
+    #[test]
+    fn state_precedence() {
+        let health = ReplicaHealth::default();
+        health.maintenance.store(true, Ordering::Relaxed);
+        assert_eq!(health.state(), HealthState::Maintenance);
+        health.degraded.store(true, Ordering::Relaxed);
+        assert_eq!(health.state(), HealthState::Degraded);
+        health.quarantined.store(true, Ordering::Relaxed);
+        assert_eq!(health.state(), HealthState::Quarantined);
+        health.drained.store(true, Ordering::Relaxed);
+        assert_eq!(health.state(), HealthState::Drained);
+    }
+
+    #[test]
+    fn surge_of_resets_quarantines() {
+        let health = ReplicaHealth::default();
+        let config = HealthCheckConfig{surge_resets: Some(3), ..config()};
+        let fast = Duration::from_millis(5);
+        assert!(!health.observe_failure("Connection reset by peer", fast, &config));
+        assert!(!health.observe_failure("connection refused", fast, &config));
+        assert!(!health.observe_failure("broken pipe", RESET_WITHIN * 2, &config));
+        assert!(!health.observe_failure("broken pipe", fast, &config));
+        assert!(health.observe_failure("unexpected EOF", fast, &config));
+        assert!(health.quarantined());
+        assert!(!health.observe_failure("broken pipe", fast, &config));
+        assert!(!ReplicaHealth::default().observe_failure("broken pipe", fast, &HealthCheckConfig::default()));
+    }
+
+    #[test]
+    fn slow_start_admits_everything_once_over() {
+        let health = ReplicaHealth::default();
+        assert!(health.admits(Duration::from_secs(60)));
+        health.rejoin();
+        assert!((0..100).filter(|_| health.admits(Duration::from_secs(3600))).count() < 5);
+        assert!(health.admits(Duration::ZERO));
+        assert!(health.admits(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn backs_off_only_while_quarantined() {
+        let health = ReplicaHealth::default();
+        let config = HealthCheckConfig{interval_ms: 100, max_backoff_ms: Some(1000), ..config()};
+        let jitter = Random::seeded(1);
+        assert_eq!(health.back_off(false, &config, &jitter), None);
+        health.quarantined.store(true, Ordering::Relaxed);
+        let waits: Vec<Duration> = (0..6).filter_map(|_| health.back_off(false, &config, &jitter)).collect();
+        assert_eq!(waits.len(), 6);
+        assert!(waits.iter().all(|&wait| wait <= Duration::from_millis(1000)));
+        assert!(waits[5] >= Duration::from_millis(500));
+        assert!(!health.due());
+        assert_eq!(health.back_off(true, &config, &jitter), None);
+        assert!(health.due());
+    }
+}
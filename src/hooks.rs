@@ -0,0 +1,309 @@
+//!Hooks run around connection checkout on each pool of a `ReadPool`
+use std::any::Any;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use rocket::futures::future::BoxFuture;
+use crate::lease::{Lease, Leases};
+use crate::logging::{db_log, LogConfig};
+use crate::{BoxError, ConfigReloaded, PoolRole, ReadPool};
+
+///A connection checkout, passed to the hooks of [`ReadPool::on_acquire`],
+///[`ReadPool::on_release`] and [`ReadPool::on_error`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolEvent {
+    ///The role of the pool
+    pub role: PoolRole,
+    ///The label of the pool, with the replica's name if there are several
+    pub label: String,
+    ///How long the acquisition took, or for [`ReadPool::on_release`] how long the connection
+    ///was held
+    pub elapsed: Duration,
+    ///The pool's error, for [`ReadPool::on_error`]
+    pub error: Option<String>,
+}
+
+type AcquireHook = dyn for<'c> Fn(&'c mut dyn Any, &'c PoolEvent) -> Option<BoxFuture<'c, Result<(), BoxError>>> + Send + Sync;
+type TestHook = dyn for<'c> Fn(&'c mut dyn Any) -> Option<BoxFuture<'c, Result<(), BoxError>>> + Send + Sync;
+type EventHook = dyn Fn(PoolEvent) -> BoxFuture<'static, ()> + Send + Sync;
+type ReloadHook = dyn Fn(ConfigReloaded) -> BoxFuture<'static, ()> + Send + Sync;
+
+///The hooks of a `ReadPool`, shared by its clones, along with the leases timed for
+///`lease_warn_ms`
+#[derive(Default)]
+pub(crate) struct Hooks {
+    acquire: RwLock<Vec<Arc<AcquireHook>>>,
+    release: RwLock<Vec<Arc<EventHook>>>,
+    error: RwLock<Vec<Arc<EventHook>>>,
+    reload: RwLock<Vec<Arc<ReloadHook>>>,
+    test: RwLock<Vec<Arc<TestHook>>>,
+    leases: Option<Arc<Leases>>,
+}
+
+///Connections tested by [`Hooks::tested`] before handing one out regardless
+const TEST_ATTEMPTS: u32 = 3;
+
+fn listed<T: ?Sized>(hooks: &RwLock<Vec<Arc<T>>>) -> Vec<Arc<T>> {
+    hooks.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+impl Hooks {
+    pub fn new(leases: Option<Leases>) -> Self {
+        Hooks{leases: leases.map(Arc::new), ..Default::default()}
+    }
+
+    pub fn leases(&self) -> Option<&Leases> {
+        self.leases.as_deref()
+    }
+
+    ///Runs the acquire hooks on `conn`, just acquired from the pool with the role `role` and
+    ///label `label`, then hands out its release ticket to the guard acquiring it, if it has
+    ///release hooks to run or a lease to time
+    pub async fn acquired<C: Send + 'static>(&self, role: PoolRole, label: &str, start: Instant, conn: &mut C, log: &Arc<LogConfig>) {
+        let hooks = listed(&self.acquire);
+        if !hooks.is_empty() {
+            let event = PoolEvent{role, label: label.to_string(), elapsed: start.elapsed(), error: None};
+            for hook in hooks {
+                let Some(run) = hook(conn, &event) else {continue};
+                if let Err(e) = run.await {
+                    db_log!(log, General, Error, "`{}` pool: acquire hook failed: {}", label, e);
+                }
+            }
+        }
+        let release = listed(&self.release);
+        if !release.is_empty() || self.leases.is_some() || log.slow_hold().is_some() {
+            let _ = TICKET.try_with(|ticket| {
+                let mut ticket = ticket.borrow_mut();
+                let route = ticket.route.clone();
+                ticket.release = Some(Release{
+                    _lease: self.leases.as_ref().and_then(|l| l.start(label, route.clone(), log)),
+                    hooks: release,
+                    role,
+                    label: label.to_string(),
+                    route,
+                    log: log.clone(),
+                    acquired: Instant::now(),
+                    streaming: false,
+                });
+            });
+        }
+    }
+
+    ///Checks `conn`, just acquired from the pool with the label `label`, with the test hooks,
+    ///replacing it from `acquire` while it fails them. After [`TEST_ATTEMPTS`] failures the
+    ///last connection is handed out regardless, as it would have been without the tests.
+    pub async fn tested<C, E, F, Fut>(&self, label: &str, mut conn: C, mut acquire: F, log: &LogConfig) -> Result<C, E>
+        where C: Send + 'static, F: FnMut() -> Fut, Fut: Future<Output = Result<C, E>>
+    {
+        let hooks = listed(&self.test);
+        let mut attempts = 1;
+        loop {
+            let mut failure = None;
+            for hook in &hooks {
+                let Some(run) = hook(&mut conn) else {continue};
+                if let Err(e) = run.await {
+                    failure = Some(e);
+                    break;
+                }
+            }
+            let Some(e) = failure else {return Ok(conn)};
+            if attempts == TEST_ATTEMPTS {
+                db_log!(log, General, Warn, "`{}` pool: handing out connection after {} failed tests: {}", label, attempts, e);
+                return Ok(conn);
+            }
+            db_log!(log, General, Debug, "`{}` pool: discarding connection which failed its test: {}", label, e);
+            drop(conn);
+            conn = acquire().await?;
+            attempts += 1;
+        }
+    }
+
+    ///Starts the error hooks for an acquisition from the pool with the role `role` and label
+    ///`label` which failed with `error`, returning it
+    pub fn failed<E: std::fmt::Display>(&self, role: PoolRole, label: &str, start: Instant, error: E) -> E {
+        let hooks = listed(&self.error);
+        if !hooks.is_empty() {
+            let event = PoolEvent{role, label: label.to_string(), elapsed: start.elapsed(), error: Some(error.to_string())};
+            spawn(hooks, event);
+        }
+        error
+    }
+
+    ///Starts the reload hooks for a reload described by `event`
+    pub fn reloaded(&self, event: ConfigReloaded) {
+        let hooks = listed(&self.reload);
+        if hooks.is_empty() {
+            return;
+        }
+        match rocket::tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                for hook in hooks {
+                    runtime.spawn(hook(event.clone()));
+                }
+            }
+            Err(_) => rocket::error!("no async runtime to run reload hooks of `{}` on", event.label),
+        }
+    }
+}
+
+///The route a request guard acquires a connection for, and the release ticket it receives
+struct Ticket {
+    route: Option<Arc<str>>,
+    release: Option<Release>,
+}
+
+rocket::tokio::task_local! {
+    ///Set while a request guard acquires a connection, to receive its release ticket
+    static TICKET: RefCell<Ticket>;
+}
+
+///Runs an acquisition `fut` for a request guard serving `route`, if it serves one, along with
+///the release ticket of the connection it acquired, if there are release hooks, a lease
+///threshold or `log.slow_hold_ms`
+pub(crate) async fn ticketed<F: Future>(route: Option<Arc<str>>, fut: F) -> (F::Output, Option<Release>) {
+    TICKET.scope(RefCell::new(Ticket{route, release: None}), async {
+        let output = fut.await;
+        (output, TICKET.with(|ticket| ticket.borrow_mut().release.take()))
+    }).await
+}
+
+///The route of the request guard acquiring a connection, if any
+pub(crate) fn route() -> Option<Arc<str>> {
+    TICKET.try_with(|ticket| ticket.borrow().route.clone()).ok().flatten()
+}
+
+///Runs the release hooks of a connection, ends its lease and logs it if it was held past
+///`log.slow_hold_ms`, when dropped along with the guard holding it
+pub(crate) struct Release {
+    hooks: Vec<Arc<EventHook>>,
+    ///Ended by being dropped
+    _lease: Option<Lease>,
+    role: PoolRole,
+    label: String,
+    route: Option<Arc<str>>,
+    log: Arc<LogConfig>,
+    acquired: Instant,
+    streaming: bool,
+}
+impl Release {
+    ///Ends the lease of a connection handed to a streamed response, which is expected to be
+    ///held for long, and leaves it out of `log.slow_hold_ms`
+    pub fn streaming(mut self) -> Self {
+        self._lease = None;
+        self.streaming = true;
+        self
+    }
+}
+impl Drop for Release {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        if !self.streaming {
+            self.log.held(&self.label, self.route.as_deref(), held);
+        }
+        if self.hooks.is_empty() {
+            return;
+        }
+        let event = PoolEvent{role: self.role, label: std::mem::take(&mut self.label), elapsed: held, error: None};
+        spawn(std::mem::take(&mut self.hooks), event);
+    }
+}
+
+///Runs `hooks` with `event` in the background on the current Tokio runtime
+fn spawn(hooks: Vec<Arc<EventHook>>, event: PoolEvent) {
+    match rocket::tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            for hook in hooks {
+                runtime.spawn(hook(event.clone()));
+            }
+        }
+        Err(_) => rocket::error!("no async runtime to run hooks of `{}` on", event.label),
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Adds a hook run on each connection of type `C` just acquired from any of the pools, before
+    ///it's handed out, e.g. to set session variables or tag the connection for instrumentation.
+    ///Hooks run in the order added, on pools whose connection type is `C`, so a `ReadPool` with
+    ///a read side of another type needs a hook for each. A hook failing is logged and the
+    ///connection handed out regardless. Clones of the pool share hooks.
+    ///```rust
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::ReadPool;
+    /// # use rocket_read_db_pools::testing::{MockPool, MockPoolConnection};
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// # fn _f(rocket: &rocket::Rocket<rocket::Orbit>) {
+    /// let db = Db::fetch(rocket).unwrap();
+    /// db.on_acquire(|conn: &mut MockPoolConnection, event| Box::pin(async move {
+    ///     conn.query("SELECT set_config('application_name', $1, false)")
+    ///         .bind(&event.label).execute().await?;
+    ///     Ok(())
+    /// }));
+    /// db.on_error(|event| Box::pin(async move {
+    ///     eprintln!("`{}` failed after {:?}: {:?}", event.label, event.elapsed, event.error);
+    /// }));
+    /// # }
+    ///```
+    pub fn on_acquire<C, F>(&self, hook: F)
+        where C: Send + 'static, F: for<'c> Fn(&'c mut C, &'c PoolEvent) -> BoxFuture<'c, Result<(), BoxError>> + Send + Sync + 'static
+    {
+        let hook: Arc<AcquireHook> = Arc::new(move |conn, event| Some(hook(conn.downcast_mut::<C>()?, event)));
+        self.hooks.acquire.write().unwrap_or_else(|e| e.into_inner()).push(hook);
+    }
+
+    ///Adds a test run on each connection of type `C` acquired from the pools with
+    ///`test_before_acquire` set, before the acquire hooks, so connections killed by a failover or
+    ///the server's idle timeout aren't handed to handlers. A connection failing a test is
+    ///dropped and another acquired, up to 3 times before one is handed out regardless. Tests run
+    ///in the order added, on pools whose connection type is `C`. Clones of the pool share tests.
+    ///
+    ///Dropping a connection usually returns it to its pool, so a test should also mark the
+    ///connection to be closed where the driver allows it. sqlx pools already ping connections
+    ///before handing them out unless `test_before_acquire` is turned off in their own options,
+    ///so this is for other pools and cheaper checks.
+    ///```rust
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::ReadPool;
+    /// # use rocket_read_db_pools::testing::{MockPool, MockPoolConnection};
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// # fn _f(rocket: &rocket::Rocket<rocket::Orbit>) {
+    /// let db = Db::fetch(rocket).unwrap();
+    /// db.test_with(|conn: &mut MockPoolConnection| Box::pin(async move {
+    ///     conn.query("SELECT 1").execute().await?;
+    ///     Ok(())
+    /// }));
+    /// # }
+    ///```
+    pub fn test_with<C, F>(&self, test: F)
+        where C: Send + 'static, F: for<'c> Fn(&'c mut C) -> BoxFuture<'c, Result<(), BoxError>> + Send + Sync + 'static
+    {
+        let test: Arc<TestHook> = Arc::new(move |conn| Some(test(conn.downcast_mut::<C>()?)));
+        self.hooks.test.write().unwrap_or_else(|e| e.into_inner()).push(test);
+    }
+
+    ///Adds a hook run in the background once a connection acquired by one of this crate's
+    ///request guards is given up: when the guard, or the
+    ///[`StreamConnection`](crate::StreamConnection) it was converted into, is dropped, or its
+    ///connection taken with `into_inner`. Guards holding a transaction, such as
+    ///[`RwTransaction`](crate::RwTransaction), give it up once the transaction ends. The pool
+    ///can't tell when connections from `Pool::get` return to it, so they don't run it. Clones of
+    ///the pool share hooks.
+    pub fn on_release<F>(&self, hook: F) where F: Fn(PoolEvent) -> BoxFuture<'static, ()> + Send + Sync + 'static {
+        self.hooks.release.write().unwrap_or_else(|e| e.into_inner()).push(Arc::new(hook));
+    }
+
+    ///Adds a hook run in the background when acquiring a connection from any of the pools
+    ///fails, whether the failure is then handled, e.g. by falling back to the main pool, or not.
+    ///Clones of the pool share hooks.
+    pub fn on_error<F>(&self, hook: F) where F: Fn(PoolEvent) -> BoxFuture<'static, ()> + Send + Sync + 'static {
+        self.hooks.error.write().unwrap_or_else(|e| e.into_inner()).push(Arc::new(hook));
+    }
+
+    ///Adds a hook run in the background after each successful [`ReadPool::reload`], with the
+    ///settings it found changed and the replicas it swapped in and removed, e.g. to audit
+    ///reconfigurations. Clones of the pool share hooks.
+    pub fn on_reload<F>(&self, hook: F) where F: Fn(ConfigReloaded) -> BoxFuture<'static, ()> + Send + Sync + 'static {
+        self.hooks.reload.write().unwrap_or_else(|e| e.into_inner()).push(Arc::new(hook));
+    }
+}
+
@@ -0,0 +1,76 @@
+//!Snapshots of a `ReadPool`'s state for applications' own status endpoints
+use rocket::serde::{Deserialize, Serialize};
+use crate::{HealthState, PoolRole, PoolStats, PoolUsage, ReadPool};
+
+///The state of every pool of a [`ReadPool`] at a point in time, from [`ReadPool::info`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolInfo {
+    ///The pool currently acting as the main pool
+    pub main: PoolRoleInfo,
+    ///The configured label of the read pool
+    pub read_label: String,
+    ///Each read replica, in order
+    pub read: Vec<ReplicaPoolInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delayed: Option<PoolRoleInfo>,
+    ///Name of the balancer choosing between the replicas, see [`ReadBalancer::name`](crate::ReadBalancer::name)
+    pub balancer: String,
+}
+
+///The main or delayed pool, in a [`PoolInfo`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolRoleInfo {
+    ///The configured label of the pool
+    pub label: String,
+    pub stats: PoolStats,
+}
+
+///One read replica, in a [`PoolInfo`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplicaPoolInfo {
+    ///Name of the replica in `read.replicas`, see [`Replicas`](crate::Replicas)
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    pub state: HealthState,
+    ///Whether reads may use the replica, which they don't in any state but healthy
+    pub available: bool,
+    pub stats: PoolStats,
+}
+
+impl<P, R> ReadPool<P, R> where P: PoolUsage, R: PoolUsage {
+    ///A serializable snapshot of the size and usage of each pool, the replicas' health and the
+    ///balancer in use, e.g. to embed in an application's own status endpoint.
+    ///```rust
+    /// # use rocket::get;
+    /// # use rocket::serde::json::Json;
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::testing::MockPool;
+    /// # use rocket_read_db_pools::{PoolInfo, ReadPool};
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// #[get("/status/db")]
+    /// fn status(db: &Db) -> Json<PoolInfo> {
+    ///     Json(db.info())
+    /// }
+    ///```
+    pub fn info(&self) -> PoolInfo {
+        let set = self.replica_set();
+        let read = set.replicas.iter().zip(&set.pools).map(|(replica, pool)| ReplicaPoolInfo{
+            name: replica.name.to_string(),
+            zone: replica.zone.as_deref().map(str::to_string),
+            state: replica.health.state(),
+            available: replica.health.available(),
+            stats: PoolStats::of(pool),
+        }).collect();
+        PoolInfo{
+            main: PoolRoleInfo{label: self.label(PoolRole::Main).to_string(), stats: PoolStats::of(self.primary())},
+            read_label: self.label(PoolRole::Read).to_string(),
+            read,
+            delayed: self.delayed.as_ref().map(|pool| PoolRoleInfo{label: self.label(PoolRole::Delayed).to_string(), stats: PoolStats::of(pool)}),
+            balancer: self.balancer.read().unwrap_or_else(|e| e.into_inner()).name().to_string(),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//!Interoperation with `rocket_db_pools`' own connection guard
+use std::ops::DerefMut;
+use rocket_db_pools::{Connection, Database, Pool};
+use crate::RwConnection;
+
+///A connection from the main pool of the database `D`: [`RwConnection<D>`] or
+///`rocket_db_pools`' [`Connection<D>`], so code migrating between them can take either.
+///
+///`Connection<D>` can't be constructed outside `rocket_db_pools`, so a function written against
+///it can't be passed an `RwConnection<D>`. Writing it against this trait instead accepts both,
+///and a `Connection<D>` converts into an `RwConnection<D>` with `From`.
+///```rust
+/// # use rocket_db_pools::{Connection, Database};
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{BoxError, ReadPool};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::response::Debug;
+/// use rocket_read_db_pools::{MainConnection, RwConnection};
+///
+/// async fn archive(conn: &mut impl MainConnection<Db>, id: i64) -> Result<(), BoxError> {
+///     conn.query("UPDATE items SET archived = true WHERE id = $1").bind(id).execute().await?;
+///     Ok(())
+/// }
+///
+/// #[rocket::post("/old/<id>/archive")]
+/// async fn old(mut conn: Connection<Db>, id: i64) -> Result<(), Debug<BoxError>> {
+///     Ok(archive(&mut conn, id).await?)
+/// }
+///
+/// #[rocket::post("/new/<id>/archive")]
+/// async fn new(mut conn: RwConnection<Db>, id: i64) -> Result<(), Debug<BoxError>> {
+///     Ok(archive(&mut conn, id).await?)
+/// }
+///```
+pub trait MainConnection<D: Database>: DerefMut<Target = <D::Pool as Pool>::Connection> {
+    ///Gets the internal connection value
+    fn into_inner(self) -> <D::Pool as Pool>::Connection;
+}
+impl<D: Database> MainConnection<D> for Connection<D> {
+    fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        Connection::into_inner(self)
+    }
+}
+impl<D: Database> MainConnection<D> for RwConnection<D> {
+    fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        RwConnection::into_inner(self)
+    }
+}
+
+impl<D: Database> From<Connection<D>> for RwConnection<D> {
+    fn from(conn: Connection<D>) -> Self {
+        RwConnection::from_inner(conn.into_inner())
+    }
+}
@@ -0,0 +1,139 @@
+//!Names of the configuration keys of a `ReadPool` database, relative to its `databases.<name>`
+//!table, for programmatic configuration without string literals.
+//!```rust
+//!use rocket::figment::providers::Serialized;
+//!use rocket_read_db_pools::keys;
+//!
+//!let figment = rocket::Config::figment()
+//!    .merge(Serialized::global(&keys::database("main", keys::URL), "postgresql://user@primary/db"))
+//!    .merge(Serialized::global(&keys::database("main", keys::READ_URL), "postgresql://user@replica/db"))
+//!    .merge(Serialized::global(&keys::database("main", keys::READ_MAX_CONNECTIONS), 20));
+//!assert_eq!(figment.extract_inner::<u32>("databases.main.read.max_connections").unwrap(), 20);
+//!```
+//!
+//!Pool options such as `url` are read by the underlying pools; the names here are those of
+//!`rocket_db_pools::Config`. See [`ReadDbConfig`](crate::ReadDbConfig) for what each key does.
+
+///The full key of `key` for the database `name`, i.e. `databases.<name>.<key>`
+pub fn database(name: &str, key: &str) -> String {
+    format!("databases.{}.{}", name, key)
+}
+
+pub const URL: &str = "url";
+pub const MIN_CONNECTIONS: &str = "min_connections";
+pub const MAX_CONNECTIONS: &str = "max_connections";
+pub const CONNECT_TIMEOUT: &str = "connect_timeout";
+pub const IDLE_TIMEOUT: &str = "idle_timeout";
+pub const LABEL: &str = "label";
+pub const ROLES: &str = "roles";
+pub const STATEMENT_TIMEOUT_MS: &str = "statement_timeout_ms";
+pub const RESOLVE: &str = "resolve";
+pub const IP_PREFERENCE: &str = "ip_preference";
+pub const TLS: &str = "tls";
+pub const SECRETS: &str = "secrets";
+pub const APPLICATION_NAME: &str = "application_name";
+pub const RESET_ON_RETURN: &str = "reset_on_return";
+pub const DRAIN_TIMEOUT_MS: &str = "drain_timeout_ms";
+pub const TEST_BEFORE_ACQUIRE: &str = "test_before_acquire";
+pub const ACQUIRE_RETRIES: &str = "acquire_retries";
+pub const ACQUIRE_BACKOFF_MS: &str = "acquire_backoff_ms";
+pub const LEASE_WARN_MS: &str = "lease_warn_ms";
+pub const WARMUP: &str = "warmup";
+pub const LOG: &str = "log";
+pub const LOG_SLOW_ACQUIRE_MS: &str = "log_slow_acquire_ms";
+pub const LOG_SLOW_HOLD_MS: &str = "log_slow_hold_ms";
+pub const READ_ONLY_MODE: &str = "read_only_mode";
+pub const PREFER: &str = "prefer";
+///Read by [`StaticRoutingFlags`](crate::StaticRoutingFlags)
+pub const ROUTING_FLAGS: &str = "routing_flags";
+
+///Read by [`MultiPrimaryPool`](crate::MultiPrimaryPool)
+pub const PRIMARIES: &str = "primaries";
+pub const PRIMARY_BALANCER: &str = "primary_balancer";
+pub const PRIMARY_HEALTH_CHECK: &str = "primary_health_check";
+
+///Read by [`ShardedReadPool`](crate::ShardedReadPool)
+pub const SHARDS: &str = "shards";
+pub const TENANTS: &str = "tenants";
+pub const DEFAULT_SHARD: &str = "default_shard";
+
+///The warm standby primary's table
+pub const FAILOVER: &str = "failover";
+pub const FAILOVER_URL: &str = "failover.url";
+
+///Shorthand for a `read` table of replicas inheriting the main pool's options
+pub const READ_URLS: &str = "read_urls";
+
+///The read replica's table
+pub const READ: &str = "read";
+pub const READ_URL: &str = "read.url";
+pub const READ_MIN_CONNECTIONS: &str = "read.min_connections";
+pub const READ_MAX_CONNECTIONS: &str = "read.max_connections";
+pub const READ_CONNECT_TIMEOUT: &str = "read.connect_timeout";
+pub const READ_IDLE_TIMEOUT: &str = "read.idle_timeout";
+pub const READ_LABEL: &str = "read.label";
+pub const READ_ROLES: &str = "read.roles";
+pub const READ_STATEMENT_TIMEOUT_MS: &str = "read.statement_timeout_ms";
+pub const READ_SEARCH_PATH: &str = "read.search_path";
+pub const READ_ENFORCE_READ_ONLY: &str = "read.enforce_read_only";
+pub const READ_RESOLVE: &str = "read.resolve";
+pub const READ_IP_PREFERENCE: &str = "read.ip_preference";
+pub const READ_TLS: &str = "read.tls";
+pub const READ_SECRETS: &str = "read.secrets";
+pub const READ_APPLICATION_NAME: &str = "read.application_name";
+pub const READ_RESET_ON_RETURN: &str = "read.reset_on_return";
+pub const READ_DRAIN_TIMEOUT_MS: &str = "read.drain_timeout_ms";
+pub const READ_TEST_BEFORE_ACQUIRE: &str = "read.test_before_acquire";
+pub const READ_INHERIT_MAIN: &str = "read.inherit_main";
+pub const READ_REPLICAS: &str = "read.replicas";
+pub const READ_BALANCER: &str = "read.balancer";
+pub const READ_WEIGHT: &str = "read.weight";
+pub const READ_ZONE: &str = "read.zone";
+pub const READ_PREFER_ZONE: &str = "read.prefer_zone";
+pub const READ_PREFER_ZONE_ENV: &str = "read.prefer_zone_env";
+pub const READ_REPLICATED_TABLES: &str = "read.replicated_tables";
+pub const READ_CHECK_REPLICATED_TABLES: &str = "read.check_replicated_tables";
+pub const READ_FALLBACK: &str = "read.fallback";
+pub const READ_ON_ERROR: &str = "read.on_error";
+pub const READ_MAX_WAIT_MS: &str = "read.max_wait_ms";
+pub const READ_VERIFY: &str = "read.verify";
+pub const READ_SHADOW_RATIO: &str = "read.shadow_ratio";
+pub const READ_PIN_AFTER_WRITE: &str = "read.pin_after_write";
+pub const READ_LAZY: &str = "read.lazy";
+pub const READ_DEGRADED_START: &str = "read.degraded_start";
+pub const READ_INIT_RETRY_MS: &str = "read.init_retry_ms";
+pub const READ_RECORD: &str = "read.record";
+pub const READ_WARMUP: &str = "read.warmup";
+pub const READ_WARMUP_CONCURRENCY: &str = "read.warmup_concurrency";
+pub const READ_BROWNOUT: &str = "read.brownout";
+pub const READ_PRIMARY_READ_RATIO: &str = "read.primary_read_ratio";
+pub const READ_MIN_HEALTHY: &str = "read.min_healthy";
+pub const READ_BELOW_MIN_HEALTHY: &str = "read.below_min_healthy";
+pub const READ_SLOW_START_MS: &str = "read.slow_start_ms";
+pub const READ_HEALTH_CHECK: &str = "read.health_check";
+pub const READ_CANARY: &str = "read.canary";
+pub const READ_DISCOVERY: &str = "read.discovery";
+pub const READ_COORDINATION: &str = "read.coordination";
+pub const READ_CACHE: &str = "read.cache";
+pub const READ_CIRCUIT_BREAKER: &str = "read.circuit_breaker";
+pub const READ_MAINTENANCE: &str = "read.maintenance";
+
+///The delayed replica's table
+pub const DELAYED: &str = "read.delayed";
+pub const DELAYED_URL: &str = "read.delayed.url";
+pub const DELAYED_MIN_CONNECTIONS: &str = "read.delayed.min_connections";
+pub const DELAYED_MAX_CONNECTIONS: &str = "read.delayed.max_connections";
+pub const DELAYED_CONNECT_TIMEOUT: &str = "read.delayed.connect_timeout";
+pub const DELAYED_IDLE_TIMEOUT: &str = "read.delayed.idle_timeout";
+pub const DELAYED_LABEL: &str = "read.delayed.label";
+pub const DELAYED_ROLES: &str = "read.delayed.roles";
+pub const DELAYED_STATEMENT_TIMEOUT_MS: &str = "read.delayed.statement_timeout_ms";
+pub const DELAYED_SEARCH_PATH: &str = "read.delayed.search_path";
+pub const DELAYED_RESOLVE: &str = "read.delayed.resolve";
+pub const DELAYED_IP_PREFERENCE: &str = "read.delayed.ip_preference";
+pub const DELAYED_TLS: &str = "read.delayed.tls";
+pub const DELAYED_SECRETS: &str = "read.delayed.secrets";
+pub const DELAYED_APPLICATION_NAME: &str = "read.delayed.application_name";
+pub const DELAYED_RESET_ON_RETURN: &str = "read.delayed.reset_on_return";
+pub const DELAYED_DRAIN_TIMEOUT_MS: &str = "read.delayed.drain_timeout_ms";
+pub const DELAYED_TEST_BEFORE_ACQUIRE: &str = "read.delayed.test_before_acquire";
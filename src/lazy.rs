@@ -0,0 +1,192 @@
+//!Read replicas created after launch: on the first read with `read.lazy`, or retried in the
+//!background with `read.degraded_start`
+use std::sync::{Arc, RwLock, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use rocket::figment::Figment;
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::sleep;
+use rocket_db_pools::Pool;
+use crate::logging::db_log;
+use crate::replicas::{self, ReplicaSet};
+use crate::{LogConfig, PoolRole, ReadPool};
+
+///Read replicas whose pools haven't been created yet, with the options to create them from
+pub(crate) struct PendingReplicas {
+    figments: Vec<(String, Figment)>,
+    ///Whether a background task is creating them, rather than reads
+    retrying: bool,
+    ready: AtomicBool,
+    creating: Mutex<()>,
+    ///The background task started by [`PendingReplicas::retry`], until it's stopped
+    task: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+impl PendingReplicas {
+    ///Replicas created by the first read
+    pub fn lazy(figments: Vec<(String, Figment)>) -> Self {
+        PendingReplicas{figments, retrying: false, ready: AtomicBool::new(false), creating: Mutex::new(()), task: Default::default()}
+    }
+
+    ///Replicas created by a background task started with [`PendingReplicas::retry`]
+    pub fn in_background(figments: Vec<(String, Figment)>) -> Self {
+        PendingReplicas{retrying: true, ..Self::lazy(figments)}
+    }
+
+    pub fn ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn retrying(&self) -> bool {
+        self.retrying
+    }
+
+    ///Creates the replicas and installs them in `read`, unless another caller already did
+    async fn create<R: Pool>(&self, read: &RwLock<Arc<ReplicaSet<R>>>, label: &str, log: &LogConfig) -> Result<(), R::Error> {
+        if self.ready() {
+            return Ok(());
+        }
+        let _creating = self.creating.lock().await;
+        if self.ready() {
+            return Ok(());
+        }
+        let set = create::<R>(&self.figments, label).await?;
+        *read.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(set);
+        self.ready.store(true, Ordering::Release);
+        db_log!(log, General, Info, "`{}` pool created", label);
+        Ok(())
+    }
+
+    ///Retries creating the replicas every `every` in the background until they're created,
+    ///every clone of the pool is dropped or [`PendingReplicas::stop`] is called
+    pub fn retry<R: Pool>(self: Arc<Self>, read: Weak<RwLock<Arc<ReplicaSet<R>>>>, label: Arc<str>, log: Arc<LogConfig>, every: Duration) {
+        let pending = self.clone();
+        let task = rocket::tokio::spawn(async move {
+            while !pending.ready() {
+                sleep(every).await;
+                let Some(read) = read.upgrade() else {break};
+                let error = pending.create::<R>(&read, &label, &log).await.err().map(|e| e.to_string());
+                if let Some(e) = error {
+                    db_log!(log, General, Warn, "`{}` pool still couldn't be created, retrying in {:?}: {}", label, every, e);
+                }
+            }
+        });
+        *self.task.lock().unwrap_or_else(|e| e.into_inner()) = Some(task);
+    }
+
+    ///Stops the background task retrying the replicas' creation, e.g. as the pool closes
+    pub fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
+
+///Creates the replicas' pools from their options. If one fails, those already created are
+///closed in the background.
+pub(crate) async fn create<R: Pool>(figments: &[(String, Figment)], label: &str) -> Result<ReplicaSet<R>, R::Error> {
+    let mut set = ReplicaSet::default();
+    for (name, figment) in figments {
+        match replicas::init::<R>(name.clone(), figment.clone(), label).await {
+            Ok((pool, weight, replica)) => {
+                set.pools.push(pool);
+                set.weights.push(weight);
+                set.replicas.push(Arc::new(replica));
+                set.configs.push(figment.clone());
+            }
+            Err(e) => {
+                for pool in set.pools {
+                    rocket::tokio::spawn(async move {pool.close().await});
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(set)
+}
+
+impl<P, R: Pool> ReadPool<P, R> {
+    ///Creates the read replicas of a `read.lazy` pool now, if they haven't been yet, instead of
+    ///on the first read. Does nothing for other pools, or while `read.degraded_start` is
+    ///retrying in the background.
+    ///
+    ///If any replica's pool can't be created, those created are closed and the error is
+    ///returned. The next read, or call, tries again.
+    pub async fn connect_read(&self) -> Result<(), R::Error> {
+        match self.pending.as_ref() {
+            Some(pending) if !pending.retrying() => pending.create(&self.read, self.label(PoolRole::Read), &self.log).await,
+            _ => Ok(()),
+        }
+    }
+
+    ///Whether the read replicas are waiting to be created, by the first read with `read.lazy`
+    ///or in the background with `read.degraded_start`
+    pub fn read_pending(&self) -> bool {
+        self.pending.as_ref().is_some_and(|p| !p.ready())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use rocket::figment::providers::{Format, Toml};
+    use crate::testing::{MockError, MockPool, MockPoolConnection};
+    use crate::{FallbackReason, ReadCapablePool};
+    use super::*;
+
+    ///Attempts to create an [`Unreachable`] pool, by url
+    static ATTEMPTS: std::sync::Mutex<Option<HashMap<String, usize>>> = std::sync::Mutex::new(None);
+
+    ///A replica pool which can't be created, as its database is unreachable
+    struct Unreachable;
+    #[rocket::async_trait]
+    impl Pool for Unreachable {
+        type Connection = MockPoolConnection;
+        type Error = MockError;
+
+        async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+            let url: String = figment.extract_inner("url")?;
+            *ATTEMPTS.lock().unwrap().get_or_insert_with(HashMap::new).entry(url.clone()).or_default() += 1;
+            Err(MockError::Unavailable(url))
+        }
+
+        async fn get(&self) -> Result<Self::Connection, Self::Error> {
+            unreachable!()
+        }
+
+        async fn close(&self) {}
+    }
+
+    fn attempts(url: &str) -> usize {
+        ATTEMPTS.lock().unwrap().as_ref().and_then(|a| a.get(url).copied()).unwrap_or(0)
+    }
+
+    async fn pool(toml: &str) -> ReadPool<MockPool, Unreachable> {
+        Pool::init(&Figment::from(Toml::string(toml))).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn lazy_reads_follow_on_error_when_the_replicas_cant_be_created() {
+        let failing = pool("url = \"main\"\nread = {url = \"lazy-error\", lazy = true}").await;
+        let (role, _, conn) = ReadCapablePool::<MockPoolConnection>::get_read_explained(&failing).await;
+        assert_eq!(role, PoolRole::Read);
+        assert!(conn.is_err());
+
+        let falling_back = pool("url = \"main\"\nread = {url = \"lazy-fallback\", lazy = true, on_error = \"fallback\"}").await;
+        let (role, reason, conn) = ReadCapablePool::<MockPoolConnection>::get_read_explained(&falling_back).await;
+        assert_eq!((role, reason), (PoolRole::Main, Some(FallbackReason::ReadFailed)));
+        assert_eq!(conn.unwrap().url(), "main");
+        assert!(falling_back.read_pending());
+    }
+
+    #[rocket::async_test]
+    async fn closing_stops_the_degraded_start_retries() {
+        let pool = pool("url = \"main\"\nread = {url = \"degraded\", degraded_start = true, init_retry_ms = 5}").await;
+        sleep(Duration::from_millis(50)).await;
+        assert!(attempts("degraded") > 1);
+        pool.close().await;
+        let attempted = attempts("degraded");
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(attempts("degraded"), attempted);
+    }
+}
@@ -0,0 +1,72 @@
+//!Warnings about connections held by request guards for longer than `lease_warn_ms`
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::Duration;
+use rocket::tokio::task::AbortHandle;
+use crate::logging::{db_log, LogConfig};
+use crate::ReadPool;
+
+const HELD: u8 = 0;
+const LONG_HELD: u8 = 1;
+const RELEASED: u8 = 2;
+
+///The lease threshold of a `ReadPool` and the count of connections held past it, shared by its
+///clones
+#[derive(Debug)]
+pub(crate) struct Leases {
+    threshold: Duration,
+    long_held: AtomicU64,
+}
+impl Leases {
+    pub fn new(threshold_ms: u64) -> Self {
+        Leases{threshold: Duration::from_millis(threshold_ms), long_held: AtomicU64::new(0)}
+    }
+
+    ///Starts timing the lease of a connection just acquired from the pool with the label
+    ///`label` for the route `route`, if there's a runtime to time it on
+    pub fn start(self: &Arc<Self>, label: &str, route: Option<Arc<str>>, log: &Arc<LogConfig>) -> Option<Lease> {
+        let runtime = rocket::tokio::runtime::Handle::try_current().ok()?;
+        let state = Arc::new(AtomicU8::new(HELD));
+        let timer = runtime.spawn({
+            let (leases, state, log, label) = (self.clone(), state.clone(), log.clone(), label.to_string());
+            async move {
+                rocket::tokio::time::sleep(leases.threshold).await;
+                //Counted before being marked, so a release racing this never uncounts it first
+                leases.long_held.fetch_add(1, Ordering::Relaxed);
+                if state.compare_exchange(HELD, LONG_HELD, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                    leases.long_held.fetch_sub(1, Ordering::Relaxed);
+                    return;
+                }
+                match route {
+                    Some(route) => db_log!(log, General, Warn, "`{}` pool: connection held by {} for over {:?}", label, route, leases.threshold),
+                    None => db_log!(log, General, Warn, "`{}` pool: connection held for over {:?}", label, leases.threshold),
+                }
+            }
+        }).abort_handle();
+        Some(Lease{leases: self.clone(), state, timer})
+    }
+}
+
+///The lease of a connection held by a request guard, warning once it's held past the threshold
+pub(crate) struct Lease {
+    leases: Arc<Leases>,
+    state: Arc<AtomicU8>,
+    timer: AbortHandle,
+}
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.timer.abort();
+        if self.state.swap(RELEASED, Ordering::AcqRel) == LONG_HELD {
+            self.leases.long_held.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Number of connections acquired by this crate's request guards which are currently held
+    ///for longer than `lease_warn_ms`, across all of the pools, or 0 if it isn't set. Clones of
+    ///the pool share the count.
+    pub fn long_held(&self) -> u64 {
+        self.hooks.leases().map_or(0, |l| l.long_held.load(Ordering::Relaxed))
+    }
+}
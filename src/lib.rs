@@ -1,31 +1,494 @@
-use rocket::figment::{Figment, providers::Serialized};
+//!Read / write splitting of Rocket database pools: reads go to read replicas and writes to the
+//!main database, see [`ReadPool`].
+//!
+//!This crate is driver-agnostic. Where it needs to talk to the database beyond acquiring
+//!connections, e.g. to probe a replica's health, reset a session or cancel a query, it does so
+//!through a trait for you to implement for the pool or connection types in use, and the
+//!features which need one are unavailable until you do. Those traits' docs give examples
+//!for Postgres and MySQL.
+//!
+//!Background tasks such as the health checks share the pools by cloning them, so they need
+//!both pool types to be `Clone`, as handle types like sqlx's pools are.
+use rocket::figment::{self, Figment};
 use rocket_db_pools::{Database, Pool};
 use rocket::request::{FromRequest, Request, Outcome};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
-use rocket::{Ignite, Rocket, Sentinel};
+use rocket::{Ignite, Orbit, Phase, Rocket, Sentinel};
 use rocket::http::Status;
-use rocket::async_trait;
 
-///Internal trait so the FromRequest implementation can match `ReadPool` databases
-#[async_trait]
-trait PoolRead: Pool{
-    ///Gets a connection from the read pool if given else the main pool
-    async fn get_read(&self) -> Result<Self::Connection, Self::Error>;
+mod acquire;
+#[cfg(feature = "json")]
+mod admin;
+mod attach;
+mod auto;
+mod balance;
+mod blocking;
+mod breaker;
+#[cfg(feature = "cache")]
+mod cache;
+mod canary;
+mod budget;
+mod brownout;
+mod cleanup;
+mod audit;
+mod config;
+mod consistency;
+mod coordination;
+mod decisions;
+mod decorate;
+mod diff;
+mod discovery;
+mod dns;
+mod drain;
+mod env;
+mod failover;
+mod failure;
+mod flags;
+mod policy;
+mod health;
+mod interop;
+mod lazy;
+mod limit;
+mod loaded;
+mod logging;
+mod maintenance;
+mod method;
+#[cfg(feature = "metrics-basic")]
+mod metrics;
+mod multi;
+mod outage;
+mod process;
+#[cfg(feature = "json")]
+mod readiness;
+mod refresh;
+mod reload;
+mod replicas;
+mod replicated;
+mod role;
+mod routed;
+mod routing;
+mod tag;
+mod trace;
+mod tls;
+mod secrets;
+mod transaction;
+mod upgrade;
+mod saturation;
+mod session;
+mod shared;
+#[cfg(feature = "discovery-dns")]
+mod srv;
+mod verify;
+mod usage;
+mod warmup;
+mod zone;
+mod plan;
+mod prefer;
+mod primary;
+mod hooks;
+mod info;
+mod lease;
+mod error;
+mod deadline;
+mod shard;
+mod source;
+mod stream;
+pub mod keys;
+pub mod record;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub use acquire::{AcquireError, TryAcquire, WithTimeout};
+pub use error::ReadPoolError;
+pub use failure::{FailureKind, FailureResponses, GuardFailure};
+pub use hooks::PoolEvent;
+pub use info::{PoolInfo, PoolRoleInfo, ReplicaPoolInfo};
+pub use replicas::ResizePool;
+#[cfg(feature = "json")]
+pub use admin::{ReplicaAdmin, ReplicaInfo, ReplicaSetInfo};
+pub use verify::{ShadowStats, Verification};
+pub use plan::{InitPlan, PlannedPool, ReadConfigCheck, ValidationError};
+pub use config::{BelowMinHealthy, DelayedConfig, OnError, Prefer, ReadDbConfig, ReplicaConfig, Replicas};
+pub use balance::{BalanceStrategy, Preferred, Random, ReadBalancer, RoundRobin};
+#[cfg(feature = "strategy-weighted")]
+pub use balance::Weighted;
+#[cfg(feature = "strategy-least-connections")]
+pub use balance::LeastConnections;
+pub use breaker::CircuitBreakerConfig;
+pub use blocking::{Blocking, SyncConnection, SyncPool, SyncReadPool};
+#[cfg(feature = "cache")]
+pub use cache::{CacheConfig, CacheStats, CachedRead};
+pub use canary::{Canary, CanaryConfig, CanaryResult, ReadCanary};
+pub use budget::RetryBudget;
+pub use deadline::RequestDeadline;
+pub use brownout::BrownoutConfig;
+pub use health::{HealthCheckConfig, HealthProbe, HealthRegistry, HealthState, ReadHealthCheck, ReplicaStatus};
+pub use diff::ConfigChange;
+pub use coordination::{CoordinationBackend, CoordinationConfig, ReadCoordination};
+pub use discovery::{DiscoveredReplica, DiscoveryConfig, ReadDiscovery, ReplicaDiscovery};
+#[cfg(feature = "discovery-dns")]
+pub use srv::SrvDiscovery;
+pub use dns::{IpPreference, Resolve};
+pub use tls::{TlsConfig, TlsVerify};
+pub use secrets::{set_secrets_provider, SecretsConfig, SecretsProvider};
+pub use drain::{ReadDrain, ReadShutdown};
+use config::PerRole;
+pub use env::ConventionalEnv;
+pub use attach::ReadDatabases;
+pub use auto::AutoConnection;
+pub use primary::Primary;
+pub use source::ConnectionSource;
+pub use stream::StreamConnection;
+pub use interop::MainConnection;
+pub use upgrade::ReadOrRw;
+pub use audit::{ReadRoleAudit, RoleAudit};
+pub use routing::{Acquisition, FallbackReason, RequestRouting};
+pub use routed::{route_statement, StatementRouter};
+pub use transaction::{ReadTransaction, RwTransaction, TransactionError, Transactional};
+pub use saturation::{PoolStats, PoolUsage, ReadPoolStats};
+pub use usage::{PoolSummary, UsageReport};
+pub use warmup::{ReadWarmup, WarmupPolicy};
+pub use process::{cancel_on_drop, CancelQuery, CancelToken, ServerProcess};
+pub use refresh::{RefreshView, ViewFreshness, ViewRefresh};
+pub use reload::{ConfigReloaded, ReadReload};
+#[cfg(feature = "tower")]
+pub use service::ReadPoolService;
+pub use decisions::{FileRoutingSink, RoutingDecision, RoutingLog, RoutingSink};
+pub use decorate::{ConnectionDecorator, ConnectionPipeline, Decorated, ReadOnly};
+pub use session::{ContextError, SessionContext, SessionSnapshot, SessionVariables, WithContext};
+pub use shared::Shared;
+#[cfg(feature = "json")]
+pub use readiness::{DbHealth, DbHealthRoute, PoolHealth};
+pub use role::{RoleSwitch, SessionRole, WithRole};
+pub use consistency::{ReadYourWrites, ReplicationPosition};
+pub use flags::{RoutingFlagPolicy, RoutingFlagProvider, RoutingFlagRegistry, RoutingFlags, StaticRoutingFlags};
+pub use policy::{RoutingContext, RoutingPolicies, RoutingPolicy};
+pub use consistency::{PinAfterWritePolicy, ReadYourWritesPolicy};
+pub use multi::MultiPrimaryPool;
+pub use shard::{ShardedReadConnection, ShardedReadPool, ShardedRwConnection, ShardHeader, ShardKey, ShardKeys, ShardParam, ShardSubdomain, TenantPool};
+pub use outage::{ReadOnlyMode, ReadOnlyModeConfig, ReadOnlySurvival};
+#[cfg(feature = "metrics-basic")]
+pub use metrics::{MetricFamily, MetricKind, ReadPoolMetrics, Sample};
+#[cfg(feature = "metrics-prometheus")]
+pub use metrics::prometheus_text;
+pub use maintenance::{MaintenanceWindow, ReadMaintenance, Weekday};
+pub use method::{MethodAudit, MethodMismatch};
+pub use loaded::{FromReadConnection, LoadError, Loaded};
+pub use limit::{LimitError, Limited};
+pub use logging::{LogConfig, LogLevel};
+pub use cleanup::{ResetStrategy, SessionCleanup, SessionReset, WithCleanup};
+
+///Makes a route's database connection guards use the read pool, by rewriting any
+///`Connection<Db>`, `RwConnection<Db>` or `AutoConnection<Db>` parameter of the handler to
+///[`ReadConnection<Db>`](ReadConnection). Handlers can then move to the read pool without
+///changing their signatures.
+///
+///It must be placed above the route attribute, so it runs first. Types are matched by name, so
+///only use it on handlers whose parameters named `Connection` are connection guards.
+///```rust
+/// # use rocket::figment::Figment;
+/// # use rocket_db_pools::Pool;
+/// # use rocket_read_db_pools::{PoolRole, ReadCapablePool};
+/// # struct SplitPool;
+/// # #[rocket::async_trait]
+/// # impl Pool for SplitPool {
+/// #     type Connection = &'static str;
+/// #     type Error = std::io::Error;
+/// #     async fn init(_figment: &Figment) -> Result<Self, Self::Error> {Ok(SplitPool)}
+/// #     async fn get(&self) -> Result<Self::Connection, Self::Error> {Ok("primary")}
+/// #     async fn close(&self) {}
+/// # }
+/// # impl ReadCapablePool for SplitPool {
+/// #     async fn get_read(&self) -> (PoolRole, Result<Self::Connection, Self::Error>) {
+/// #         (PoolRole::Read, Ok("replica"))
+/// #     }
+/// # }
+/// use rocket::get;
+/// use rocket::local::blocking::Client;
+/// use rocket_db_pools::{Connection, Database};
+/// use rocket_read_db_pools::read_only;
+///
+/// #[derive(Database)]
+/// #[database("split")]
+/// struct Db(SplitPool);
+///
+/// #[read_only]
+/// #[get("/")]
+/// fn index(conn: Connection<Db>) -> &'static str {
+///     conn.into_inner()
+/// }
+///
+/// let rocket = rocket::build().attach(Db::init()).mount("/", rocket::routes![index]);
+/// let client = Client::tracked(rocket).unwrap();
+/// assert_eq!(client.get("/").dispatch().into_string().as_deref(), Some("replica"));
+///```
+pub use rocket_read_db_pools_codegen::read_only;
+
+///Derives `Database` for a struct wrapping a pool, like `rocket_db_pools`' own derive, and
+///declares the aliases `<Name>Read` for [`ReadConnection<Name>`](ReadConnection) and `<Name>Rw`
+///for [`RwConnection<Name>`](RwConnection), with the struct's visibility.
+///
+///The database's fairing is still attached with `Name::init()`.
+///```rust
+/// # use rocket::figment::Figment;
+/// # use rocket_db_pools::Pool;
+/// # use rocket_read_db_pools::{PoolRole, ReadCapablePool};
+/// # struct SplitPool;
+/// # #[rocket::async_trait]
+/// # impl Pool for SplitPool {
+/// #     type Connection = &'static str;
+/// #     type Error = std::io::Error;
+/// #     async fn init(_figment: &Figment) -> Result<Self, Self::Error> {Ok(SplitPool)}
+/// #     async fn get(&self) -> Result<Self::Connection, Self::Error> {Ok("primary")}
+/// #     async fn close(&self) {}
+/// # }
+/// # impl ReadCapablePool for SplitPool {
+/// #     async fn get_read(&self) -> (PoolRole, Result<Self::Connection, Self::Error>) {
+/// #         (PoolRole::Read, Ok("replica"))
+/// #     }
+/// # }
+/// use rocket::get;
+/// use rocket::local::blocking::Client;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::ReadDatabase;
+///
+/// #[derive(ReadDatabase)]
+/// #[database("split")]
+/// struct Db(SplitPool);
+///
+/// #[get("/read")]
+/// fn read(conn: DbRead) -> &'static str {
+///     conn.into_inner()
+/// }
+///
+/// #[get("/write")]
+/// fn write(conn: DbRw) -> &'static str {
+///     conn.into_inner()
+/// }
+///
+/// let rocket = rocket::build().attach(Db::init()).mount("/", rocket::routes![read, write]);
+/// let client = Client::tracked(rocket).unwrap();
+/// assert_eq!(client.get("/read").dispatch().into_string().as_deref(), Some("replica"));
+/// assert_eq!(client.get("/write").dispatch().into_string().as_deref(), Some("primary"));
+///```
+pub use rocket_read_db_pools_codegen::ReadDatabase;
+
+///Boxed error returned by the driver-specific traits users implement for their connections
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+use record::Recorder;
+use logging::db_log;
+
+///Identifies which of a `ReadPool`'s pools served a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, rocket::serde::Serialize, rocket::serde::Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum PoolRole {
+    ///The main read-write pool
+    Main,
+    ///The read replica pool
+    Read,
+    ///The delayed replica pool
+    Delayed,
+}
+
+///A pool which hands out connections for reads separately from writes, as required by the
+///[`ReadConnection`] and [`DelayedReadConnection`] guards. ([`RwConnection`] only needs
+///`Pool::get`.)
+///
+///[`ReadPool`] implements this for any pair of pools. Other crates can implement it for their
+///own pool types to get read/write-split support without this crate depending on them, e.g.
+///for a pool which already routes reads internally:
+///```rust
+/// use rocket::figment::Figment;
+/// use rocket_db_pools::{Database, Pool};
+/// use rocket_read_db_pools::{PoolRole, ReadCapablePool, ReadConnection};
+///
+/// struct SplitPool;
+/// #[rocket::async_trait]
+/// impl Pool for SplitPool {
+///     type Connection = &'static str;
+///     type Error = std::io::Error;
+///
+///     async fn init(_figment: &Figment) -> Result<Self, Self::Error> {
+///         Ok(SplitPool)
+///     }
+///
+///     async fn get(&self) -> Result<Self::Connection, Self::Error> {
+///         Ok("primary")
+///     }
+///
+///     async fn close(&self) {}
+/// }
+/// impl ReadCapablePool for SplitPool {
+///     async fn get_read(&self) -> (PoolRole, Result<Self::Connection, Self::Error>) {
+///         (PoolRole::Read, Ok("replica"))
+///     }
+/// }
+///
+/// #[derive(Database)]
+/// #[database("split")]
+/// struct Db(SplitPool);
+///
+/// #[rocket::get("/")]
+/// fn index(conn: ReadConnection<Db>) -> &'static str {
+///     conn.into_inner()
+/// }
+///```
+///
+///`C` is the connection type handed out to the guard, which both the read and main
+///connections are converted into.
+///
+///A wrapper around a [`ReadPool`], e.g. one which instruments it, implements `Pool` and this
+///trait by delegating to it. Delegate the provided methods too, or the `ReadPool`'s fallback
+///reasons, labels and settings are replaced by the defaults:
+///```rust
+/// # use rocket::figment::Figment;
+/// # struct Inner;
+/// # #[rocket::async_trait]
+/// # impl rocket_db_pools::Pool for Inner {
+/// #     type Connection = &'static str;
+/// #     type Error = rocket_db_pools::Error<std::io::Error>;
+/// #     async fn init(_figment: &Figment) -> Result<Self, Self::Error> {Ok(Inner)}
+/// #     async fn get(&self) -> Result<Self::Connection, Self::Error> {Ok("primary")}
+/// #     async fn close(&self) {}
+/// # }
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use rocket_db_pools::Pool;
+/// use rocket_read_db_pools::{FallbackReason, PoolRole, ReadCapablePool, ReadPool};
+///
+/// type Split = ReadPool<Inner>;
+///
+/// struct Counted(Split, Arc<AtomicU64>);
+/// #[rocket::async_trait]
+/// impl Pool for Counted {
+///     type Connection = &'static str;
+///     type Error = rocket_db_pools::Error<std::io::Error>;
+///
+///     async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+///         Ok(Counted(ReadPool::init(figment).await?, Arc::default()))
+///     }
+///
+///     async fn get(&self) -> Result<Self::Connection, Self::Error> {
+///         self.0.get().await
+///     }
+///
+///     async fn close(&self) {
+///         self.0.close().await
+///     }
+/// }
+/// impl ReadCapablePool for Counted {
+///     async fn get_read(&self) -> (PoolRole, Result<Self::Connection, Self::Error>) {
+///         self.1.fetch_add(1, Ordering::Relaxed);
+///         <Split as ReadCapablePool>::get_read(&self.0).await
+///     }
+///
+///     async fn get_read_explained(&self) -> (PoolRole, Option<FallbackReason>, Result<Self::Connection, Self::Error>) {
+///         self.1.fetch_add(1, Ordering::Relaxed);
+///         <Split as ReadCapablePool>::get_read_explained(&self.0).await
+///     }
+///
+///     async fn get_read_main_for(&self, reason: FallbackReason) -> Option<Result<Self::Connection, Self::Error>> {
+///         <Split as ReadCapablePool>::get_read_main_for(&self.0, reason).await
+///     }
+///
+///     fn pool_label(&self, role: PoolRole) -> Arc<str> {
+///         <Split as ReadCapablePool>::pool_label(&self.0, role)
+///     }
+///
+///     fn pins_after_write(&self) -> bool {
+///         <Split as ReadCapablePool>::pins_after_write(&self.0)
+///     }
+///
+///     // ...and likewise get_delayed, get_read_main, has_delayed, statement_timeout,
+///     // log_config, replica_counts and sheds_low_priority
+/// }
+///```
+///
+///This trait is kept stable: any methods added to it will have default implementations.
+///Acquisition returns unboxed futures since it is on every request's path; implementations can
+///use `async fn`.
+pub trait ReadCapablePool<C = <Self as Pool>::Connection>: Pool{
+    ///Gets a connection for reading, along with the pool which served it
+    fn get_read(&self) -> impl Future<Output = (PoolRole, Result<C, Self::Error>)> + Send;
+    ///Gets a connection for reading as `get_read` does, along with why the main pool served it
+    ///if it did, for [`RequestRouting`]. Defaults to `get_read` without a reason.
+    fn get_read_explained(&self) -> impl Future<Output = (PoolRole, Option<FallbackReason>, Result<C, Self::Error>)> + Send {
+        async {
+            let (role, result) = self.get_read().await;
+            (role, None, result)
+        }
+    }
+    ///Gets a connection from the delayed replica, or `None` if there isn't one
+    fn get_delayed(&self) -> impl Future<Output = Option<Result<C, Self::Error>>> + Send {
+        async {None}
+    }
+    ///Gets a connection for reading from the main pool, for reads which must see the latest
+    ///writes, or `None` if the pool can't provide one
+    fn get_read_main(&self) -> impl Future<Output = Option<Result<C, Self::Error>>> + Send {
+        async {None}
+    }
+    ///Gets a connection for reading from the main pool as `get_read_main` does, for a read kept
+    ///off the replicas for `reason`. Defaults to `get_read_main`.
+    fn get_read_main_for(&self, reason: FallbackReason) -> impl Future<Output = Option<Result<C, Self::Error>>> + Send {
+        let _ = reason;
+        self.get_read_main()
+    }
+    ///Returns true if a delayed replica is configured
+    fn has_delayed(&self) -> bool {
+        false
+    }
+    ///The label of the pool with the given role. Defaults to the role's name.
+    fn pool_label(&self, role: PoolRole) -> Arc<str> {
+        match role {
+            PoolRole::Main => "main".into(),
+            PoolRole::Read => "read".into(),
+            PoolRole::Delayed => "delayed".into(),
+        }
+    }
+    ///The configured default statement timeout of the pool with the given role, if known
+    fn statement_timeout(&self, _role: PoolRole) -> Option<Duration> {
+        None
+    }
+    ///The pool's `log` table, if it has one, controlling this crate's output about it
+    fn log_config(&self) -> Option<&LogConfig> {
+        None
+    }
+    ///Whether reads by a request which has acquired an `RwConnection` use the main pool, so
+    ///they see its writes. Defaults to false.
+    fn pins_after_write(&self) -> bool {
+        false
+    }
+    ///The number of read replicas, and of those which reads may use, for
+    ///[`RoutingContext`]. Defaults to `None`, for unknown.
+    fn replica_counts(&self) -> Option<(usize, usize)> {
+        None
+    }
+    ///Whether `ReadConnection` guards of low-priority requests fail rather than read, see
+    ///[`RequestRouting::set_low_priority`]. Defaults to false.
+    fn sheds_low_priority(&self) -> bool {
+        false
+    }
 }
 
 ///A pool which supports separate read-write and read-only connections.
 ///Example:
 ///```rust
-/// # #[cfg(feature = "sqlx_sqlite")] mod _inner {
 /// # use rocket::get;
-/// # type Pool = rocket_db_pools::sqlx::SqlitePool;
+/// # type Pool = rocket_read_db_pools::testing::MockPool;
 /// use rocket_db_pools::Database;
 /// use rocket_read_db_pools::ReadPool;
 ///
 /// #[derive(Database)]
 /// #[database("db")]
 /// struct Db(ReadPool<Pool>);
-/// # }
 ///```
 ///```toml
 ///[default.databases.main]
@@ -34,42 +497,594 @@ trait PoolRead: Pool{
 ///url = "postgresql://user@readreplica.example/dbname"
 ///max_connections = 10
 ///```
-pub struct ReadPool<P>{
+///
+///Handlers take a [`ReadConnection`] for reads, served by the replicas, and an [`RwConnection`]
+///for writes, served by the main pool. Endpoints which must never read stale data, such as
+///login, can take the [`Primary`] guard instead. Guards which fail to acquire a connection fail
+///the request with [`ReadPoolError::status`], or as the [`FailureResponses`] fairing sets.
+///
+///# Configuration
+///
+///The database's table is documented on [`ReadDbConfig`], the `read` table on
+///[`ReplicaConfig`] and its `delayed` table on [`DelayedConfig`]. Their nested tables are
+///documented on their own types: [`LogConfig`], [`HealthCheckConfig`],
+///[`CircuitBreakerConfig`], [`BrownoutConfig`], [`CanaryConfig`], [`DiscoveryConfig`],
+///[`ReadOnlyModeConfig`], [`TlsConfig`], [`SecretsConfig`] and [`MaintenanceWindow`].
+///
+///An invalid value for any of this crate's options fails `Pool::init`, and so ignition, with
+///the configuration error, which is why the main pool's error type must convert from
+///`figment::Error`, as `rocket_db_pools::Error` does.
+///
+///# Routing
+///
+///Reads are spread between the replicas by a [`ReadBalancer`], and kept off replicas which are
+///failing, lagging or in maintenance by the fairings below. They use the main pool when no
+///replica can serve them, when [`RoutingPolicies`] send them there, e.g. a client's reads after
+///it writes with [`ReadYourWrites`], or as configured, with the reason reported by
+///[`RequestRouting`].
+///
+///Background work is done by fairings: [`ReadHealthCheck`], [`ReadCanary`],
+///[`ReadDiscovery`], [`ReadCoordination`], [`ReadReload`], [`ReadMaintenance`], [`ReadWarmup`]
+///and [`ReadOnlySurvival`]. With the `metrics-prometheus` feature, on by default, the
+///`ReadPoolMetrics` fairing serves each pool's acquisitions, waits, fallbacks and replica states
+///in the Prometheus text format (`metrics-basic` alone only passes them to a callback); with the
+///`json` feature, `DbHealthRoute` and `ReplicaAdmin`
+///mount readiness and replica administration routes; and with the `cache` feature, the
+///`CachedRead` guard caches the results of hot read queries.
+///
+///Blocking pools, such as Diesel's r2d2 pools, are split the same way by a [`SyncReadPool`]
+///once they implement [`SyncPool`]; its guards run closures on blocking threads.
+///
+///# Pool types
+///
+///The read side may use a different pool implementation to the main side, as long as its
+///errors convert into the main pool's error type. Connections are converted at the guard:
+///`ReadConnection<Db>` requires the read connection to convert into the main connection type,
+///while `ReadConnection<Db, C>` accepts any `C` both connection types convert into.
+///```rust
+/// # type MainPool = rocket_read_db_pools::testing::MockPool;
+/// # type ReplicaPool = rocket_read_db_pools::testing::MockPool;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::ReadPool;
+///
+/// #[derive(Database)]
+/// #[database("db")]
+/// struct Db(ReadPool<MainPool, ReplicaPool>);
+///```
+///
+///`ReadPool` is `Clone` when both pool types are. Clones share the underlying connections along
+///with divergence counts and recordings, so a test suite can initialize one pool and `manage`
+///it in many Rocket instances instead of attaching `Db::init()` to each, which would open new
+///connections every time:
+///```rust
+/// # type Pool = rocket_read_db_pools::testing::MockPool;
+/// use rocket::figment::Figment;
+/// use rocket_db_pools::{Database, Pool as _};
+/// use rocket_read_db_pools::ReadPool;
+///
+/// #[derive(Database)]
+/// #[database("db")]
+/// struct Db(ReadPool<Pool>);
+///
+/// async fn rockets(figment: &Figment) -> Vec<rocket::Rocket<rocket::Build>> {
+///     let pool = ReadPool::<Pool>::init(&figment.focus("databases.db")).await.unwrap();
+///     (0..10).map(|_| rocket::build().manage(Db::from(pool.clone()))).collect()
+/// }
+///```
+///Closing is then up to the test suite, as no `Db::init()` fairing closes the pool on shutdown.
+pub struct ReadPool<P, R = P>{
     main: P,
-    read: Option<P>,
+    standby: Option<P>,
+    failed_over: Arc<std::sync::atomic::AtomicBool>,
+    read: Arc<std::sync::RwLock<Arc<replicas::ReplicaSet<R>>>>,
+    pending: Option<Arc<lazy::PendingReplicas>>,
+    balancer: Arc<std::sync::RwLock<Arc<dyn ReadBalancer<R>>>>,
+    health_check: Option<health::HealthCheckConfig>,
+    canary: Option<canary::CanaryConfig>,
+    delayed: Option<R>,
+    replicated_tables: Option<Vec<String>>,
+    check_replicated: bool,
+    on_error: OnError,
+    verify: bool,
+    pin_after_write: bool,
+    prefer_read: bool,
+    divergences: Arc<AtomicU64>,
+    shadow: Option<Arc<verify::Shadow>>,
+    recorder: Option<Arc<Recorder>>,
+    labels: PerRole<Arc<str>>,
+    statement_timeouts: PerRole<Option<Duration>>,
+    drain_timeouts: PerRole<Option<Duration>>,
+    tests_before_acquire: PerRole<bool>,
+    smoothed_saturation: Arc<std::sync::Mutex<PerRole<Option<saturation::Smoothed>>>>,
+    brownout: Option<Arc<brownout::Brownout>>,
+    primary_share: Option<Arc<brownout::PrimaryShare>>,
+    slow_start: Option<Duration>,
+    min_healthy: Option<(usize, BelowMinHealthy)>,
+    max_wait: Option<Duration>,
+    prefer_zone: Option<Arc<str>>,
+    spill: Arc<std::sync::RwLock<Option<zone::Spill<R>>>>,
+    breaker: Option<Arc<breaker::CircuitBreaker>>,
+    outage: Option<Arc<breaker::CircuitBreaker>>,
+    usage: Arc<PerRole<usage::Counters>>,
+    usage_sampled: Arc<std::sync::atomic::AtomicBool>,
+    warmup: Option<(u32, usize)>,
+    min_connections: Option<Arc<warmup::MinConnections>>,
+    retry: Option<acquire::Retry>,
+    hooks: Arc<hooks::Hooks>,
+    stopping: Arc<drain::Stopping>,
+    log: Arc<LogConfig>,
+    config: Arc<std::sync::RwLock<Arc<ReadDbConfig>>>,
+    #[cfg(feature = "cache")]
+    cache: Arc<cache::ResultCache>,
+    #[cfg(feature = "testing")]
+    script: Arc<std::sync::RwLock<Option<Arc<testing::RoutingScript>>>>,
+}
+impl<P: Clone, R: Clone> Clone for ReadPool<P, R> {
+    fn clone(&self) -> Self {
+        ReadPool{
+            main: self.main.clone(),
+            standby: self.standby.clone(),
+            failed_over: self.failed_over.clone(),
+            read: self.read.clone(),
+            pending: self.pending.clone(),
+            balancer: self.balancer.clone(),
+            health_check: self.health_check.clone(),
+            canary: self.canary.clone(),
+            delayed: self.delayed.clone(),
+            replicated_tables: self.replicated_tables.clone(),
+            check_replicated: self.check_replicated,
+            on_error: self.on_error,
+            verify: self.verify,
+            pin_after_write: self.pin_after_write,
+            prefer_read: self.prefer_read,
+            divergences: self.divergences.clone(),
+            shadow: self.shadow.clone(),
+            recorder: self.recorder.clone(),
+            labels: self.labels.clone(),
+            statement_timeouts: self.statement_timeouts.clone(),
+            drain_timeouts: self.drain_timeouts.clone(),
+            tests_before_acquire: self.tests_before_acquire.clone(),
+            smoothed_saturation: self.smoothed_saturation.clone(),
+            brownout: self.brownout.clone(),
+            primary_share: self.primary_share.clone(),
+            slow_start: self.slow_start,
+            min_healthy: self.min_healthy,
+            max_wait: self.max_wait,
+            prefer_zone: self.prefer_zone.clone(),
+            spill: self.spill.clone(),
+            breaker: self.breaker.clone(),
+            outage: self.outage.clone(),
+            usage: self.usage.clone(),
+            usage_sampled: self.usage_sampled.clone(),
+            warmup: self.warmup,
+            min_connections: self.min_connections.clone(),
+            retry: self.retry,
+            hooks: self.hooks.clone(),
+            stopping: self.stopping.clone(),
+            log: self.log.clone(),
+            config: self.config.clone(),
+            #[cfg(feature = "cache")]
+            cache: self.cache.clone(),
+            #[cfg(feature = "testing")]
+            script: self.script.clone(),
+        }
+    }
 }
 #[rocket::async_trait]
-impl<P> Pool for ReadPool<P> where P: Pool
+impl<P, R> Pool for ReadPool<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static
 {
     type Error = P::Error;
 
     type Connection = P::Connection;
 
     async fn init(figment: &Figment) -> Result<Self, Self::Error> {
-        let main_pool = P::init(figment).await?;
-        if figment.contains("read"){
-            let read_config = figment.focus("read")
-                .join(Serialized::default("read.connect_timeout", 5));
-            let read_pool = P::init(&read_config).await?;
-            Ok(ReadPool{main: main_pool, read: Some(read_pool)})
-        } else {
-            Ok(ReadPool{main: main_pool, read: None})
+        let figment = &plan::with_read_urls(figment);
+        let config = ReadDbConfig::extract(figment).map_err(|e| P::Error::from(*e))?;
+        let main_pool = P::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(figment.clone()).await))).await).await?;
+        let (replicated_tables, check_replicated) = replicated::config(config.read.as_ref());
+        let delayed = match plan::delayed_figment(figment) {
+            Some(delayed_config) => Some(R::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(delayed_config).await))).await).await.map_err(Into::into)?),
+            None => None,
+        };
+        let labels = config.labels();
+        let read_figments = plan::read_figments(figment);
+        let (read, pending) = match config.read.as_ref() {
+            Some(r) if r.lazy => (replicas::ReplicaSet::default(), Some(lazy::PendingReplicas::lazy(read_figments))),
+            read_config => match lazy::create::<R>(&read_figments, &labels.read).await {
+                Ok(read) => (read, None),
+                Err(e) if read_config.is_some_and(|r| r.degraded_start) => {
+                    rocket::warn!("`{}` pool couldn't be created, serving reads from `{}` meanwhile: {}", labels.read, labels.main, e);
+                    (replicas::ReplicaSet::default(), Some(lazy::PendingReplicas::in_background(read_figments)))
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+        let read = Arc::new(std::sync::RwLock::new(Arc::new(read)));
+        let pending = pending.map(Arc::new);
+        let log = Arc::new(config.log_config());
+        if let Some(pending) = pending.as_ref().filter(|p| p.retrying()) {
+            let every = Duration::from_millis(config.read.as_ref().and_then(|r| r.init_retry_ms).unwrap_or(5000));
+            pending.clone().retry(Arc::downgrade(&read), labels.read.clone(), log.clone(), every);
         }
+        let standby = match plan::failover_figment(figment) {
+            Some(standby_config) => Some(P::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(standby_config).await))).await).await?),
+            None => None,
+        };
+        Ok(ReadPool{
+            main: main_pool,
+            standby,
+            failed_over: Default::default(),
+            read,
+            pending,
+            balancer: Arc::new(std::sync::RwLock::new(config.read.as_ref().and_then(|r| r.balancer).unwrap_or_default().balancer())),
+            health_check: config.read.as_ref().and_then(|r| r.health_check.clone()),
+            canary: config.read.as_ref().and_then(|r| r.canary.clone()),
+            delayed,
+            replicated_tables,
+            check_replicated,
+            on_error: config.read.as_ref().map(ReplicaConfig::on_error).unwrap_or_default(),
+            verify: config.read.as_ref().is_some_and(|r| r.verify),
+            pin_after_write: config.read.as_ref().is_some_and(|r| r.pin_after_write),
+            prefer_read: prefer_read::<P, R>(&config, &labels),
+            divergences: Default::default(),
+            shadow: config.read.as_ref().and_then(|r| verify::Shadow::new(r.shadow_ratio?)).map(Arc::new),
+            recorder: config.read.as_ref().and_then(|r| r.record.as_deref()).and_then(create_recorder).map(Arc::new),
+            labels,
+            statement_timeouts: config.statement_timeouts(),
+            drain_timeouts: config.drain_timeouts(),
+            tests_before_acquire: config.tests_before_acquire(),
+            smoothed_saturation: Default::default(),
+            brownout: config.read.as_ref().and_then(|r| r.brownout.clone()).map(|b| Arc::new(brownout::Brownout::new(b))),
+            primary_share: config.read.as_ref().and_then(|r| brownout::PrimaryShare::new(r.primary_read_ratio?)).map(Arc::new),
+            slow_start: config.read.as_ref().and_then(|r| r.slow_start_ms).map(Duration::from_millis),
+            min_healthy: config.read.as_ref().and_then(|r| Some((r.min_healthy?, r.below_min_healthy.unwrap_or_default()))),
+            max_wait: config.read.as_ref().and_then(|r| r.max_wait_ms).map(Duration::from_millis),
+            prefer_zone: zone::preferred(config.read.as_ref()),
+            spill: Default::default(),
+            breaker: config.read.as_ref().and_then(|r| r.circuit_breaker.clone()).map(|b| Arc::new(breaker::CircuitBreaker::new(b))),
+            outage: config.read_only_mode.as_ref().map(|o| Arc::new(o.breaker())),
+            usage: Default::default(),
+            usage_sampled: Default::default(),
+            warmup: config.read.as_ref().and_then(|r| Some((r.warmup?, r.warmup_concurrency.unwrap_or(warmup::DEFAULT_CONCURRENCY)))),
+            min_connections: config.warmup.map(|policy| Arc::new(warmup::MinConnections{
+                policy,
+                main: figment.extract_inner(keys::MIN_CONNECTIONS).unwrap_or(0),
+            })),
+            retry: acquire::Retry::configured(&config),
+            hooks: Arc::new(hooks::Hooks::new(config.lease_warn_ms.map(lease::Leases::new))),
+            stopping: Default::default(),
+            log,
+            #[cfg(feature = "cache")]
+            cache: Arc::new(cache::ResultCache::new(config.read.as_ref().and_then(|r| r.cache).unwrap_or_default())),
+            #[cfg(feature = "testing")]
+            script: Default::default(),
+            config: Arc::new(std::sync::RwLock::new(Arc::new(config))),
+        })
     }
 
     async fn get(&self) -> Result<Self::Connection, Self::Error> {
-        self.main.get().await
+        if self.prefer_read && !prefer::is_writing() {
+            let checkout = trace::Checkout::new("get_read", &self.labels.read);
+            let start = Instant::now();
+            let (role, reason, result) = checkout.run(self.read_routed(|conn| conn, prefer::cast)).await;
+            checkout.served(role, Some(self.labels.get(role)), start, result.is_ok(), reason);
+            return result;
+        }
+        let checkout = trace::Checkout::new("get", &self.labels.main);
+        let start = Instant::now();
+        let result = checkout.run(async {
+            self.route(PoolRole::Main);
+            self.get_primary().await
+        }).await;
+        checkout.served(PoolRole::Main, Some(&self.labels.main), start, result.is_ok(), None);
+        result
     }
 
     async fn close(&self) {
-        self.main.close().await;
-        if let Some(ref read) = self.read {read.close().await;}
+        self.close_within(|_| None, |_| None).await;
     }
 }
-#[async_trait]
-impl<P> PoolRead for ReadPool<P> where P: Pool{
-    async fn get_read(&self) -> Result<<P>::Connection, P::Error> {
-        self.read.as_ref().unwrap_or(&self.main).get().await
+///Whether `prefer = "read"` is set and can be honoured, as it can only while both pools hand
+///out the same connection type
+fn prefer_read<P: Pool, R: Pool>(config: &ReadDbConfig, labels: &PerRole<Arc<str>>) -> bool
+    where P::Connection: 'static, R::Connection: 'static
+{
+    if config.prefer != Some(Prefer::Read) {
+        return false;
+    }
+    let castable = prefer::castable::<R::Connection, P::Connection>();
+    if !castable {
+        rocket::warn!("`{}` pool: `prefer = \"read\"` ignored, as `{}` hands out a different connection type", labels.main, labels.read);
+    }
+    castable
+}
+fn create_recorder(path: &str) -> Option<Recorder> {
+    Recorder::create(path)
+        .map_err(|e| rocket::error!("failed to create read query recording `{}`: {}", path, e))
+        .ok()
+}
+impl<P, R> ReadPool<P, R> {
+    ///The configured label of the pool with the given role
+    pub fn label(&self, role: PoolRole) -> &str {
+        self.labels.get(role)
+    }
+
+    ///Settles which pool serves an acquisition, given the pool normal routing would use
+    #[cfg_attr(not(feature = "testing"), inline(always))]
+    fn route(&self, role: PoolRole) -> PoolRole {
+        #[cfg(feature = "testing")]
+        if let Some(ref script) = *self.script.read().unwrap_or_else(|e| e.into_inner()) {
+            return script.route(role);
+        }
+        role
+    }
+
+    ///The index of the read replica of `set` to use next, as chosen by the balancer if there
+    ///are several. If the chosen replica is unavailable, e.g. quarantined, the next available one
+    ///is used, or none if all are unavailable. With `read.prefer_zone`, replicas in that zone
+    ///come first.
+    fn next_replica(&self, set: &replicas::ReplicaSet<R>) -> Option<usize> {
+        self.next_replica_except(set, None)
+    }
+
+    ///The index of the read replica of `set` to use next, as by `next_replica`, other than
+    ///`failed`
+    fn next_replica_except(&self, set: &replicas::ReplicaSet<R>, failed: Option<usize>) -> Option<usize> {
+        let admits = |i: usize| Some(i) != failed && set.replicas[i].health.available()
+            && self.slow_start.is_none_or(|window| set.replicas[i].health.admits(window));
+        if let Some(ref zone) = self.prefer_zone {
+            if set.replicas.iter().any(|r| r.zone.as_deref() == Some(&**zone)) {
+                return self.next_local_replica(set, zone, admits);
+            }
+        }
+        balance::pick_admitted(&**self.balancer.read().unwrap_or_else(|e| e.into_inner()), &set.pools, &set.weights, admits)
+    }
+
+    ///What reads do while fewer than `read.min_healthy` of the replicas of `set` are healthy, or
+    ///`None` while enough are
+    fn below_min_healthy(&self, set: &replicas::ReplicaSet<R>) -> Option<BelowMinHealthy> {
+        let (min, below) = self.min_healthy?;
+        (set.replicas.iter().filter(|r| r.health.available()).count() < min).then_some(below)
+    }
+
+    ///Whether brownout mode sheds the next read to the main pool
+    fn shed_read(&self) -> bool {
+        self.brownout.as_ref().is_some_and(|b| b.shed())
+    }
+
+    ///Records a read query and how long it took, if `read.record` is configured
+    pub fn record_read(&self, sql: &str, duration: std::time::Duration) {
+        if let Some(Err(e)) = self.recorder.as_ref().map(|r| r.record(sql, duration)) {
+            rocket::error!("failed to record read query: {}", e);
+        }
+    }
+}
+impl<P, R: Pool> ReadPool<P, R> {
+    ///Where the next read goes: the index of the replica of `set` to read from, or why the main
+    ///pool serves it instead. Replica failures aside, every read acquisition is routed by it.
+    fn read_target(&self, set: &replicas::ReplicaSet<R>) -> Result<usize, FallbackReason> {
+        match self.next_replica(set) {
+            None => {
+                self.route(PoolRole::Main);
+                match set.is_empty() && !self.read_pending() {
+                    true => Err(FallbackReason::NoReadPool),
+                    false => Err(FallbackReason::Unavailable),
+                }
+            }
+            Some(_) if self.route(PoolRole::Read) != PoolRole::Read => Err(FallbackReason::Forced),
+            Some(_) if self.below_min_healthy(set) == Some(BelowMinHealthy::Main) => Err(FallbackReason::MinHealthy),
+            Some(_) if !self.read_only() && self.primary_share.as_ref().is_some_and(|s| s.take()) => Err(FallbackReason::PrimaryRatio),
+            Some(_) if !self.read_only() && self.shed_read() => Err(FallbackReason::Brownout),
+            Some(_) if !self.breaker_allows() => Err(FallbackReason::CircuitOpen),
+            Some(i) => Ok(i),
+        }
+    }
+}
+impl<P: Pool, R> ReadPool<P, R> where P::Connection: Send + 'static {
+    ///Gets a read connection from the main pool, counting it as a fallback for `reason` if
+    ///there are replicas
+    async fn read_main<C>(&self, reason: Option<FallbackReason>) -> Option<Result<C, P::Error>> where P::Connection: Into<C> {
+        self.route(PoolRole::Main);
+        match self.replica_set().is_empty() {
+            true => self.usage.main.unreplicated(),
+            false => self.usage.read.fell_back(reason),
+        }
+        Some(self.get_primary().await.map(Into::into))
+    }
+
+    ///Gets a connection from read replica `i` of `set`, counting it towards the usage summary
+    ///and circuit breaker and running the hooks, along with the replica's label
+    async fn get_replica(&self, set: &replicas::ReplicaSet<R>, i: usize) -> (String, Result<R::Connection, R::Error>)
+        where R: Pool, R::Connection: Send + 'static
+    {
+        let label = set.label(&self.labels.read, i);
+        let start = Instant::now();
+        let mut conn = {
+            let result = self.acquire_tested(PoolRole::Read, &label, || set.pools[i].get()).await;
+            self.observe_read_latency(start.elapsed());
+            self.breaker_record(result.is_ok());
+            self.usage.read.acquired(start, result.is_ok());
+            set.replicas[i].usage.acquired(start, result.is_ok());
+            match result {
+                Ok(conn) => conn,
+                Err(e) => {
+                    self.observe_replica_failure(set, i, &e, start.elapsed());
+                    return (label.clone(), Err(self.hooks.failed(PoolRole::Read, &label, start, e)));
+                }
+            }
+        };
+        self.hooks.acquired(PoolRole::Read, &label, start, &mut conn, &self.log).await;
+        (label, Ok(conn))
+    }
+
+    ///Records a failed acquisition from read replica `i` of `set` with its health, for
+    ///`read.health_check.surge_resets`
+    fn observe_replica_failure(&self, set: &replicas::ReplicaSet<R>, i: usize, error: &R::Error, elapsed: Duration) where R: Pool {
+        let Some(config) = self.health_check.as_ref() else {return};
+        if set.replicas[i].health.observe_failure(&error.to_string(), elapsed, config) {
+            db_log!(self.log, General, Warn, "`{}` replica `{}` quarantined as a surge queue, accepting then resetting connections: {}",
+                self.labels.read, set.replicas[i].name, error);
+        }
+    }
+
+    ///Gets a connection from the current primary, counting it towards the usage summary and
+    ///read-only mode and running the hooks
+    async fn get_primary(&self) -> Result<P::Connection, P::Error> {
+        let start = Instant::now();
+        let mut conn = {
+            let result = self.acquire_tested(PoolRole::Main, &self.labels.main, || self.primary().get()).await;
+            self.outage_record(result.is_ok());
+            self.usage.main.acquired(start, result.is_ok());
+            result.map_err(|e| self.hooks.failed(PoolRole::Main, &self.labels.main, start, e))?
+        };
+        self.hooks.acquired(PoolRole::Main, &self.labels.main, start, &mut conn, &self.log).await;
+        Ok(conn)
+    }
+}
+impl<P: Pool, R: Pool> ReadPool<P, R>
+    where R::Error: Into<P::Error>, P::Error: From<figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static
+{
+    ///Gets a connection for reading as `ReadCapablePool::get_read_explained` does, converting
+    ///it with `main` or `read` according to the pool which served it
+    async fn read_routed<C>(&self, main: fn(P::Connection) -> C, read: fn(R::Connection) -> C) -> (PoolRole, Option<FallbackReason>, Result<C, P::Error>) {
+        let uncreated = match self.connect_read().await {
+            Err(e) if self.on_error == OnError::Error || !budget::take() => {
+                db_log!(self.log, General, Warn, "`{}` pool couldn't be created: {}", self.labels.read, e);
+                return (PoolRole::Read, None, Err(e.into()));
+            }
+            Err(e) => {
+                db_log!(self.log, Routing, Warn, "`{}` pool couldn't be created, falling back to `{}`: {}", self.labels.read, self.labels.main, e);
+                true
+            }
+            Ok(()) => false,
+        };
+        if uncreated {
+            self.usage.read.fell_back(Some(FallbackReason::ReadFailed));
+            return (PoolRole::Main, Some(FallbackReason::ReadFailed), self.get_primary().await.map(main));
+        }
+        let set = self.replica_set();
+        let reason = match self.read_target(&set) {
+            Err(FallbackReason::NoReadPool) => {
+                self.usage.main.unreplicated();
+                return (PoolRole::Main, Some(FallbackReason::NoReadPool), self.get_primary().await.map(main));
+            }
+            Err(reason) => reason,
+            Ok(i) => 'replica: {
+                let (mut i, mut retried) = (i, false);
+                let (label, e) = loop {
+                    let attempt = match self.max_wait {
+                        Some(wait) => rocket::tokio::time::timeout(wait, self.get_replica(&set, i)).await,
+                        None => Ok(self.get_replica(&set, i).await),
+                    };
+                    let (label, e) = match attempt {
+                        Ok((_, Ok(conn))) => {
+                            source::served_by(&set.replicas[i].name);
+                            return (PoolRole::Read, None, Ok(read(conn)));
+                        }
+                        Ok((label, Err(e))) => (label, e),
+                        Err(_) => {
+                            db_log!(self.log, Routing, Debug, "`{}` pool had no connection within `read.max_wait_ms`, using `{}`",
+                                set.label(&self.labels.read, i), self.labels.main);
+                            break 'replica FallbackReason::MaxWait;
+                        }
+                    };
+                    let retry = match self.on_error {
+                        OnError::Error => return (PoolRole::Read, None, Err(e.into())),
+                        OnError::RetryThenFallback if !retried => self.next_replica_except(&set, Some(i)).filter(|_| budget::take()),
+                        _ => None,
+                    };
+                    match retry {
+                        Some(next) => {
+                            db_log!(self.log, Routing, Debug, "`{}` pool failed, retrying: {}", label, e);
+                            (i, retried) = (next, true);
+                        }
+                        None => break (label, e),
+                    }
+                };
+                if !budget::take() {
+                    db_log!(self.log, Routing, Debug, "`{}` pool failed with the request's retry budget spent", label);
+                    return (PoolRole::Read, None, Err(e.into()));
+                }
+                db_log!(self.log, Routing, Warn, "`{}` pool failed, falling back to `{}`: {}", label, self.labels.main, e);
+                FallbackReason::ReadFailed
+            }
+        };
+        self.usage.read.fell_back(Some(reason));
+        (PoolRole::Main, Some(reason), self.get_primary().await.map(main))
+    }
+}
+impl<P, R, C> ReadCapablePool<C> for ReadPool<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<figment::Error>,
+        P::Connection: Into<C> + Send + 'static, R::Connection: Into<C> + Send + 'static, C: Send + 'static
+{
+    async fn get_read(&self) -> (PoolRole, Result<C, P::Error>) {
+        let checkout = trace::Checkout::new("get_read", &self.labels.read);
+        let start = Instant::now();
+        let (role, reason, result) = checkout.run(self.get_read_explained()).await;
+        checkout.served(role, Some(self.labels.get(role)), start, result.is_ok(), reason);
+        (role, result)
+    }
+
+    async fn get_read_explained(&self) -> (PoolRole, Option<FallbackReason>, Result<C, P::Error>) {
+        self.read_routed(Into::into, Into::into).await
+    }
+
+    async fn get_read_main(&self) -> Option<Result<C, P::Error>> {
+        self.read_main(None).await
+    }
+
+    async fn get_read_main_for(&self, reason: FallbackReason) -> Option<Result<C, P::Error>> {
+        self.read_main(Some(reason)).await
+    }
+
+    async fn get_delayed(&self) -> Option<Result<C, P::Error>> {
+        match self.delayed {
+            Some(ref delayed) => {
+                self.route(PoolRole::Delayed);
+                let start = Instant::now();
+                let mut conn = {
+                    let result = self.acquire_tested(PoolRole::Delayed, &self.labels.delayed, || delayed.get()).await;
+                    self.usage.delayed.acquired(start, result.is_ok());
+                    match result {
+                        Ok(conn) => conn,
+                        Err(e) => return Some(Err(self.hooks.failed(PoolRole::Delayed, &self.labels.delayed, start, e).into())),
+                    }
+                };
+                self.hooks.acquired(PoolRole::Delayed, &self.labels.delayed, start, &mut conn, &self.log).await;
+                Some(Ok(conn.into()))
+            }
+            None => None,
+        }
+    }
+
+    fn has_delayed(&self) -> bool {
+        self.delayed.is_some()
+    }
+
+    fn pool_label(&self, role: PoolRole) -> Arc<str> {
+        self.labels.get(role).clone()
+    }
+
+    fn statement_timeout(&self, role: PoolRole) -> Option<Duration> {
+        *self.statement_timeouts.get(role)
+    }
+
+    fn log_config(&self) -> Option<&LogConfig> {
+        Some(&self.log)
+    }
+
+    fn pins_after_write(&self) -> bool {
+        self.pin_after_write
+    }
+
+    fn replica_counts(&self) -> Option<(usize, usize)> {
+        let set = self.replica_set();
+        Some((set.len(), set.replicas.iter().filter(|r| r.health.available()).count()))
+    }
+
+    fn sheds_low_priority(&self) -> bool {
+        self.below_min_healthy(&self.replica_set()) == Some(BelowMinHealthy::Shed)
     }
 }
 
@@ -77,58 +1092,198 @@ impl<P> PoolRead for ReadPool<P> where P: Pool{
 ///
 /// For a database type of `Db`, a request guard of `ReadConnection<Db>` retrieves a
 /// single connection to `Db`.
-pub struct ReadConnection<D: Database>(<D::Pool as Pool>::Connection);
-impl<D: Database> ReadConnection<D> {
+///
+/// The connection type defaults to the main pool's connection type. When the read side is a
+/// different backend (e.g. a wire-compatible analytical replica), name the connection type
+/// explicitly as `ReadConnection<Db, C>`: connections from either pool are converted into `C`
+/// with `Into` when the guard is constructed.
+///
+/// Like the crate's other connection guards, it fails with a [`ReadPoolError`] telling which pool
+/// failed, or why no pool was tried.
+pub struct ReadConnection<D: Database, C = <<D as Database>::Pool as Pool>::Connection>(C, PhantomData<fn() -> D>, Option<Duration>, Option<hooks::Release>, ConnectionSource);
+impl<D: Database, C> ReadConnection<D, C> {
     ///Gets the internal connection value
-    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+    pub fn into_inner(self) -> C {
+        //The connection's release can't be observed beyond the guard
+        drop(self.3);
         self.0
     }
+
+    ///Gets the internal connection value along with its release ticket, for guards wrapping
+    ///this one to keep until they give up the connection
+    pub(crate) fn into_parts(self) -> (C, Option<hooks::Release>) {
+        (self.0, self.3)
+    }
+
+    ///The pool which served the connection, and the replica if one did
+    pub fn source(&self) -> &ConnectionSource {
+        &self.4
+    }
+
+    ///Whether a read replica served the connection, rather than the main pool
+    pub fn is_replica(&self) -> bool {
+        self.4.is_replica()
+    }
+
+    ///Converts into a connection a streamed response can own, returned to its pool when the
+    ///stream is dropped, see [`StreamConnection`]
+    pub fn into_stream_guard(self) -> StreamConnection<C> {
+        StreamConnection::new(self.0, self.3, self.4)
+    }
+}
+impl<D: Database, C: Send> ReadConnection<D, C> where D::Pool: ReadCapablePool<C> {
+    ///Acquires a connection with the same routing as the request guard, for code which has no
+    ///request to hand or composes a connection into its own guard. Unlike the guard, the
+    ///acquisition isn't recorded in [`RequestRouting`].
+    ///
+    ///Fails with [`ReadPoolError::DatabaseNotAttached`] if the database isn't attached.
+    pub async fn from_rocket<P: Phase>(rocket: &Rocket<P>) -> Result<Self, ReadPoolError<<D::Pool as Pool>::Error>> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(rocket, PoolRole::Read) {
+            return Err(ReadPoolError::Injected(PoolRole::Read));
+        }
+        let db = D::fetch(rocket).ok_or(ReadPoolError::DatabaseNotAttached)?;
+        let (((role, result), release), replica) = source::traced(hooks::ticketed(None, db.get_read())).await;
+        let conn = result.map_err(|e| ReadPoolError::acquisition(role, &db.pool_label(role), e))?;
+        Ok(ReadConnection(conn, PhantomData, db.statement_timeout(role), release, ConnectionSource::new(role, replica, None)))
+    }
 }
 #[rocket::async_trait]
-impl<'r, D: Database> FromRequest<'r> for ReadConnection<D> where D::Pool: PoolRead {
-    type Error = Option<<D::Pool as Pool>::Error>;
+impl<'r, D: Database, C> FromRequest<'r> for ReadConnection<D, C> where D::Pool: ReadCapablePool<C>, C: Send + 'static {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(req.rocket(), PoolRole::Read) {
+            return failure::outcome(req, D::NAME, ReadPoolError::Injected(PoolRole::Read));
+        }
+        if let Err(e) = shard::resolve::<D, _>(req) {
+            return failure::outcome(req, D::NAME, e);
+        }
+        if let Some(db) = D::fetch(req.rocket()).filter(|_| RequestRouting::of(req).low_priority()) {
+            if shard::scoped::<D, _>(RequestRouting::of(req), async {db.sheds_low_priority()}).await {
+                return failure::outcome(req, D::NAME, ReadPoolError::Shed);
+            }
+        }
         match D::fetch(req.rocket()) {
-            Some(db) => match db.get_read().await {
-                Ok(conn) => Outcome::Success(ReadConnection(conn)),
-                Err(e) => Outcome::Error((Status::ServiceUnavailable, Some(e))),
+            Some(db) => {
+                let checkout = trace::Checkout::new("read_connection", D::NAME);
+                let start = Instant::now();
+                let acquisition = source::traced(hooks::ticketed(Some(routing::route(req)), checkout.run(budget::scoped(req, consistency::get_read::<D, C>(req, db)))));
+                let acquisition = shard::scoped::<D, _>(RequestRouting::of(req), acquisition);
+                let (((role, fallback, result), release), replica) = match deadline::within(RequestRouting::of(req), acquisition).await {
+                    Ok(acquired) => acquired,
+                    Err(waited) => return failure::outcome(req, D::NAME, ReadPoolError::Timeout(waited)),
+                };
+                checkout.served(role, Some(&db.pool_label(role)), start, result.is_ok(), fallback);
+                if let Some(reason) = fallback {
+                    let log = db.log_config().cloned().unwrap_or_default();
+                    db_log!(log, Routing, Debug, "database `{}`: read served by the main pool: {}", D::NAME, reason.name());
+                }
+                RequestRouting::record_fallback::<D>(req, role, Some(db.pool_label(role)), start, result.is_ok(), fallback);
+                match result {
+                    Ok(conn) => {
+                        let source = ConnectionSource::new(role, replica, fallback);
+                        Outcome::Success(ReadConnection(conn, PhantomData, db.statement_timeout(role), release, source))
+                    }
+                    Err(e) => failure::outcome(req, D::NAME, ReadPoolError::acquisition(role, &db.pool_label(role), e)),
+                }
             },
-            None => Outcome::Error((Status::InternalServerError, None)),
+            None => failure::outcome(req, D::NAME, ReadPoolError::DatabaseNotAttached),
         }
     }
 }
-impl<D: Database> Sentinel for ReadConnection<D> {
+impl<D: Database, C> Sentinel for ReadConnection<D, C> {
     fn abort(rocket: &Rocket<Ignite>) -> bool {
         D::fetch(rocket).is_none()
     }
 }
-impl<D: Database> Deref for ReadConnection<D> {
-    type Target = <D::Pool as Pool>::Connection;
+impl<D: Database, C> Deref for ReadConnection<D, C> {
+    type Target = C;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
-impl<D: Database> DerefMut for ReadConnection<D> {
+impl<D: Database, C> DerefMut for ReadConnection<D, C> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
-impl<D: Database> ReadConnection<D> {
+impl<D: Database, C: StatementTimeout> ReadConnection<D, C> {
+    ///Bounds how long statements on this connection may run, for the current transaction.
+    ///
+    ///The timeout can only be tightened: it is capped by the pool's `statement_timeout_ms` and
+    ///by any timeout previously set through this guard, so a generous pool default can't leave
+    ///public endpoints with unbounded queries.
+    pub async fn with_statement_timeout(&mut self, timeout: Duration) -> Result<&mut Self, BoxError> {
+        let timeout = self.2.map_or(timeout, |current| current.min(timeout));
+        self.0.set_statement_timeout(timeout).await?;
+        self.2 = Some(timeout);
+        Ok(self)
+    }
+
+    ///The statement timeout in effect through this guard, if known
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.2
+    }
+}
+
+///Sets the statement timeout on a connection, for [`ReadConnection::with_statement_timeout`].
+///
+///Implement it for your connection type, e.g. with `SET LOCAL statement_timeout = ...` on
+///Postgres.
+#[rocket::async_trait]
+pub trait StatementTimeout: Send {
+    async fn set_statement_timeout(&mut self, timeout: Duration) -> Result<(), BoxError>;
+}
+
+impl<D: Database, C> ReadConnection<D, C> {
     ///Provided for symmetry with RwConnection
-    pub fn into_read_connection(self) -> ReadConnection<D>{
+    pub fn into_read_connection(self) -> ReadConnection<D, C>{
         self
     }
     ///Provided for symmetry with RwConnection
-    pub fn as_read_connection(&self) -> &ReadConnection<D>{
+    pub fn as_read_connection(&self) -> &ReadConnection<D, C>{
         self
     }
     ///Provided for symmetry with RwConnection
-    pub fn as_read_connection_mut(&mut self) -> &mut ReadConnection<D>{
+    pub fn as_read_connection_mut(&mut self) -> &mut ReadConnection<D, C>{
         self
     }
 }
+impl<D: Database> ReadConnection<D> {
+    ///Escalates to a main pool connection, for code which finds it needs to write after
+    ///reading. The read connection is returned to its pool first, so the two are never held
+    ///together, then a connection is acquired as the [`RwConnection`] guard would, recorded in
+    ///the request's [`RequestRouting`]. Handlers, which have no `Request` to hand, can use the
+    ///[`ReadOrRw`] guard instead.
+    ///
+    ///Anything read should be read again through the new connection if it must be current, as
+    ///the replica may have lagged.
+    ///```rust
+    /// # use rocket::Request;
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::testing::MockPool;
+    /// # use rocket_read_db_pools::{BoxError, ReadPool};
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// use rocket_read_db_pools::ReadConnection;
+    ///
+    /// async fn touch(req: &Request<'_>, mut conn: ReadConnection<Db>, id: i64) -> Result<(), BoxError> {
+    ///     let stale: bool = conn.query("SELECT updated_at < now() - interval '1 day' FROM items WHERE id = $1")
+    ///         .bind(id).fetch_one().await?;
+    ///     if stale {
+    ///         let mut conn = conn.upgrade(req).await.map_err(|_| "main pool unavailable")?;
+    ///         conn.query("UPDATE items SET updated_at = now() WHERE id = $1").bind(id).execute().await?;
+    ///     }
+    ///     Ok(())
+    /// }
+    ///```
+    pub async fn upgrade(self, req: &Request<'_>) -> Result<RwConnection<D>, ReadPoolError<<D::Pool as Pool>::Error>> {
+        drop(self);
+        RwConnection::acquire(req.rocket(), RequestRouting::routed(req)).await.map_err(|(_, e)| e)
+    }
+}
 
 /// A request guard which retrieves a single connection to a [`Database`] using the main connection url.
 /// Can be downgraded into a `ReadConnection`
@@ -139,7 +1294,14 @@ pub struct RwConnection<D: Database>(ReadConnection<D>);
 impl<D: Database> RwConnection<D> {
     ///Gets the internal connection value
     pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
-        self.0.0
+        self.0.into_inner()
+    }
+    ///Gets the internal connection value along with its release ticket
+    pub(crate) fn into_parts(self) -> (<D::Pool as Pool>::Connection, Option<hooks::Release>) {
+        self.0.into_parts()
+    }
+    pub(crate) fn from_inner(conn: <D::Pool as Pool>::Connection) -> Self {
+        RwConnection(ReadConnection(conn, PhantomData, None, None, ConnectionSource::main()))
     }
     ///Dowgrades this into a `ReadConnection`
     pub fn into_read_connection(self) -> ReadConnection<D>{
@@ -153,18 +1315,67 @@ impl<D: Database> RwConnection<D> {
     pub fn as_read_connection_mut(&mut self) -> &mut ReadConnection<D>{
         &mut self.0
     }
+    ///Converts into a connection a streamed response can own, returned to the main pool when
+    ///the stream is dropped, see [`StreamConnection`]
+    pub fn into_stream_guard(self) -> StreamConnection<<D::Pool as Pool>::Connection> {
+        self.0.into_stream_guard()
+    }
+}
+impl<D: Database> RwConnection<D> {
+    ///Acquires a connection from the main pool as the request guard does, for code which has no
+    ///request to hand or composes a connection into its own guard. Unlike the guard, the
+    ///acquisition isn't recorded in [`RequestRouting`].
+    ///
+    ///Fails with [`ReadPoolError::DatabaseNotAttached`] if the database isn't attached.
+    pub async fn from_rocket<P: Phase>(rocket: &Rocket<P>) -> Result<Self, ReadPoolError<<D::Pool as Pool>::Error>> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(rocket, PoolRole::Main) {
+            return Err(ReadPoolError::Injected(PoolRole::Main));
+        }
+        let db = D::fetch(rocket).ok_or(ReadPoolError::DatabaseNotAttached)?;
+        let (result, release) = hooks::ticketed(None, prefer::writing(db.get())).await;
+        let conn = result.map_err(ReadPoolError::MainUnavailable)?;
+        Ok(RwConnection(ReadConnection(conn, PhantomData, None, release, ConnectionSource::main())))
+    }
+
+    ///Acquires a connection as the request guard does, for the request with the routing record
+    ///`routing`
+    pub(crate) async fn acquire(rocket: &Rocket<Orbit>, routing: &RequestRouting) -> Result<Self, (Status, ReadPoolError<<D::Pool as Pool>::Error>)> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(rocket, PoolRole::Main) {
+            return Err((Status::ServiceUnavailable, ReadPoolError::Injected(PoolRole::Main)));
+        }
+        let db = D::fetch(rocket).ok_or((Status::InternalServerError, ReadPoolError::DatabaseNotAttached))?;
+        let start = Instant::now();
+        if rocket.state::<outage::Registry>().is_some_and(|r| r.refuses(D::NAME)) {
+            routing.push::<D>(PoolRole::Main, None, start, false, None);
+            routing.refuse_write::<D>();
+            return Err((Status::ServiceUnavailable, ReadPoolError::ReadOnly));
+        }
+        let checkout = trace::Checkout::new("rw_connection", D::NAME);
+        let acquisition = hooks::ticketed(routing.route(), checkout.run(budget::scoped_in(rocket, routing, prefer::writing(db.get()))));
+        let acquisition = shard::scoped::<D, _>(routing, acquisition);
+        let (result, release) = deadline::within(routing, acquisition).await
+            .map_err(|waited| (Status::ServiceUnavailable, ReadPoolError::Timeout(waited)))?;
+        checkout.served(PoolRole::Main, None, start, result.is_ok(), None);
+        routing.push::<D>(PoolRole::Main, None, start, result.is_ok(), None);
+        match result {
+            Ok(conn) => Ok(RwConnection(ReadConnection(conn, PhantomData, None, release, ConnectionSource::main()))),
+            Err(e) => Err((Status::ServiceUnavailable, ReadPoolError::MainUnavailable(e))),
+        }
+    }
 }
 #[rocket::async_trait]
 impl<'r, D: Database> FromRequest<'r> for RwConnection<D> {
-    type Error = Option<<D::Pool as Pool>::Error>;
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        match D::fetch(req.rocket()) {
-            Some(db) => match db.get().await {
-                Ok(conn) => Outcome::Success(RwConnection(ReadConnection(conn))),
-                Err(e) => Outcome::Error((Status::ServiceUnavailable, Some(e))),
-            },
-            None => Outcome::Error((Status::InternalServerError, None)),
+        if let Err(e) = shard::resolve::<D, _>(req) {
+            return failure::outcome(req, D::NAME, e);
+        }
+        match Self::acquire(req.rocket(), RequestRouting::routed(req)).await {
+            Ok(conn) => Outcome::Success(conn),
+            Err((_, e)) => failure::outcome(req, D::NAME, e),
         }
     }
 }
@@ -186,6 +1397,81 @@ impl<D: Database> DerefMut for RwConnection<D> {
     }
 }
 
+/// A request guard which retrieves a single connection to a [`Database`] from the delayed
+/// replica configured under `read.delayed`.
+///
+/// Unlike [`ReadConnection`] this never falls back to another pool, as that would silently serve
+/// current data. Launch is aborted if a route uses this guard without a delayed replica configured.
+pub struct DelayedReadConnection<D: Database, C = <<D as Database>::Pool as Pool>::Connection>(C, PhantomData<fn() -> D>, Option<hooks::Release>);
+impl<D: Database, C> DelayedReadConnection<D, C> {
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> C {
+        drop(self.2);
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database, C> FromRequest<'r> for DelayedReadConnection<D, C> where D::Pool: ReadCapablePool<C>, C: Send {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(req.rocket(), PoolRole::Delayed) {
+            return failure::outcome(req, D::NAME, ReadPoolError::Injected(PoolRole::Delayed));
+        }
+        if let Err(e) = shard::resolve::<D, _>(req) {
+            return failure::outcome(req, D::NAME, e);
+        }
+        match D::fetch(req.rocket()) {
+            Some(db) => {
+                let start = Instant::now();
+                let acquisition = shard::scoped::<D, _>(RequestRouting::of(req), hooks::ticketed(Some(routing::route(req)), db.get_delayed()));
+                let (result, release) = match deadline::within(RequestRouting::of(req), acquisition).await {
+                    Ok(acquired) => acquired,
+                    Err(waited) => return failure::outcome(req, D::NAME, ReadPoolError::Timeout(waited)),
+                };
+                if let Some(ref result) = result {
+                    RequestRouting::record::<D>(req, PoolRole::Delayed, Some(db.pool_label(PoolRole::Delayed)), start, result.is_ok());
+                }
+                match result {
+                    Some(Ok(conn)) => Outcome::Success(DelayedReadConnection(conn, PhantomData, release)),
+                    Some(Err(e)) => {
+                        let label = db.pool_label(PoolRole::Delayed);
+                        failure::outcome(req, D::NAME, ReadPoolError::acquisition(PoolRole::Delayed, &label, e))
+                    },
+                    None => failure::outcome(req, D::NAME, ReadPoolError::Unconfigured(PoolRole::Delayed)),
+                }
+            },
+            None => failure::outcome(req, D::NAME, ReadPoolError::DatabaseNotAttached),
+        }
+    }
+}
+impl<D: Database, C> Sentinel for DelayedReadConnection<D, C> where D::Pool: ReadCapablePool<C> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        match D::fetch(rocket) {
+            Some(db) if db.has_delayed() => false,
+            Some(_) => {
+                rocket::error!("`DelayedReadConnection<{}>` used without a `read.delayed` replica configured",
+                    std::any::type_name::<D>());
+                true
+            }
+            None => true,
+        }
+    }
+}
+impl<D: Database, C> Deref for DelayedReadConnection<D, C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<D: Database, C> DerefMut for DelayedReadConnection<D, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
 #[cfg(feature="rocket_okapi")]
 mod okapi{
     use super::*;
@@ -193,7 +1479,37 @@ mod okapi{
     use rocket_okapi::gen::OpenApiGenerator;
     use rocket_okapi::request::RequestHeaderInput;
     use rocket_okapi::OpenApiError;
-    impl<'r, D: Database> OpenApiFromRequest<'r> for ReadConnection<D> where D::Pool: PoolRead {
+    impl<'r, D: Database, C: Send + 'static> OpenApiFromRequest<'r> for ReadConnection<D, C> where D::Pool: ReadCapablePool<C> {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database, C: Send> OpenApiFromRequest<'r> for DelayedReadConnection<D, C> where D::Pool: ReadCapablePool<C> {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database> OpenApiFromRequest<'r> for AutoConnection<D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database> OpenApiFromRequest<'r> for Primary<D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database> OpenApiFromRequest<'r> for StatementRouter<'r, D> {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database> OpenApiFromRequest<'r> for ReadTransaction<D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Transactional {
+        fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
+            Ok(RequestHeaderInput::None)
+        }
+    }
+    impl<'r, D: Database> OpenApiFromRequest<'r> for RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
         fn from_request_input(_gen: &mut OpenApiGenerator, _name: String, _required: bool) -> Result<RequestHeaderInput, OpenApiError> {
             Ok(RequestHeaderInput::None)
         }
@@ -204,3 +1520,148 @@ mod okapi{
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Format, Toml};
+    use crate::testing::{pair_figment, MockError, MockPool, MockPoolConnection};
+    use super::*;
+
+    type Read = (PoolRole, Option<FallbackReason>, Result<MockPoolConnection, MockError>);
+
+    async fn pool(toml: &str) -> ReadPool<MockPool> {
+        ReadPool::init(&Figment::from(Toml::string(toml))).await.unwrap()
+    }
+
+    async fn read(pool: &ReadPool<MockPool>) -> Read {
+        pool.get_read_explained().await
+    }
+
+    ///The role, fallback reason and url of the pool serving a read
+    async fn served(pool: &ReadPool<MockPool>) -> (PoolRole, Option<FallbackReason>, String) {
+        let (role, reason, conn) = read(pool).await;
+        (role, reason, conn.unwrap().url().to_string())
+    }
+
+    const TWO_REPLICAS: &str = "url = \"main\"\n[read]\nreplicas = [{url = \"r0\"}, {url = \"r1\"}]\n";
+
+    #[rocket::async_test]
+    async fn reads_use_the_replica_and_writes_the_main_pool() {
+        let pool = ReadPool::<MockPool>::init(&pair_figment("main", "read")).await.unwrap();
+        assert_eq!(served(&pool).await, (PoolRole::Read, None, "read".to_string()));
+        assert_eq!(pool.get().await.unwrap().url(), "main");
+        assert!(pool.has_read_replica());
+    }
+
+    #[rocket::async_test]
+    async fn reads_without_a_read_pool_use_the_main_pool() {
+        let pool = pool("url = \"main\"").await;
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::NoReadPool), "main".to_string()));
+        assert!(!pool.has_read_replica());
+        let off = self::pool("url = \"main\"\nread = false\nread_urls = [\"r0\"]").await;
+        assert!(!off.has_read_replica());
+    }
+
+    #[rocket::async_test]
+    async fn replicas_take_turns() {
+        let pool = pool(TWO_REPLICAS).await;
+        let mut urls = Vec::new();
+        for _ in 0..4 {
+            urls.push(served(&pool).await.2);
+        }
+        assert_eq!(urls, ["r0", "r1", "r0", "r1"]);
+    }
+
+    #[rocket::async_test]
+    async fn read_failures_follow_on_error() {
+        let pool = pool("url = \"main\"\n[read]\nurl = \"r0\"").await;
+        pool.read().unwrap().fail_next(1);
+        let (role, _, conn) = read(&pool).await;
+        assert!(matches!((role, conn), (PoolRole::Read, Err(MockError::Unavailable(url))) if url == "r0"));
+
+        let pool = self::pool("url = \"main\"\n[read]\nurl = \"r0\"\non_error = \"fallback\"").await;
+        pool.read().unwrap().fail_next(1);
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::ReadFailed), "main".to_string()));
+        assert_eq!(served(&pool).await.2, "r0");
+
+        let pool = self::pool(&format!("{}on_error = \"retry_then_fallback\"\nbalancer = \"preferred\"", TWO_REPLICAS)).await;
+        pool.replica("0").unwrap().fail_next(1);
+        assert_eq!(served(&pool).await, (PoolRole::Read, None, "r1".to_string()));
+        pool.replica("0").unwrap().fail_next(1);
+        pool.replica("1").unwrap().fail_next(1);
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::ReadFailed), "main".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn unhealthy_replicas_are_skipped() {
+        let pool = pool(&format!("{}[read.health_check]\nquarantine_after = 1\nreadmit_after = 1", TWO_REPLICAS)).await;
+        pool.replica("0").unwrap().set_down(true);
+        pool.check_health().await;
+        assert_eq!(pool.quarantined(), [true, false]);
+        for _ in 0..3 {
+            assert_eq!(served(&pool).await.2, "r1");
+        }
+        pool.replica("1").unwrap().set_down(true);
+        pool.check_health().await;
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::Unavailable), "main".to_string()));
+        pool.replica("0").unwrap().set_down(false);
+        pool.check_health().await;
+        assert_eq!(pool.quarantined(), [false, true]);
+        assert_eq!(served(&pool).await.2, "r0");
+    }
+
+    #[rocket::async_test]
+    async fn drained_replicas_are_skipped() {
+        let pool = pool(TWO_REPLICAS).await;
+        assert!(pool.drain_replica("1"));
+        assert!(!pool.drain_replica("2"));
+        for _ in 0..3 {
+            assert_eq!(served(&pool).await.2, "r0");
+        }
+        assert!(pool.enable_replica("1"));
+        assert_eq!(served(&pool).await.2, "r1");
+    }
+
+    #[rocket::async_test]
+    async fn reads_use_the_main_pool_below_min_healthy() {
+        let pool = pool(&format!("{}min_healthy = 2", TWO_REPLICAS)).await;
+        assert_eq!(served(&pool).await.0, PoolRole::Read);
+        pool.drain_replica("0");
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::MinHealthy), "main".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn circuit_opens_after_failures() {
+        let pool = pool("url = \"main\"\n[read]\nurl = \"r0\"\non_error = \"fallback\"\n[read.circuit_breaker]\nfailure_threshold = 2\ncooldown_ms = 60000").await;
+        pool.read().unwrap().fail_next(2);
+        assert_eq!(served(&pool).await.1, Some(FallbackReason::ReadFailed));
+        assert!(!pool.circuit_open());
+        assert_eq!(served(&pool).await.1, Some(FallbackReason::ReadFailed));
+        assert!(pool.circuit_open());
+        assert_eq!(served(&pool).await, (PoolRole::Main, Some(FallbackReason::CircuitOpen), "main".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn primary_read_ratio_sends_a_share_to_the_main_pool() {
+        let pool = pool("url = \"main\"\n[read]\nurl = \"r0\"\nprimary_read_ratio = 0.5").await;
+        let mut reasons = Vec::new();
+        for _ in 0..4 {
+            reasons.push(served(&pool).await.1);
+        }
+        assert_eq!(reasons, [None, Some(FallbackReason::PrimaryRatio), None, Some(FallbackReason::PrimaryRatio)]);
+    }
+
+    #[rocket::async_test]
+    async fn delayed_reads_use_the_delayed_pool() {
+        let pool = pool("url = \"main\"\n[read]\nurl = \"r0\"\n[read.delayed]\nurl = \"d\"").await;
+        let delayed: MockPoolConnection = pool.get_delayed().await.unwrap().unwrap();
+        assert_eq!(delayed.url(), "d");
+        let none: Option<Result<MockPoolConnection, MockError>> = self::pool("url = \"main\"").await.get_delayed().await;
+        assert!(none.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn init_fails_without_a_main_url() {
+        assert!(matches!(ReadPool::<MockPool>::init(&Figment::new()).await, Err(MockError::Config(_))));
+    }
+}
@@ -0,0 +1,95 @@
+//!Per-route limits on the connections held at once
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+///The permits of each limited route, by method, uri, rank and limit
+type Limits = Mutex<HashMap<(String, String, isize, usize), Arc<Semaphore>>>;
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+///Error of the [`Limited`] guard
+#[derive(Debug)]
+pub enum LimitError<E> {
+    ///The wrapped guard failed
+    Guard(E),
+    ///The route already held its limit of connections
+    Limited(usize),
+}
+impl<E: fmt::Display> fmt::Display for LimitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Guard(e) => e.fmt(f),
+            LimitError::Limited(n) => write!(f, "route already holds its limit of {} connections", n),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display> std::error::Error for LimitError<E> {}
+
+///A request guard wrapping a connection guard (`Limited<ReadConnection<Db>, 4>`,
+///`Limited<RwConnection<Db>, 2>`, ...) which lets at most `N` requests to the route hold the
+///wrapped guard at once, failing others with [`LimitError::Limited`] and `429 Too Many
+///Requests` before they wait on the pool, so one heavy endpoint can't starve the others.
+///
+///The limit is per route and per process, shared by every Rocket instance, and its slot is
+///held until the guard is dropped.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::{Limited, ReadConnection};
+///
+/// #[rocket::get("/reports/yearly")]
+/// async fn yearly(db: Limited<ReadConnection<Db>, 2>) -> &'static str {
+///     # let _ = db;
+///     "..."
+/// }
+///```
+pub struct Limited<G, const N: usize>(pub G, OwnedSemaphorePermit);
+impl<G, const N: usize> Limited<G, N> {
+    ///Gets the wrapped guard, freeing its slot for another request
+    pub fn into_inner(self) -> G {
+        let Limited(guard, permit) = self;
+        drop(permit);
+        guard
+    }
+}
+#[rocket::async_trait]
+impl<'r, G: FromRequest<'r>, const N: usize> FromRequest<'r> for Limited<G, N> {
+    type Error = LimitError<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = match req.route() {
+            Some(route) => (route.method.to_string(), route.uri.to_string(), route.rank, N),
+            None => (req.method().to_string(), req.uri().path().to_string(), 0, N),
+        };
+        let semaphore = LIMITS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+            .entry(key).or_insert_with(|| Arc::new(Semaphore::new(N)))
+            .clone();
+        let Ok(permit) = semaphore.try_acquire_owned() else {
+            return Outcome::Error((Status::TooManyRequests, LimitError::Limited(N)));
+        };
+        match G::from_request(req).await {
+            Outcome::Success(guard) => Outcome::Success(Limited(guard, permit)),
+            Outcome::Error((status, e)) => Outcome::Error((status, LimitError::Guard(e))),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+impl<G: Deref, const N: usize> Deref for Limited<G, N> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<G: DerefMut, const N: usize> DerefMut for Limited<G, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
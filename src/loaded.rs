@@ -0,0 +1,109 @@
+//!Guards loaded from a read connection which is released before the handler runs
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use rocket::Request;
+use rocket::request::{FromRequest, Outcome};
+use rocket_db_pools::{Database, Pool};
+use crate::{ReadCapablePool, ReadConnection, ReadPoolError};
+
+///A value loaded using a read connection to the database `D`, for [`Loaded`]
+#[rocket::async_trait]
+pub trait FromReadConnection<D: Database>: Sized + Send {
+    type Error: Send + fmt::Debug;
+
+    ///Loads the value for `req` using `conn`
+    async fn from_read_connection(req: &Request<'_>, conn: &mut <D::Pool as Pool>::Connection) -> Outcome<Self, Self::Error>;
+}
+
+///Error from a [`Loaded`] guard
+#[derive(Debug)]
+pub enum LoadError<E, L> {
+    ///No read connection could be acquired
+    Connection(E),
+    ///The loader failed
+    Load(L),
+}
+impl<E: fmt::Display, L: fmt::Display> fmt::Display for LoadError<E, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Connection(e) => write!(f, "failed to acquire a read connection: {}", e),
+            LoadError::Load(e) => e.fmt(f),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display, L: fmt::Debug + fmt::Display> std::error::Error for LoadError<E, L> {}
+
+///A request guard which acquires a read connection to `D`, loads a `T` with it and releases
+///the connection before the handler runs, so handlers needing only e.g. the current user don't
+///hold a connection for their whole body.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::ReadPool;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool, MockPoolConnection};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::Request;
+/// use rocket::http::Status;
+/// use rocket::request::Outcome;
+/// use rocket_read_db_pools::{FromReadConnection, Loaded};
+///
+/// struct CurrentUser{name: String}
+///
+/// #[rocket::async_trait]
+/// impl FromReadConnection<Db> for CurrentUser {
+///     type Error = MockError;
+///
+///     async fn from_read_connection(req: &Request<'_>, conn: &mut MockPoolConnection) -> Outcome<Self, Self::Error> {
+///         let Some(session) = req.cookies().get("session") else {return Outcome::Forward(Status::Unauthorized)};
+///         match conn.query("SELECT name FROM sessions JOIN users USING (user_id) WHERE token = $1")
+///             .bind(session.value()).fetch_optional().await
+///         {
+///             Ok(Some(name)) => Outcome::Success(CurrentUser{name}),
+///             Ok(None) => Outcome::Forward(Status::Unauthorized),
+///             Err(e) => Outcome::Error((Status::InternalServerError, e)),
+///         }
+///     }
+/// }
+///
+/// #[rocket::get("/me")]
+/// fn me(user: Loaded<CurrentUser, Db>) -> String {
+///     user.into_inner().name
+/// }
+///```
+pub struct Loaded<T, D>(pub T, std::marker::PhantomData<fn() -> D>);
+impl<T, D> Loaded<T, D> {
+    ///Gets the loaded value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, T, D> FromRequest<'r> for Loaded<T, D>
+    where D: Database, D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send, T: FromReadConnection<D>
+{
+    type Error = LoadError<ReadPoolError<<D::Pool as Pool>::Error>, T::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut conn = match ReadConnection::<D>::from_request(req).await {
+            Outcome::Success(conn) => conn,
+            Outcome::Error((status, e)) => return Outcome::Error((status, LoadError::Connection(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        match T::from_read_connection(req, &mut conn).await {
+            Outcome::Success(value) => Outcome::Success(Loaded(value, std::marker::PhantomData)),
+            Outcome::Error((status, e)) => Outcome::Error((status, LoadError::Load(e))),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+impl<T, D> Deref for Loaded<T, D> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<T, D> DerefMut for Loaded<T, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
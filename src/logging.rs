@@ -0,0 +1,147 @@
+//!Per-database control of this crate's log output
+use std::fmt;
+use std::time::{Duration, Instant};
+use rocket::serde::{Deserialize, Serialize};
+use crate::hooks;
+
+///Default of `log.slow_acquire_ms`
+const DEFAULT_SLOW_ACQUIRE_MS: u64 = 1000;
+///Default of `log.slow_hold_ms`
+const DEFAULT_SLOW_HOLD_MS: u64 = 5000;
+
+///Verbosity of a kind of output in a [`LogConfig`], from least to most verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+///The kinds of output a [`LogConfig`] controls separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogKind {
+    General,
+    Routing,
+    SlowAcquire,
+    SlowHold,
+}
+
+///Configuration of this crate's output about one database: the `databases.<name>.log` table.
+///
+///Messages still pass through Rocket's own log level, so they can only be made quieter than
+///it. Messages logged before the pool exists, such as configuration errors, aren't affected.
+///```toml
+///[default.databases.main]
+///log = {level = "warn", slow_acquire_ms = 250, slow_hold_ms = 2000, routing = "off"}
+///```
+///The thresholds can also be set by the `log_slow_acquire_ms` and `log_slow_hold_ms` keys of
+///the database's table, e.g. `log_slow_acquire_ms = 250`, which those of this table override.
+///
+///Slow acquisitions and holds are logged with the pool's label and, for request guards, the
+///route, and with the `tracing` feature also emitted as `tracing` events with the fields
+///`label`, `route`, `elapsed_ms` and `threshold_ms`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LogConfig {
+    ///`level`: most verbose messages logged. Defaults to `"debug"`, leaving it to Rocket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    ///`routing`: level at which messages about where reads were sent are logged instead of
+    ///their own, such as fallbacks to the main pool and the circuit breaker opening. Defaults
+    ///to their own level, subject to `level`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<LogLevel>,
+    ///`slow_acquire`: level at which acquisitions slower than `slow_acquire_ms` are logged.
+    ///Defaults to `"warn"` if `slow_acquire_ms` is set, otherwise `"off"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_acquire: Option<LogLevel>,
+    ///`slow_acquire_ms`: wait for a connection beyond which `slow_acquire` logs it. Defaults
+    ///to 1000.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_acquire_ms: Option<u64>,
+    ///`slow_hold`: level at which connections from this crate's request guards held for longer
+    ///than `slow_hold_ms` are logged once returned. Defaults to `"warn"` if `slow_hold_ms` is
+    ///set, otherwise `"off"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_hold: Option<LogLevel>,
+    ///`slow_hold_ms`: time a connection may be held before `slow_hold` logs it. Defaults to
+    ///5000. Unlike `lease_warn_ms`, the time it was held for is logged when it's returned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_hold_ms: Option<u64>,
+}
+impl LogConfig {
+    ///The level a message of `kind`, normally logged at `level`, is logged at
+    fn effective(&self, kind: LogKind, level: LogLevel) -> LogLevel {
+        let general = if level <= self.level.unwrap_or(LogLevel::Debug) {level} else {LogLevel::Off};
+        match kind {
+            LogKind::General => general,
+            LogKind::Routing => self.routing.unwrap_or(general),
+            LogKind::SlowAcquire => Self::slow_level(self.slow_acquire, self.slow_acquire_ms),
+            LogKind::SlowHold => Self::slow_level(self.slow_hold, self.slow_hold_ms),
+        }
+    }
+
+    ///The level of slow acquisition or hold messages, given their `level` and `threshold`
+    fn slow_level(level: Option<LogLevel>, threshold: Option<u64>) -> LogLevel {
+        level.unwrap_or(if threshold.is_some() {LogLevel::Warn} else {LogLevel::Off})
+    }
+
+    ///The threshold of `slow_hold_ms`, if slow holds are logged
+    pub(crate) fn slow_hold(&self) -> Option<Duration> {
+        (Self::slow_level(self.slow_hold, self.slow_hold_ms) != LogLevel::Off)
+            .then(|| Duration::from_millis(self.slow_hold_ms.unwrap_or(DEFAULT_SLOW_HOLD_MS)))
+    }
+
+    pub(crate) fn log(&self, kind: LogKind, level: LogLevel, args: fmt::Arguments<'_>) {
+        match self.effective(kind, level) {
+            LogLevel::Off => {}
+            LogLevel::Error => rocket::error!("{}", args),
+            LogLevel::Warn => rocket::warn!("{}", args),
+            LogLevel::Info => rocket::info!("{}", args),
+            LogLevel::Debug => rocket::debug!("{}", args),
+        }
+    }
+
+    ///Logs an acquisition from the pool `label` begun at `start` if it was slow, with the route
+    ///of the request guard acquiring it, if any
+    pub(crate) fn acquired(&self, label: &str, start: Instant) {
+        let wait = start.elapsed();
+        let threshold = Duration::from_millis(self.slow_acquire_ms.unwrap_or(DEFAULT_SLOW_ACQUIRE_MS));
+        if Self::slow_level(self.slow_acquire, self.slow_acquire_ms) == LogLevel::Off || wait < threshold {
+            return;
+        }
+        let route = hooks::route();
+        match &route {
+            Some(route) => self.log(LogKind::SlowAcquire, LogLevel::Off, format_args!("`{}` pool acquisition for {} took {:?}, over {:?}", label, route, wait, threshold)),
+            None => self.log(LogKind::SlowAcquire, LogLevel::Off, format_args!("`{}` pool acquisition took {:?}, over {:?}", label, wait, threshold)),
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(label, route = route.as_deref(), elapsed_ms = wait.as_millis() as u64, threshold_ms = threshold.as_millis() as u64, "slow connection acquisition");
+    }
+
+    ///Logs a connection from the pool `label`, acquired by a request guard for `route`, if it
+    ///was `held` for too long
+    pub(crate) fn held(&self, label: &str, route: Option<&str>, held: Duration) {
+        let Some(threshold) = self.slow_hold() else {return};
+        if held < threshold {
+            return;
+        }
+        match route {
+            Some(route) => self.log(LogKind::SlowHold, LogLevel::Off, format_args!("`{}` pool connection held by {} for {:?}, over {:?}", label, route, held, threshold)),
+            None => self.log(LogKind::SlowHold, LogLevel::Off, format_args!("`{}` pool connection held for {:?}, over {:?}", label, held, threshold)),
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(label, route, elapsed_ms = held.as_millis() as u64, threshold_ms = threshold.as_millis() as u64, "slow connection hold");
+    }
+}
+
+///Logs through a [`LogConfig`], e.g. `db_log!(self.log, General, Warn, "...", args)`
+macro_rules! db_log {
+    ($config:expr, $kind:ident, $level:ident, $($arg:tt)+) => {
+        $config.log($crate::logging::LogKind::$kind, $crate::LogLevel::$level, format_args!($($arg)+))
+    };
+}
+pub(crate) use db_log;
@@ -0,0 +1,239 @@
+//!Scheduled maintenance windows moving reads away from a replica
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{interval, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{HealthRegistry, PoolRole, ReadPool};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+///How often [`ReadMaintenance`] checks the windows
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+///A day of the week, for [`MaintenanceWindow::days`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+///A recurring maintenance window of a read replica, from the `maintenance` list of a
+///`read.replicas` entry (or of the `read` table for a single replica). Times are UTC.
+///
+///While a window is open the replica is skipped by reads, as if quarantined, and reads move
+///back once it closes. Windows are applied by the [`ReadMaintenance`] fairing.
+///```toml
+///[default.databases.main.read]
+///replicas = [
+///    {url = "postgresql://user@replica-1.example/dbname", maintenance = [{days = ["sun"], start = "03:00", minutes = 60}]},
+///    {url = "postgresql://user@replica-2.example/dbname", maintenance = [{days = ["sun"], start = "04:00", minutes = 60}]},
+///]
+///```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MaintenanceWindow {
+    ///`days`: days on which the window opens. Defaults to every day.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+    ///`start`: time the window opens, as `"HH:MM"`
+    pub start: String,
+    ///`minutes`: how long the window stays open
+    pub minutes: u32,
+}
+impl MaintenanceWindow {
+    ///Minutes after midnight at which the window opens, if `start` is valid
+    fn start_minute(&self) -> Option<u32> {
+        let (hours, minutes) = self.start.split_once(':')?;
+        let (hours, minutes): (u32, u32) = (hours.trim().parse().ok()?, minutes.trim().parse().ok()?);
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    }
+
+    ///Whether the window is open at `time`
+    ///```rust
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use rocket_read_db_pools::{MaintenanceWindow, Weekday};
+    ///
+    /// let window = MaintenanceWindow{days: vec![Weekday::Sun], start: "23:30".into(), minutes: 60};
+    /// //Sunday 2024-01-07 23:45 and Monday 00:15 UTC
+    /// assert!(window.contains(UNIX_EPOCH + Duration::from_secs(1704671100)));
+    /// assert!(window.contains(UNIX_EPOCH + Duration::from_secs(1704672900)));
+    /// assert!(!window.contains(UNIX_EPOCH + Duration::from_secs(1704674700)));
+    ///```
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let Some(start) = self.start_minute() else {return false};
+        let minutes = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 60;
+        //The epoch was a Thursday
+        let now = ((minutes + 3 * MINUTES_PER_DAY as u64) % MINUTES_PER_WEEK as u64) as u32;
+        let days: Vec<u32> = match self.days.is_empty() {
+            true => (0..7).collect(),
+            false => self.days.iter().map(|&d| d as u32).collect(),
+        };
+        days.into_iter().any(|day| {
+            let opens = day * MINUTES_PER_DAY + start;
+            (now + MINUTES_PER_WEEK - opens) % MINUTES_PER_WEEK < self.minutes
+        })
+    }
+
+    ///Logs windows whose `start` is invalid, which never open
+    pub(crate) fn check(windows: &[MaintenanceWindow], label: &str) {
+        for window in windows.iter().filter(|w| w.start_minute().is_none()) {
+            rocket::error!("`{}` pool: invalid maintenance window start `{}`, expected \"HH:MM\"", label, window.start);
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Opens and closes the read replicas' maintenance windows as of `time`, returning the
+    ///replicas which changed and whether they're now in maintenance
+    pub fn apply_maintenance(&self, time: SystemTime) -> Vec<(usize, bool)> {
+        let label = self.label(PoolRole::Read);
+        let mut changes = Vec::new();
+        for (i, replica) in self.replica_set().replicas.iter().enumerate() {
+            let open = replica.maintenance.iter().any(|w| w.contains(time));
+            if replica.health.maintenance.swap(open, Ordering::Relaxed) != open {
+                match open {
+                    true => db_log!(self.log, General, Warn, "`{}` replica `{}` entered its maintenance window", label, replica.name),
+                    false => {
+                        db_log!(self.log, General, Info, "`{}` replica `{}` left its maintenance window", label, replica.name);
+                        replica.health.rejoin();
+                    }
+                }
+                changes.push((i, open));
+            }
+        }
+        changes
+    }
+
+    ///Whether each read replica is currently in a maintenance window
+    pub fn in_maintenance(&self) -> Vec<bool> {
+        self.replica_set().replicas.iter().map(|r| r.health.maintenance.load(Ordering::Relaxed)).collect()
+    }
+}
+
+///A fairing which applies the read replicas' [`MaintenanceWindow`]s for the database `D`,
+///checking them at liftoff and every 30 seconds until shutdown. Changes are logged and
+///published to the managed [`HealthRegistry`], if any.
+///
+///Attach it after `D::init()`. Like [`ReadHealthCheck`](crate::ReadHealthCheck), both pool types
+///must be `Clone`.
+pub struct ReadMaintenance<D>(PhantomData<fn() -> D>);
+impl<D> ReadMaintenance<D> {
+    pub fn new() -> Self {
+        ReadMaintenance(PhantomData)
+    }
+}
+impl<D> Default for ReadMaintenance<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadMaintenance<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Maintenance",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadMaintenance` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let registry = rocket.state::<HealthRegistry>().cloned();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut ticks = interval(CHECK_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                rocket::tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = &mut shutdown => break,
+                }
+                if !pool.apply_maintenance(SystemTime::now()).is_empty() {
+                    if let Some(ref registry) = registry {
+                        registry.update(D::NAME, &pool);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Monday 2024-01-01 00:00 UTC
+    const MONDAY: u64 = 1704067200;
+
+    fn at(day: u64, hours: u64, minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(MONDAY + day * 86400 + hours * 3600 + minutes * 60)
+    }
+
+    fn window(days: Vec<Weekday>, start: &str, minutes: u32) -> MaintenanceWindow {
+        MaintenanceWindow{days, start: start.to_string(), minutes}
+    }
+
+    #[test]
+    fn parses_start() {
+        assert_eq!(window(vec![], "03:30", 1).start_minute(), Some(210));
+        assert_eq!(window(vec![], " 0:05", 1).start_minute(), Some(5));
+        assert_eq!(window(vec![], "24:00", 1).start_minute(), None);
+        assert_eq!(window(vec![], "12:60", 1).start_minute(), None);
+        assert_eq!(window(vec![], "noon", 1).start_minute(), None);
+    }
+
+    #[test]
+    fn opens_on_its_days() {
+        let window = window(vec![Weekday::Tue, Weekday::Thu], "03:00", 60);
+        assert!(!window.contains(at(0, 3, 30)));
+        assert!(window.contains(at(1, 3, 0)));
+        assert!(window.contains(at(1, 3, 59)));
+        assert!(!window.contains(at(1, 4, 0)));
+        assert!(!window.contains(at(1, 2, 59)));
+        assert!(window.contains(at(3, 3, 30)));
+    }
+
+    #[test]
+    fn defaults_to_every_day() {
+        let window = window(vec![], "12:00", 30);
+        assert!((0..7).all(|day| window.contains(at(day, 12, 15))));
+        assert!(!(0..7).any(|day| window.contains(at(day, 13, 0))));
+    }
+
+    #[test]
+    fn wraps_around_the_week() {
+        let window = window(vec![Weekday::Sun], "23:00", 120);
+        assert!(window.contains(at(6, 23, 30)));
+        assert!(window.contains(at(7, 0, 30)));
+        assert!(window.contains(at(0, 0, 30)));
+        assert!(!window.contains(at(0, 1, 0)));
+    }
+
+    #[test]
+    fn invalid_windows_never_open() {
+        let window = window(vec![], "25:00", MINUTES_PER_WEEK);
+        assert!(!window.contains(at(0, 1, 0)));
+    }
+}
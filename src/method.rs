@@ -0,0 +1,78 @@
+//!Report of routes whose connection guards don't match their HTTP method
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use rocket::{Orbit, Request, Response, Rocket};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use crate::RequestRouting;
+
+///How a route's connection guards differ from what its HTTP method suggests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MethodMismatch {
+    ///A `GET` or `HEAD` route acquired an `RwConnection`: a candidate for the read path
+    SafeMethodOnPrimary,
+    ///A mutating route only acquired read connections, so it may not see its own writes
+    MutatingMethodOnReplica,
+}
+
+impl MethodMismatch {
+    fn describe(self) -> &'static str {
+        match self {
+            MethodMismatch::SafeMethodOnPrimary => "read-only method acquired a read-write connection",
+            MethodMismatch::MutatingMethodOnReplica => "mutating method only acquired read connections",
+        }
+    }
+}
+
+///A fairing which checks the acquisitions in each request's [`RequestRouting`] against its HTTP
+///method, and reports routes where they differ, to find candidates for read-path migration.
+///
+///Each mismatching route is logged the first time it's seen, and all of them with their counts
+///at shutdown. Only acquisitions made through this crate's request guards are seen.
+#[derive(Default)]
+pub struct MethodAudit {
+    seen: Mutex<BTreeMap<(String, String, MethodMismatch), u64>>,
+}
+impl MethodAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///The mismatch in a request's acquisitions, if any
+    fn mismatch(req: &Request<'_>) -> Option<MethodMismatch> {
+        let acquisitions = RequestRouting::of(req).acquisitions();
+        let rw = acquisitions.iter().any(|a| a.read_write());
+        match req.method() {
+            Method::Get | Method::Head if rw => Some(MethodMismatch::SafeMethodOnPrimary),
+            Method::Post | Method::Put | Method::Patch | Method::Delete if !rw && !acquisitions.is_empty() =>
+                Some(MethodMismatch::MutatingMethodOnReplica),
+            _ => None,
+        }
+    }
+}
+#[rocket::async_trait]
+impl Fairing for MethodAudit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Method Audit",
+            kind: Kind::Response | Kind::Shutdown,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, _res: &mut Response<'r>) {
+        let Some(mismatch) = Self::mismatch(req) else {return};
+        let route = req.route().map_or_else(|| req.uri().path().to_string(), |r| r.uri.to_string());
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if !seen.contains_key(&(req.method().to_string(), route.clone(), mismatch)) {
+            rocket::warn!("{} {}: {}", req.method(), route, mismatch.describe());
+        }
+        *seen.entry((req.method().to_string(), route, mismatch)).or_default() += 1;
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        let seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        for ((method, route, mismatch), count) in seen.iter() {
+            rocket::warn!("{} {}: {} in {} requests", method, route, mismatch.describe(), count);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//!Pool statistics, served in the Prometheus exposition format with the `metrics-prometheus`
+//!feature
+use std::marker::PhantomData;
+use std::sync::Arc;
+use rocket::{Build, Request, Response, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+#[cfg(feature = "metrics-prometheus")]
+use rocket::{Data, Route};
+#[cfg(feature = "metrics-prometheus")]
+use rocket::http::{ContentType, Method, Status};
+#[cfg(feature = "metrics-prometheus")]
+use rocket::route::{self, Handler};
+use rocket_db_pools::{Database, Pool};
+use crate::usage::BUCKETS;
+use crate::{FallbackReason, PoolRole, PoolUsage, ReadPool};
+
+///The type of a [`MetricFamily`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+///One value of a [`MetricFamily`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    ///Appended to the family's name, e.g. `"_bucket"` for histogram buckets
+    pub suffix: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+///A named metric and its values for each pool, from [`ReadPool::metrics`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    pub samples: Vec<Sample>,
+}
+impl MetricFamily {
+    fn new(name: &'static str, help: &'static str, kind: MetricKind) -> Self {
+        MetricFamily{name, help, kind, samples: Vec::new()}
+    }
+
+    fn push(&mut self, suffix: &'static str, labels: Vec<(&'static str, String)>, value: f64) {
+        self.samples.push(Sample{suffix, labels, value});
+    }
+}
+
+///Renders metrics in the Prometheus text exposition format
+#[cfg(feature = "metrics-prometheus")]
+pub fn prometheus_text(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let kind = match family.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        };
+        out += &format!("# HELP {} {}\n# TYPE {} {}\n", family.name, family.help, family.name, kind);
+        for sample in &family.samples {
+            let labels: Vec<String> = sample.labels.iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+                .collect();
+            out += &format!("{}{}{{{}}} {}\n", family.name, sample.suffix, labels.join(","), sample.value);
+        }
+    }
+    out
+}
+
+fn pool_labels<P, R>(pool: &ReadPool<P, R>, database: &str, role: PoolRole) -> Vec<(&'static str, String)> {
+    let role_name = match role {
+        PoolRole::Main => "main",
+        PoolRole::Read => "read",
+        PoolRole::Delayed => "delayed",
+    };
+    vec![("database", database.to_string()), ("role", role_name.to_string()), ("label", pool.label(role).to_string())]
+}
+
+impl<P: Pool, R: Pool> ReadPool<P, R> {
+    ///The pool's usage totals as metrics, labelled with `database`, each pool's role and its
+    ///label. Read replicas are counted together, and also separately in the
+    ///`read_db_pool_replica_*` metrics, labelled with each replica's name as `replica`.
+    ///Acquisition waits are a histogram with power of two microsecond buckets.
+    ///
+    ///Reads served by the main pool are counted by `cause` in `read_db_pool_main_reads_total`:
+    ///`"no_read_pool"` while none is configured, which shows how much load one would take, and
+    ///`"fallback"` when the read pool was passed over, the latter also counted by `reason` in
+    ///`read_db_pool_fallback_reasons_total`. Reads kept on the main pool through
+    ///`ReadCapablePool::get_read_main` have no reason. Replica health is given by the
+    ///`read_db_pool_replica_quarantined` and `read_db_pool_replica_in_maintenance` gauges.
+    pub fn metrics(&self, database: &str) -> Vec<MetricFamily> {
+        let mut acquisitions = MetricFamily::new("read_db_pool_acquisitions_total", "Connection acquisitions attempted", MetricKind::Counter);
+        let mut errors = MetricFamily::new("read_db_pool_errors_total", "Connection acquisitions which failed", MetricKind::Counter);
+        let mut fallbacks = MetricFamily::new("read_db_pool_fallbacks_total", "Reads served by the main pool instead of the read pool", MetricKind::Counter);
+        let mut reasons = MetricFamily::new("read_db_pool_fallback_reasons_total", "Reads served by the main pool instead of the read pool, by reason", MetricKind::Counter);
+        let mut main_reads = MetricFamily::new("read_db_pool_main_reads_total", "Reads served by the main pool, by cause", MetricKind::Counter);
+        let mut waits = MetricFamily::new("read_db_pool_acquire_seconds", "Time spent waiting for a connection", MetricKind::Histogram);
+        let summaries = self.usage_summary();
+        for summary in &summaries {
+            let role = summary.role;
+            let labels = pool_labels(self, database, role);
+            acquisitions.push("", labels.clone(), summary.acquisitions as f64);
+            errors.push("", labels.clone(), summary.errors as f64);
+            match role {
+                PoolRole::Main => {
+                    let mut main = labels.clone();
+                    main.push(("cause", "no_read_pool".to_string()));
+                    main_reads.push("", main, summary.unreplicated_reads as f64);
+                }
+                PoolRole::Read => {
+                    fallbacks.push("", labels.clone(), summary.fallbacks as f64);
+                    for (reason, count) in self.usage.read.fallback_reasons() {
+                        if reason != FallbackReason::NoReadPool {
+                            let mut labels = labels.clone();
+                            labels.push(("reason", reason.name().to_string()));
+                            reasons.push("", labels, count as f64);
+                        }
+                    }
+                }
+                PoolRole::Delayed => {}
+            }
+            let (buckets, total) = self.usage.get(role).waits();
+            let mut seen = 0;
+            for (i, count) in buckets.iter().enumerate().take(BUCKETS - 1) {
+                seen += count;
+                let mut labels = labels.clone();
+                labels.push(("le", format!("{}", (1u64 << i) as f64 / 1e6)));
+                waits.push("_bucket", labels, seen as f64);
+            }
+            let count: u64 = buckets.iter().sum();
+            let mut inf = labels.clone();
+            inf.push(("le", "+Inf".to_string()));
+            waits.push("_bucket", inf, count as f64);
+            waits.push("_sum", labels.clone(), total.as_secs_f64());
+            waits.push("_count", labels, count as f64);
+        }
+        let mut replica_acquisitions = MetricFamily::new("read_db_pool_replica_acquisitions_total", "Connection acquisitions attempted from each read replica", MetricKind::Counter);
+        let mut replica_errors = MetricFamily::new("read_db_pool_replica_errors_total", "Connection acquisitions from each read replica which failed", MetricKind::Counter);
+        let mut replica_quarantined = MetricFamily::new("read_db_pool_replica_quarantined", "Whether each read replica is quarantined by health checks", MetricKind::Gauge);
+        let mut replica_degraded = MetricFamily::new("read_db_pool_replica_degraded", "Whether each read replica is degraded by failing canary queries", MetricKind::Gauge);
+        let mut replica_maintenance = MetricFamily::new("read_db_pool_replica_in_maintenance", "Whether each read replica is in one of its maintenance windows", MetricKind::Gauge);
+        let mut replica_available = MetricFamily::new("read_db_pool_replica_available", "Whether each read replica may serve reads, being neither drained, quarantined, degraded nor in maintenance", MetricKind::Gauge);
+        for replica in &self.replica_set().replicas {
+            let mut labels = pool_labels(self, database, PoolRole::Read);
+            labels.push(("replica", replica.name.to_string()));
+            let (attempted, failed) = replica.usage.totals();
+            replica_acquisitions.push("", labels.clone(), attempted as f64);
+            replica_errors.push("", labels.clone(), failed as f64);
+            replica_quarantined.push("", labels.clone(), if replica.health.quarantined() {1.0} else {0.0});
+            replica_degraded.push("", labels.clone(), if replica.health.degraded() {1.0} else {0.0});
+            replica_maintenance.push("", labels.clone(), if replica.health.in_maintenance() {1.0} else {0.0});
+            replica_available.push("", labels, if replica.health.available() {1.0} else {0.0});
+        }
+        let fallen_back: u64 = summaries.iter().map(|s| s.fallbacks).sum();
+        let mut main = pool_labels(self, database, PoolRole::Main);
+        main.push(("cause", "fallback".to_string()));
+        main_reads.push("", main, fallen_back as f64);
+        vec![acquisitions, errors, fallbacks, reasons, main_reads, waits, replica_acquisitions, replica_errors,
+            replica_quarantined, replica_degraded, replica_maintenance, replica_available]
+    }
+}
+
+impl<P: PoolUsage, R: PoolUsage> ReadPool<P, R> {
+    ///The current connections in use and maximum size of each pool as gauges, labelled as by
+    ///[`ReadPool::metrics`]
+    pub fn usage_metrics(&self, database: &str) -> Vec<MetricFamily> {
+        let mut in_use = MetricFamily::new("read_db_pool_connections_in_use", "Connections currently checked out", MetricKind::Gauge);
+        let mut max = MetricFamily::new("read_db_pool_max_connections", "Most connections the pool will open", MetricKind::Gauge);
+        for role in self.roles() {
+            let (used, size) = match role {
+                PoolRole::Main => (self.primary().in_use(), self.primary().max_connections()),
+                PoolRole::Read => {
+                    let set = self.replica_set();
+                    (set.pools.iter().map(PoolUsage::in_use).sum(), set.pools.iter().map(PoolUsage::max_connections).sum())
+                }
+                PoolRole::Delayed => match self.delayed {
+                    Some(ref delayed) => (delayed.in_use(), delayed.max_connections()),
+                    None => continue,
+                },
+            };
+            in_use.push("", pool_labels(self, database, role), used as f64);
+            max.push("", pool_labels(self, database, role), size as f64);
+        }
+        vec![in_use, max]
+    }
+}
+
+type Collect<D> = fn(&<D as Database>::Pool) -> Vec<MetricFamily>;
+
+///A fairing which exposes the metrics of the database `D` from [`ReadPool::metrics`], either by
+///mounting a route serving them in the Prometheus text format, with the `metrics-prometheus`
+///feature, or by passing them to a callback after each response, e.g. to update an existing
+///registry.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::ReadPoolMetrics;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadPoolMetrics::<Db>::route("/metrics"))
+/// # }
+///```
+///With several databases, mount each at its own path or render them together with
+///`prometheus_text` in a route of your own.
+pub struct ReadPoolMetrics<D: Database> {
+    path: Option<String>,
+    callback: Option<Arc<dyn Fn(Vec<MetricFamily>) + Send + Sync>>,
+    collect: Vec<Collect<D>>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D, P, R> ReadPoolMetrics<D> where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool {
+    fn new(path: Option<String>, callback: Option<Arc<dyn Fn(Vec<MetricFamily>) + Send + Sync>>) -> Self {
+        let metrics: Collect<D> = |db| db.metrics(D::NAME);
+        ReadPoolMetrics{path, callback, collect: vec![metrics], _db: PhantomData}
+    }
+
+    ///Serves the metrics at `path`
+    #[cfg(feature = "metrics-prometheus")]
+    pub fn route<T: Into<String>>(path: T) -> Self {
+        Self::new(Some(path.into()), None)
+    }
+
+    ///Passes the metrics to `callback` after each response
+    pub fn callback<F: Fn(Vec<MetricFamily>) + Send + Sync + 'static>(callback: F) -> Self {
+        Self::new(None, Some(Arc::new(callback)))
+    }
+}
+impl<D, P, R> ReadPoolMetrics<D> where D: Database<Pool = ReadPool<P, R>>, P: PoolUsage, R: PoolUsage {
+    ///Also exposes [`ReadPool::usage_metrics`]
+    pub fn with_pool_usage(mut self) -> Self {
+        self.collect.push(|db| db.usage_metrics(D::NAME));
+        self
+    }
+}
+
+fn collect<D: Database>(collect: &[Collect<D>], db: &D::Pool) -> Vec<MetricFamily> {
+    collect.iter().flat_map(|f| f(db)).collect()
+}
+
+#[cfg(feature = "metrics-prometheus")]
+struct MetricsHandler<D: Database>(Vec<Collect<D>>);
+#[cfg(feature = "metrics-prometheus")]
+impl<D: Database> Clone for MetricsHandler<D> {
+    fn clone(&self) -> Self {
+        MetricsHandler(self.0.clone())
+    }
+}
+#[cfg(feature = "metrics-prometheus")]
+#[rocket::async_trait]
+impl<D: Database> Handler for MetricsHandler<D> {
+    async fn handle<'r>(&self, req: &'r Request<'_>, _data: Data<'r>) -> route::Outcome<'r> {
+        match D::fetch(req.rocket()) {
+            Some(db) => {
+                let body = prometheus_text(&collect::<D>(&self.0, db));
+                route::Outcome::from(req, (ContentType::new("text", "plain").with_params(("version", "0.0.4")), body))
+            }
+            None => route::Outcome::Error(Status::ServiceUnavailable),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<D: Database> Fairing for ReadPoolMetrics<D> {
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Pool Metrics",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        match self.path {
+            #[cfg(feature = "metrics-prometheus")]
+            Some(ref path) => {
+                let route = Route::new(Method::Get, "/", MetricsHandler::<D>(self.collect.clone()));
+                Ok(rocket.mount(path.as_str(), vec![route]))
+            }
+            _ => Ok(rocket),
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, _res: &mut Response<'r>) {
+        if let (Some(callback), Some(db)) = (self.callback.as_ref(), D::fetch(req.rocket())) {
+            callback(collect::<D>(&self.collect, db));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//!Experimental support for several writable primaries
+use std::sync::{Arc, RwLock};
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::figment::value::Dict;
+use rocket_db_pools::Pool;
+use crate::health::{probe_all, ReplicaHealth};
+use crate::{dns, keys, secrets, tag, tls, BalanceStrategy, LogConfig, HealthCheckConfig, HealthProbe, PoolUsage, ReadBalancer};
+
+///A pool over several writable primaries of an active-active setup, choosing one for each
+///acquisition. **Experimental**: conflict handling between the primaries is entirely up to the
+///database.
+///
+///The primaries are listed under `primaries`, each entry overriding the database's pool
+///options as `read.replicas` entries do, and chosen between by `primary_balancer`, which takes
+///the same strategies as `read.balancer`. `"preferred"` keeps writes on the first primary (e.g.
+///the home region's), moving to the next only while it's quarantined by
+///[`MultiPrimaryPool::check_health`], which is tuned by a `primary_health_check` table:
+///```toml
+///[default.databases.main]
+///max_connections = 10
+///primary_balancer = "preferred"
+///primaries = [
+///    {url = "postgresql://user@primary-eu.example/dbname"},
+///    {url = "postgresql://user@primary-us.example/dbname"},
+///]
+///```
+///Without `primaries`, the database's own options configure a single primary.
+///
+///It can be used as the main pool of a [`ReadPool`](crate::ReadPool), so `RwConnection` and
+///reads falling back to the main pool use it, with the read side configured as usual:
+///```rust
+/// # type PgPool = rocket_read_db_pools::testing::MockPool;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{MultiPrimaryPool, ReadPool};
+///
+/// #[derive(Database)]
+/// #[database("main")]
+/// struct Db(ReadPool<MultiPrimaryPool<PgPool>, PgPool>);
+///```
+pub struct MultiPrimaryPool<P> {
+    primaries: Vec<P>,
+    balancer: Arc<RwLock<Arc<dyn ReadBalancer<P>>>>,
+    weights: Arc<[u32]>,
+    health: Arc<[ReplicaHealth]>,
+    health_check: HealthCheckConfig,
+}
+impl<P: Clone> Clone for MultiPrimaryPool<P> {
+    fn clone(&self) -> Self {
+        MultiPrimaryPool{
+            primaries: self.primaries.clone(),
+            balancer: self.balancer.clone(),
+            weights: self.weights.clone(),
+            health: self.health.clone(),
+            health_check: self.health_check.clone(),
+        }
+    }
+}
+impl<P> MultiPrimaryPool<P> {
+    ///The primary to use next, as chosen by the balancer. If it's quarantined the next healthy
+    ///one is used, or the chosen one if all are.
+    fn next(&self) -> &P {
+        let first = match self.primaries.len() {
+            1 => 0,
+            n => self.balancer.read().unwrap_or_else(|e| e.into_inner()).pick(&self.primaries, &self.weights) % n,
+        };
+        let healthy = (0..self.primaries.len())
+            .map(|i| (first + i) % self.primaries.len())
+            .find(|&i| !self.health[i].quarantined());
+        &self.primaries[healthy.unwrap_or(first)]
+    }
+
+    ///Replaces the balancer choosing between primaries. Clones of the pool share the change.
+    pub fn set_balancer(&self, balancer: impl ReadBalancer<P> + 'static) {
+        *self.balancer.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(balancer);
+    }
+
+    ///The pool of each primary, in configuration order
+    pub fn primaries(&self) -> &[P] {
+        &self.primaries
+    }
+
+    ///Whether each primary is currently quarantined by health checks
+    pub fn quarantined(&self) -> Vec<bool> {
+        self.health.iter().map(ReplicaHealth::quarantined).collect()
+    }
+}
+impl<P> MultiPrimaryPool<P> where P: Pool, P::Connection: HealthProbe {
+    ///Probes each primary once, concurrently, quarantining or readmitting them according to
+    ///`primary_health_check`. Call it periodically, e.g. from a task spawned at liftoff.
+    pub async fn check_health(&self) {
+        let names: Vec<usize> = (0..self.primaries.len()).collect();
+        let health: Vec<_> = self.health.iter().collect();
+        probe_all(&self.primaries, &names, &health, &self.health_check, "primary", &LogConfig::default()).await;
+    }
+}
+#[rocket::async_trait]
+impl<P: Pool> Pool for MultiPrimaryPool<P> {
+    type Error = P::Error;
+
+    type Connection = P::Connection;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let configs = match figment.extract_inner::<Vec<Dict>>(keys::PRIMARIES) {
+            Ok(primaries) if !primaries.is_empty() => primaries.into_iter()
+                .map(|primary| figment.clone().merge(Serialized::globals(primary)))
+                .collect(),
+            _ => vec![figment.clone()],
+        };
+        let mut primaries = Vec::new();
+        let mut weights = Vec::new();
+        for config in configs {
+            weights.push(config.extract_inner("weight").unwrap_or(1));
+            primaries.push(P::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(config).await))).await).await?);
+        }
+        let strategy: BalanceStrategy = figment.extract_inner(keys::PRIMARY_BALANCER).unwrap_or_default();
+        Ok(MultiPrimaryPool{
+            primaries,
+            balancer: Arc::new(RwLock::new(strategy.balancer())),
+            health: weights.iter().map(|_| Default::default()).collect(),
+            weights: weights.into(),
+            health_check: figment.extract_inner(keys::PRIMARY_HEALTH_CHECK).unwrap_or_default(),
+        })
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        self.next().get().await
+    }
+
+    async fn close(&self) {
+        for primary in &self.primaries {primary.close().await;}
+    }
+}
+impl<P: PoolUsage> PoolUsage for MultiPrimaryPool<P> {
+    fn in_use(&self) -> u32 {
+        self.primaries.iter().map(PoolUsage::in_use).sum()
+    }
+
+    fn max_connections(&self) -> u32 {
+        self.primaries.iter().map(PoolUsage::max_connections).sum()
+    }
+
+    fn idle(&self) -> u32 {
+        self.primaries.iter().map(PoolUsage::idle).sum()
+    }
+}
@@ -0,0 +1,159 @@
+//!Read-only mode, serving reads from the replicas while the primary is down
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use rocket::{Build, Request, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::Database;
+use crate::breaker::CircuitBreaker;
+use crate::logging::db_log;
+use crate::{CircuitBreakerConfig, PoolRole, ReadPool};
+
+///Configuration of read-only mode: the `databases.<name>.read_only_mode` table.
+///
+///After `failure_threshold` acquisitions from the main pool fail in a row, the database enters
+///read-only mode. `ReadConnection`s are still served by the replicas, no longer sent to the main
+///pool by `read.primary_read_ratio` or brownout, and with the [`ReadOnlySurvival`] fairing
+///attached `RwConnection`s fail at once. Once `retry_ms` has passed a single acquisition tries
+///the main pool again: if it succeeds the database leaves read-only mode.
+///```toml
+///[default.databases.main.read_only_mode]
+///failure_threshold = 3
+///retry_ms = 5000
+///```
+///The [`ReadOnlyMode`] guard tells handlers whether the database is in read-only mode, e.g. to
+///show a banner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ReadOnlyModeConfig {
+    ///`failure_threshold`: consecutive failed main pool acquisitions which enter read-only
+    ///mode. Defaults to 3.
+    pub failure_threshold: u32,
+    ///`retry_ms`: time between attempts to use the main pool in read-only mode. Defaults to
+    ///5000.
+    pub retry_ms: u64,
+}
+impl Default for ReadOnlyModeConfig {
+    fn default() -> Self {
+        ReadOnlyModeConfig{failure_threshold: 3, retry_ms: 5000}
+    }
+}
+impl ReadOnlyModeConfig {
+    pub(crate) fn breaker(&self) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig{failure_threshold: self.failure_threshold, cooldown_ms: self.retry_ms})
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Whether the database is in read-only mode after repeated main pool failures, see
+    ///[`ReadOnlyModeConfig`]
+    pub fn read_only(&self) -> bool {
+        self.outage.as_ref().is_some_and(|o| o.is_open())
+    }
+
+    ///Records the result of a main pool acquisition towards read-only mode
+    pub(crate) fn outage_record(&self, ok: bool) {
+        match self.outage.as_ref().and_then(|o| o.record(ok)) {
+            Some(true) => db_log!(self.log, General, Error, "`{}` pool failing, serving reads only", self.label(PoolRole::Main)),
+            Some(false) => db_log!(self.log, General, Info, "`{}` pool recovered, leaving read-only mode", self.label(PoolRole::Main)),
+            None => {}
+        }
+    }
+}
+
+///Read-only mode of each database with a [`ReadOnlySurvival`] fairing, by `Database::NAME`
+#[derive(Default)]
+pub(crate) struct Registry(Mutex<HashMap<&'static str, Arc<CircuitBreaker>>>);
+impl Registry {
+    ///Whether a write to the database `database` should be refused without trying the main
+    ///pool. After `retry_ms`, one write at a time is let through to try it.
+    pub fn refuses(&self, database: &str) -> bool {
+        let outage = self.0.lock().unwrap_or_else(|e| e.into_inner()).get(database).cloned();
+        outage.is_some_and(|o| o.is_open() && !o.allows())
+    }
+}
+
+///A fairing which makes the `RwConnection` guards of the database `D` fail at once with
+///`503 Service Unavailable` while it's in read-only mode, rather than each waiting on the main
+///pool. [`RequestRouting::refused_writes`](crate::RequestRouting::refused_writes) tells them apart
+///from other failures, e.g. in a catcher.
+///
+///Attach it after `D::init()`. It does nothing unless `read_only_mode` is configured.
+pub struct ReadOnlySurvival<D>(PhantomData<fn() -> D>);
+impl<D> ReadOnlySurvival<D> {
+    pub fn new() -> Self {
+        ReadOnlySurvival(PhantomData)
+    }
+}
+impl<D> Default for ReadOnlySurvival<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadOnlySurvival<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Send + Sync + 'static, R: Send + Sync + 'static
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read-Only Survival",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let Some(db) = D::fetch(&rocket) else {
+            rocket::error!("`ReadOnlySurvival` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        };
+        let Some(outage) = db.outage.clone() else {
+            rocket::warn!("`ReadOnlySurvival` attached for database `{}` without `read_only_mode` configured", D::NAME);
+            return Ok(rocket);
+        };
+        let rocket = match rocket.state::<Registry>() {
+            Some(_) => rocket,
+            None => rocket.manage(Registry::default()),
+        };
+        if let Some(registry) = rocket.state::<Registry>() {
+            registry.0.lock().unwrap_or_else(|e| e.into_inner()).insert(D::NAME, outage);
+        }
+        Ok(rocket)
+    }
+}
+
+///A request guard telling whether the database `D` is in read-only mode, see
+///[`ReadOnlyModeConfig`]. It never fails, so handlers can use it to warn users that changes
+///can't be saved.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::get;
+/// use rocket_read_db_pools::ReadOnlyMode;
+///
+/// #[get("/banner")]
+/// fn banner(mode: ReadOnlyMode<Db>) -> &'static str {
+///     match mode.active() {
+///         true => "Changes can't be saved right now",
+///         false => "",
+///     }
+/// }
+///```
+pub struct ReadOnlyMode<D>(bool, PhantomData<fn() -> D>);
+impl<D> ReadOnlyMode<D> {
+    ///Whether the database is in read-only mode
+    pub fn active(&self) -> bool {
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, D, P, R> FromRequest<'r> for ReadOnlyMode<D> where D: Database<Pool = ReadPool<P, R>> {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ReadOnlyMode(D::fetch(req.rocket()).is_some_and(|db| db.read_only()), PhantomData))
+    }
+}
@@ -0,0 +1,368 @@
+//!Resolution of a database's configuration into the pools `ReadPool::init` creates
+use std::fmt;
+use std::marker::PhantomData;
+use rocket::{Build, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::figment::{self, Figment};
+use rocket::figment::providers::Serialized;
+use rocket::figment::value::Dict;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{Database, Pool};
+use crate::{keys, replicated, secrets, PoolRole, ReadDbConfig, ReadPool, Replicas};
+
+///Whether the database has a `read` table, rather than none or `read = false` turning off a
+///replica configured by another profile
+pub(crate) fn has_read(figment: &Figment) -> bool {
+    figment.contains(keys::READ) && figment.extract_inner::<bool>(keys::READ).ok() != Some(false)
+}
+
+///The database's figment with `read_urls` expanded into a `read` table of one replica per url,
+///inheriting the main pool's other options, unless `read = false`
+pub(crate) fn with_read_urls(figment: &Figment) -> Figment {
+    if figment.contains(keys::READ) && !has_read(figment) {
+        return figment.clone();
+    }
+    let urls = match figment.extract_inner::<Vec<String>>(keys::READ_URLS) {
+        Ok(urls) if !urls.is_empty() => urls,
+        _ => return figment.clone(),
+    };
+    if figment.contains(keys::READ) {
+        rocket::warn!("`{}` ignored, as a `{}` table is configured", keys::READ_URLS, keys::READ);
+        return figment.clone();
+    }
+    let replicas: Vec<Dict> = urls.into_iter().map(|url| Dict::from([(keys::URL.to_string(), url.into())])).collect();
+    figment.clone().merge(Serialized::default(keys::READ_REPLICAS, replicas))
+}
+
+///Config for the read pool, if one is configured, over the main pool's options unless
+///`read.inherit_main = false`
+pub(crate) fn read_figment(figment: &Figment) -> Option<Figment> {
+    if !has_read(figment) {
+        return None;
+    }
+    //`read` is focused, so its defaults are at the pool's own keys, as `rocket_db_pools` sets them
+    let read = figment.focus(keys::READ);
+    if !figment.extract_inner::<bool>(keys::READ_INHERIT_MAIN).unwrap_or(true) {
+        return Some(read
+            .join(Serialized::default(keys::MAX_CONNECTIONS, rocket::Config::default().workers * 4))
+            .join(Serialized::default(keys::CONNECT_TIMEOUT, 5)));
+    }
+    let mut main = figment.extract::<ReadDbConfig>().map(|config| config.main).unwrap_or_default();
+    main.remove(keys::URL);
+    Some(read.join(Serialized::defaults(main)).join(Serialized::default(keys::CONNECT_TIMEOUT, 5)))
+}
+
+///Config for each read replica pool: one per `read.replicas` entry over the `read` table, or
+///the `read` table itself, named as in [`Replicas`]
+pub(crate) fn read_figments(figment: &Figment) -> Vec<(String, Figment)> {
+    let Some(read) = read_figment(figment) else {return Vec::new()};
+    match read.extract_inner::<Replicas>("replicas") {
+        Ok(replicas) if !replicas.is_empty() => replicas.named().into_iter()
+            .map(|(name, replica)| (name, read.clone().merge(Serialized::globals(replica))))
+            .collect(),
+        _ => vec![("0".to_string(), read)],
+    }
+}
+
+///Config for the delayed replica pool, if one is configured
+pub(crate) fn delayed_figment(figment: &Figment) -> Option<Figment> {
+    figment.contains(keys::DELAYED).then(|| figment.focus(keys::DELAYED)
+        .join(Serialized::default("connect_timeout", 5)))
+}
+
+///Config for the warm standby primary pool, if one is configured. It inherits the main pool's
+///`max_connections` and keeps a connection open by default.
+pub(crate) fn failover_figment(figment: &Figment) -> Option<Figment> {
+    figment.contains(keys::FAILOVER).then(|| {
+        let mut standby = figment.focus(keys::FAILOVER)
+            .join(Serialized::default("min_connections", 1))
+            .join(Serialized::default("connect_timeout", 5));
+        if let Ok(max_connections) = figment.extract_inner::<u64>("max_connections") {
+            standby = standby.join(Serialized::default("max_connections", max_connections));
+        }
+        standby
+    })
+}
+
+///A pool `ReadPool::init` would create, with the fully merged options passed to its `Pool::init`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PlannedPool {
+    pub role: PoolRole,
+    pub label: String,
+    ///The name of a read replica, see [`Replicas`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replica: Option<String>,
+    pub config: Dict,
+}
+
+///The fully resolved result of a database's configuration, without connecting to anything.
+///
+///Intended for snapshot testing configuration handling: resolve the plan from the same figment
+///given to `ReadPool::init` (i.e. the `databases.<name>` table) and compare its serialized form.
+///Note that options are included verbatim, so URLs may contain credentials.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InitPlan {
+    ///Pools to create, main (and any standby primary) first
+    pub pools: Vec<PlannedPool>,
+    pub replicated_tables: Option<Vec<String>>,
+    pub check_replicated_tables: bool,
+    pub verify: bool,
+    pub record: Option<String>,
+}
+impl InitPlan {
+    ///Resolves the plan for a database config
+    pub fn resolve(figment: &Figment) -> Result<Self, Box<figment::Error>> {
+        let figment = &with_read_urls(figment);
+        let config = ReadDbConfig::extract(figment)?;
+        let labels = config.labels();
+        let mut pools = vec![PlannedPool{role: PoolRole::Main, label: labels.get(PoolRole::Main).to_string(), replica: None, config: figment.extract()?}];
+        if let Some(standby) = failover_figment(figment) {
+            pools.push(PlannedPool{role: PoolRole::Main, label: "standby".to_string(), replica: None, config: standby.extract()?});
+        }
+        let read = read_figments(figment).into_iter().map(|(name, pool)| (PoolRole::Read, Some(name), pool));
+        for (role, replica, pool) in read.chain(delayed_figment(figment).map(|pool| (PoolRole::Delayed, None, pool))) {
+            pools.push(PlannedPool{role, label: labels.get(role).to_string(), replica, config: pool.extract()?});
+        }
+        let (replicated_tables, check_replicated_tables) = replicated::config(config.read.as_ref());
+        Ok(InitPlan{
+            pools,
+            replicated_tables,
+            check_replicated_tables,
+            verify: config.read.as_ref().is_some_and(|r| r.verify),
+            record: config.read.and_then(|r| r.record),
+        })
+    }
+}
+
+///Error from [`ReadPool::validate`]
+#[derive(Debug)]
+pub enum ValidationError<E> {
+    ///The configuration couldn't be parsed
+    Config(Box<figment::Error>),
+    ///A pool failed to initialize or serve a connection
+    Pool{label: String, error: E},
+}
+impl<E: fmt::Display> fmt::Display for ValidationError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Config(e) => write!(f, "invalid configuration: {}", e),
+            ValidationError::Pool{label, error} => write!(f, "`{}` pool failed: {}", label, error),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ValidationError<E> {}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error> {
+    ///Checks a prospective database config (the `databases.<name>` table) without touching any
+    ///running pool, e.g. to verify new Rocket.toml sections in a deployment pipeline.
+    ///
+    ///This crate's options are always parsed strictly. With `probe`, every pool is also
+    ///initialized, which validates its own options, then serves one connection and is closed.
+    ///Returns the resolved plan.
+    pub async fn validate(figment: &Figment, probe: bool) -> Result<InitPlan, ValidationError<P::Error>> {
+        let plan = InitPlan::resolve(figment).map_err(ValidationError::Config)?;
+        if probe {
+            for pool in &plan.pools {
+                Self::probe_planned(pool).await.map_err(|error| ValidationError::Pool{label: pool.label.clone(), error})?;
+            }
+        }
+        Ok(plan)
+    }
+
+    ///Initializes a planned pool, has it serve one connection and closes it
+    async fn probe_planned(pool: &PlannedPool) -> Result<(), P::Error> {
+        let config = Figment::from(Serialized::defaults(&pool.config));
+        match pool.role {
+            PoolRole::Main => probe_pool::<P>(&config).await,
+            _ => probe_pool::<R>(&config).await.map_err(Into::into),
+        }
+    }
+}
+
+async fn probe_pool<T: Pool>(figment: &Figment) -> Result<(), T::Error> {
+    let pool = T::init(&secrets::resolve(figment.clone()).await).await?;
+    //A pool which failed is just dropped, so no error is held across an await
+    drop(pool.get().await?);
+    pool.close().await;
+    Ok(())
+}
+
+///The key under `databases.<name>` configuring a planned pool, for error messages
+fn pool_key(figment: &Figment, pool: &PlannedPool) -> String {
+    match (pool.role, pool.replica.as_deref()) {
+        (PoolRole::Main, _) if pool.label == "standby" => keys::FAILOVER.to_string(),
+        (PoolRole::Main, _) => "url".to_string(),
+        (PoolRole::Read, Some(name)) if figment.contains(keys::READ_REPLICAS) => match figment.extract_inner::<Replicas>(keys::READ_REPLICAS) {
+            Ok(Replicas::List(_)) => format!("{}[{}]", keys::READ_REPLICAS, name),
+            _ => format!("{}.{}", keys::READ_REPLICAS, name),
+        },
+        (PoolRole::Read, _) => keys::READ.to_string(),
+        (PoolRole::Delayed, _) => keys::DELAYED.to_string(),
+    }
+}
+
+///A fairing which checks the configuration of the database `D` at ignite with
+///[`ReadPool::validate`], aborting launch with an error naming the database and the offending
+///key. Without it, a mistyped replica `url` may only surface as `503`s once reads need it.
+///
+///Attach it before `D::init()`. By default only the configuration is parsed; with
+///[`ReadConfigCheck::probe`] every pool also serves a test connection, which opens (and closes)
+///one connection per pool at each launch.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::ReadConfigCheck;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(ReadConfigCheck::<Db>::new().probe())
+///     .attach(Db::init())
+/// # }
+///```
+pub struct ReadConfigCheck<D> {
+    probe: bool,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D: Database> ReadConfigCheck<D> {
+    pub fn new() -> Self {
+        ReadConfigCheck{probe: false, _db: PhantomData}
+    }
+
+    ///Also has every pool serve a test connection
+    pub fn probe(mut self) -> Self {
+        self.probe = true;
+        self
+    }
+}
+impl<D: Database> Default for ReadConfigCheck<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadConfigCheck<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Config Check",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        //Same defaults as rocket_db_pools' initializer
+        let workers: usize = rocket.figment()
+            .extract_inner(rocket::Config::WORKERS)
+            .unwrap_or_else(|_| rocket::Config::default().workers);
+        let figment = rocket.figment()
+            .focus(&format!("databases.{}", D::NAME))
+            .join(Serialized::default(keys::MAX_CONNECTIONS, workers * 4))
+            .join(Serialized::default(keys::CONNECT_TIMEOUT, 5));
+        let plan = match InitPlan::resolve(&figment) {
+            Ok(plan) => plan,
+            Err(e) => {
+                rocket::error!("database `{}`: invalid configuration under `{}`: {}", D::NAME, keys::database(D::NAME, ""), e);
+                return Err(rocket);
+            }
+        };
+        for pool in plan.pools.iter().filter(|_| self.probe) {
+            if let Err(e) = ReadPool::<P, R>::probe_planned(pool).await {
+                rocket::error!("database `{}`: `{}` pool failed, check `{}`: {}",
+                    D::NAME, pool.label, keys::database(D::NAME, &pool_key(&figment, pool)), e);
+                return Err(rocket);
+            }
+        }
+        Ok(rocket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Format, Toml};
+    use super::*;
+
+    fn figment(toml: &str) -> Figment {
+        Figment::from(Toml::string(toml))
+    }
+
+    fn urls(figments: &[(String, Figment)]) -> Vec<(String, String)> {
+        figments.iter().map(|(name, f)| (name.clone(), f.extract_inner::<String>(keys::URL).unwrap())).collect()
+    }
+
+    #[test]
+    fn read_false_has_no_read_pool() {
+        assert!(!has_read(&figment("url = \"m\"")));
+        assert!(!has_read(&figment("url = \"m\"\nread = false")));
+        assert!(has_read(&figment("url = \"m\"\n[read]\nurl = \"r\"")));
+        assert!(read_figments(&figment("url = \"m\"\nread = false\nread_urls = [\"r\"]")).is_empty());
+    }
+
+    #[test]
+    fn read_urls_expand_to_replicas() {
+        let figment = with_read_urls(&figment("url = \"m\"\nread_urls = [\"r1\", \"r2\"]"));
+        assert_eq!(urls(&read_figments(&figment)), [("0".into(), "r1".into()), ("1".into(), "r2".into())]);
+        let table = with_read_urls(&self::figment("url = \"m\"\nread_urls = [\"r1\"]\n[read]\nurl = \"r\""));
+        assert_eq!(urls(&read_figments(&table)), [("0".into(), "r".into())]);
+    }
+
+    #[test]
+    fn read_inherits_main_options() {
+        let figment = figment("url = \"m\"\nmax_connections = 7\nidle_timeout = 60\n[read]\nurl = \"r\"\nidle_timeout = 30");
+        let read = read_figment(&figment).unwrap();
+        assert_eq!(read.extract_inner::<String>(keys::URL).unwrap(), "r");
+        assert_eq!(read.extract_inner::<u32>(keys::MAX_CONNECTIONS).unwrap(), 7);
+        assert_eq!(read.extract_inner::<u32>(keys::IDLE_TIMEOUT).unwrap(), 30);
+        assert_eq!(read.extract_inner::<u32>(keys::CONNECT_TIMEOUT).unwrap(), 5);
+        let own = read_figment(&self::figment("url = \"m\"\nmax_connections = 7\n[read]\nurl = \"r\"\ninherit_main = false")).unwrap();
+        assert_eq!(own.extract_inner::<usize>(keys::MAX_CONNECTIONS).unwrap(), rocket::Config::default().workers * 4);
+        assert_eq!(own.extract_inner::<u32>(keys::CONNECT_TIMEOUT).unwrap(), 5);
+    }
+
+    #[test]
+    fn replicas_override_read_table() {
+        let figment = figment("url = \"m\"\n[read]\nmax_connections = 3\nreplicas = [{url = \"r1\"}, {url = \"r2\", max_connections = 9}]");
+        let replicas = read_figments(&figment);
+        assert_eq!(urls(&replicas), [("0".into(), "r1".into()), ("1".into(), "r2".into())]);
+        let sizes: Vec<u32> = replicas.iter().map(|(_, f)| f.extract_inner(keys::MAX_CONNECTIONS).unwrap()).collect();
+        assert_eq!(sizes, [3, 9]);
+    }
+
+    #[test]
+    fn standby_inherits_max_connections() {
+        let figment = figment("url = \"m\"\nmax_connections = 12\n[failover]\nurl = \"s\"");
+        let standby = failover_figment(&figment).unwrap();
+        assert_eq!(standby.extract_inner::<u32>(keys::MAX_CONNECTIONS).unwrap(), 12);
+        assert_eq!(standby.extract_inner::<u32>(keys::MIN_CONNECTIONS).unwrap(), 1);
+        assert!(failover_figment(&self::figment("url = \"m\"")).is_none());
+    }
+
+    #[test]
+    fn resolves_every_pool() {
+        let plan = InitPlan::resolve(&figment(r#"
+            url = "m"
+            label = "primary"
+            [failover]
+            url = "s"
+            [read]
+            verify = true
+            replicas = [{url = "r1"}, {url = "r2"}]
+            [read.delayed]
+            url = "d"
+        "#)).unwrap();
+        let pools: Vec<_> = plan.pools.iter().map(|p| (p.role, p.label.as_str(), p.replica.as_deref())).collect();
+        assert_eq!(pools, [
+            (PoolRole::Main, "primary", None),
+            (PoolRole::Main, "standby", None),
+            (PoolRole::Read, "read", Some("0")),
+            (PoolRole::Read, "read", Some("1")),
+            (PoolRole::Delayed, "delayed", None),
+        ]);
+        assert!(plan.verify);
+        assert_eq!(plan.record, None);
+    }
+}
@@ -0,0 +1,99 @@
+//!Application-defined rules for which reads use the main pool
+use rocket::Request;
+use crate::consistency::{PinAfterWritePolicy, ReadYourWritesPolicy};
+use crate::{FallbackReason, RoutingFlagPolicy, RoutingFlags};
+
+///What a [`RoutingPolicy`] knows of a read besides its request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingContext {
+    ///`Database::NAME` of the database read from
+    pub database: &'static str,
+    ///The request's flags for the database, see [`RoutingFlagProvider`](crate::RoutingFlagProvider)
+    pub flags: RoutingFlags,
+    ///Number of read replicas, if the pool reports it
+    pub replicas: Option<usize>,
+    ///Number of read replicas whose health checks let reads use them, if the pool reports it
+    pub available_replicas: Option<usize>,
+    ///Whether the database sets `read.pin_after_write`
+    pub pin_after_write: bool,
+}
+
+///Decides whether a read by a [`ReadConnection`](crate::ReadConnection) guard, or an
+///[`AutoConnection`](crate::AutoConnection) for a `GET` or `HEAD` request, must use the main
+///pool rather than the read replicas, e.g. for staff users or by path prefix.
+///
+///Register policies by managing [`RoutingPolicies`]. They're consulted in order on each read,
+///and the first reason returned sends it to the main pool; if none returns one, the read pool's
+///balancer picks a replica. The built-in routing by [`RoutingFlags`], [`ReadYourWrites`](crate::ReadYourWrites)
+///and `read.pin_after_write` are policies too, which come first unless the chain is built from
+///[`RoutingPolicies::empty`].
+///```rust
+/// use rocket::Request;
+/// use rocket_read_db_pools::{FallbackReason, RoutingContext, RoutingPolicies, RoutingPolicy};
+///
+/// struct StaffOnPrimary;
+/// impl RoutingPolicy for StaffOnPrimary {
+///     fn route(&self, req: &Request<'_>, _cx: &RoutingContext) -> Option<FallbackReason> {
+///         let staff = req.headers().get_one("X-Role") == Some("staff");
+///         staff.then_some(FallbackReason::Policy)
+///     }
+/// }
+///
+/// struct AdminOnPrimary;
+/// impl RoutingPolicy for AdminOnPrimary {
+///     fn route(&self, req: &Request<'_>, _cx: &RoutingContext) -> Option<FallbackReason> {
+///         req.uri().path().starts_with("/admin/").then_some(FallbackReason::Policy)
+///     }
+/// }
+///
+/// let rocket = rocket::build()
+///     .manage(RoutingPolicies::new().with(StaffOnPrimary).with(AdminOnPrimary));
+///```
+///Policies run on every read, so should answer from the request and local state.
+pub trait RoutingPolicy: Send + Sync + 'static {
+    ///Why the read described by `cx` while serving `req` must use the main pool, usually
+    ///[`FallbackReason::Policy`], or `None` to leave it to the next policy
+    fn route(&self, req: &Request<'_>, cx: &RoutingContext) -> Option<FallbackReason>;
+}
+
+///The built-in policies, in the order they run
+const BUILT_IN: [&dyn RoutingPolicy; 3] = [&RoutingFlagPolicy, &ReadYourWritesPolicy, &PinAfterWritePolicy];
+
+///Managed state registering the application's chain of [`RoutingPolicy`]s. Without it, the
+///built-in policies run alone.
+pub struct RoutingPolicies {
+    policies: Vec<Box<dyn RoutingPolicy>>,
+}
+impl RoutingPolicies {
+    ///The built-in policies: [`RoutingFlagPolicy`], [`ReadYourWritesPolicy`] and
+    ///[`PinAfterWritePolicy`]
+    pub fn new() -> Self {
+        RoutingPolicies{policies: vec![Box::new(RoutingFlagPolicy), Box::new(ReadYourWritesPolicy), Box::new(PinAfterWritePolicy)]}
+    }
+
+    ///No policies, to order the built-in ones among the application's or leave them out. Left
+    ///out, the routing they stand for is switched off.
+    pub fn empty() -> Self {
+        RoutingPolicies{policies: Vec::new()}
+    }
+
+    ///Adds `policy` to the end of the chain
+    pub fn with<T: RoutingPolicy>(mut self, policy: T) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    ///Why the read described by `cx` while serving `req` must use the main pool, if any policy
+    ///says it must
+    pub(crate) fn route(req: &Request<'_>, cx: &RoutingContext) -> Option<FallbackReason> {
+        match req.rocket().state::<Self>() {
+            Some(policies) => policies.policies.iter().find_map(|p| p.route(req, cx)),
+            None => BUILT_IN.iter().find_map(|p| p.route(req, cx)),
+        }
+    }
+}
+impl Default for RoutingPolicies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,31 @@
+//!Routing plain `Pool::get` calls to the read replicas under `prefer = "read"`
+use std::any::{Any, TypeId};
+use std::future::Future;
+
+rocket::tokio::task_local! {
+    ///Set while a connection is acquired for writing, e.g. by `RwConnection`
+    static WRITING: ();
+}
+
+///Runs an acquisition `fut` which must use the main pool whatever `prefer` says
+pub(crate) async fn writing<F: Future>(fut: F) -> F::Output {
+    WRITING.scope((), fut).await
+}
+
+///Whether the current acquisition must use the main pool
+pub(crate) fn is_writing() -> bool {
+    WRITING.try_with(|_| ()).is_ok()
+}
+
+///Whether connections of type `R` can be handed out as `P`, which `prefer = "read"` requires
+pub(crate) fn castable<R: 'static, P: 'static>() -> bool {
+    TypeId::of::<R>() == TypeId::of::<P>()
+}
+
+///Hands out a read connection as a main pool connection of the same type
+pub(crate) fn cast<R: 'static, P: 'static>(conn: R) -> P {
+    let mut conn = Some(conn);
+    (&mut conn as &mut dyn Any).downcast_mut::<Option<P>>()
+        .and_then(Option::take)
+        .expect("`prefer = \"read\"` is only enabled for identical connection types")
+}
@@ -0,0 +1,112 @@
+//!Request guard reading from the main pool whatever the routing
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+use rocket::{Ignite, Rocket, Sentinel};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{budget, deadline, failure, hooks, prefer, routing, shard, trace};
+#[cfg(feature = "testing")]
+use crate::testing;
+use crate::{ConnectionSource, FallbackReason, PoolRole, ReadCapablePool, ReadConnection, ReadPoolError, RequestRouting, StreamConnection};
+
+/// A request guard which retrieves a connection for reading from the main pool, for endpoints
+/// such as login or payment confirmation which must never read stale data.
+///
+/// Unlike [`ReadConnection`], no [`RoutingPolicy`](crate::RoutingPolicy), balancer or
+/// `prefer` setting can send it to a replica, and [`read_only`](crate::read_only) leaves it
+/// alone. Unlike [`RwConnection`](crate::RwConnection), it's recorded in [`RequestRouting`] as a
+/// read served by the main pool, with [`FallbackReason::Primary`], so it doesn't count as a
+/// write for [`ReadYourWrites`](crate::ReadYourWrites) or `read.pin_after_write`.
+/// ```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool};
+/// # use rocket_read_db_pools::ReadPool;
+/// use rocket::response::Debug;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::Primary;
+///
+/// #[rocket::get("/payments/<id>/status")]
+/// async fn status(mut conn: Primary<Db>, id: i64) -> Result<String, Debug<MockError>> {
+///     Ok(conn.query("SELECT status FROM payments WHERE id = $1").bind(id).fetch_one().await?)
+/// }
+/// ```
+pub struct Primary<D: Database>(ReadConnection<D>);
+impl<D: Database> Primary<D> {
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        self.0.into_inner()
+    }
+
+    ///Downgrades this into a `ReadConnection`, which keeps the main pool's connection
+    pub fn into_read_connection(self) -> ReadConnection<D> {
+        self.0
+    }
+
+    ///Converts into a connection a streamed response can own, returned to the main pool when
+    ///the stream is dropped, see [`StreamConnection`]
+    pub fn into_stream_guard(self) -> StreamConnection<<D::Pool as Pool>::Connection> {
+        self.0.into_stream_guard()
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for Primary<D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        #[cfg(feature = "testing")]
+        if testing::injected_failure::<D, _>(req.rocket(), PoolRole::Main) {
+            return failure::outcome(req, D::NAME, ReadPoolError::Injected(PoolRole::Main));
+        }
+        if let Err(e) = shard::resolve::<D, _>(req) {
+            return failure::outcome(req, D::NAME, e);
+        }
+        let Some(db) = D::fetch(req.rocket()) else {
+            return failure::outcome(req, D::NAME, ReadPoolError::DatabaseNotAttached);
+        };
+        let checkout = trace::Checkout::new("primary_connection", D::NAME);
+        let start = Instant::now();
+        let acquire = async {
+            if let Some(result) = db.get_read_main_for(FallbackReason::Primary).await {
+                return result;
+            }
+            prefer::writing(db.get()).await
+        };
+        let acquisition = hooks::ticketed(Some(routing::route(req)), checkout.run(budget::scoped(req, acquire)));
+        let acquisition = shard::scoped::<D, _>(RequestRouting::of(req), acquisition);
+        let (result, release) = match deadline::within(RequestRouting::of(req), acquisition).await {
+            Ok(acquired) => acquired,
+            Err(waited) => return failure::outcome(req, D::NAME, ReadPoolError::Timeout(waited)),
+        };
+        let label = db.pool_label(PoolRole::Main);
+        checkout.served(PoolRole::Main, Some(&label), start, result.is_ok(), Some(FallbackReason::Primary));
+        let log = db.log_config().cloned().unwrap_or_default();
+        db_log!(log, Routing, Debug, "database `{}`: read served by the main pool: {}", D::NAME, FallbackReason::Primary.name());
+        RequestRouting::record_fallback::<D>(req, PoolRole::Main, Some(label.clone()), start, result.is_ok(), Some(FallbackReason::Primary));
+        match result {
+            Ok(conn) => {
+                let source = ConnectionSource::new(PoolRole::Main, None, Some(FallbackReason::Primary));
+                Outcome::Success(Primary(ReadConnection(conn, PhantomData, db.statement_timeout(PoolRole::Main), release, source)))
+            }
+            Err(e) => failure::outcome(req, D::NAME, ReadPoolError::acquisition(PoolRole::Main, &label, e)),
+        }
+    }
+}
+impl<D: Database> Sentinel for Primary<D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for Primary<D> {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<D: Database> DerefMut for Primary<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,102 @@
+//!Identification and cancellation of the server-side process behind a connection
+use std::future::Future;
+use rocket::futures::future::BoxFuture;
+use crate::BoxError;
+
+///Gets the server-side id of a connection: the backend PID on Postgres (`pg_backend_pid()`) or
+///the connection id on MySQL (`CONNECTION_ID()`).
+///
+///Implement it for your connection type. Guards deref to the connection, so the id of the
+///connection behind one is `conn.server_pid().await`, e.g. to log alongside a slow request for
+///correlation with `pg_stat_activity` on the server which served it:
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::Request;
+/// use rocket_read_db_pools::{ReadConnection, RequestRouting, ServerProcess};
+///
+/// async fn log_backend(req: &Request<'_>, conn: &mut ReadConnection<Db>) {
+///     if let (Ok(pid), Some(acquisition)) = (conn.server_pid().await, RequestRouting::of(req).last()) {
+///         rocket::info!("`{}` served by `{:?}` backend {}", req.uri(), acquisition.label, pid);
+///     }
+/// }
+///```
+#[rocket::async_trait]
+pub trait ServerProcess: Send {
+    ///The server-side id of this connection
+    async fn server_pid(&mut self) -> Result<u64, BoxError>;
+}
+
+///Sends a cancel request for whatever statement a connection is running, from
+///[`CancelQuery::cancel_token`]
+pub struct CancelToken(Box<dyn FnOnce() -> BoxFuture<'static, Result<(), BoxError>> + Send>);
+impl CancelToken {
+    ///Creates a token which cancels by running `cancel`, e.g. sending a Postgres cancel request
+    ///with the backend's PID and secret key over a new connection
+    pub fn new<F, Fut>(cancel: F) -> Self
+        where F: FnOnce() -> Fut + Send + 'static, Fut: Future<Output = Result<(), BoxError>> + Send + 'static
+    {
+        CancelToken(Box::new(move || Box::pin(cancel())))
+    }
+
+    ///Sends the cancel request
+    pub async fn cancel(self) -> Result<(), BoxError> {
+        (self.0)().await
+    }
+}
+
+///Gets a token to cancel a connection's in-flight statement, for [`cancel_on_drop`].
+///
+///Implement it for your connection type, e.g. from the backend key data on Postgres.
+pub trait CancelQuery {
+    ///A token for this connection, or `None` if it can't be cancelled
+    fn cancel_token(&self) -> Option<CancelToken>;
+}
+
+///Runs `query`, sending a cancel request with `token` if the future is dropped before `query`
+///completes, so abandoned expensive statements don't keep running on the server.
+///
+///The cancel request is sent in the background on the current Tokio runtime. Futures are
+///dropped early by e.g. `tokio::time::timeout` or `select!`; note that Rocket 0.5 runs each
+///handler to completion even if the client disconnects, so disconnects don't drop it.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use std::time::Duration;
+/// use rocket::tokio::time::timeout;
+/// use rocket_read_db_pools::{cancel_on_drop, CancelQuery, ReadConnection};
+///
+/// #[rocket::get("/report")]
+/// async fn report(mut conn: ReadConnection<Db>) -> Option<String> {
+///     let token = conn.cancel_token();
+///     let query = conn.query("SELECT expensive_report()").fetch_one();
+///     timeout(Duration::from_secs(10), cancel_on_drop(token, query)).await.ok()?.ok()
+/// }
+///```
+pub async fn cancel_on_drop<F: Future>(token: Option<CancelToken>, query: F) -> F::Output {
+    let mut armed = Armed(token);
+    let output = query.await;
+    armed.0 = None;
+    output
+}
+
+struct Armed(Option<CancelToken>);
+impl Drop for Armed {
+    fn drop(&mut self) {
+        let Some(token) = self.0.take() else {return};
+        match rocket::tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = token.cancel().await {
+                        rocket::error!("failed to cancel abandoned statement: {}", e);
+                    }
+                });
+            }
+            Err(_) => rocket::error!("no async runtime to cancel abandoned statement on"),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//!A route reporting the health of a database's pools as JSON, with the `json` feature
+use std::marker::PhantomData;
+use std::time::Duration;
+use rocket::{Build, Data, Request, Rocket, Route};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::future::{join_all, BoxFuture};
+use rocket::http::{Method, Status};
+use rocket::route::{self, Handler};
+use rocket::serde::Serialize;
+use rocket::serde::json::Json;
+use rocket::tokio::time::timeout;
+use rocket_db_pools::Database;
+use crate::{HealthProbe, HealthState, PoolRole, PoolStats, PoolUsage, ReadPool};
+
+///The health of one pool, from [`ReadPool::health`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolHealth {
+    pub role: PoolRole,
+    ///The pool's label, with the replica's name if there are several
+    pub label: String,
+    ///Whether a connection was acquired within the probe's timeout
+    pub reachable: bool,
+    ///Why no connection was acquired
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    ///The replica's state, for read replicas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<HealthState>,
+    pub stats: PoolStats,
+    ///The replica's replication lag, if measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag_ms: Option<u64>,
+}
+
+///The health of every pool of a database, from [`ReadPool::health`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DbHealth {
+    pub database: String,
+    ///Whether the main pool is reachable and shutdown hasn't begun. Reads fall back to it, so
+    ///the database is usable.
+    pub ready: bool,
+    ///Whether shutdown has begun, see [`ReadPool::shutting_down`]
+    pub shutting_down: bool,
+    ///Whether any read replica is reachable and healthy
+    pub read_ready: bool,
+    ///The main pool, then each read replica in order, then the delayed replica
+    pub pools: Vec<PoolHealth>,
+}
+
+///Measures the lag of a replica connection
+type Lag<C> = for<'c> fn(&'c mut C) -> BoxFuture<'c, Option<Duration>>;
+
+///Acquires a connection from `pool` within `patience`, measuring its lag with `lag`
+async fn probe<T: PoolUsage>(pool: &T, role: PoolRole, label: String, patience: Duration, lag: Option<Lag<T::Connection>>) -> PoolHealth
+    where T::Connection: Send
+{
+    let stats = PoolStats::of(pool);
+    let mut health = PoolHealth{role, label, reachable: false, error: None, state: None, stats, lag_ms: None};
+    let conn = match timeout(patience, pool.get()).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => {
+            health.error = Some(e.to_string());
+            return health;
+        }
+        Err(_) => {
+            health.error = Some(format!("timed out after {:?}", patience));
+            return health;
+        }
+    };
+    health.reachable = true;
+    if let Some(lag) = lag {
+        let mut conn = conn;
+        health.lag_ms = timeout(patience, lag(&mut conn)).await.ok().flatten().map(|lag| lag.as_millis() as u64);
+    }
+    health
+}
+
+impl<P, R> ReadPool<P, R> where P: PoolUsage, R: PoolUsage, P::Connection: Send, R::Connection: Send {
+    ///Probes every pool concurrently, acquiring a connection from each within `patience`, and
+    ///reports whether they're reachable along with their usage and the replicas' states
+    pub async fn health(&self, database: &str, patience: Duration) -> DbHealth {
+        self.health_with(database, patience, None).await
+    }
+
+    async fn health_with(&self, database: &str, patience: Duration, lag: Option<Lag<R::Connection>>) -> DbHealth {
+        let set = self.replica_set();
+        let main = probe(self.primary(), PoolRole::Main, self.labels.main.to_string(), patience, None);
+        let read = join_all(set.pools.iter().enumerate().map(|(i, pool)| {
+            probe(pool, PoolRole::Read, set.label(&self.labels.read, i), patience, lag)
+        }));
+        let delayed = async {
+            match self.delayed {
+                Some(ref delayed) => Some(probe(delayed, PoolRole::Delayed, self.labels.delayed.to_string(), patience, None).await),
+                None => None,
+            }
+        };
+        let (main, mut read, delayed) = rocket::tokio::join!(main, read, delayed);
+        for (health, replica) in read.iter_mut().zip(&set.replicas) {
+            health.state = Some(replica.health.state());
+        }
+        let read_ready = read.iter().any(|r| r.reachable && r.state == Some(HealthState::Healthy));
+        let mut pools = vec![main];
+        pools.append(&mut read);
+        pools.extend(delayed);
+        let shutting_down = self.shutting_down();
+        DbHealth{database: database.to_string(), ready: pools[0].reachable && !shutting_down, shutting_down, read_ready, pools}
+    }
+}
+
+impl<P, R> ReadPool<P, R> where P: PoolUsage, R: PoolUsage, P::Connection: Send, R::Connection: HealthProbe {
+    ///Probes every pool as [`ReadPool::health`] does, also measuring each replica's lag
+    pub async fn health_with_lag(&self, database: &str, patience: Duration) -> DbHealth {
+        self.health_with(database, patience, Some(|conn| Box::pin(async move {conn.lag().await.ok()}))).await
+    }
+}
+
+type Report<D> = for<'a> fn(&'a <D as Database>::Pool, Duration) -> BoxFuture<'a, DbHealth>;
+
+///A fairing which mounts a route reporting [`ReadPool::health`] for the database `D` as JSON,
+///for readiness probes. It responds `200 OK` while the main pool is reachable, since reads
+///fall back to it, and `503 Service Unavailable` otherwise, or once
+///[`ReadShutdown`](crate::ReadShutdown) begins shutdown.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use std::time::Duration;
+/// use rocket_read_db_pools::DbHealthRoute;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(DbHealthRoute::<Db>::new("/health/db").timeout(Duration::from_secs(1)))
+/// # }
+///```
+///With several databases, mount each at its own path.
+pub struct DbHealthRoute<D: Database> {
+    path: String,
+    patience: Duration,
+    require_replica: bool,
+    report: Report<D>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D, P, R> DbHealthRoute<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: PoolUsage, R: PoolUsage, P::Connection: Send, R::Connection: Send
+{
+    ///Serves the report at `path`
+    pub fn new<T: Into<String>>(path: T) -> Self {
+        DbHealthRoute{
+            path: path.into(),
+            patience: Duration::from_secs(2),
+            require_replica: false,
+            report: |db, patience| Box::pin(db.health(D::NAME, patience)),
+            _db: PhantomData,
+        }
+    }
+
+    ///Time allowed for acquiring each pool's connection, and measuring lag. Defaults to 2s.
+    pub fn timeout(mut self, patience: Duration) -> Self {
+        self.patience = patience;
+        self
+    }
+
+    ///Also responds `503 Service Unavailable` unless a read replica is reachable and healthy
+    pub fn require_replica(mut self) -> Self {
+        self.require_replica = true;
+        self
+    }
+}
+impl<D, P, R> DbHealthRoute<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: PoolUsage, R: PoolUsage, P::Connection: Send, R::Connection: HealthProbe
+{
+    ///Also reports each replica's lag, with [`ReadPool::health_with_lag`]
+    pub fn with_lag(mut self) -> Self {
+        self.report = |db, patience| Box::pin(db.health_with_lag(D::NAME, patience));
+        self
+    }
+}
+
+struct HealthHandler<D: Database> {
+    patience: Duration,
+    require_replica: bool,
+    report: Report<D>,
+}
+impl<D: Database> Clone for HealthHandler<D> {
+    fn clone(&self) -> Self {
+        HealthHandler{patience: self.patience, require_replica: self.require_replica, report: self.report}
+    }
+}
+#[rocket::async_trait]
+impl<D: Database> Handler for HealthHandler<D> {
+    async fn handle<'r>(&self, req: &'r Request<'_>, _data: Data<'r>) -> route::Outcome<'r> {
+        match D::fetch(req.rocket()) {
+            Some(db) => {
+                let health = (self.report)(db, self.patience).await;
+                let status = match health.ready && (health.read_ready || !self.require_replica) {
+                    true => Status::Ok,
+                    false => Status::ServiceUnavailable,
+                };
+                route::Outcome::from(req, (status, Json(health)))
+            }
+            None => route::Outcome::Error(Status::ServiceUnavailable),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<D: Database> Fairing for DbHealthRoute<D> {
+    fn info(&self) -> Info {
+        Info {
+            name: "Database Health Route",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let handler = HealthHandler::<D>{patience: self.patience, require_replica: self.require_replica, report: self.report};
+        Ok(rocket.mount(self.path.as_str(), vec![Route::new(Method::Get, "/", handler)]))
+    }
+}
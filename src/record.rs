@@ -0,0 +1,206 @@
+//!Recording of read-path query fingerprints and replaying them against a candidate pool
+//!
+//!Enable recording with `read.record = "path/to/file"`, then report each read query with
+//![`ReadPool::record_read`](crate::ReadPool::record_read). The file can later be loaded with
+//![`load`] and re-executed against a candidate replica with [`replay`] to compare capacity.
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use rocket::futures::stream::{self, StreamExt};
+use rocket_db_pools::Pool;
+use rocket::serde::{Deserialize, Serialize};
+
+///Appends read query fingerprints and timings to a file
+pub struct Recorder {
+    start: Instant,
+    out: Mutex<BufWriter<File>>,
+}
+impl Recorder {
+    ///Creates (or truncates) the recording file at `path`
+    pub fn create<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(Recorder{start: Instant::now(), out: Mutex::new(BufWriter::new(File::create(path)?))})
+    }
+
+    ///Records one execution of `sql` which took `duration`
+    pub fn record(&self, sql: &str, duration: Duration) -> io::Result<()> {
+        let offset = self.start.elapsed();
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(out, "{}\t{}\t{}\t{}", offset.as_micros(), duration.as_micros(),
+            escape(&fingerprint(sql)), escape(sql))
+    }
+
+    ///Flushes buffered records to the file
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().unwrap_or_else(|e| e.into_inner()).flush()
+    }
+}
+
+///A query loaded from a recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RecordedQuery {
+    ///Time since recording started
+    pub offset: Duration,
+    ///How long the query took when recorded
+    pub duration: Duration,
+    ///Normalized form of the query, see [`fingerprint`]
+    pub fingerprint: String,
+    ///The query as executed
+    pub sql: String,
+}
+
+///Loads a recording made by [`Recorder`]
+pub fn load<T: AsRef<Path>>(path: T) -> io::Result<Vec<RecordedQuery>> {
+    let invalid = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("invalid record on line {}", line + 1));
+    let mut queries = Vec::new();
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let mut fields = line.splitn(4, '\t');
+        let mut next = || fields.next().ok_or_else(|| invalid(n));
+        let offset = next()?.parse().map_err(|_| invalid(n))?;
+        let duration = next()?.parse().map_err(|_| invalid(n))?;
+        queries.push(RecordedQuery{
+            offset: Duration::from_micros(offset),
+            duration: Duration::from_micros(duration),
+            fingerprint: unescape(next()?),
+            sql: unescape(next()?),
+        });
+    }
+    Ok(queries)
+}
+
+///Replay timings for all queries sharing a fingerprint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FingerprintReport {
+    pub fingerprint: String,
+    ///Number of queries replayed
+    pub count: usize,
+    ///Number of replayed queries which failed to acquire a connection or execute
+    pub errors: usize,
+    ///Mean duration when recorded
+    pub recorded_mean: Duration,
+    ///Mean duration of successful replays, including connection acquisition
+    pub replayed_mean: Duration,
+}
+
+///Results of [`replay`], ordered by fingerprint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReplayReport {
+    ///Wall-clock time taken for the whole replay
+    pub elapsed: Duration,
+    pub fingerprints: Vec<FingerprintReport>,
+}
+
+///Re-executes recorded queries against `pool` with up to `concurrency` in flight, using
+///`execute` to run each query on a connection.
+///
+///Initialize the candidate replica with `Pool::init` from its prospective config to compare
+///it against the recorded timings before cutting traffic over.
+pub async fn replay<P, F, Fut, E>(pool: &P, queries: &[RecordedQuery], concurrency: usize, execute: F) -> ReplayReport
+    where P: Pool, F: Fn(P::Connection, &RecordedQuery) -> Fut, Fut: Future<Output = Result<(), E>>
+{
+    let start = Instant::now();
+    let results = Mutex::new(HashMap::<&str, (usize, usize, Duration, Duration)>::new());
+    stream::iter(queries).for_each_concurrent(concurrency.max(1), |query| {
+        let (results, execute) = (&results, &execute);
+        async move {
+            let begin = Instant::now();
+            let ok = match pool.get().await {
+                Ok(conn) => execute(conn, query).await.is_ok(),
+                Err(_) => false,
+            };
+            let elapsed = begin.elapsed();
+            let mut results = results.lock().unwrap_or_else(|e| e.into_inner());
+            let entry = results.entry(&query.fingerprint).or_default();
+            entry.0 += 1;
+            entry.2 += query.duration;
+            if ok {entry.3 += elapsed} else {entry.1 += 1}
+        }
+    }).await;
+    let mut fingerprints: Vec<_> = results.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter()
+        .map(|(fingerprint, (count, errors, recorded, replayed))| FingerprintReport{
+            fingerprint: fingerprint.to_string(),
+            count,
+            errors,
+            recorded_mean: recorded / count as u32,
+            replayed_mean: replayed.checked_div((count - errors) as u32).unwrap_or_default(),
+        })
+        .collect();
+    fingerprints.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+    ReplayReport{elapsed: start.elapsed(), fingerprints}
+}
+
+///Normalizes a query so that executions differing only in literal values share a fingerprint.
+///
+///String and numeric literals become `?`, lists of placeholders collapse to one, whitespace is
+///collapsed and unquoted text is lowercased.
+pub fn fingerprint(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && !out.is_empty() {out.push(' ');}
+        space = false;
+        match c {
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {break;}
+                }
+                out.push('?');
+            }
+            '"' | '`' => {
+                out.push(c);
+                for q in chars.by_ref() {
+                    out.push(q);
+                    if q == c {break;}
+                }
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                out.push('?');
+            }
+            c if c.is_ascii_digit() && !out.ends_with(|p: char| p.is_alphanumeric() || p == '_') => {
+                while chars.next_if(|c| c.is_ascii_digit() || *c == '.').is_some() {}
+                out.push('?');
+            }
+            c => out.extend(c.to_lowercase()),
+        }
+        while out.ends_with("?, ?") || out.ends_with("?,?") {
+            out.truncate(out.rfind(',').unwrap_or(out.len()));
+        }
+    }
+    out
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
@@ -0,0 +1,132 @@
+//!Scheduled refreshes of materialized views through the main pool
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::tokio::time::{interval, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{BoxError, ReadPool};
+
+///Refreshes a materialized view, for [`ViewRefresh`].
+///
+///Implement it for the main pool's connection type, e.g. with
+///`REFRESH MATERIALIZED VIEW CONCURRENTLY` on Postgres.
+#[rocket::async_trait]
+pub trait RefreshView: Send {
+    ///Refreshes the view `view`
+    async fn refresh_view(&mut self, view: &str) -> Result<(), BoxError>;
+}
+
+///Managed state holding when each view refreshed by the [`ViewRefresh`] of database `D` last
+///completed, so read-path responses can report data freshness
+pub struct ViewFreshness<D> {
+    refreshed: Arc<Mutex<HashMap<String, SystemTime>>>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ViewFreshness<D> {
+    ///When `view` was last successfully refreshed, if it has been since launch
+    pub fn last_refresh(&self, view: &str) -> Option<SystemTime> {
+        self.refreshed.lock().unwrap_or_else(|e| e.into_inner()).get(view).copied()
+    }
+}
+
+///A fairing which refreshes materialized views of the database `D` at fixed intervals through
+///its main pool, from launch until shutdown. Each view is first refreshed at launch.
+///
+///Attach it after `D::init()`. Refresh times are available to handlers through the managed
+///[`ViewFreshness<D>`](ViewFreshness). The pool is shared with the refresh tasks by cloning it,
+///so both pool types must be `Clone`.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use std::time::Duration;
+/// use rocket::State;
+/// use rocket_read_db_pools::{ViewFreshness, ViewRefresh};
+///
+/// #[rocket::get("/report")]
+/// fn report(freshness: &State<ViewFreshness<Db>>) -> String {
+///     format!("as of {:?}", freshness.last_refresh("daily_totals"))
+/// }
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ViewRefresh::<Db>::new().view("daily_totals", Duration::from_secs(300)))
+/// # }
+///```
+pub struct ViewRefresh<D> {
+    views: Vec<(String, Duration)>,
+    refreshed: Arc<Mutex<HashMap<String, SystemTime>>>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ViewRefresh<D> {
+    pub fn new() -> Self {
+        ViewRefresh{views: Vec::new(), refreshed: Default::default(), _db: PhantomData}
+    }
+
+    ///Refreshes `view` every `every`
+    pub fn view(mut self, view: &str, every: Duration) -> Self {
+        self.views.push((view.to_string(), every));
+        self
+    }
+}
+impl<D> Default for ViewRefresh<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ViewRefresh<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>,
+        P::Connection: RefreshView, R::Connection: Send
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Materialized View Refresh",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ViewRefresh` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket.manage(ViewFreshness::<D>{refreshed: self.refreshed.clone(), _db: PhantomData}))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        for (view, every) in self.views.clone() {
+            let pool: ReadPool<P, R> = (**db).clone();
+            let refreshed = self.refreshed.clone();
+            let mut shutdown = rocket.shutdown();
+            rocket::tokio::spawn(async move {
+                let mut ticks = interval(every);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    rocket::tokio::select! {
+                        _ = ticks.tick() => {}
+                        _ = &mut shutdown => break,
+                    }
+                    let conn = pool.get().await.map_err(|e| e.to_string());
+                    let result = match conn {
+                        Ok(mut conn) => conn.refresh_view(&view).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    match result {
+                        Ok(()) => {
+                            refreshed.lock().unwrap_or_else(|e| e.into_inner()).insert(view.clone(), SystemTime::now());
+                        }
+                        Err(e) => db_log!(pool.log, General, Error, "database `{}`: failed to refresh view `{}`: {}", D::NAME, view, e),
+                    }
+                }
+            });
+        }
+    }
+}
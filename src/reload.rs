@@ -0,0 +1,365 @@
+//!Reloading of the read replicas from a changed configuration
+use std::future::pending;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use rocket::{Build, Orbit, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::figment::value::Value;
+use rocket::tokio::time::{interval, Interval, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{attach, plan, replicas, ConfigChange, HealthRegistry, PoolRole, ReadDbConfig, ReadPool};
+
+///A reload of a `ReadPool`'s config by [`ReadPool::reload`], passed to the hooks of
+///[`ReadPool::on_reload`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigReloaded {
+    ///The label of the read pool
+    pub label: String,
+    ///The settings which changed since the pool was created or last reloaded, with secrets
+    ///redacted, including those which only take effect when the pool is created again
+    pub changes: Vec<ConfigChange>,
+    ///The replicas created, as they're new or their options changed
+    pub added: Vec<String>,
+    ///The replicas removed, as they're no longer configured
+    pub removed: Vec<String>,
+}
+
+impl<P, R: Pool + Clone> ReadPool<P, R> {
+    ///Brings the read replicas in line with the database's config `figment`, as `Pool::init`
+    ///receives it from rocket_db_pools: pools are created for replicas which are new, or whose
+    ///options changed, then once all of them are ready they're swapped in, and those no longer
+    ///configured removed, together, their connections in use being returned as usual. Replicas
+    ///whose options didn't change keep their pools. Without a `read` table, every replica is
+    ///removed and reads use the main pool.
+    ///
+    ///Only the replica set is reloaded; other settings, such as `read.balancer`, keep the values
+    ///they had when the pool was created. The settings which changed are logged, with secrets
+    ///redacted, and passed to the hooks of [`ReadPool::on_reload`] along with the replicas added
+    ///and removed. If a replica's pool can't be created, the error is returned and the replicas
+    ///are left as they were. Clones of the pool share the change.
+    ///```rust
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::testing::MockPool;
+    /// # use rocket_read_db_pools::ReadPool;
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// # async fn _f(rocket: &rocket::Rocket<rocket::Orbit>) -> Result<(), rocket_read_db_pools::testing::MockError> {
+    /// let db = Db::fetch(rocket).unwrap();
+    /// use rocket::figment::providers::Serialized;
+    ///
+    /// //Rocket.toml and the environment as they are now, with rocket_db_pools' defaults
+    /// let figment = rocket::Config::figment().focus("databases.main")
+    ///     .join(Serialized::default("max_connections", rocket.config().workers * 4))
+    ///     .join(Serialized::default("connect_timeout", 5));
+    /// db.reload(&figment).await?;
+    /// # Ok(()) }
+    ///```
+    pub async fn reload(&self, figment: &Figment) -> Result<(), R::Error> {
+        self.connect_read().await?;
+        let figment = plan::with_read_urls(figment);
+        let configured = plan::read_figments(&figment);
+        let label = self.label(PoolRole::Read);
+        let mut staged = Vec::new();
+        {
+            let set = self.replica_set();
+            for (name, config) in &configured {
+                if set.position(name).is_some_and(|i| same_options(&set.configs[i], config)) {
+                    continue;
+                }
+                match replicas::init::<R>(name.clone(), config.clone(), label).await {
+                    Ok(replica) => staged.push((config.clone(), replica)),
+                    Err(e) => {
+                        for (_, (pool, ..)) in staged {
+                            rocket::tokio::spawn(async move {pool.close().await});
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        let (mut retired, mut added, mut removed) = (Vec::new(), Vec::new(), Vec::new());
+        {
+            let mut current = self.read.write().unwrap_or_else(|e| e.into_inner());
+            let mut set = (**current).clone();
+            for (config, (pool, weight, replica)) in staged {
+                replica.health.rejoin();
+                added.push(replica.name.to_string());
+                match set.position(&replica.name) {
+                    Some(i) => {
+                        set.weights[i] = weight;
+                        set.replicas[i] = Arc::new(replica);
+                        set.configs[i] = config;
+                        retired.push(std::mem::replace(&mut set.pools[i], pool));
+                    }
+                    None => {
+                        set.pools.push(pool);
+                        set.weights.push(weight);
+                        set.replicas.push(Arc::new(replica));
+                        set.configs.push(config);
+                    }
+                }
+            }
+            while let Some(i) = set.replicas.iter().position(|r| !configured.iter().any(|(name, _)| **name == *r.name)) {
+                removed.push(set.replicas.remove(i).name.to_string());
+                set.weights.remove(i);
+                set.configs.remove(i);
+                retired.push(set.pools.remove(i));
+            }
+            *current = Arc::new(set);
+        }
+        for name in &added {
+            db_log!(self.log, General, Info, "`{}` replica `{}` added", label, name);
+        }
+        for name in &removed {
+            db_log!(self.log, General, Info, "`{}` replica `{}` removed", label, name);
+        }
+        db_log!(self.log, General, Info, "`{}` replicas reloaded: {} configured, {} removed", label, configured.len(), removed.len());
+        let changes = self.reconfigured(&figment);
+        for change in &changes {
+            db_log!(self.log, General, Info, "`{}` config changed: {}", label, change);
+        }
+        self.hooks.reloaded(ConfigReloaded{label: label.to_string(), changes, added, removed});
+        for pool in retired {
+            pool.close().await;
+        }
+        Ok(())
+    }
+
+    ///Records the config `figment` as the pool's config, returning what changed since the last
+    ///one recorded
+    fn reconfigured(&self, figment: &Figment) -> Vec<ConfigChange> {
+        let new = match ReadDbConfig::extract(figment) {
+            Ok(new) => Arc::new(new),
+            Err(e) => {
+                db_log!(self.log, General, Warn, "`{}` reloaded config couldn't be compared: {}", self.label(PoolRole::Read), e);
+                return Vec::new();
+            }
+        };
+        let old = std::mem::replace(&mut *self.config.write().unwrap_or_else(|e| e.into_inner()), new.clone());
+        old.diff(&new)
+    }
+}
+
+///Whether two replicas' configs have the same options, leaving out the `replicas` table each
+///inherits from the `read` table
+fn same_options(a: &Figment, b: &Figment) -> bool {
+    let options = |figment: &Figment| match figment.extract::<Value>() {
+        Ok(Value::Dict(_, mut dict)) => {
+            dict.remove("replicas");
+            Some(dict)
+        }
+        _ => None,
+    };
+    matches!((options(a), options(b)), (Some(a), Some(b)) if a == b)
+}
+
+#[cfg(unix)]
+type Hangup = rocket::tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+type Hangup = std::convert::Infallible;
+
+///Listens for SIGHUP, where there's such a signal
+fn listen_for_hangup() -> Option<Hangup> {
+    #[cfg(unix)]
+    {
+        use rocket::tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(hangup) => return Some(hangup),
+            Err(e) => rocket::warn!("couldn't listen for SIGHUP to reload read replicas: {}", e),
+        }
+    }
+    None
+}
+
+///Waits for the next SIGHUP, or forever without one to wait for
+async fn hung_up(hangup: &mut Option<Hangup>) {
+    #[cfg(unix)]
+    if let Some(hangup) = hangup {
+        if hangup.recv().await.is_some() {
+            return;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = hangup;
+    pending().await
+}
+
+///Waits for the next tick of `ticks`, or forever without them
+async fn ticked(ticks: &mut Option<Interval>) {
+    match ticks {
+        Some(ticks) => {ticks.tick().await;}
+        None => pending().await,
+    }
+}
+
+async fn modified(path: &PathBuf) -> Option<SystemTime> {
+    rocket::tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok()
+}
+
+///A fairing which calls [`ReadPool::reload`] on the database `D` when the process receives
+///SIGHUP, on Unix, and, if [`ReadReload::watch`] is set, when a file's modification time
+///changes, so replica endpoints can be rotated without restarting.
+///
+///The config is read again by [`rocket::Config::figment`], i.e. from `Rocket.toml` and
+///`ROCKET_` environment variables, unless [`ReadReload::source`] replaces it. Replica states
+///are published to the managed [`HealthRegistry`] after each reload, if there's one.
+///
+///Attach it after `D::init()`. The read pool type must be `Clone`.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{ReadPool, ReadReload};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// # fn _f() -> rocket::Rocket<rocket::Build> {
+/// use std::time::Duration;
+///
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(ReadReload::<Db>::new().watch("Rocket.toml", Duration::from_secs(5)))
+/// # }
+///```
+pub struct ReadReload<D> {
+    source: Arc<dyn Fn() -> Figment + Send + Sync>,
+    watch: Option<(PathBuf, Duration)>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D> ReadReload<D> {
+    pub fn new() -> Self {
+        ReadReload{source: Arc::new(rocket::Config::figment), watch: None, _db: PhantomData}
+    }
+
+    ///Reads the config from the figment `source` returns rather than `rocket::Config::figment`,
+    ///e.g. to add the providers the application was built with
+    pub fn source<F: Fn() -> Figment + Send + Sync + 'static>(mut self, source: F) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    ///Also reloads when the modification time of the file at `path` changes, checked every
+    ///`every`
+    pub fn watch<T: Into<PathBuf>>(mut self, path: T, every: Duration) -> Self {
+        self.watch = Some((path.into(), every));
+        self
+    }
+}
+impl<D> Default for ReadReload<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadReload<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Replica Reload",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if D::fetch(&rocket).is_none() {
+            rocket::error!("`ReadReload` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let pool: ReadPool<P, R> = (**db).clone();
+        let registry = rocket.state::<HealthRegistry>().cloned();
+        let source = self.source.clone();
+        let watch = self.watch.clone();
+        let mut shutdown = rocket.shutdown();
+        rocket::tokio::spawn(async move {
+            let mut hangup = listen_for_hangup();
+            let (path, mut ticks) = match watch {
+                Some((path, every)) => {
+                    let mut ticks = interval(every.max(Duration::from_millis(1)));
+                    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    (Some(path), Some(ticks))
+                }
+                None => (None, None),
+            };
+            let mut last_modified = match &path {
+                Some(path) => modified(path).await,
+                None => None,
+            };
+            loop {
+                let cause = rocket::tokio::select! {
+                    _ = hung_up(&mut hangup) => "SIGHUP",
+                    _ = ticked(&mut ticks) => {
+                        let Some(path) = &path else {continue};
+                        let now = modified(path).await;
+                        if now == last_modified {
+                            continue;
+                        }
+                        last_modified = now;
+                        "a change to the watched file"
+                    }
+                    _ = &mut shutdown => break,
+                };
+                db_log!(pool.log, General, Info, "database `{}`: reloading read replicas on {}", D::NAME, cause);
+                let figment = attach::database_figment(&source(), D::NAME);
+                if let Err(e) = pool.reload(&figment).await {
+                    db_log!(pool.log, General, Error, "database `{}`: failed to reload read replicas: {}", D::NAME, e);
+                }
+                if let Some(ref registry) = registry {
+                    registry.update(D::NAME, &pool);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::tokio::sync::mpsc;
+    use crate::testing::MockPool;
+    use super::*;
+
+    const REPLICAS: &str = "url = \"main\"\n[read.replicas.a]\nurl = \"a\"\n[read.replicas.b]\nurl = \"b\"\n";
+
+    fn figment(toml: &str) -> Figment {
+        Figment::from(Toml::string(toml))
+    }
+
+    fn urls(pool: &ReadPool<MockPool>) -> Vec<(String, String)> {
+        pool.replica_names().into_iter().map(|name| {
+            let url = pool.replica(&name).unwrap().url().to_string();
+            (name, url)
+        }).collect()
+    }
+
+    #[rocket::async_test]
+    async fn reload_adds_changed_and_removes_unconfigured_replicas() {
+        let pool = ReadPool::<MockPool>::init(&figment(REPLICAS)).await.unwrap();
+        let (send, mut reloads) = mpsc::unbounded_channel();
+        pool.on_reload(move |event| {
+            let _ = send.send(event);
+            Box::pin(async {})
+        });
+        pool.reload(&figment("url = \"main\"\n[read.replicas.b]\nurl = \"postgres://u:pw@b2/db\"\n[read.replicas.c]\nurl = \"c\"\n")).await.unwrap();
+        assert_eq!(urls(&pool), vec![("b".into(), "postgres://u:pw@b2/db".into()), ("c".into(), "c".into())]);
+        let reload = reloads.recv().await.unwrap();
+        assert_eq!((reload.added, reload.removed), (vec!["b".to_string(), "c".to_string()], vec!["a".to_string()]));
+        let changes = reload.changes.iter().map(|change| change.to_string()).collect::<Vec<_>>();
+        assert!(changes.contains(&"read.replicas.b.url: <redacted> -> postgres://b2".to_string()), "{:?}", changes);
+        assert!(changes.iter().any(|change| change.starts_with("read.replicas.a.url: ")), "{:?}", changes);
+    }
+
+    #[rocket::async_test]
+    async fn a_failed_reload_changes_no_replica() {
+        let pool = ReadPool::<MockPool>::init(&figment(REPLICAS)).await.unwrap();
+        let before = urls(&pool);
+        let reload = pool.reload(&figment("url = \"main\"\n[read.replicas.b]\nurl = \"b2\"\n[read.replicas.c]\nweight = 2\n")).await;
+        assert!(reload.is_err());
+        assert_eq!(urls(&pool), before);
+    }
+}
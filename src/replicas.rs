@@ -0,0 +1,304 @@
+//!The read replicas of a `ReadPool`, which can be added, removed and resized at runtime
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use rocket::figment::Figment;
+use rocket_db_pools::Pool;
+use crate::health::ReplicaHealth;
+use crate::logging::db_log;
+use crate::{dns, keys, secrets, tag, tls, usage, MaintenanceWindow, PoolRole, ReadPool};
+
+///State of one read replica, kept as other replicas are added and removed
+#[derive(Debug, Default)]
+pub(crate) struct Replica {
+    pub(crate) name: Arc<str>,
+    pub(crate) zone: Option<Arc<str>>,
+    pub(crate) health: ReplicaHealth,
+    pub(crate) maintenance: Vec<MaintenanceWindow>,
+    pub(crate) min_connections: u32,
+    pub(crate) usage: usage::Counters,
+}
+
+///The read replicas at one point in time, replaced as a whole when they change. Pools and
+///weights are kept in their own lists for `ReadBalancer::pick`, and each pool's options for
+///recreating it.
+pub(crate) struct ReplicaSet<R> {
+    pub(crate) pools: Vec<R>,
+    pub(crate) weights: Vec<u32>,
+    pub(crate) replicas: Vec<Arc<Replica>>,
+    pub(crate) configs: Vec<Figment>,
+}
+impl<R> ReplicaSet<R> {
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    ///The index of the replica named `name`
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.replicas.iter().position(|r| &*r.name == name)
+    }
+
+    ///The read pool's label and the name of replica `i`, for logs
+    pub fn label(&self, read: &str, i: usize) -> String {
+        match self.len() {
+            1 => read.to_string(),
+            _ => format!("{}/{}", read, self.replicas[i].name),
+        }
+    }
+}
+impl<R> Default for ReplicaSet<R> {
+    fn default() -> Self {
+        ReplicaSet{pools: Vec::new(), weights: Vec::new(), replicas: Vec::new(), configs: Vec::new()}
+    }
+}
+impl<R: Clone> Clone for ReplicaSet<R> {
+    fn clone(&self) -> Self {
+        ReplicaSet{pools: self.pools.clone(), weights: self.weights.clone(), replicas: self.replicas.clone(), configs: self.configs.clone()}
+    }
+}
+
+///Resizing of a pool in place, for [`ReadPool::resize_read`], e.g. with deadpool's
+///`Pool::resize`. Pools which can't be resized in place, such as sqlx's, return `false` and
+///are recreated with the new size instead.
+pub trait ResizePool: Pool {
+    ///Sets the most connections the pool opens, opening more as needed when it grows, and when
+    ///it shrinks, closing idle connections first and others as they're returned. Returns
+    ///`false` if the pool can't be resized in place.
+    fn resize(&self, max_connections: u32) -> bool;
+}
+
+///Creates a replica's pool, weight and state from its pool options
+pub(crate) async fn init<R: Pool>(name: String, figment: Figment, label: &str) -> Result<(R, u32, Replica), R::Error> {
+    let weight = figment.extract_inner("weight").unwrap_or(1);
+    let zone = figment.extract_inner::<String>("zone").ok().map(Into::into);
+    let maintenance: Vec<MaintenanceWindow> = figment.extract_inner("maintenance").unwrap_or_default();
+    MaintenanceWindow::check(&maintenance, label);
+    let min_connections = figment.extract_inner(keys::MIN_CONNECTIONS).unwrap_or(0);
+    let pool = R::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(figment).await))).await).await?;
+    Ok((pool, weight, Replica{name: name.into(), zone, maintenance, min_connections, ..Default::default()}))
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///The current read replicas
+    pub(crate) fn replica_set(&self) -> Arc<ReplicaSet<R>> {
+        self.read.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    ///The name of each read replica, as configured in `read.replicas` or added since, in order
+    pub fn replica_names(&self) -> Vec<String> {
+        self.replica_set().replicas.iter().map(|r| r.name.to_string()).collect()
+    }
+
+    ///Whether any read replica is configured, available or not. Without one, reads are served
+    ///by the main pool.
+    pub fn has_read_replica(&self) -> bool {
+        !self.replica_set().is_empty()
+    }
+
+    ///Takes the read replica named `name` out of rotation, e.g. ahead of work on it. New reads
+    ///skip it while connections already acquired are returned as usual; its pool stays open.
+    ///Returns `false` if there's no such replica. Clones of the pool share the change.
+    pub fn drain_replica(&self, name: &str) -> bool {
+        self.set_drained(name, true)
+    }
+
+    ///Puts the read replica named `name` back into rotation after [`ReadPool::drain_replica`].
+    ///Its share of reads ramps up under `read.slow_start_ms`. Returns `false` if there's no
+    ///such replica.
+    pub fn enable_replica(&self, name: &str) -> bool {
+        self.set_drained(name, false)
+    }
+
+    fn set_drained(&self, name: &str, drained: bool) -> bool {
+        let set = self.replica_set();
+        let Some(i) = set.position(name) else {return false};
+        let health = &set.replicas[i].health;
+        if health.drained.swap(drained, Ordering::Relaxed) != drained {
+            let label = self.label(PoolRole::Read);
+            match drained {
+                true => db_log!(self.log, General, Warn, "`{}` replica `{}` drained", label, name),
+                false => {
+                    db_log!(self.log, General, Info, "`{}` replica `{}` enabled", label, name);
+                    health.rejoin();
+                }
+            }
+        }
+        true
+    }
+
+    ///Whether each read replica is currently drained by [`ReadPool::drain_replica`]
+    pub fn drained(&self) -> Vec<bool> {
+        self.replica_set().replicas.iter().map(|r| r.health.drained()).collect()
+    }
+}
+
+impl<P, R: Clone> ReadPool<P, R> {
+    ///The pool of each read replica, in order
+    pub fn replicas(&self) -> Vec<R> {
+        self.replica_set().pools.clone()
+    }
+
+    ///The pool of the read replica the next read would use, chosen by the balancer among the
+    ///available replicas as for [`ReadConnection`](crate::ReadConnection), for reads outside a
+    ///request. `None` if there's none, in which case reads belong on [`ReadPool::main`].
+    pub fn read(&self) -> Option<R> {
+        let set = self.replica_set();
+        self.next_replica(&set).map(|i| set.pools[i].clone())
+    }
+
+    ///The pool of the read replica named `name`
+    pub fn replica(&self, name: &str) -> Option<R> {
+        let set = self.replica_set();
+        set.position(name).map(|i| set.pools[i].clone())
+    }
+}
+
+impl<P, R: Pool + Clone> ReadPool<P, R> {
+    ///Adds a read replica named `name`, created from the pool options in `figment` as a
+    ///`read.replicas` entry would be, including its `weight` and `maintenance`. A replica of the
+    ///same name is replaced and its pool closed. Its share of reads ramps up under
+    ///`read.slow_start_ms`. Clones of the pool share the change.
+    ///
+    ///Options aren't inherited from the `read` table, so merge the entry over it if needed:
+    ///```rust
+    /// # use rocket_db_pools::Database;
+    /// # use rocket_read_db_pools::testing::MockPool;
+    /// # use rocket_read_db_pools::ReadPool;
+    /// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+    /// # async fn _f(rocket: &rocket::Rocket<rocket::Orbit>) -> Result<(), rocket_read_db_pools::testing::MockError> {
+    /// let db = Db::fetch(rocket).unwrap();
+    /// let figment = rocket.figment().focus("databases.main.read")
+    ///     .merge(("url", "postgresql://user@replica-eu2.example/dbname"));
+    /// db.add_replica("eu2", &figment).await?;
+    /// # Ok(()) }
+    ///```
+    pub async fn add_replica(&self, name: &str, figment: &Figment) -> Result<(), R::Error> {
+        self.connect_read().await?;
+        let label = self.label(PoolRole::Read);
+        let (pool, weight, replica) = init::<R>(name.to_string(), figment.clone(), label).await?;
+        replica.health.rejoin();
+        let replaced = {
+            let mut current = self.read.write().unwrap_or_else(|e| e.into_inner());
+            let mut set = (**current).clone();
+            let replaced = match set.position(name) {
+                Some(i) => {
+                    set.weights[i] = weight;
+                    set.replicas[i] = Arc::new(replica);
+                    set.configs[i] = figment.clone();
+                    Some(std::mem::replace(&mut set.pools[i], pool))
+                }
+                None => {
+                    set.pools.push(pool);
+                    set.weights.push(weight);
+                    set.replicas.push(Arc::new(replica));
+                    set.configs.push(figment.clone());
+                    None
+                }
+            };
+            *current = Arc::new(set);
+            replaced
+        };
+        db_log!(self.log, General, Info, "`{}` replica `{}` added", label, name);
+        if let Some(old) = replaced {
+            old.close().await;
+        }
+        Ok(())
+    }
+
+    ///Removes the read replica named `name`, then closes its pool, which waits for its
+    ///connections in use to be returned. Returns `false` if there's no such replica. Clones of
+    ///the pool share the change.
+    pub async fn remove_replica(&self, name: &str) -> bool {
+        let removed = {
+            let mut current = self.read.write().unwrap_or_else(|e| e.into_inner());
+            let mut set = (**current).clone();
+            let Some(i) = set.position(name) else {return false};
+            set.weights.remove(i);
+            set.replicas.remove(i);
+            set.configs.remove(i);
+            let removed = set.pools.remove(i);
+            *current = Arc::new(set);
+            removed
+        };
+        db_log!(self.log, General, Info, "`{}` replica `{}` removed", self.label(PoolRole::Read), name);
+        removed.close().await;
+        true
+    }
+
+    ///Recreates every read replica's pool, resolving its `secrets` again, e.g. after its
+    ///password is rotated. Each new pool is swapped in once it's ready, then the old one is
+    ///closed, retiring its idle connections at once and those in use as they're returned.
+    ///Connections opened before keep the old credentials until then, so the old password should
+    ///stay valid until this returns. The main and delayed pools only pick up rotated secrets
+    ///when the database is initialized again.
+    ///
+    ///Replicas added or removed meanwhile are left as they are. If a new pool can't be created,
+    ///the remaining replicas keep their pools and the error is returned. Clones of the pool
+    ///share the change.
+    pub async fn rotate_read_credentials(&self) -> Result<(), R::Error> {
+        self.recreate_read(|config| config, |_| false, "recreated with rotated credentials").await
+    }
+
+    ///Recreates each read replica's pool from its config as changed by `change`, swapping it in
+    ///once it's ready and closing the old one, unless `in_place` applies the change to the
+    ///existing pool, logging that the replica was `done`
+    async fn recreate_read(&self, change: impl Fn(Figment) -> Figment, in_place: impl Fn(&R) -> bool, done: &str) -> Result<(), R::Error> {
+        let label = self.label(PoolRole::Read);
+        let set = self.replica_set();
+        for ((replica, config), pool) in set.replicas.iter().zip(&set.configs).zip(&set.pools) {
+            let config = change(config.clone());
+            if in_place(pool) {
+                let mut current = self.read.write().unwrap_or_else(|e| e.into_inner());
+                let mut set = (**current).clone();
+                if let Some(i) = set.replicas.iter().position(|r| Arc::ptr_eq(r, replica)) {
+                    set.configs[i] = config;
+                    *current = Arc::new(set);
+                }
+                drop(current);
+                db_log!(self.log, General, Info, "`{}` replica `{}` {}", label, replica.name, done);
+                continue;
+            }
+            let pool = R::init(&dns::pin(tls::apply(tag::apply(secrets::resolve(config.clone()).await))).await).await?;
+            let retired = {
+                let mut current = self.read.write().unwrap_or_else(|e| e.into_inner());
+                let mut set = (**current).clone();
+                match set.replicas.iter().position(|r| Arc::ptr_eq(r, replica)) {
+                    Some(i) => {
+                        set.configs[i] = config;
+                        let retired = std::mem::replace(&mut set.pools[i], pool);
+                        *current = Arc::new(set);
+                        retired
+                    }
+                    //Replaced or removed meanwhile
+                    None => pool,
+                }
+            };
+            db_log!(self.log, General, Info, "`{}` replica `{}` {}", label, replica.name, done);
+            retired.close().await;
+        }
+        Ok(())
+    }
+}
+
+impl<P, R: ResizePool + Clone> ReadPool<P, R> {
+    ///Sets the `max_connections` of every read replica while serving reads.
+    ///
+    ///Pools which [`ResizePool::resize`] resizes in place keep their connections: growing opens
+    ///more as needed, and shrinking closes idle connections first, then others as they're
+    ///returned. Other pools are recreated with the new size as by
+    ///[`ReadPool::rotate_read_credentials`], so until the old pool's connections in use are
+    ///returned, the replica may have up to both sizes' connections open, and the old pool's idle
+    ///connections are closed rather than reused.
+    ///
+    ///Replicas added or removed meanwhile are left as they are. If a new pool can't be created,
+    ///the remaining replicas keep their size and the error is returned. Clones of the pool share
+    ///the change.
+    pub async fn resize_read(&self, max_connections: u32) -> Result<(), R::Error> {
+        let resized = format!("resized to {} connections", max_connections);
+        let change = |config: Figment| config.merge((keys::MAX_CONNECTIONS, max_connections));
+        self.recreate_read(change, |pool| pool.resize(max_connections), &resized).await
+    }
+}
@@ -0,0 +1,189 @@
+//!Allowlist of tables present on a partial (e.g. logical replication) replica
+use crate::logging::db_log;
+use crate::{ReadPool, ReplicaConfig};
+
+///Normalized `replicated_tables` and whether queries are checked against them
+pub(crate) fn config(replica: Option<&ReplicaConfig>) -> (Option<Vec<String>>, bool) {
+    let Some(replica) = replica else {return (None, false)};
+    let tables = replica.replicated_tables.as_ref()
+        .map(|tables| tables.iter().map(|t| normalize(t)).collect());
+    (tables, replica.check_replicated_tables())
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Returns true if `table` is available on the read pool.
+    ///
+    ///All tables are considered replicated unless `read.replicated_tables` is configured.
+    ///Schema-qualified names match either the qualified or bare entry.
+    pub fn is_replicated(&self, table: &str) -> bool {
+        let Some(ref tables) = self.replicated_tables else {return true};
+        let table = normalize(table);
+        let bare = table.rsplit('.').next().unwrap_or(&table);
+        tables.iter().any(|t| *t == table || t == bare || t.rsplit('.').next() == Some(&table))
+    }
+
+    ///Checks a query intended for the read path against `read.replicated_tables`, returning
+    ///the referenced tables which aren't replicated.
+    ///
+    ///Table references are found heuristically from `FROM` and `JOIN` clauses, so this is a
+    ///development aid rather than a guarantee. When `read.check_replicated_tables` is enabled
+    ///(the default in debug builds) offending queries are also logged as warnings; otherwise
+    ///this always returns an empty list.
+    pub fn check_read_query(&self, sql: &str) -> Vec<String> {
+        if !self.check_replicated || self.replicated_tables.is_none() {
+            return Vec::new();
+        }
+        let missing: Vec<String> = referenced_tables(sql).into_iter()
+            .filter(|t| !self.is_replicated(t))
+            .collect();
+        if !missing.is_empty() {
+            db_log!(self.log, General, Warn, "read query references tables not replicated to `{}` ({}): {}",
+                self.labels.read, missing.join(", "), sql);
+        }
+        missing
+    }
+}
+
+///Lowercases unquoted identifiers and strips quoting
+fn normalize(ident: &str) -> String {
+    ident.split('.')
+        .map(|part| match part.strip_prefix(['"', '`']).and_then(|p| p.strip_suffix(['"', '`'])) {
+            Some(quoted) => quoted.to_string(),
+            None => part.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[derive(PartialEq)]
+pub(crate) enum Token<'a> {
+    Ident(&'a str),
+    Comma,
+    Open,
+    Close,
+    Other,
+}
+
+pub(crate) fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        match c {
+            b',' => {tokens.push(Token::Comma); i += 1;}
+            b'(' => {tokens.push(Token::Open); i += 1;}
+            b')' => {tokens.push(Token::Close); i += 1;}
+            b'\'' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'\'' {i += 1;}
+                i += 1;
+                tokens.push(Token::Other);
+            }
+            _ if c.is_ascii_alphanumeric() || c == b'_' || c == b'"' || c == b'`' => {
+                while i < bytes.len() {
+                    match bytes[i] {
+                        q @ (b'"' | b'`') => {
+                            i += 1;
+                            while i < bytes.len() && bytes[i] != q {i += 1;}
+                            i += 1;
+                        }
+                        b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'$' => i += 1,
+                        _ => break,
+                    }
+                }
+                tokens.push(Token::Ident(&sql[start..i.min(sql.len())]));
+            }
+            _ if c.is_ascii_whitespace() => i += 1,
+            _ => {tokens.push(Token::Other); i += 1;}
+        }
+    }
+    tokens
+}
+
+///Keywords which may directly follow a table reference, so aren't aliases
+const CLAUSE_KEYWORDS: &[&str] = &["where", "join", "inner", "left", "right", "full", "cross",
+    "natural", "on", "using", "group", "order", "limit", "offset", "having", "window", "union",
+    "intersect", "except", "for", "returning", "set", "values", "lateral", "fetch", "tablesample"];
+
+///Functions whose arguments use `FROM` as a separator rather than introducing a table
+const FROM_FUNCTIONS: &[&str] = &["extract", "substring", "trim", "overlay", "position"];
+
+///Finds the table names referenced by `FROM` and `JOIN` clauses
+fn referenced_tables(sql: &str) -> Vec<String> {
+    let tokens = tokenize(sql);
+    let mut tables = Vec::new();
+    //Whether each open parenthesis belongs to one of `FROM_FUNCTIONS`
+    let mut parens = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let is_source = match tokens[i] {
+            Token::Open => {
+                let function = matches!(i.checked_sub(1).map(|p| &tokens[p]), Some(Token::Ident(f))
+                    if FROM_FUNCTIONS.iter().any(|kw| f.eq_ignore_ascii_case(kw)));
+                parens.push(function);
+                false
+            }
+            Token::Close => {parens.pop(); false}
+            Token::Ident(kw) if kw.eq_ignore_ascii_case("join") => true,
+            Token::Ident(kw) if kw.eq_ignore_ascii_case("from") => parens.last() != Some(&true),
+            _ => false,
+        };
+        i += 1;
+        if !is_source {continue;}
+        while let Some(Token::Ident(name)) = tokens.get(i) {
+            i += 1;
+            if tokens.get(i) == Some(&Token::Open) {
+                //Set-returning function call (e.g. `unnest(...)`), skip its arguments
+                let mut depth = 0;
+                while let Some(token) = tokens.get(i) {
+                    i += 1;
+                    match token {
+                        Token::Open => depth += 1,
+                        Token::Close => {depth -= 1; if depth == 0 {break;}}
+                        _ => {}
+                    }
+                }
+            } else {
+                tables.push(normalize(name));
+            }
+            //Skip an optional `AS alias` or bare alias
+            if matches!(tokens.get(i), Some(Token::Ident(kw)) if kw.eq_ignore_ascii_case("as")) {
+                i += 1;
+            }
+            if matches!(tokens.get(i), Some(Token::Ident(alias))
+                if !CLAUSE_KEYWORDS.iter().any(|kw| alias.eq_ignore_ascii_case(kw))) {
+                i += 1;
+            }
+            if tokens.get(i) != Some(&Token::Comma) {break;}
+            i += 1;
+        }
+    }
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_identifiers() {
+        assert_eq!(normalize("Public.Users"), "public.users");
+        assert_eq!(normalize("\"Mixed\".`Case`"), "Mixed.Case");
+    }
+
+    #[test]
+    fn finds_from_and_join_tables() {
+        assert_eq!(referenced_tables("SELECT * FROM users u JOIN orders AS o ON o.user_id = u.id"), ["users", "orders"]);
+        assert_eq!(referenced_tables("select 1 from a, b c, public.\"D\" where 1 = 1"), ["a", "b", "public.D"]);
+        assert_eq!(referenced_tables("SELECT * FROM (SELECT id FROM inner_t) sub LEFT JOIN x USING (id)"), ["inner_t", "x"]);
+    }
+
+    #[test]
+    fn skips_functions_and_literals() {
+        assert_eq!(referenced_tables("SELECT EXTRACT(YEAR FROM created) FROM events"), ["events"]);
+        assert_eq!(referenced_tables("SELECT * FROM unnest(ARRAY[1, 2]) JOIN t ON true"), ["t"]);
+        assert_eq!(referenced_tables("SELECT 'from nowhere' FROM t"), ["t"]);
+    }
+}
@@ -0,0 +1,150 @@
+//!Per-request database role switching
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Deref, DerefMut};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use crate::{BoxError, ContextError, PoolRole, ReadDbConfig, RequestRouting};
+
+///Switches the database role of a connection, for [`WithRole`].
+///
+///Implement it for your connection type, e.g. with `SET ROLE` / `RESET ROLE` on Postgres.
+#[rocket::async_trait]
+pub trait SessionRole: Send {
+    ///Switches to `role`
+    async fn set_role(&mut self, role: &str) -> Result<(), BoxError>;
+    ///Switches back to the connection's login role
+    async fn reset_role(&mut self) -> Result<(), BoxError>;
+}
+
+type ClassFn = Box<dyn Fn(&Request<'_>) -> Option<String> + Send + Sync>;
+
+///Managed state mapping the authenticated user class of a request to a database role, per
+///database and pool.
+///
+///The user class comes from a hook (typically reading request-local auth state), and roles
+///can be configured per pool under `roles`, `read.roles` and `read.delayed.roles`:
+///```toml
+///[default.databases.main.roles]
+///staff = "app_staff"
+///public = "app_public"
+///[default.databases.main.read.roles]
+///staff = "app_staff_ro"
+///```
+///```rust
+/// # fn _inner(figment: &rocket::figment::Figment) -> Result<(), Box<rocket::figment::Error>> {
+/// use rocket_read_db_pools::{ReadDbConfig, RoleSwitch};
+///
+/// # struct User{class: String}
+/// let config = ReadDbConfig::extract(&figment.focus("databases.main"))?;
+/// let roles = RoleSwitch::new(|req| req.local_cache(|| None::<User>).as_ref().map(|u| u.class.clone()))
+///     .configured("main", &config);
+/// let rocket = rocket::build().manage(roles);
+/// # Ok(()) }
+///```
+pub struct RoleSwitch {
+    class: ClassFn,
+    roles: HashMap<(String, PoolRole), BTreeMap<String, String>>,
+}
+impl RoleSwitch {
+    ///Creates a switch with no roles, taking the user class of each request from `class`
+    pub fn new<F>(class: F) -> Self
+        where F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static
+    {
+        RoleSwitch{class: Box::new(class), roles: HashMap::new()}
+    }
+
+    ///Uses `role` for requests with user class `class` on the `pool` of database `database`
+    pub fn role(mut self, database: &str, pool: PoolRole, class: &str, role: &str) -> Self {
+        self.roles.entry((database.to_string(), pool)).or_default().insert(class.to_string(), role.to_string());
+        self
+    }
+
+    ///Adds the roles configured for the database `database`
+    pub fn configured(mut self, database: &str, config: &ReadDbConfig) -> Self {
+        let read = config.read.as_ref();
+        for (pool, roles) in [
+            (PoolRole::Main, config.roles.as_ref()),
+            (PoolRole::Read, read.and_then(|r| r.roles.as_ref())),
+            (PoolRole::Delayed, read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.roles.as_ref())),
+        ] {
+            if let Some(roles) = roles {
+                self.roles.entry((database.to_string(), pool)).or_default().extend(roles.clone());
+            }
+        }
+        self
+    }
+
+    pub(crate) fn role_for(&self, req: &Request<'_>, database: &str, pool: PoolRole) -> Option<&str> {
+        let class = (self.class)(req)?;
+        self.roles.get(&(database.to_string(), pool))?.get(&class).map(String::as_str)
+    }
+}
+
+///A request guard wrapping a connection guard (`WithRole<ReadConnection<Db>>`,
+///`WithRole<RwConnection<Db>>`, ...) which switches the connection to the role the managed
+///[`RoleSwitch`] gives for the request and pool, or resets it if there is none.
+///
+///The role is reset when the guard is dropped, before the connection returns to its pool. This
+///needs an async task, so happens in the background on the current Tokio runtime.
+pub struct WithRole<G> where G: DerefMut + Send + 'static, G::Target: SessionRole {
+    guard: Option<G>,
+    switched: bool,
+}
+impl<G> WithRole<G> where G: DerefMut + Send + 'static, G::Target: SessionRole {
+    ///Whether the connection was switched to a role, rather than reset
+    pub fn switched(&self) -> bool {
+        self.switched
+    }
+}
+#[rocket::async_trait]
+impl<'r, G> FromRequest<'r> for WithRole<G>
+    where G: FromRequest<'r> + DerefMut + Send + 'static, G::Target: SessionRole
+{
+    type Error = ContextError<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut guard = match G::from_request(req).await {
+            Outcome::Success(guard) => guard,
+            Outcome::Error((status, e)) => return Outcome::Error((status, ContextError::Guard(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        let role = req.rocket().state::<RoleSwitch>().zip(RequestRouting::of(req).last())
+            .and_then(|(switch, acquisition)| switch.role_for(req, acquisition.database, acquisition.role));
+        let result = match role {
+            Some(role) => guard.set_role(role).await,
+            None => guard.reset_role().await,
+        };
+        match result {
+            Ok(()) => Outcome::Success(WithRole{guard: Some(guard), switched: role.is_some()}),
+            Err(e) => Outcome::Error((Status::InternalServerError, ContextError::Apply(e))),
+        }
+    }
+}
+impl<G> Drop for WithRole<G> where G: DerefMut + Send + 'static, G::Target: SessionRole {
+    fn drop(&mut self) {
+        let Some(mut guard) = self.guard.take().filter(|_| self.switched) else {return};
+        match rocket::tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = guard.reset_role().await {
+                        rocket::error!("failed to reset connection role: {}", e);
+                    }
+                });
+            }
+            Err(_) => rocket::error!("no async runtime to reset connection role on"),
+        }
+    }
+}
+impl<G> Deref for WithRole<G> where G: DerefMut + Send + 'static, G::Target: SessionRole {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().expect("guard present until dropped")
+    }
+}
+impl<G> DerefMut for WithRole<G> where G: DerefMut + Send + 'static, G::Target: SessionRole {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().expect("guard present until dropped")
+    }
+}
@@ -0,0 +1,157 @@
+//!Routing of individual statements by their SQL
+use std::marker::PhantomData;
+use rocket::{Ignite, Rocket, Sentinel};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::{Database, Pool};
+use crate::{failure, prefer};
+use crate::replicated::{tokenize, Token};
+use crate::{PoolRole, ReadCapablePool, ReadPoolError};
+
+///Keywords after `SELECT ... FOR` which lock rows, so need the primary
+const LOCKING: &[&str] = &["update", "share", "no", "key"];
+
+///Keywords of data-modifying statements, which may appear in a `WITH` clause
+const MODIFYING: &[&str] = &["insert", "update", "delete", "merge", "upsert", "replace"];
+
+///Whether a statement ends a transaction, `ROLLBACK TO SAVEPOINT` aside
+fn ends_transaction(tokens: &[Token<'_>]) -> bool {
+    let keyword = |i: usize, kw: &str| matches!(tokens.get(i), Some(Token::Ident(t)) if t.eq_ignore_ascii_case(kw));
+    (keyword(0, "commit") || keyword(0, "end") || keyword(0, "abort") || keyword(0, "rollback"))
+        && !(1..3).any(|i| keyword(i, "to"))
+}
+
+///The pool a statement should run on: [`PoolRole::Read`] for plain `SELECT` statements
+///(including ones with a `WITH` clause), and [`PoolRole::Main`] for anything else, including
+///`SELECT ... FOR UPDATE`, `SELECT ... INTO` and several statements at once.
+///
+///The SQL is only scanned for keywords, erring towards the main pool. `SELECT`s calling
+///functions with side effects, such as `nextval()`, can't be told apart and must be sent to the
+///main pool explicitly.
+///```rust
+///use rocket_read_db_pools::{route_statement, PoolRole};
+///
+///assert_eq!(route_statement("SELECT * FROM users WHERE id = $1"), PoolRole::Read);
+///assert_eq!(route_statement("WITH recent AS (SELECT 1) SELECT * FROM recent"), PoolRole::Read);
+///assert_eq!(route_statement("SELECT * FROM users FOR UPDATE"), PoolRole::Main);
+///assert_eq!(route_statement("WITH gone AS (DELETE FROM users RETURNING id) SELECT * FROM gone"), PoolRole::Main);
+///assert_eq!(route_statement("UPDATE users SET name = 'x'"), PoolRole::Main);
+///```
+pub fn route_statement(sql: &str) -> PoolRole {
+    if sql.trim_end().trim_end_matches(';').contains(';') {
+        return PoolRole::Main;
+    }
+    let tokens = tokenize(sql);
+    let read = match tokens.first() {
+        Some(Token::Ident(kw)) if kw.eq_ignore_ascii_case("select") || kw.eq_ignore_ascii_case("with") => {
+            tokens.iter().enumerate().all(|(i, token)| match token {
+                Token::Ident(kw) if kw.eq_ignore_ascii_case("into") => false,
+                Token::Ident(kw) if MODIFYING.iter().any(|m| kw.eq_ignore_ascii_case(m)) => false,
+                Token::Ident(kw) if kw.eq_ignore_ascii_case("for") => !matches!(tokens.get(i + 1),
+                    Some(Token::Ident(next)) if LOCKING.iter().any(|l| next.eq_ignore_ascii_case(l))),
+                _ => true,
+            })
+        }
+        _ => false,
+    };
+    if read {PoolRole::Read} else {PoolRole::Main}
+}
+
+///A request guard which hands out, for each statement, a connection to the pool
+///[`route_statement`] chooses, for applications which don't want to choose between
+///`ReadConnection` and `RwConnection`. It doesn't run statements itself, nor implement a
+///driver's executor trait such as sqlx's `Executor`: ask it for the connection with
+///[`StatementRouter::connection_for`], then run the statement on that.
+///
+///Connections are acquired when first needed: a read connection as `ReadConnection` gets it,
+///and a main pool connection as `RwConnection` does, though neither is recorded in
+///[`RequestRouting`](crate::RequestRouting). Once a transaction is begun through the guard,
+///every statement uses the main pool until it's committed or rolled back. Reads after a write
+///outside a transaction still use the read connection, so use [`StatementRouter::write`] where
+///they must see it.
+///
+///The guard fails with [`ReadPoolError::DatabaseNotAttached`] if the database isn't attached.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::ReadPool;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::response::Debug;
+/// use rocket_read_db_pools::StatementRouter;
+///
+/// #[rocket::post("/rename/<id>/<name>")]
+/// async fn rename(mut db: StatementRouter<'_, Db>, id: i64, name: &str) -> Result<(), Debug<MockError>> {
+///     let sql = "UPDATE users SET name = $1 WHERE id = $2";
+///     db.connection_for(sql).await?.query(sql).bind(name).bind(id).execute().await?;
+///     Ok(())
+/// }
+///```
+pub struct StatementRouter<'r, D: Database> {
+    db: &'r D::Pool,
+    read: Option<<D::Pool as Pool>::Connection>,
+    write: Option<<D::Pool as Pool>::Connection>,
+    transaction: bool,
+    _db: PhantomData<fn() -> D>,
+}
+impl<'r, D: Database> StatementRouter<'r, D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+    ///The connection `sql` should run on, acquiring it if this is its first use. Transaction
+    ///statements are tracked, so must be run through it too.
+    pub async fn connection_for(&mut self, sql: &str) -> Result<&mut <D::Pool as Pool>::Connection, <D::Pool as Pool>::Error> {
+        match self.route(sql) {
+            PoolRole::Read => self.read().await,
+            _ => self.write().await,
+        }
+    }
+
+    ///The pool `sql` runs on given the guard's transaction state, which is updated by it
+    pub fn route(&mut self, sql: &str) -> PoolRole {
+        let tokens = tokenize(sql);
+        let begins = matches!(tokens.first(), Some(Token::Ident(kw))
+            if kw.eq_ignore_ascii_case("begin") || kw.eq_ignore_ascii_case("start"));
+        let role = if self.transaction || begins {PoolRole::Main} else {route_statement(sql)};
+        if begins {
+            self.transaction = true;
+        } else if ends_transaction(&tokens) {
+            self.transaction = false;
+        }
+        role
+    }
+
+    ///Whether a transaction begun through the guard is open
+    pub fn in_transaction(&self) -> bool {
+        self.transaction
+    }
+
+    ///The read connection, acquiring it if necessary
+    pub async fn read(&mut self) -> Result<&mut <D::Pool as Pool>::Connection, <D::Pool as Pool>::Error> {
+        let conn = match self.read.take() {
+            Some(conn) => conn,
+            None => self.db.get_read().await.1?,
+        };
+        Ok(self.read.insert(conn))
+    }
+
+    ///The main pool connection, acquiring it if necessary
+    pub async fn write(&mut self) -> Result<&mut <D::Pool as Pool>::Connection, <D::Pool as Pool>::Error> {
+        let conn = match self.write.take() {
+            Some(conn) => conn,
+            None => prefer::writing(self.db.get()).await?,
+        };
+        Ok(self.write.insert(conn))
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for StatementRouter<'r, D> {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match D::fetch(req.rocket()) {
+            Some(db) => Outcome::Success(StatementRouter{db, read: None, write: None, transaction: false, _db: PhantomData}),
+            None => failure::outcome(req, D::NAME, ReadPoolError::DatabaseNotAttached),
+        }
+    }
+}
+impl<D: Database> Sentinel for StatementRouter<'_, D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
@@ -0,0 +1,288 @@
+//!Per-request record of connection acquisitions, for consumption by other fairings
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use rocket::Request;
+use rocket::serde::{Deserialize, Serialize, Serializer};
+use rocket_db_pools::Database;
+use crate::budget::Spent;
+use crate::{GuardFailure, PoolRole};
+
+///Why a read was served by the main pool instead of a read replica
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum FallbackReason {
+    ///No read pool is configured
+    NoReadPool,
+    ///Every replica is quarantined by health checks, degraded by canaries or in a maintenance
+    ///window, or the replicas are still being created under `read.degraded_start`
+    Unavailable,
+    ///The replica failed to provide a connection and `read.on_error` allows falling back
+    ReadFailed,
+    ///Brownout mode shed the read while the replica is slow
+    Brownout,
+    ///`read.primary_read_ratio` sent the read to the main pool
+    PrimaryRatio,
+    ///The circuit breaker is open after repeated replica failures
+    CircuitOpen,
+    ///The request's [`RoutingFlags`](crate::RoutingFlags) kept it off the replicas
+    RoutingFlags,
+    ///[`ReadYourWrites`](crate::ReadYourWrites): the replicas hadn't caught up with the
+    ///client's writes, or the request had already written
+    ReadYourWrites,
+    ///`read.pin_after_write`: the request had already written through an `RwConnection`
+    AfterWrite,
+    ///No replica connection was acquired within `read.max_wait_ms`
+    MaxWait,
+    ///Fewer than `read.min_healthy` replicas were healthy
+    MinHealthy,
+    ///A routing script of the `testing` feature forced it
+    Forced,
+    ///An application's [`RoutingPolicy`](crate::RoutingPolicy) sent it there
+    Policy,
+    ///It was read through the [`Primary`](crate::Primary) guard
+    Primary,
+}
+impl FallbackReason {
+    ///Every reason, in declaration order
+    pub(crate) const ALL: [FallbackReason; 14] = [
+        FallbackReason::NoReadPool, FallbackReason::Unavailable, FallbackReason::ReadFailed, FallbackReason::Brownout,
+        FallbackReason::PrimaryRatio, FallbackReason::CircuitOpen, FallbackReason::RoutingFlags, FallbackReason::ReadYourWrites,
+        FallbackReason::AfterWrite, FallbackReason::MaxWait, FallbackReason::MinHealthy, FallbackReason::Forced,
+        FallbackReason::Policy, FallbackReason::Primary,
+    ];
+
+    ///The reason's name as serialized, e.g. `"read_failed"`
+    pub fn name(self) -> &'static str {
+        match self {
+            FallbackReason::NoReadPool => "no_read_pool",
+            FallbackReason::Unavailable => "unavailable",
+            FallbackReason::ReadFailed => "read_failed",
+            FallbackReason::Brownout => "brownout",
+            FallbackReason::PrimaryRatio => "primary_ratio",
+            FallbackReason::CircuitOpen => "circuit_open",
+            FallbackReason::RoutingFlags => "routing_flags",
+            FallbackReason::ReadYourWrites => "read_your_writes",
+            FallbackReason::AfterWrite => "after_write",
+            FallbackReason::MaxWait => "max_wait",
+            FallbackReason::MinHealthy => "min_healthy",
+            FallbackReason::Forced => "forced",
+            FallbackReason::Policy => "policy",
+            FallbackReason::Primary => "primary",
+        }
+    }
+}
+
+///A connection acquisition made by one of this crate's request guards
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Acquisition {
+    ///`Database::NAME` of the database
+    pub database: &'static str,
+    ///The pool which served (or failed to serve) the connection
+    pub role: PoolRole,
+    ///The configured label of that pool. Only known to guards which require a `ReadPool`, so
+    ///`None` for `RwConnection`.
+    #[serde(serialize_with = "serialize_label")]
+    pub label: Option<Arc<str>>,
+    ///Time spent waiting for the connection
+    pub wait: Duration,
+    ///Whether a connection was acquired
+    pub success: bool,
+    ///Why a read was served by the main pool, if it was and the pool reports why
+    pub fallback: Option<FallbackReason>,
+}
+
+impl Acquisition {
+    ///Whether this was an `RwConnection`. Reads falling back to the main pool are labelled.
+    pub(crate) fn read_write(&self) -> bool {
+        self.role == PoolRole::Main && self.label.is_none()
+    }
+}
+
+///The acquisitions made by this crate's request guards during a request, in order.
+///
+///Stored in [`Request::local_cache`] so fairings (e.g. APM integrations) can read routing data
+///in `on_response` without depending on any optional feature of this crate:
+///```rust
+/// use rocket::{Request, Response};
+/// use rocket::fairing::{Fairing, Info, Kind};
+/// use rocket_read_db_pools::RequestRouting;
+///
+/// struct Apm;
+/// #[rocket::async_trait]
+/// impl Fairing for Apm {
+///     fn info(&self) -> Info {
+///         Info{name: "APM", kind: Kind::Response}
+///     }
+///
+///     async fn on_response<'r>(&self, req: &'r Request<'_>, _res: &mut Response<'r>) {
+///         for acquisition in RequestRouting::of(req).acquisitions() {
+///             println!("{} via {:?} in {:?}", acquisition.database, acquisition.role, acquisition.wait);
+///         }
+///     }
+/// }
+///```
+#[derive(Debug, Default)]
+pub struct RequestRouting {
+    acquisitions: Mutex<Vec<Acquisition>>,
+    refused: Mutex<Vec<&'static str>>,
+    spent: Arc<Spent>,
+    deadline: OnceLock<Instant>,
+    missed: AtomicBool,
+    low_priority: AtomicBool,
+    shards: Mutex<Vec<(&'static str, Arc<str>)>>,
+    route: Mutex<Option<Arc<str>>>,
+    failures: Mutex<Vec<GuardFailure>>,
+}
+impl RequestRouting {
+    ///The routing record of a request
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r RequestRouting {
+        req.local_cache(RequestRouting::default)
+    }
+
+    ///The routing record of a request, noting the route whose guard is acquiring a connection
+    ///for guards which acquire later from the record alone
+    pub(crate) fn routed<'r>(req: &'r Request<'_>) -> &'r RequestRouting {
+        let routing = Self::of(req);
+        *routing.route.lock().unwrap_or_else(|e| e.into_inner()) = Some(route(req));
+        routing
+    }
+
+    ///The route noted by [`RequestRouting::routed`]
+    pub(crate) fn route(&self) -> Option<Arc<str>> {
+        self.route.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    ///The acquisitions made so far
+    pub fn acquisitions(&self) -> Vec<Acquisition> {
+        self.acquisitions.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    ///Retries and fallbacks after failures made so far, each taking a token of the managed
+    ///[`RetryBudget`](crate::RetryBudget) if there is one
+    pub fn retries(&self) -> u32 {
+        self.spent.used()
+    }
+
+    ///`Database::NAME` of each database whose `RwConnection` was refused because it was in
+    ///read-only mode, see [`ReadOnlySurvival`](crate::ReadOnlySurvival)
+    pub fn refused_writes(&self) -> Vec<&'static str> {
+        self.refused.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn refuse_write<D: Database>(&self) {
+        self.refused.lock().unwrap_or_else(|e| e.into_inner()).push(D::NAME);
+    }
+
+    ///When the request's guards stop waiting for connections, as set by
+    ///[`RequestDeadline`](crate::RequestDeadline)
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.get().copied()
+    }
+
+    ///Whether a guard gave up waiting for a connection at the request's deadline
+    pub fn missed_deadline(&self) -> bool {
+        self.missed.load(Ordering::Relaxed)
+    }
+
+    ///Whether the request's reads are low-priority, see [`RequestRouting::set_low_priority`]
+    pub fn low_priority(&self) -> bool {
+        self.low_priority.load(Ordering::Relaxed)
+    }
+
+    ///Marks the request's reads as low-priority, e.g. from a fairing's `on_request` for
+    ///background or reporting routes, so its `ReadConnection` guards are the first to fail
+    ///under `read.below_min_healthy = "shed"`:
+    ///```rust
+    /// use rocket::{Data, Request};
+    /// use rocket::fairing::{Fairing, Info, Kind};
+    /// use rocket_read_db_pools::RequestRouting;
+    ///
+    /// struct ReportsLowPriority;
+    /// #[rocket::async_trait]
+    /// impl Fairing for ReportsLowPriority {
+    ///     fn info(&self) -> Info {
+    ///         Info{name: "Low-priority reports", kind: Kind::Request}
+    ///     }
+    ///
+    ///     async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+    ///         if req.uri().path().starts_with("/reports/") {
+    ///             RequestRouting::of(req).set_low_priority();
+    ///         }
+    ///     }
+    /// }
+    ///```
+    pub fn set_low_priority(&self) {
+        self.low_priority.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_deadline(&self, deadline: Instant) {
+        let _ = self.deadline.set(deadline);
+    }
+
+    pub(crate) fn miss_deadline(&self) {
+        self.missed.store(true, Ordering::Relaxed);
+    }
+
+    ///The shard of the [`ShardedReadPool`](crate::ShardedReadPool) database named `database`
+    ///which the request's guards use, if [`ShardKeys`](crate::ShardKeys) resolved one
+    pub fn shard(&self, database: &str) -> Option<Arc<str>> {
+        self.shards.lock().unwrap_or_else(|e| e.into_inner()).iter()
+            .find(|(name, _)| *name == database)
+            .map(|(_, shard)| shard.clone())
+    }
+
+    pub(crate) fn set_shard<D: Database>(&self, shard: Arc<str>) {
+        self.shards.lock().unwrap_or_else(|e| e.into_inner()).push((D::NAME, shard));
+    }
+
+    pub(crate) fn spent(&self) -> Arc<Spent> {
+        self.spent.clone()
+    }
+
+    ///The failures of connection guards so far, in order
+    pub fn failures(&self) -> Vec<GuardFailure> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    ///The most recent failure of a connection guard, if any, e.g. for a catcher to render
+    pub fn failure(&self) -> Option<GuardFailure> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner()).last().cloned()
+    }
+
+    pub(crate) fn fail(&self, failure: GuardFailure) {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner()).push(failure);
+    }
+
+    ///The most recent acquisition, if any
+    pub fn last(&self) -> Option<Acquisition> {
+        self.acquisitions.lock().unwrap_or_else(|e| e.into_inner()).last().cloned()
+    }
+
+    pub(crate) fn record<D: Database>(req: &Request<'_>, role: PoolRole, label: Option<Arc<str>>, start: Instant, success: bool) {
+        Self::record_fallback::<D>(req, role, label, start, success, None);
+    }
+
+    pub(crate) fn record_fallback<D: Database>(req: &Request<'_>, role: PoolRole, label: Option<Arc<str>>, start: Instant, success: bool, fallback: Option<FallbackReason>) {
+        Self::of(req).push::<D>(role, label, start, success, fallback);
+    }
+
+    pub(crate) fn push<D: Database>(&self, role: PoolRole, label: Option<Arc<str>>, start: Instant, success: bool, fallback: Option<FallbackReason>) {
+        let acquisition = Acquisition{database: D::NAME, role, label, wait: start.elapsed(), success, fallback};
+        self.acquisitions.lock().unwrap_or_else(|e| e.into_inner()).push(acquisition);
+    }
+}
+
+///The method and route handling `req`, or its path before it's routed, for logs
+pub(crate) fn route(req: &Request<'_>) -> Arc<str> {
+    let route = req.route().map_or_else(|| req.uri().path().to_string(), |r| r.uri.to_string());
+    format!("{} {}", req.method(), route).into()
+}
+
+fn serialize_label<S: Serializer>(label: &Option<Arc<str>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match label {
+        Some(label) => serializer.serialize_some(&**label),
+        None => serializer.serialize_none(),
+    }
+}
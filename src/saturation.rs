@@ -0,0 +1,116 @@
+//!Pool saturation, for load shedding before acquisition is attempted
+use std::time::{Duration, Instant};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::Pool;
+use crate::{PoolRole, ReadPool};
+
+///Time constant of [`ReadPool::smoothed_saturation`]
+const SMOOTHING: Duration = Duration::from_secs(5);
+
+///Current usage of a pool, for [`ReadPool::saturation`].
+///
+///Implement it for the pool types in use, e.g. with sqlx's `Pool::size`, `Pool::num_idle` and
+///`PoolOptions::get_max_connections`.
+pub trait PoolUsage: Pool {
+    ///Number of connections currently checked out
+    fn in_use(&self) -> u32;
+    ///Maximum number of connections the pool will open
+    fn max_connections(&self) -> u32;
+    ///Number of connections open but not checked out. Defaults to 0 for pools which don't
+    ///report it.
+    fn idle(&self) -> u32 {
+        0
+    }
+}
+
+///Usage of one pool at a point in time, from [`ReadPool::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolStats {
+    ///Connections open, idle or in use
+    pub size: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub max_connections: u32,
+}
+impl PoolStats {
+    pub(crate) fn of<T: PoolUsage>(pool: &T) -> Self {
+        let (idle, in_use) = (pool.idle(), pool.in_use());
+        PoolStats{size: idle + in_use, idle, in_use, max_connections: pool.max_connections()}
+    }
+}
+
+///Usage of every pool of a [`ReadPool`] at a point in time, from [`ReadPool::stats`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ReadPoolStats {
+    ///The pool currently acting as the main pool
+    pub main: PoolStats,
+    ///Each read replica by name, in order
+    pub read: Vec<(String, PoolStats)>,
+    pub delayed: Option<PoolStats>,
+    ///Connections held past `lease_warn_ms`, see [`ReadPool::long_held`]
+    pub long_held: u64,
+    ///Hits and misses of the read result cache
+    #[cfg(feature = "cache")]
+    pub cache: crate::CacheStats,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Smoothed {
+    at: Instant,
+    value: f32,
+}
+
+impl<P, R> ReadPool<P, R> where P: PoolUsage, R: PoolUsage {
+    ///A snapshot of the size and usage of each pool, e.g. for monitoring from a background task
+    pub fn stats(&self) -> ReadPoolStats {
+        let set = self.replica_set();
+        ReadPoolStats{
+            main: PoolStats::of(self.primary()),
+            read: set.replicas.iter().zip(&set.pools).map(|(r, pool)| (r.name.to_string(), PoolStats::of(pool))).collect(),
+            delayed: self.delayed.as_ref().map(PoolStats::of),
+            long_held: self.long_held(),
+            #[cfg(feature = "cache")]
+            cache: self.cache.stats(),
+        }
+    }
+
+    ///Fraction of the pool with the given role currently in use, from 0 to 1, or `None` if the
+    ///pool isn't configured. Read replicas are counted together.
+    pub fn saturation(&self, role: PoolRole) -> Option<f32> {
+        let usage = |in_use: u32, max: u32| if max == 0 {1.0} else {(in_use as f32 / max as f32).min(1.0)};
+        match role {
+            PoolRole::Main => Some(usage(self.primary().in_use(), self.primary().max_connections())),
+            PoolRole::Read => {
+                let set = self.replica_set();
+                (!set.is_empty()).then(|| usage(
+                    set.pools.iter().map(PoolUsage::in_use).sum(),
+                    set.pools.iter().map(PoolUsage::max_connections).sum(),
+                ))
+            }
+            PoolRole::Delayed => self.delayed.as_ref().map(|d| usage(d.in_use(), d.max_connections())),
+        }
+    }
+
+    ///[`ReadPool::saturation`] exponentially smoothed over the last few seconds, so load
+    ///shedding decisions based on it don't flap with momentary spikes.
+    ///
+    ///Each call samples the current saturation; the smoothing is by elapsed time, so it doesn't
+    ///depend on how often this is called.
+    pub fn smoothed_saturation(&self, role: PoolRole) -> Option<f32> {
+        let current = self.saturation(role)?;
+        let now = Instant::now();
+        let mut smoothed = self.smoothed_saturation.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = smoothed.get_mut(role);
+        let value = match *slot {
+            Some(prev) => {
+                let weight = 1.0 - (-now.duration_since(prev.at).as_secs_f32() / SMOOTHING.as_secs_f32()).exp();
+                prev.value + (current - prev.value) * weight
+            }
+            None => current,
+        };
+        *slot = Some(Smoothed{at: now, value});
+        Some(value)
+    }
+}
@@ -0,0 +1,65 @@
+//!`tower::Service` adapter for connection acquisition
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use rocket::futures::future::BoxFuture;
+use rocket_db_pools::Pool;
+use tower_service::Service;
+use crate::{prefer, AcquireError, PoolRole, ReadCapablePool, ReadPool};
+
+///A `tower::Service` which acquires connections from a [`ReadPool`], for use by tower-based
+///components running alongside Rocket.
+///
+///The request is the kind of connection wanted, routed as the corresponding guard would:
+///`PoolRole::Main` as [`RwConnection`](crate::RwConnection), `PoolRole::Read` as
+///[`ReadConnection`](crate::ReadConnection) and `PoolRole::Delayed` as
+///[`DelayedReadConnection`](crate::DelayedReadConnection). The response is the connection along
+///with the pool which served it. Limits are those of the pools, so the service is always ready.
+///
+///Clones share the pool. Build it from a clone of a managed pool to share its connections with
+///Rocket handlers, e.g. `ReadPoolService::new((*db).clone())` for sqlx pools.
+pub struct ReadPoolService<P, R = P> {
+    pool: Arc<ReadPool<P, R>>,
+}
+impl<P, R> ReadPoolService<P, R> {
+    pub fn new(pool: ReadPool<P, R>) -> Self {
+        ReadPoolService{pool: Arc::new(pool)}
+    }
+}
+impl<P, R> From<Arc<ReadPool<P, R>>> for ReadPoolService<P, R> {
+    fn from(pool: Arc<ReadPool<P, R>>) -> Self {
+        ReadPoolService{pool}
+    }
+}
+impl<P, R> Clone for ReadPoolService<P, R> {
+    fn clone(&self) -> Self {
+        ReadPoolService{pool: self.pool.clone()}
+    }
+}
+impl<P, R> Service<PoolRole> for ReadPoolService<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, R::Connection: Into<P::Connection> + Send, P::Connection: Send
+{
+    type Response = (PoolRole, P::Connection);
+    type Error = AcquireError<P::Error>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, role: PoolRole) -> Self::Future {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            match role {
+                PoolRole::Main => prefer::writing(pool.get()).await.map(|conn| (PoolRole::Main, conn)).map_err(AcquireError::Pool),
+                PoolRole::Read => {
+                    let (role, result) = ReadCapablePool::<P::Connection>::get_read(&*pool).await;
+                    result.map(|conn| (role, conn)).map_err(AcquireError::Pool)
+                }
+                PoolRole::Delayed => match ReadCapablePool::<P::Connection>::get_delayed(&*pool).await {
+                    Some(result) => result.map(|conn| (PoolRole::Delayed, conn)).map_err(AcquireError::Pool),
+                    None => Err(AcquireError::Unconfigured(PoolRole::Delayed)),
+                },
+            }
+        })
+    }
+}
@@ -0,0 +1,354 @@
+//!Per-request session state applied to connections from either pool
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use crate::{BoxError, PoolRole, ReadDbConfig, RequestRouting};
+
+///Sets session variables on a connection, for [`WithContext`].
+///
+///Implement it for your connection type, e.g. with `SELECT set_config($1, $2, false)` on
+///Postgres.
+#[rocket::async_trait]
+pub trait SessionVariables: Send {
+    ///Sets the session variable `name` to `value`
+    async fn set_variable(&mut self, name: &str, value: &str) -> Result<(), BoxError>;
+
+    ///Makes the session's transactions read-only, for pools marked with
+    ///[`SessionContext::read_only`]. Defaults to setting `default_transaction_read_only` to
+    ///`on`, as on Postgres; override it for other databases, e.g. with
+    ///`SET SESSION TRANSACTION READ ONLY` on MySQL.
+    async fn set_read_only(&mut self) -> Result<(), BoxError> {
+        self.set_variable("default_transaction_read_only", "on").await
+    }
+}
+
+type ValueFn = Box<dyn Fn(&Request<'_>) -> Option<String> + Send + Sync>;
+
+///Managed state declaring session variables derived from each request, e.g. a tenant id for
+///row-level security.
+///
+///Every declared variable is set whenever a [`WithContext`] guard is constructed, to an empty
+///string if the request has no value, so values never carry over between requests sharing a
+///pooled connection.
+///```rust
+/// use rocket_read_db_pools::SessionContext;
+///
+/// # struct Tenant(String);
+/// let context = SessionContext::new()
+///     .variable("app.tenant_id", |req| req.local_cache(|| None::<Tenant>).as_ref().map(|t| t.0.clone()));
+/// let rocket = rocket::build().manage(context);
+///```
+///
+///Fixed variables can also be set only on connections from one pool, e.g. a `search_path`
+///exposing reporting schemas on the replica but not the primary. These come from the per-pool
+///`search_path` keys with [`SessionContext::configured`]:
+///```toml
+///[default.databases.main.read]
+///search_path = "reporting, public"
+///```
+///
+///Variables only needed for writes, such as the user and request ids read by audit triggers,
+///can be declared with [`SessionContext::audit_variable`] to be set on main pool connections
+///alone, sparing reads the round trips:
+///```rust
+/// use rocket_read_db_pools::SessionContext;
+///
+/// # struct User(String);
+/// let context = SessionContext::new()
+///     .audit_variable("audit.user_id", |req| req.local_cache(|| None::<User>).as_ref().map(|u| u.0.clone()))
+///     .audit_variable("audit.request_id", |req| req.headers().get_one("X-Request-Id").map(str::to_string));
+///```
+///
+///Likewise, `enforce_read_only` makes the read pool's sessions read-only, so a write through
+///`ReadConnection` fails loudly rather than reaching a replica:
+///```toml
+///[default.databases.main.read]
+///enforce_read_only = true
+///```
+#[derive(Default)]
+pub struct SessionContext {
+    variables: Vec<(String, ValueFn)>,
+    audit_variables: Vec<(String, ValueFn)>,
+    pool_variables: HashMap<(String, PoolRole), Vec<(String, String)>>,
+    read_only: HashSet<(String, PoolRole)>,
+}
+impl SessionContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Declares the variable `name`, with its value for each request given by `value`
+    pub fn variable<F>(mut self, name: &str, value: F) -> Self
+        where F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static
+    {
+        self.variables.push((name.to_string(), Box::new(value)));
+        self
+    }
+
+    ///Declares the variable `name` as [`SessionContext::variable`] does, but only sets it on
+    ///connections from the main pool, where writes happen. It isn't recorded in the
+    ///[`SessionSnapshot`].
+    pub fn audit_variable<F>(mut self, name: &str, value: F) -> Self
+        where F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static
+    {
+        self.audit_variables.push((name.to_string(), Box::new(value)));
+        self
+    }
+
+    ///Sets the variable `name` to `value` on connections from the `pool` of database `database`
+    pub fn pool_variable(mut self, database: &str, pool: PoolRole, name: &str, value: &str) -> Self {
+        self.pool_variables.entry((database.to_string(), pool)).or_default().push((name.to_string(), value.to_string()));
+        self
+    }
+
+    ///Makes sessions of connections from the `pool` of database `database` read-only with
+    ///[`SessionVariables::set_read_only`].
+    ///
+    ///Reads which fall back to the main pool aren't affected, as the session would stay
+    ///read-only once the connection is reused for writes.
+    pub fn read_only(mut self, database: &str, pool: PoolRole) -> Self {
+        self.read_only.insert((database.to_string(), pool));
+        self
+    }
+
+    ///Adds the `search_path` configured for the read and delayed pools of the database
+    ///`database`, and marks the read pool read-only if `read.enforce_read_only` is set
+    pub fn configured(mut self, database: &str, config: &ReadDbConfig) -> Self {
+        let read = config.read.as_ref();
+        if read.and_then(|r| r.enforce_read_only).unwrap_or(false) {
+            self = self.read_only(database, PoolRole::Read);
+        }
+        for (pool, search_path) in [
+            (PoolRole::Read, read.and_then(|r| r.search_path.as_deref())),
+            (PoolRole::Delayed, read.and_then(|r| r.delayed.as_ref()).and_then(|d| d.search_path.as_deref())),
+        ] {
+            if let Some(search_path) = search_path {
+                self = self.pool_variable(database, pool, "search_path", search_path);
+            }
+        }
+        self
+    }
+
+    ///Sets every declared variable on `conn` from `req`, then the variables of the pool which
+    ///served the request's latest acquisition, making the session read-only if that pool is.
+    ///Audit variables are set too if it was the main pool. The declared variables are recorded in the
+    ///request's [`SessionSnapshot`].
+    pub async fn apply<C: SessionVariables + ?Sized>(&self, req: &Request<'_>, conn: &mut C) -> Result<(), BoxError> {
+        let snapshot = SessionSnapshot::of(req);
+        for (name, value) in &self.variables {
+            let value = value(req).unwrap_or_default();
+            conn.set_variable(name, &value).await?;
+            snapshot.record(name, &value);
+        }
+        let pool = RequestRouting::of(req).last().map(|a| (a.database.to_string(), a.role));
+        if pool.as_ref().is_some_and(|(_, role)| *role == PoolRole::Main) {
+            for (name, value) in &self.audit_variables {
+                conn.set_variable(name, &value(req).unwrap_or_default()).await?;
+            }
+        }
+        let pool_variables = pool.as_ref().and_then(|pool| self.pool_variables.get(pool));
+        for (name, value) in pool_variables.into_iter().flatten() {
+            conn.set_variable(name, value).await?;
+        }
+        if pool.is_some_and(|pool| self.read_only.contains(&pool)) {
+            conn.set_read_only().await?;
+        }
+        Ok(())
+    }
+}
+
+///The session variables set on the request's connections, so they can be replayed onto a
+///connection acquired later in the request, e.g. from the main pool after reading from a
+///replica, keeping invariants like a tenant id across the switch.
+///
+///Variables declared in [`SessionContext`] are recorded as [`WithContext`] sets them; record
+///any set by the application itself with [`SessionSnapshot::record`]. Per-pool variables and
+///roles differ between pools, so aren't recorded.
+#[derive(Debug, Default)]
+pub struct SessionSnapshot {
+    variables: Mutex<Vec<(String, String)>>,
+}
+impl SessionSnapshot {
+    ///The snapshot of a request
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r SessionSnapshot {
+        req.local_cache(SessionSnapshot::default)
+    }
+
+    ///Records that the session variable `name` was set to `value`, replacing any earlier value
+    pub fn record(&self, name: &str, value: &str) {
+        let mut variables = self.variables.lock().unwrap_or_else(|e| e.into_inner());
+        match variables.iter_mut().find(|(n, _)| n == name) {
+            Some(variable) => variable.1 = value.to_string(),
+            None => variables.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    ///The recorded variables, in the order they were first set
+    pub fn variables(&self) -> Vec<(String, String)> {
+        self.variables.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    ///Sets every recorded variable on `conn`
+    pub async fn restore<C: SessionVariables + ?Sized>(&self, conn: &mut C) -> Result<(), BoxError> {
+        for (name, value) in self.variables() {
+            conn.set_variable(&name, &value).await?;
+        }
+        Ok(())
+    }
+}
+
+///Error from a [`WithContext`] or [`WithRole`](crate::WithRole) guard
+#[derive(Debug)]
+pub enum ContextError<E> {
+    ///The wrapped guard failed
+    Guard(E),
+    ///Setting up the connection's session failed
+    Apply(BoxError),
+}
+impl<E: fmt::Display> fmt::Display for ContextError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextError::Guard(e) => e.fmt(f),
+            ContextError::Apply(e) => write!(f, "failed to set up connection session: {}", e),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ContextError<E> {}
+
+///A request guard wrapping a connection guard (`WithContext<ReadConnection<Db>>`,
+///`WithContext<RwConnection<Db>>`, ...) which applies the managed [`SessionContext`] to the
+///connection before the handler runs, so session state is uniform across both pools.
+///
+///If no `SessionContext` is managed the connection is passed through unchanged. To order it
+///among other decorations, add the context to a [`ConnectionPipeline`](crate::ConnectionPipeline)
+///and use [`Decorated`](crate::Decorated) instead.
+pub struct WithContext<G>(pub G);
+impl<G> WithContext<G> {
+    ///Gets the wrapped guard
+    pub fn into_inner(self) -> G {
+        self.0
+    }
+}
+#[rocket::async_trait]
+impl<'r, G> FromRequest<'r> for WithContext<G>
+    where G: FromRequest<'r> + DerefMut + Send, G::Target: SessionVariables
+{
+    type Error = ContextError<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut guard = match G::from_request(req).await {
+            Outcome::Success(guard) => guard,
+            Outcome::Error((status, e)) => return Outcome::Error((status, ContextError::Guard(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        if let Some(context) = req.rocket().state::<SessionContext>() {
+            if let Err(e) = context.apply(req, &mut *guard).await {
+                return Outcome::Error((Status::InternalServerError, ContextError::Apply(e)));
+            }
+        }
+        Outcome::Success(WithContext(guard))
+    }
+}
+impl<G: Deref> Deref for WithContext<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<G: DerefMut> DerefMut for WithContext<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use rocket::figment::Figment;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use rocket_db_pools::Database;
+    use crate::testing::MockPool;
+    use crate::ReadPool;
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    ///A connection recording the variables set on it
+    #[derive(Default)]
+    struct Recorded(Vec<(String, String)>);
+    #[rocket::async_trait]
+    impl SessionVariables for Recorded {
+        async fn set_variable(&mut self, name: &str, value: &str) -> Result<(), BoxError> {
+            self.0.push((name.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn context() -> SessionContext {
+        SessionContext::new()
+            .variable("app.tenant", |req| req.headers().get_one("X-Tenant").map(str::to_string))
+            .audit_variable("audit.user", |_| Some("u1".to_string()))
+            .pool_variable("db", PoolRole::Read, "search_path", "reporting")
+            .read_only("db", PoolRole::Read)
+    }
+
+    ///The variables `context` sets on a connection from `role` for a request naming `tenant`,
+    ///and those recorded in its snapshot
+    async fn applied(context: &SessionContext, role: PoolRole, tenant: Option<&'static str>) -> (Vec<(String, String)>, Vec<(String, String)>) {
+        let client = Client::tracked(rocket::build()).await.unwrap();
+        let mut req = client.get("/");
+        if let Some(tenant) = tenant {req = req.header(Header::new("X-Tenant", tenant));}
+        RequestRouting::of(&req).push::<Db>(role, None, Instant::now(), true, None);
+        let mut conn = Recorded::default();
+        context.apply(&req, &mut conn).await.unwrap();
+        (conn.0, SessionSnapshot::of(&req).variables())
+    }
+
+    #[rocket::async_test]
+    async fn variables_are_set_per_pool() {
+        let (read, snapshot) = applied(&context(), PoolRole::Read, Some("acme")).await;
+        assert_eq!(read, pairs(&[("app.tenant", "acme"), ("search_path", "reporting"), ("default_transaction_read_only", "on")]));
+        assert_eq!(snapshot, pairs(&[("app.tenant", "acme")]));
+
+        let (main, snapshot) = applied(&context(), PoolRole::Main, None).await;
+        assert_eq!(main, pairs(&[("app.tenant", ""), ("audit.user", "u1")]));
+        assert_eq!(snapshot, pairs(&[("app.tenant", "")]));
+    }
+
+    #[rocket::async_test]
+    async fn snapshots_are_restored_with_the_latest_values() {
+        let snapshot = SessionSnapshot::default();
+        snapshot.record("app.tenant", "acme");
+        snapshot.record("app.locale", "en");
+        snapshot.record("app.tenant", "globex");
+        let mut conn = Recorded::default();
+        snapshot.restore(&mut conn).await.unwrap();
+        assert_eq!(conn.0, pairs(&[("app.tenant", "globex"), ("app.locale", "en")]));
+    }
+
+    #[rocket::async_test]
+    async fn configured_search_paths_and_read_only_sessions() {
+        let toml = "url = \"main\"\n[read]\nurl = \"read\"\nsearch_path = \"reporting\"\nenforce_read_only = true\ndelayed = {url = \"delayed\", search_path = \"archive\"}";
+        let config: ReadDbConfig = Figment::from(Toml::string(toml)).extract().unwrap();
+        let context = SessionContext::new().configured("db", &config);
+        let (read, _) = applied(&context, PoolRole::Read, None).await;
+        assert_eq!(read, pairs(&[("search_path", "reporting"), ("default_transaction_read_only", "on")]));
+        let (delayed, _) = applied(&context, PoolRole::Delayed, None).await;
+        assert_eq!(delayed, pairs(&[("search_path", "archive")]));
+        let (main, _) = applied(&context, PoolRole::Main, None).await;
+        assert!(main.is_empty());
+    }
+}
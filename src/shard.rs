@@ -0,0 +1,584 @@
+//!Sharding of a database over several `ReadPool`s, each with its own main and read pools
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use rocket::{Ignite, Orbit, Request, Rocket, Sentinel};
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::figment::value::Dict;
+use rocket::request::{FromRequest, Outcome};
+use rocket_db_pools::{Database, Pool};
+use crate::{keys, FallbackReason, LogConfig, PoolRole, ReadCapablePool, ReadConnection, ReadPool, ReadPoolError, RequestRouting, RwConnection};
+
+///Name of the only shard when no `shards` are configured
+const DEFAULT_SHARD: &str = "default";
+
+rocket::tokio::task_local! {
+    ///The shard a sharded guard is acquiring a connection from
+    static SHARD: Arc<str>;
+}
+
+///For each type of [`ShardedReadPool`] initialized, whether a pool of that type needs the
+///request to name a shard, as it has no default, so guards of requests naming none can fail
+static NEEDS_SHARD: RwLock<Vec<(TypeId, NeedsShard)>> = RwLock::new(Vec::new());
+
+///Whether a pool, of the type it's registered for, has no default shard
+type NeedsShard = fn(&dyn Any) -> bool;
+
+///A pool over several shards of a database, each a [`ReadPool`] with its own main and read
+///pools, such as one per region or group of tenants.
+///
+///The shards are named under `shards`, each given as options overriding the database's own,
+///including its own `read` table, and labelled with its name in logs unless it sets `label`
+///(and `read.label`). Without `shards`, the database's own options configure a single shard
+///named `default`:
+///```toml
+///[default.databases.main]
+///max_connections = 10
+///default_shard = "eu"
+///[default.databases.main.shards.eu]
+///url = "postgresql://user@primary-eu.example/dbname"
+///read = {url = "postgresql://user@replica-eu.example/dbname"}
+///[default.databases.main.shards.us]
+///url = "postgresql://user@primary-us.example/dbname"
+///read = {url = "postgresql://user@replica-us.example/dbname"}
+///```
+///
+///This crate's connection guards ([`ReadConnection`], [`RwConnection`] and those built on them)
+///use the shard the managed [`ShardKeys`] resolve for the request, and
+///[`ShardedReadConnection`] and [`ShardedRwConnection`] also tell which it was. Requests which
+///name no shard, and other uses of the pool such as `rocket_db_pools::Connection`, use the
+///`default_shard` if one is set, and fail otherwise rather than serve another shard's data.
+///[`ShardedReadPool::shard`] gives direct access to each shard's pool.
+///
+///Each shard has its own `url` and `read` table: the database's own `url`, `read`, `read_urls`
+///and `failover` aren't inherited by the shards, though its other options are.
+///
+///For a database per tenant, the shards can be configured as `tenants` instead, by tenant
+///identifier, with the identifier taken from a header, subdomain or authentication claim by
+///[`ShardKeys`]. [`TenantPool`] names the pool accordingly:
+///```toml
+///[default.databases.main.tenants.acme]
+///url = "postgresql://user@primary.example/acme"
+///read = {url = "postgresql://user@replica.example/acme"}
+///[default.databases.main.tenants.globex]
+///url = "postgresql://user@primary.example/globex"
+///```
+///```rust
+/// # type PgPool = rocket_read_db_pools::testing::MockPool;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{ShardedReadConnection, ShardedReadPool, ShardHeader, ShardKeys};
+///
+/// #[derive(Database)]
+/// #[database("main")]
+/// struct Db(ShardedReadPool<PgPool>);
+///
+/// #[rocket::get("/")]
+/// fn index(conn: ShardedReadConnection<Db>) -> String {
+///     format!("served by shard {}", conn.shard())
+/// }
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .manage(ShardKeys::new(ShardHeader("X-Region".to_string())))
+///     .mount("/", rocket::routes![index])
+/// # }
+///```
+pub struct ShardedReadPool<P, R = P> {
+    shards: Vec<(Arc<str>, ReadPool<P, R>)>,
+    ///The shard used when none is resolved: the `default_shard`, or the only one when no
+    ///`shards` are configured
+    default: Option<usize>,
+}
+///A [`ShardedReadPool`] with a shard per tenant, configured under `tenants`
+pub type TenantPool<P, R = P> = ShardedReadPool<P, R>;
+
+impl<P: Clone, R: Clone> Clone for ShardedReadPool<P, R> {
+    fn clone(&self) -> Self {
+        ShardedReadPool{shards: self.shards.clone(), default: self.default}
+    }
+}
+impl<P, R> ShardedReadPool<P, R> {
+    ///The pool of the shard named `name`
+    pub fn shard(&self, name: &str) -> Option<&ReadPool<P, R>> {
+        self.index(name).map(|i| &self.shards[i].1)
+    }
+
+    ///The name of each shard along with its pool, ordered by name
+    pub fn shards(&self) -> impl Iterator<Item = (&str, &ReadPool<P, R>)> {
+        self.shards.iter().map(|(name, pool)| (&**name, pool))
+    }
+
+    ///The name of the shard used when none is resolved, if any
+    pub fn default_shard(&self) -> Option<&str> {
+        self.default.map(|i| &*self.shards[i].0)
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.shards.iter().position(|(shard, _)| &**shard == name)
+    }
+
+    ///The pool of the shard a sharded guard is acquiring from, or the default shard, if any
+    fn current(&self) -> Option<&ReadPool<P, R>> {
+        let i = SHARD.try_with(|name| self.index(name)).ok().flatten().or(self.default)?;
+        Some(&self.shards[i].1)
+    }
+
+    ///The pool whose settings are reported when no shard is resolved, as they're read before
+    ///acquiring, which then fails
+    fn settings(&self) -> &ReadPool<P, R> {
+        self.current().unwrap_or(&self.shards[0].1)
+    }
+}
+impl<P: Pool, R> ShardedReadPool<P, R> {
+    ///The pool of the current shard, or the error of a request naming none
+    fn resolved(&self) -> Result<&ReadPool<P, R>, P::Error> where P::Error: From<rocket::figment::Error> {
+        self.current().ok_or_else(|| rocket::figment::Error::from(format!(
+            "no shard named, and no `{}` is configured", keys::DEFAULT_SHARD,
+        )).into())
+    }
+}
+
+///Options of the database which configure a single pool, and so aren't inherited by its shards
+const UNSHARED: [&str; 7] = [keys::URL, keys::READ, keys::READ_URLS, keys::FAILOVER, keys::SHARDS, keys::TENANTS, keys::DEFAULT_SHARD];
+
+///The options of each shard by name, from `shards` or else `tenants`, if there are any
+fn shard_configs(figment: &Figment) -> Option<BTreeMap<String, Dict>> {
+    [keys::SHARDS, keys::TENANTS].into_iter()
+        .find_map(|key| figment.extract_inner::<BTreeMap<String, Dict>>(key).ok().filter(|shards| !shards.is_empty()))
+}
+
+///The config of each shard, named as in `shards` or `tenants`
+fn shard_figments(figment: &Figment) -> Vec<(String, Figment)> {
+    let Some(shards) = shard_configs(figment) else {
+        return vec![(DEFAULT_SHARD.to_string(), figment.clone())];
+    };
+    let mut shared = figment.extract::<Dict>().unwrap_or_default();
+    shared.retain(|key, _| !UNSHARED.contains(&key.as_str()));
+    shards.into_iter().map(|(name, options)| {
+        let mut shard = Figment::from(Serialized::defaults(&shared)).merge(Serialized::globals(options))
+            .join(Serialized::default(keys::LABEL, &name));
+        if shard.contains(keys::READ) {
+            shard = shard.join(Serialized::default(keys::READ_LABEL, format!("{}-read", name)));
+        }
+        if shard.contains(keys::DELAYED) {
+            shard = shard.join(Serialized::default(keys::DELAYED_LABEL, format!("{}-delayed", name)));
+        }
+        (name, shard)
+    }).collect()
+}
+
+#[rocket::async_trait]
+impl<P, R> Pool for ShardedReadPool<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static
+{
+    type Error = P::Error;
+
+    type Connection = P::Connection;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let mut shards = Vec::new();
+        for (name, config) in shard_figments(figment) {
+            shards.push((Arc::from(name), ReadPool::<P, R>::init(&config).await?));
+        }
+        let default = match figment.extract_inner::<String>(keys::DEFAULT_SHARD) {
+            Ok(name) => match shards.iter().position(|(shard, _)| **shard == name) {
+                Some(i) => Some(i),
+                None => return Err(rocket::figment::Error::from(format!("`{}` names no shard: `{}`", keys::DEFAULT_SHARD, name)).into()),
+            },
+            Err(_) => shard_configs(figment).is_none().then_some(0),
+        };
+        let pool = ShardedReadPool{shards, default};
+        let mut needs_shard = NEEDS_SHARD.write().unwrap_or_else(|e| e.into_inner());
+        if !needs_shard.iter().any(|(id, _)| *id == TypeId::of::<Self>()) {
+            needs_shard.push((TypeId::of::<Self>(), |pool| pool.downcast_ref::<Self>().is_some_and(|p| p.default.is_none())));
+        }
+        Ok(pool)
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        let shard = self.resolved()?;
+        shard.get().await
+    }
+
+    async fn close(&self) {
+        for (_, shard) in &self.shards {shard.close().await;}
+    }
+}
+
+///Whether the pool of the database `D` is a [`ShardedReadPool`] needing requests to name a shard
+fn needs_shard<D: Database>(rocket: &Rocket<Orbit>) -> bool {
+    let needs_shard = NEEDS_SHARD.read().unwrap_or_else(|e| e.into_inner());
+    let Some((_, needs)) = needs_shard.iter().find(|(id, _)| *id == TypeId::of::<D::Pool>()) else {return false};
+    D::fetch(rocket).is_some_and(|db| needs(&**db as &dyn Any))
+}
+impl<P, R, C> ReadCapablePool<C> for ShardedReadPool<P, R>
+    where P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>,
+        P::Connection: Into<C> + Send + 'static, R::Connection: Into<C> + Send + 'static, C: Send + 'static
+{
+    async fn get_read(&self) -> (PoolRole, Result<C, P::Error>) {
+        let shard = match self.resolved() {
+            Ok(shard) => shard,
+            Err(e) => return (PoolRole::Read, Err(e)),
+        };
+        ReadCapablePool::<C>::get_read(shard).await
+    }
+
+    async fn get_read_explained(&self) -> (PoolRole, Option<FallbackReason>, Result<C, P::Error>) {
+        let shard = match self.resolved() {
+            Ok(shard) => shard,
+            Err(e) => return (PoolRole::Read, None, Err(e)),
+        };
+        ReadCapablePool::<C>::get_read_explained(shard).await
+    }
+
+    async fn get_delayed(&self) -> Option<Result<C, P::Error>> {
+        let shard = match self.resolved() {
+            Ok(shard) => shard,
+            Err(e) => return Some(Err(e)),
+        };
+        ReadCapablePool::<C>::get_delayed(shard).await
+    }
+
+    async fn get_read_main(&self) -> Option<Result<C, P::Error>> {
+        let shard = match self.resolved() {
+            Ok(shard) => shard,
+            Err(e) => return Some(Err(e)),
+        };
+        ReadCapablePool::<C>::get_read_main(shard).await
+    }
+
+    async fn get_read_main_for(&self, reason: FallbackReason) -> Option<Result<C, P::Error>> {
+        let shard = match self.resolved() {
+            Ok(shard) => shard,
+            Err(e) => return Some(Err(e)),
+        };
+        ReadCapablePool::<C>::get_read_main_for(shard, reason).await
+    }
+
+    fn has_delayed(&self) -> bool {
+        ReadCapablePool::<C>::has_delayed(self.settings())
+    }
+
+    fn pool_label(&self, role: PoolRole) -> Arc<str> {
+        ReadCapablePool::<C>::pool_label(self.settings(), role)
+    }
+
+    fn statement_timeout(&self, role: PoolRole) -> Option<Duration> {
+        ReadCapablePool::<C>::statement_timeout(self.settings(), role)
+    }
+
+    fn log_config(&self) -> Option<&LogConfig> {
+        ReadCapablePool::<C>::log_config(self.settings())
+    }
+
+    fn pins_after_write(&self) -> bool {
+        ReadCapablePool::<C>::pins_after_write(self.settings())
+    }
+
+    fn replica_counts(&self) -> Option<(usize, usize)> {
+        ReadCapablePool::<C>::replica_counts(self.settings())
+    }
+
+    fn sheds_low_priority(&self) -> bool {
+        ReadCapablePool::<C>::sheds_low_priority(self.settings())
+    }
+}
+
+///Resolves the shard of a database a request uses, for [`ShardKeys`]
+pub trait ShardKey: Send + Sync + 'static {
+    ///The name of the shard of the database named `database` which `req` uses, or `None` if it
+    ///names none, when the `default_shard` is used if one is set
+    fn shard(&self, req: &Request<'_>, database: &'static str) -> Option<String>;
+}
+
+///Takes the shard from the request header with this name
+#[derive(Debug, Clone)]
+pub struct ShardHeader(pub String);
+impl ShardKey for ShardHeader {
+    fn shard(&self, req: &Request<'_>, _database: &'static str) -> Option<String> {
+        req.headers().get_one(&self.0).map(str::to_string)
+    }
+}
+
+///Takes the shard from the path segment at this index after the route's mount point, as
+///`Request::param` counts them, e.g. `0` for `<region>` in `/<region>/users`
+#[derive(Debug, Clone, Copy)]
+pub struct ShardParam(pub usize);
+impl ShardKey for ShardParam {
+    fn shard(&self, req: &Request<'_>, _database: &'static str) -> Option<String> {
+        req.param::<String>(self.0)?.ok()
+    }
+}
+
+impl<F> ShardKey for F where F: Fn(&Request<'_>, &'static str) -> Option<String> + Send + Sync + 'static {
+    fn shard(&self, req: &Request<'_>, database: &'static str) -> Option<String> {
+        self(req, database)
+    }
+}
+
+///Takes the shard from the subdomain of the request's host under this domain, e.g. `acme` for
+///`acme.example.com` under `example.com`
+#[derive(Debug, Clone)]
+pub struct ShardSubdomain(pub String);
+impl ShardKey for ShardSubdomain {
+    fn shard(&self, req: &Request<'_>, _database: &'static str) -> Option<String> {
+        let host = req.host()?.domain().as_str();
+        let subdomain = host.strip_suffix(self.0.as_str())?.strip_suffix('.')?;
+        (!subdomain.is_empty()).then(|| subdomain.to_string())
+    }
+}
+
+///Managed state resolving which shard, or tenant, of each [`ShardedReadPool`] database the
+///connection guards use for each request. Without it, they use the `default_shard`.
+///
+///Guards fail with [`ReadPoolError::UnknownShard`] and `404 Not Found` if the request names a
+///shard which isn't configured, and with [`ReadPoolError::MissingShard`] and `400 Bad Request`
+///if it names none and no `default_shard` is set, rather than use another's data. Whether a
+///database is sharded, and its shards' names, are read from Rocket's configuration on first use.
+///```rust
+/// use rocket_read_db_pools::ShardKeys;
+///
+/// # struct Claims{tenant: String}
+/// let keys = ShardKeys::new(|req: &rocket::Request<'_>, _database: &'static str| {
+///     req.local_cache(|| None::<Claims>).as_ref().map(|claims| claims.tenant.clone())
+/// });
+/// let rocket = rocket::build().manage(keys);
+///```
+pub struct ShardKeys {
+    key: Box<dyn ShardKey>,
+    configured: Mutex<HashMap<&'static str, Option<Arc<BTreeSet<String>>>>>,
+}
+impl ShardKeys {
+    pub fn new(key: impl ShardKey) -> Self {
+        ShardKeys{key: Box::new(key), configured: Mutex::new(HashMap::new())}
+    }
+
+    ///The names of the shards configured for the database `database`, or `None` if it isn't
+    ///sharded
+    fn configured(&self, rocket: &Rocket<Orbit>, database: &'static str) -> Option<Arc<BTreeSet<String>>> {
+        let mut configured = self.configured.lock().unwrap_or_else(|e| e.into_inner());
+        configured.entry(database).or_insert_with(|| {
+            let figment = rocket.figment().focus(&format!("databases.{}", database));
+            shard_configs(&figment).map(|shards| Arc::new(shards.into_keys().collect()))
+        }).clone()
+    }
+}
+
+///Resolves the shard of the database `D` which `req` uses if it's sharded, recording it for the
+///request's guards
+pub(crate) fn resolve<D: Database, E>(req: &Request<'_>) -> Result<(), ReadPoolError<E>> {
+    let routing = RequestRouting::of(req);
+    if routing.shard(D::NAME).is_some() {
+        return Ok(());
+    }
+    let keys = req.rocket().state::<ShardKeys>();
+    if let Some((keys, shards)) = keys.and_then(|keys| Some((keys, keys.configured(req.rocket(), D::NAME)?))) {
+        match keys.key.shard(req, D::NAME) {
+            Some(name) if shards.contains(&name) => {
+                routing.set_shard::<D>(name.into());
+                return Ok(());
+            }
+            Some(name) => return Err(ReadPoolError::UnknownShard(name)),
+            None => {}
+        }
+    }
+    match needs_shard::<D>(req.rocket()) {
+        true => Err(ReadPoolError::MissingShard),
+        false => Ok(()),
+    }
+}
+
+///Runs a guard's acquisition `fut` on the shard of the database `D` recorded for the request
+///with the routing record `routing`, if any
+pub(crate) async fn scoped<D: Database, F: Future>(routing: &RequestRouting, fut: F) -> F::Output {
+    match routing.shard(D::NAME) {
+        Some(shard) => SHARD.scope(shard, fut).await,
+        None => fut.await,
+    }
+}
+
+///The shard of the database `D` the guards of `req` use
+fn used<D, P, R>(req: &Request<'_>) -> Arc<str>
+    where D: Database<Pool = ShardedReadPool<P, R>>, P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static
+{
+    RequestRouting::of(req).shard(D::NAME)
+        .or_else(|| D::fetch(req.rocket()).and_then(|db| Some(db.shards[db.default?].0.clone())))
+        .unwrap_or_else(|| DEFAULT_SHARD.into())
+}
+
+///A request guard which retrieves a read connection as [`ReadConnection`] does, from the shard
+///of a [`ShardedReadPool`] resolved for the request by the managed [`ShardKeys`], telling which
+///shard it was.
+pub struct ShardedReadConnection<D: Database, C = <<D as Database>::Pool as Pool>::Connection> {
+    conn: ReadConnection<D, C>,
+    shard: Arc<str>,
+}
+impl<D: Database, C> ShardedReadConnection<D, C> {
+    ///The name of the shard which served the connection
+    pub fn shard(&self) -> &str {
+        &self.shard
+    }
+
+    ///Gets the connection, which returns to its pool when dropped
+    pub fn into_inner(self) -> C {
+        self.conn.into_inner()
+    }
+}
+#[rocket::async_trait]
+impl<'r, D, P, R, C> FromRequest<'r> for ShardedReadConnection<D, C>
+    where D: Database<Pool = ShardedReadPool<P, R>>, P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static,
+        ShardedReadPool<P, R>: ReadCapablePool<C>, C: Send + 'static
+{
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        ReadConnection::<D, C>::from_request(req).await
+            .map(|conn| ShardedReadConnection{conn, shard: used::<D, P, R>(req)})
+    }
+}
+impl<D: Database, C> Sentinel for ShardedReadConnection<D, C> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database, C> Deref for ShardedReadConnection<D, C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl<D: Database, C> DerefMut for ShardedReadConnection<D, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+///A request guard which retrieves a read-write connection as [`RwConnection`] does, from the
+///shard of a [`ShardedReadPool`] resolved for the request by the managed [`ShardKeys`], telling
+///which shard it was.
+pub struct ShardedRwConnection<D: Database> {
+    conn: RwConnection<D>,
+    shard: Arc<str>,
+}
+impl<D: Database> ShardedRwConnection<D> {
+    ///The name of the shard which served the connection
+    pub fn shard(&self) -> &str {
+        &self.shard
+    }
+
+    ///Gets the connection, which returns to its pool when dropped
+    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        self.conn.into_inner()
+    }
+}
+#[rocket::async_trait]
+impl<'r, D, P, R> FromRequest<'r> for ShardedRwConnection<D>
+    where D: Database<Pool = ShardedReadPool<P, R>>, P: Pool, R: Pool, R::Error: Into<P::Error>, P::Error: From<rocket::figment::Error>, P::Connection: Send + 'static, R::Connection: Send + 'static
+{
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        RwConnection::<D>::from_request(req).await
+            .map(|conn| ShardedRwConnection{conn, shard: used::<D, P, R>(req)})
+    }
+}
+impl<D: Database> Sentinel for ShardedRwConnection<D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for ShardedRwConnection<D> {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl<D: Database> DerefMut for ShardedRwConnection<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::http::{Header, Status};
+    use rocket::local::asynchronous::Client;
+    use crate::testing::{MockError, MockPool, MockPoolConnection};
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ShardedReadPool<MockPool>);
+
+    const TENANTS: &str = "url = \"main\"\n[read]\nurl = \"read\"\n[tenants.acme]\nurl = \"acme\"\nread = {url = \"acme-read\"}\n[tenants.globex]\nurl = \"globex\"\n";
+
+    async fn client(db: &str, keys: bool) -> Client {
+        let db = Figment::from(Toml::string(db)).extract::<Dict>().unwrap();
+        let figment = rocket::Config::figment().merge(Serialized::default("databases.db", db));
+        let mut rocket = rocket::custom(figment).attach(Db::init());
+        if keys {rocket = rocket.manage(ShardKeys::new(ShardHeader("X-Tenant".into())));}
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    ///The shard and url serving a read for a request naming `tenant`, as the guards resolve it,
+    ///or the status they fail with
+    async fn read(client: &Client, tenant: Option<&'static str>) -> Result<String, Status> {
+        let mut req = client.get("/");
+        if let Some(tenant) = tenant {req = req.header(Header::new("X-Tenant", tenant));}
+        resolve::<Db, MockError>(&req).map_err(|e| e.status())?;
+        let db = Db::fetch(client.rocket()).unwrap();
+        let acquisition = ReadCapablePool::<MockPoolConnection>::get_read_explained(&**db);
+        let (_, _, conn) = scoped::<Db, _>(RequestRouting::of(&req), acquisition).await;
+        Ok(format!("{} {}", used::<Db, _, _>(&req), conn.unwrap().url()))
+    }
+
+    #[rocket::async_test]
+    async fn requests_use_the_shard_they_name() {
+        let client = client(TENANTS, true).await;
+        assert_eq!(read(&client, Some("acme")).await, Ok("acme acme-read".into()));
+        assert_eq!(read(&client, Some("initech")).await, Err(Status::NotFound));
+    }
+
+    #[rocket::async_test]
+    async fn requests_naming_no_shard_fail_without_a_default() {
+        let client = client(TENANTS, true).await;
+        assert_eq!(read(&client, None).await, Err(Status::BadRequest));
+        let unkeyed = self::client(TENANTS, false).await;
+        assert_eq!(read(&unkeyed, None).await, Err(Status::BadRequest));
+        let db = Db::fetch(unkeyed.rocket()).unwrap();
+        assert!(db.default_shard().is_none());
+        assert!(db.get().await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn requests_naming_no_shard_use_the_default_shard() {
+        let client = client(&format!("default_shard = \"globex\"\n{}", TENANTS), true).await;
+        assert_eq!(read(&client, None).await, Ok("globex globex".into()));
+        let unsharded = self::client("url = \"main\"", false).await;
+        assert_eq!(read(&unsharded, None).await, Ok("default main".into()));
+    }
+
+    #[rocket::async_test]
+    async fn an_unknown_default_shard_fails_init() {
+        let figment = Figment::from(Toml::string(&format!("default_shard = \"initech\"\n{}", TENANTS)));
+        assert!(ShardedReadPool::<MockPool>::init(&figment).await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn shards_inherit_no_pools_of_the_database() {
+        let figment = Figment::from(Toml::string(&format!("read_urls = [\"r0\"]\n{}", TENANTS)));
+        let pool = ShardedReadPool::<MockPool>::init(&figment).await.unwrap();
+        assert!(pool.shard("acme").unwrap().has_read_replica());
+        assert!(!pool.shard("globex").unwrap().has_read_replica());
+        let client = client(TENANTS, true).await;
+        assert_eq!(read(&client, Some("globex")).await, Ok("globex globex".into()));
+    }
+}
@@ -0,0 +1,81 @@
+//!Connections shared by the guards of one request
+use std::sync::Mutex as StdMutex;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::tokio::sync::{Mutex, MutexGuard};
+
+///The connection shared by the `Shared<G>` guards of a request, or why it couldn't be acquired
+struct Slot<G: Send + 'static, E: Send + 'static> {
+    ///The guard, or the status it failed or forwarded with
+    conn: Result<Mutex<G>, (Status, bool)>,
+    ///The wrapped guard's error, for the first `Shared<G>` guard to report
+    error: StdMutex<Option<E>>,
+}
+
+///A request guard wrapping a connection guard (`Shared<RwConnection<Db>>`,
+///`Shared<ReadConnection<Db>>`, ...) which acquires the connection once per request and shares
+///it between every `Shared` guard of that type in the request, e.g. one in another guard's
+///`from_request` and one in the handler, so the request holds one pool slot instead of two.
+///
+///Use [`Shared::lock`] to use the connection. The unwrapped guards still acquire a connection
+///each, so handlers needing a second connection of their own can keep using them.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::{MockError, MockPool};
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::request::{FromRequest, Outcome, Request};
+/// use rocket::response::Debug;
+/// use rocket_read_db_pools::{RwConnection, Shared};
+///
+/// struct User(i64);
+/// #[rocket::async_trait]
+/// impl<'r> FromRequest<'r> for User {
+///     type Error = ();
+///
+///     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+///         let Outcome::Success(db) = req.guard::<Shared<'_, RwConnection<Db>>>().await else {
+///             return Outcome::Error((rocket::http::Status::ServiceUnavailable, ()));
+///         };
+///         let mut conn = db.lock().await;
+///         match conn.query("SELECT id FROM users WHERE token = $1").bind(req.headers().get_one("X-Token"))
+///             .fetch_optional().await
+///         {
+///             Ok(Some(id)) => Outcome::Success(User(id)),
+///             _ => Outcome::Error((rocket::http::Status::Unauthorized, ())),
+///         }
+///     }
+/// }
+///
+/// #[rocket::post("/touch")]
+/// async fn touch(user: User, db: Shared<'_, RwConnection<Db>>) -> Result<(), Debug<MockError>> {
+///     db.lock().await.query("UPDATE users SET seen_at = now() WHERE id = $1").bind(user.0).execute().await?;
+///     Ok(())
+/// }
+///```
+pub struct Shared<'r, G>(&'r Mutex<G>);
+impl<'r, G> Shared<'r, G> {
+    ///Locks the shared guard, waiting while another holder of it is using it
+    pub async fn lock(&self) -> MutexGuard<'r, G> {
+        self.0.lock().await
+    }
+}
+#[rocket::async_trait]
+impl<'r, G> FromRequest<'r> for Shared<'r, G> where G: FromRequest<'r> + Send + 'static, G::Error: Send + 'static {
+    type Error = Option<G::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let slot = req.local_cache_async(async {
+            match G::from_request(req).await {
+                Outcome::Success(guard) => Slot{conn: Ok(Mutex::new(guard)), error: StdMutex::new(None)},
+                Outcome::Error((status, e)) => Slot{conn: Err((status, false)), error: StdMutex::new(Some(e))},
+                Outcome::Forward(status) => Slot{conn: Err((status, true)), error: StdMutex::new(None)},
+            }
+        }).await;
+        match slot.conn {
+            Ok(ref conn) => Outcome::Success(Shared(conn)),
+            Err((status, false)) => Outcome::Error((status, slot.error.lock().unwrap_or_else(|e| e.into_inner()).take())),
+            Err((status, true)) => Outcome::Forward(status),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//!Which pool, and which replica, served a request guard's connection
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+use crate::{FallbackReason, PoolRole};
+
+rocket::tokio::task_local! {
+    ///Set while a request guard acquires a connection, to receive the name of the replica which
+    ///served it
+    static REPLICA: RefCell<Option<Arc<str>>>;
+}
+
+///Runs an acquisition `fut` for a request guard, along with the name of the read replica which
+///served it, if one did
+pub(crate) async fn traced<F: Future>(fut: F) -> (F::Output, Option<Arc<str>>) {
+    REPLICA.scope(RefCell::new(None), async {
+        let output = fut.await;
+        (output, REPLICA.with(|slot| slot.borrow_mut().take()))
+    }).await
+}
+
+///Notes that the replica named `name` served the current acquisition
+pub(crate) fn served_by(name: &Arc<str>) {
+    let _ = REPLICA.try_with(|slot| *slot.borrow_mut() = Some(name.clone()));
+}
+
+///The pool which served a connection guard, from [`ReadConnection::source`](crate::ReadConnection::source)
+///or [`AutoConnection::source`](crate::AutoConnection::source), e.g. to annotate responses or
+///logs, or to assert routing in tests:
+///```rust
+/// # use rocket::figment::Figment;
+/// # struct TextPool(String);
+/// # #[rocket::async_trait]
+/// # impl rocket_db_pools::Pool for TextPool {
+/// #     type Connection = String;
+/// #     type Error = rocket_db_pools::Error<std::io::Error>;
+/// #     async fn init(figment: &Figment) -> Result<Self, Self::Error> {Ok(TextPool(figment.extract_inner("url")?))}
+/// #     async fn get(&self) -> Result<Self::Connection, Self::Error> {Ok(self.0.clone())}
+/// #     async fn close(&self) {}
+/// # }
+/// use rocket::get;
+/// use rocket::local::blocking::Client;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{ReadConnection, ReadPool};
+///
+/// #[derive(Database)]
+/// #[database("main")]
+/// struct Db(ReadPool<TextPool>);
+///
+/// #[get("/")]
+/// fn index(conn: ReadConnection<Db>) -> String {
+///     format!("{:?} {:?}", conn.source().role, conn.source().replica.as_deref())
+/// }
+///
+/// let figment = rocket::Config::figment()
+///     .merge(("databases.main.url", "primary"))
+///     .merge(("databases.main.read.replicas.eu1.url", "replica"));
+/// let rocket = rocket::custom(figment).attach(Db::init()).mount("/", rocket::routes![index]);
+/// let client = Client::tracked(rocket).unwrap();
+/// assert_eq!(client.get("/").dispatch().into_string().as_deref(), Some("Read Some(\"eu1\")"));
+///```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionSource {
+    ///The role of the pool
+    pub role: PoolRole,
+    ///The name of the read replica, as in `read.replicas`, if a replica of a [`ReadPool`](crate::ReadPool)
+    ///served it
+    pub replica: Option<Arc<str>>,
+    ///Why the main pool served a read, if it did
+    pub fallback: Option<FallbackReason>,
+}
+impl ConnectionSource {
+    pub(crate) fn new(role: PoolRole, replica: Option<Arc<str>>, fallback: Option<FallbackReason>) -> Self {
+        //A replica may have served the acquisition before its connection was passed over
+        let replica = replica.filter(|_| role == PoolRole::Read);
+        ConnectionSource{role, replica, fallback}
+    }
+
+    pub(crate) fn main() -> Self {
+        ConnectionSource{role: PoolRole::Main, replica: None, fallback: None}
+    }
+
+    ///Whether a read replica served the connection
+    pub fn is_replica(&self) -> bool {
+        self.role == PoolRole::Read
+    }
+}
@@ -0,0 +1,294 @@
+//!Discovery of the read replicas from DNS SRV records, with the `discovery-dns` feature
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpStream, UdpSocket};
+use rocket::tokio::time::Instant;
+use crate::{BoxError, DiscoveredReplica, DiscoveryConfig, Random, ReplicaDiscovery};
+///SRV resource record type
+const SRV: u16 = 33;
+///Compression pointers followed in one name before it's taken as a loop
+const MAX_POINTERS: usize = 16;
+
+///Discovers the read replicas from the SRV record `record`, as `read.discovery.srv` does.
+///
+///Only the targets of the lowest priority are used, as the others are backups under SRV's
+///rules, each weighted by its record's weight (at least 1) and named `host:port`.
+#[derive(Debug, Clone)]
+pub struct SrvDiscovery {
+    record: String,
+    nameserver: Option<SocketAddr>,
+    patience: Duration,
+}
+impl SrvDiscovery {
+    pub fn new(record: &str) -> Self {
+        SrvDiscovery{record: record.to_string(), nameserver: None, patience: Duration::from_secs(5)}
+    }
+
+    ///Queries `nameserver` instead of the first in `/etc/resolv.conf`
+    pub fn nameserver(mut self, nameserver: SocketAddr) -> Self {
+        self.nameserver = Some(nameserver);
+        self
+    }
+
+    pub(crate) fn from_config(record: &str, config: &DiscoveryConfig) -> Result<Self, String> {
+        let mut discovery = SrvDiscovery::new(record);
+        discovery.patience = Duration::from_millis(config.timeout_ms);
+        if let Some(nameserver) = &config.nameserver {
+            discovery.nameserver = Some(parse_nameserver(nameserver).ok_or_else(|| format!("invalid nameserver `{}`", nameserver))?);
+        }
+        Ok(discovery)
+    }
+}
+#[rocket::async_trait]
+impl ReplicaDiscovery for SrvDiscovery {
+    async fn discover(&self) -> Result<Vec<DiscoveredReplica>, BoxError> {
+        let nameserver = match self.nameserver {
+            Some(nameserver) => nameserver,
+            None => system_nameserver().await?,
+        };
+        let records = lookup_srv(&self.record, nameserver, self.patience).await?;
+        let Some(priority) = records.iter().map(|r| r.priority).min() else {return Ok(Vec::new())};
+        Ok(records.into_iter().filter(|r| r.priority == priority).map(|r| DiscoveredReplica{
+            name: format!("{}:{}", r.target, r.port),
+            host: r.target,
+            port: r.port,
+            weight: (r.weight as u32).max(1),
+        }).collect())
+    }
+}
+
+///An SRV record's data
+#[derive(Debug)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+///Parses `ip` or `ip:port`, defaulting to DNS's port
+fn parse_nameserver(nameserver: &str) -> Option<SocketAddr> {
+    nameserver.parse().ok().or_else(|| nameserver.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+///The first nameserver of `/etc/resolv.conf`
+async fn system_nameserver() -> Result<SocketAddr, BoxError> {
+    let conf = rocket::tokio::fs::read_to_string("/etc/resolv.conf").await
+        .map_err(|e| format!("couldn't read /etc/resolv.conf: {}", e))?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|ip| parse_nameserver(ip.trim()))
+        .ok_or_else(|| "no nameserver in /etc/resolv.conf".into())
+}
+
+///Looks up the SRV records of `name` from `nameserver` over UDP, or TCP if the answer is
+///truncated
+async fn lookup_srv(name: &str, nameserver: SocketAddr, patience: Duration) -> Result<Vec<SrvRecord>, BoxError> {
+    let id = Random::default().next() as u16;
+    let query = query(id, name)?;
+    let deadline = Instant::now() + patience;
+    let bind: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.send_to(&query, nameserver).await?;
+    let mut buf = vec![0; 4096];
+    let (records, truncated) = loop {
+        let (len, from) = rocket::tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+            .map_err(|_| format!("no answer from {} within {:?}", nameserver, patience))??;
+        //Answers from elsewhere, or to earlier queries, are ignored
+        if from == nameserver && buf[..len].starts_with(&id.to_be_bytes()) {
+            break parse(&buf[..len])?;
+        }
+    };
+    if !truncated {
+        return Ok(records);
+    }
+    let over_tcp = async {
+        let mut stream = TcpStream::connect(nameserver).await?;
+        stream.write_all(&(query.len() as u16).to_be_bytes()).await?;
+        stream.write_all(&query).await?;
+        let len = stream.read_u16().await? as usize;
+        let mut answer = vec![0; len];
+        stream.read_exact(&mut answer).await?;
+        Ok::<_, BoxError>(answer)
+    };
+    let answer = rocket::tokio::time::timeout_at(deadline, over_tcp).await
+        .map_err(|_| format!("no answer from {} over TCP within {:?}", nameserver, patience))??;
+    if !answer.starts_with(&id.to_be_bytes()) {
+        return Err(format!("{} answered another query", nameserver).into());
+    }
+    Ok(parse(&answer)?.0)
+}
+
+///A recursive query for the SRV records of `name`, with the id `id`
+fn query(id: u16, name: &str) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    //Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid SRV record name `{}`", name));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&SRV.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    Ok(query)
+}
+
+///The SRV records in the answer `msg`, and whether it was truncated
+fn parse(msg: &[u8]) -> Result<(Vec<SrvRecord>, bool), String> {
+    let malformed = || "malformed DNS answer".to_string();
+    let u16_at = |i: usize| msg.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(malformed);
+    let flags = u16_at(2)?;
+    match flags & 0x000F {
+        0 => {}
+        3 => return Err("no such SRV record".to_string()),
+        rcode => return Err(format!("DNS server answered with error code {}", rcode)),
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+    let mut i = 12;
+    for _ in 0..questions {
+        i = skip_name(msg, i).ok_or_else(malformed)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        i = skip_name(msg, i).ok_or_else(malformed)?;
+        let (kind, len) = (u16_at(i)?, u16_at(i + 8)? as usize);
+        let data = i + 10;
+        if msg.len() < data + len {
+            return Err(malformed());
+        }
+        if kind == SRV && len >= 7 {
+            let target = read_name(msg, data + 6).ok_or_else(malformed)?;
+            records.push(SrvRecord{priority: u16_at(data)?, weight: u16_at(data + 2)?, port: u16_at(data + 4)?, target});
+        }
+        i = data + len;
+    }
+    Ok((records, flags & 0x0200 != 0))
+}
+
+///The index just past the name at `i` in `msg`
+fn skip_name(msg: &[u8], mut i: usize) -> Option<usize> {
+    loop {
+        match *msg.get(i)? {
+            0 => return Some(i + 1),
+            len if len & 0xC0 == 0xC0 => return Some(i + 2),
+            len => i += 1 + len as usize,
+        }
+    }
+}
+
+///The name at `i` in `msg`, following compression pointers, without its trailing dot
+fn read_name(msg: &[u8], mut i: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut pointers = 0;
+    loop {
+        match *msg.get(i)? {
+            0 => return Some(labels.join(".")),
+            len if len & 0xC0 == 0xC0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                i = ((len as usize & 0x3F) << 8) | *msg.get(i + 1)? as usize;
+            }
+            len => {
+                let label = msg.get(i + 1..i + 1 + len as usize)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                i += 1 + len as usize;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "_pg._tcp.example";
+    ///Offset of the `example` label in the question of [`answer`]
+    const EXAMPLE: u8 = 21;
+
+    ///An answer to `query(id, NAME)` with `flags`, holding an SRV record for each of `records`:
+    ///its priority, weight, port and encoded target
+    fn answer(id: u16, flags: u16, records: &[(u16, u16, u16, &[u8])]) -> Vec<u8> {
+        let mut msg = query(id, NAME).unwrap();
+        msg[2..4].copy_from_slice(&flags.to_be_bytes());
+        msg[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for &(priority, weight, port, target) in records {
+            //The question's name, by pointer, then the type, class and TTL
+            msg.extend_from_slice(&[0xC0, 12, 0, 33, 0, 1, 0, 0, 0, 60]);
+            msg.extend_from_slice(&(6 + target.len() as u16).to_be_bytes());
+            for value in [priority, weight, port] {
+                msg.extend_from_slice(&value.to_be_bytes());
+            }
+            msg.extend_from_slice(target);
+        }
+        msg
+    }
+
+    fn targets(records: &[SrvRecord]) -> Vec<(u16, u16, u16, &str)> {
+        records.iter().map(|r| (r.priority, r.weight, r.port, r.target.as_str())).collect()
+    }
+
+    #[test]
+    fn answers_are_parsed_following_compression_pointers() {
+        let msg = answer(7, 0x8180, &[(10, 5, 5432, b"\x03db1\x07example\x00"), (20, 0, 5433, &[3, b'd', b'b', b'2', 0xC0, EXAMPLE])]);
+        let (records, truncated) = parse(&msg).unwrap();
+        assert_eq!(targets(&records), [(10, 5, 5432, "db1.example"), (20, 0, 5433, "db2.example")]);
+        assert!(!truncated);
+
+        let (_, truncated) = parse(&answer(7, 0x8380, &[])).unwrap();
+        assert!(truncated);
+        assert_eq!(parse(&answer(7, 0x8183, &[])).unwrap_err(), "no such SRV record");
+        assert!(parse(&answer(7, 0x8182, &[])).unwrap_err().contains("error code 2"));
+    }
+
+    #[test]
+    fn malformed_and_truncated_answers_are_errors() {
+        let msg = answer(7, 0x8180, &[(10, 5, 5432, b"\x03db1\x07example\x00")]);
+        for len in 0..msg.len() {
+            assert!(parse(&msg[..len]).is_err(), "{} of {} bytes parsed", len, msg.len());
+        }
+
+        //A target pointing at itself
+        let mut looping = answer(7, 0x8180, &[(10, 5, 5432, &[0xC0, 0])]);
+        let at = looping.len() - 2;
+        looping[at..].copy_from_slice(&(0xC000 | at as u16).to_be_bytes());
+        assert!(read_name(&looping, at).is_none());
+        assert!(parse(&looping).is_err());
+
+        //A label running past the end of the message
+        assert_eq!(skip_name(b"\x05ab", 0), None);
+        assert_eq!(read_name(b"\x05ab", 0), None);
+        assert_eq!(skip_name(b"\x02ab\x00", 0), Some(4));
+        assert!(query(1, "a..example").is_err());
+    }
+
+    #[rocket::async_test]
+    async fn discovery_keeps_the_lowest_priority_targets() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let nameserver = server.local_addr().unwrap();
+        rocket::tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (_, from) = server.recv_from(&mut buf).await.unwrap();
+            let id = u16::from_be_bytes([buf[0], buf[1]]);
+            let records: [(u16, u16, u16, &[u8]); 3] = [
+                (10, 0, 5432, b"\x03db1\x07example\x00"),
+                (20, 9, 5432, b"\x06backup\x07example\x00"),
+                (10, 3, 5433, &[3, b'd', b'b', b'2', 0xC0, EXAMPLE]),
+            ];
+            server.send_to(&answer(id, 0x8180, &records), from).await.unwrap();
+        });
+        let discovered = SrvDiscovery::new(NAME).nameserver(nameserver).discover().await.unwrap();
+        let found: Vec<_> = discovered.iter().map(|r| (r.name.as_str(), r.port, r.weight)).collect();
+        assert_eq!(found, [("db1.example:5432", 5432, 1), ("db2.example:5433", 5433, 3)]);
+    }
+}
@@ -0,0 +1,122 @@
+//!Connections kept for the duration of streamed responses
+use std::ops::{Deref, DerefMut};
+use rocket::futures::{Stream, StreamExt};
+use rocket::futures::stream::BoxStream;
+use crate::hooks::Release;
+use crate::ConnectionSource;
+
+///A connection owned by a streamed response, from `into_stream_guard` on
+///[`ReadConnection`](crate::ReadConnection), [`RwConnection`](crate::RwConnection),
+///[`Primary`](crate::Primary) or [`AutoConnection`](crate::AutoConnection).
+///
+///It's `'static`, so it can move into a Rocket stream outliving the handler, and returns to the
+///pool which served it when the stream is dropped, once the response is sent or the client goes
+///away. Release hooks run then, with the whole time it was held. As long streams are expected,
+///it's exempt from `lease_warn_ms` and `log.slow_hold_ms`.
+///
+///[`StreamConnection::stream`] streams items borrowing the connection, such as a driver's row
+///stream, without the handler having to keep the connection alive:
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::futures::StreamExt;
+/// use rocket::response::stream::TextStream;
+/// use rocket_read_db_pools::ReadConnection;
+///
+/// #[rocket::get("/export")]
+/// fn export(conn: ReadConnection<Db>) -> TextStream![String] {
+///     let rows = conn.into_stream_guard().stream(|conn| conn.query("SELECT name FROM items").fetch::<String>());
+///     TextStream(rows.map(|row| match row {
+///         Ok(name) => format!("{}\n", name),
+///         Err(e) => format!("error: {}\n", e),
+///     }))
+/// }
+///```
+pub struct StreamConnection<C> {
+    conn: C,
+    release: Option<Release>,
+    source: ConnectionSource,
+}
+impl<C> StreamConnection<C> {
+    pub(crate) fn new(conn: C, release: Option<Release>, source: ConnectionSource) -> Self {
+        let release = release.map(Release::streaming);
+        StreamConnection{conn, release, source}
+    }
+
+    ///The pool which served the connection, and the replica if one did
+    pub fn source(&self) -> &ConnectionSource {
+        &self.source
+    }
+
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> C {
+        drop(self.release);
+        self.conn
+    }
+}
+impl<C: Send + 'static> StreamConnection<C> {
+    ///Streams the items of the stream `f` makes from the connection, keeping the connection
+    ///until the stream is dropped.
+    ///```rust
+    /// # use rocket::figment::Figment;
+    /// # struct TextPool(String);
+    /// # #[rocket::async_trait]
+    /// # impl rocket_db_pools::Pool for TextPool {
+    /// #     type Connection = String;
+    /// #     type Error = rocket_db_pools::Error<std::io::Error>;
+    /// #     async fn init(figment: &Figment) -> Result<Self, Self::Error> {Ok(TextPool(figment.extract_inner("url")?))}
+    /// #     async fn get(&self) -> Result<Self::Connection, Self::Error> {Ok(self.0.clone())}
+    /// #     async fn close(&self) {}
+    /// # }
+    /// use rocket::futures::{stream, StreamExt};
+    /// use rocket::get;
+    /// use rocket::local::blocking::Client;
+    /// use rocket::response::stream::TextStream;
+    /// use rocket_db_pools::Database;
+    /// use rocket_read_db_pools::{ReadConnection, ReadPool};
+    ///
+    /// #[derive(Database)]
+    /// #[database("main")]
+    /// struct Db(ReadPool<TextPool>);
+    ///
+    /// #[get("/")]
+    /// fn lines(conn: ReadConnection<Db>) -> TextStream![String] {
+    ///     let words = conn.into_stream_guard().stream(|conn| stream::iter(conn.split(',')).map(str::to_owned).boxed());
+    ///     TextStream(words.map(|word| format!("{}\n", word)))
+    /// }
+    ///
+    /// let figment = rocket::Config::figment()
+    ///     .merge(("databases.main.url", "primary"))
+    ///     .merge(("databases.main.read.url", "a,b,c"));
+    /// let rocket = rocket::custom(figment).attach(Db::init()).mount("/", rocket::routes![lines]);
+    /// let client = Client::tracked(rocket).unwrap();
+    /// assert_eq!(client.get("/").dispatch().into_string().as_deref(), Some("a\nb\nc\n"));
+    ///```
+    pub fn stream<T, F>(self, f: F) -> impl Stream<Item = T> + Send + 'static
+        where T: Send + 'static, F: for<'c> FnOnce(&'c mut C) -> BoxStream<'c, T> + Send + 'static
+    {
+        let StreamConnection{mut conn, release, ..} = self;
+        rocket::async_stream::stream! {
+            //Moved into the stream, to be released when it's dropped
+            let _release = release;
+            let mut items = f(&mut conn);
+            while let Some(item) = items.next().await {
+                yield item;
+            }
+        }
+    }
+}
+impl<C> Deref for StreamConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl<C> DerefMut for StreamConnection<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
@@ -0,0 +1,17 @@
+//!Per-pool `application_name`s, translated into the driver's url parameter
+use rocket::figment::Figment;
+use crate::tls;
+
+///Applies the `application_name` option of a pool's figment, appending it to the pool's `url`.
+///Only Postgres urls take it, so it's ignored with a warning for other schemes.
+pub(crate) fn apply(figment: Figment) -> Figment {
+    let Ok(name) = figment.extract_inner::<String>("application_name") else {return figment};
+    let Ok(url) = figment.extract_inner::<String>("url") else {return figment};
+    match url.split_once("://").map_or("", |(scheme, _)| scheme) {
+        "postgres" | "postgresql" => tls::with_params(figment, &url, vec![("application_name", name)]),
+        scheme => {
+            rocket::warn!("`application_name` ignored: unsupported url scheme `{}`", scheme);
+            figment
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//!Helpers for testing applications which use `ReadPool`, enabled by the `testing` feature
+use std::any::TypeId;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::{Phase, Rocket};
+use rocket_db_pools::Database;
+use tempfile::TempDir;
+use crate::{PoolRole, ReadPool};
+
+pub mod conformance;
+#[cfg(feature = "testcontainers")]
+mod containers;
+mod mock;
+mod pool;
+mod strategy;
+
+#[cfg(feature = "testcontainers")]
+pub use containers::{PostgresPair, DEFAULT_IMAGE};
+pub use mock::{MockCall, MockConnection, MockPoolError, MockReadPool};
+pub use pool::{MockError, MockPool, MockPoolConnection, MockQuery};
+pub use strategy::{check_strategy, ScenarioReplica, StrategyCheck, StrategyReport};
+
+///Records, and optionally dictates, which pools a `ReadPool` routes acquisitions to.
+///
+///Install a script with [`ReadPool::set_routing_script`]. A scripted sequence of
+///[`PoolRole::Main`] / [`PoolRole::Read`] overrides routing for read acquisitions while it lasts
+///(writes always use the main pool), so tests
+///depending on routing are reproducible. Every acquisition is recorded either way and can be
+///checked with [`RoutingScript::assert_sequence`].
+///
+///```rust
+/// # async fn _inner(db: &rocket_read_db_pools::ReadPool<rocket_read_db_pools::testing::MockPool>) {
+/// use rocket_read_db_pools::PoolRole;
+/// use rocket_read_db_pools::testing::RoutingScript;
+///
+/// let script = RoutingScript::scripted([PoolRole::Main, PoolRole::Read]);
+/// db.set_routing_script(Some(script.clone()));
+/// // ...exercise the application...
+/// script.assert_sequence(&[PoolRole::Main, PoolRole::Read, PoolRole::Main]);
+/// # }
+///```
+#[derive(Debug, Default)]
+pub struct RoutingScript {
+    planned: Mutex<VecDeque<PoolRole>>,
+    used: Mutex<Vec<PoolRole>>,
+}
+impl RoutingScript {
+    ///Creates a script which only records routing decisions
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    ///Creates a script which routes the next read acquisitions to the given pools in order
+    pub fn scripted<I: IntoIterator<Item = PoolRole>>(roles: I) -> Arc<Self> {
+        Arc::new(RoutingScript{planned: Mutex::new(roles.into_iter().collect()), used: Mutex::default()})
+    }
+
+    pub(crate) fn route(&self, default: PoolRole) -> PoolRole {
+        let role = match default {
+            PoolRole::Read => match lock(&self.planned).pop_front() {
+                Some(PoolRole::Main) => PoolRole::Main,
+                _ => PoolRole::Read,
+            },
+            _ => default,
+        };
+        lock(&self.used).push(role);
+        role
+    }
+
+    ///The pools used so far, in order
+    pub fn used(&self) -> Vec<PoolRole> {
+        lock(&self.used).clone()
+    }
+
+    ///Number of scripted roles not yet consumed
+    pub fn remaining(&self) -> usize {
+        lock(&self.planned).len()
+    }
+
+    ///Panics unless exactly `expected` pools have been used, in order
+    #[track_caller]
+    pub fn assert_sequence(&self, expected: &[PoolRole]) {
+        let used = self.used();
+        assert_eq!(used, expected, "unexpected sequence of pools used");
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///Installs or removes a [`RoutingScript`] for this pool
+    pub fn set_routing_script(&self, script: Option<Arc<RoutingScript>>) {
+        *self.script.write().unwrap_or_else(|e| e.into_inner()) = script;
+    }
+}
+
+///A pair of temporary SQLite database files standing in for a main database and its replica,
+///so routing can be exercised in CI without Postgres.
+///
+///Both files live in a temporary directory which is removed when the fixture is dropped.
+///```rust
+/// # async fn _inner() -> std::io::Result<()> {
+/// # type SqlitePool = rocket_read_db_pools::testing::MockPool;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::ReadPool;
+/// use rocket_read_db_pools::testing::SqliteFixture;
+///
+/// #[derive(Database)]
+/// #[database("db")]
+/// struct Db(ReadPool<SqlitePool>);
+///
+/// let fixture = SqliteFixture::new()?;
+/// // ...create the schema and seed data in `fixture.main_path()`...
+/// fixture.sync_read()?;
+/// let rocket = rocket::custom(fixture.rocket_figment("db")).attach(Db::init());
+/// # Ok(()) }
+///```
+#[derive(Debug)]
+pub struct SqliteFixture {
+    dir: TempDir,
+}
+impl SqliteFixture {
+    ///Creates empty main and read database files in a new temporary directory
+    pub fn new() -> io::Result<Self> {
+        let fixture = SqliteFixture{dir: tempfile::tempdir()?};
+        std::fs::File::create(fixture.main_path())?;
+        std::fs::File::create(fixture.read_path())?;
+        Ok(fixture)
+    }
+
+    ///Path of the main database file
+    pub fn main_path(&self) -> PathBuf {
+        self.dir.path().join("main.sqlite")
+    }
+
+    ///Path of the read database file
+    pub fn read_path(&self) -> PathBuf {
+        self.dir.path().join("read.sqlite")
+    }
+
+    ///Database config for the pair, as passed to `Pool::init`
+    pub fn figment(&self) -> Figment {
+        pair_figment(&sqlite_url(&self.main_path()), &sqlite_url(&self.read_path()))
+    }
+
+    ///Rocket config with the pair configured as the database `name`
+    pub fn rocket_figment(&self, name: &str) -> Figment {
+        pair_rocket_figment(name, &sqlite_url(&self.main_path()), &sqlite_url(&self.read_path()))
+    }
+
+    ///Replaces the read database with a copy of the main database's schema and data.
+    ///
+    ///SQLite doesn't expect files to change underneath open connections, so call this before
+    ///the read pool is created or while it holds no connections mid-transaction.
+    pub fn sync_read(&self) -> io::Result<()> {
+        let (main, read) = (self.main_path(), self.read_path());
+        std::fs::copy(&main, &read)?;
+        for suffix in ["-wal", "-shm"] {
+            let (main, read) = (with_suffix(&main, suffix), with_suffix(&read, suffix));
+            if main.exists() {
+                std::fs::copy(&main, &read)?;
+            } else if read.exists() {
+                std::fs::remove_file(&read)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+fn sqlite_url(path: &Path) -> String {
+    format!("sqlite://{}?mode=rwc", path.display())
+}
+
+///Database config for a main URL and a read replica URL, as passed to `Pool::init`.
+///
+///Useful with servers started by the test itself, e.g. a primary and streaming replica
+///Postgres pair, which the `testcontainers` feature's `PostgresPair` starts in containers.
+pub fn pair_figment(main_url: &str, read_url: &str) -> Figment {
+    Figment::new()
+        .merge(Serialized::default("url", main_url))
+        .merge(Serialized::default(crate::keys::READ_URL, read_url))
+}
+
+///Rocket config with a main URL and read replica URL configured as the database `name`
+pub fn pair_rocket_figment(name: &str, main_url: &str, read_url: &str) -> Figment {
+    rocket::Config::figment()
+        .merge(Serialized::default(&format!("databases.{}.url", name), main_url))
+        .merge(Serialized::default(&format!("databases.{}.read.url", name), read_url))
+}
+
+///The URL of a new in-memory SQLite database, uniquely named within the process and shared by
+///every connection to it. It's deleted once the last connection closes.
+pub fn unified_sqlite_url() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!("sqlite:file:read_db_pools_{}_{}?mode=memory&cache=shared", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+///Rocket config in "unified" mode: the main and read pools of the database `name` both use one
+///new in-memory SQLite database from [`unified_sqlite_url`], so writes are visible to reads at
+///once and integration tests run deterministically without replicas, while routing still goes
+///through both pools.
+///
+///Each pool keeps a connection open so the database outlives idle periods.
+///```rust
+/// # async fn _inner() {
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::ReadPool;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # #[derive(Database)] #[database("db")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::testing::unified_rocket_figment;
+///
+/// let rocket = rocket::custom(unified_rocket_figment("db")).attach(Db::init());
+/// # }
+///```
+pub fn unified_rocket_figment(name: &str) -> Figment {
+    let url = unified_sqlite_url();
+    pair_rocket_figment(name, &url, &url)
+        .merge(Serialized::default(&format!("databases.{}.min_connections", name), 1))
+        .merge(Serialized::default(&format!("databases.{}.read.min_connections", name), 1))
+}
+
+///Polls `check` every `interval` until it returns true, giving up after `timeout`.
+///
+///Returns whether `check` succeeded. Use it to wait for a replica to catch up, e.g. by checking
+///for a row written to the primary:
+///```rust
+/// # async fn replica_has_marker() -> bool { true }
+/// # async fn _inner() {
+/// use std::time::Duration;
+/// use rocket_read_db_pools::testing::wait_until;
+///
+/// assert!(wait_until(Duration::from_secs(30), Duration::from_millis(100), || replica_has_marker()).await);
+/// # }
+///```
+pub async fn wait_until<F, Fut>(timeout: Duration, interval: Duration, mut check: F) -> bool
+    where F: FnMut() -> Fut, Fut: Future<Output = bool>
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if check().await {return true;}
+        if Instant::now() + interval > deadline {return false;}
+        rocket::tokio::time::sleep(interval).await;
+    }
+}
+
+///Managed state holding failure rules for the connection guards.
+///
+///Manage an instance while building the Rocket under test, then install rules at runtime with
+///[`fail_next_read_acquire`], [`fail_next_acquire`] and [`fail_next_delayed_acquire`]. A failed
+///acquisition makes the guard fail with `Status::ServiceUnavailable` and a `None` error, without
+///touching the pool.
+///```rust
+/// # async fn _inner() {
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::ReadPool;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # #[derive(Database)] #[database("db")] struct Db(ReadPool<MockPool>);
+/// use rocket::local::asynchronous::Client;
+/// use rocket_read_db_pools::testing::{FailureInjector, fail_next_read_acquire};
+///
+/// let rocket = rocket::build().attach(Db::init()).manage(FailureInjector::default());
+/// let client = Client::tracked(rocket).await.unwrap();
+/// fail_next_read_acquire::<Db, _>(client.rocket(), 3);
+/// # }
+///```
+#[derive(Debug, Default)]
+pub struct FailureInjector {
+    rules: Mutex<HashMap<(TypeId, PoolRole), usize>>,
+}
+impl FailureInjector {
+    ///Makes the next `count` acquisitions of `role` for database `D` fail
+    pub fn fail_next<D: Database>(&self, role: PoolRole, count: usize) {
+        lock(&self.rules).insert((TypeId::of::<D>(), role), count);
+    }
+
+    ///Removes all failure rules
+    pub fn clear(&self) {
+        lock(&self.rules).clear();
+    }
+
+    ///Consumes one failure for `role` of database `D`, returning true if the acquisition should fail
+    fn take<D: Database>(&self, role: PoolRole) -> bool {
+        match lock(&self.rules).get_mut(&(TypeId::of::<D>(), role)) {
+            Some(count) if *count > 0 => {*count -= 1; true}
+            _ => false,
+        }
+    }
+}
+
+fn injector<P: Phase>(rocket: &Rocket<P>) -> &FailureInjector {
+    rocket.state::<FailureInjector>()
+        .expect("`FailureInjector` must be managed by the Rocket instance to inject failures")
+}
+
+///Makes the next `count` `ReadConnection<D>` acquisitions fail. Panics unless a
+///[`FailureInjector`] is managed.
+pub fn fail_next_read_acquire<D: Database, P: Phase>(rocket: &Rocket<P>, count: usize) {
+    injector(rocket).fail_next::<D>(PoolRole::Read, count);
+}
+
+///Makes the next `count` `RwConnection<D>` acquisitions fail. Panics unless a
+///[`FailureInjector`] is managed.
+pub fn fail_next_acquire<D: Database, P: Phase>(rocket: &Rocket<P>, count: usize) {
+    injector(rocket).fail_next::<D>(PoolRole::Main, count);
+}
+
+///Makes the next `count` `DelayedReadConnection<D>` acquisitions fail. Panics unless a
+///[`FailureInjector`] is managed.
+pub fn fail_next_delayed_acquire<D: Database, P: Phase>(rocket: &Rocket<P>, count: usize) {
+    injector(rocket).fail_next::<D>(PoolRole::Delayed, count);
+}
+
+///Used by the guards to check for an injected failure
+pub(crate) fn injected_failure<D: Database, P: Phase>(rocket: &Rocket<P>, role: PoolRole) -> bool {
+    rocket.state::<FailureInjector>().is_some_and(|injector| injector.take::<D>(role))
+}
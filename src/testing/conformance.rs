@@ -0,0 +1,69 @@
+//!Conformance checks for `Pool` implementations used inside `ReadPool`
+//!
+//!Pool implementors can run [`run`] from their own test suite against real databases to verify
+//!their pool behaves as `ReadPool` expects:
+//!```rust
+//! # type MyPool = rocket_read_db_pools::testing::MockPool;
+//! use rocket_read_db_pools::testing::{conformance, pair_figment};
+//!
+//! #[rocket::async_test]
+//! async fn conforms() {
+//!     conformance::run::<MyPool>(&pair_figment("postgres://localhost/main", "postgres://localhost/replica")).await;
+//! }
+//!```
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::figment::value::Dict;
+use rocket_db_pools::Pool;
+use crate::{PoolRole, ReadCapablePool, ReadPool};
+
+///Checks that `P` works as the main and read pools of a `ReadPool`, panicking on the first
+///failure.
+///
+///`figment` is a database config in the form given to `Pool::init` (the `databases.<name>`
+///table) which must have a `read` table; a `read.delayed` table is checked too if present.
+///Checks that:
+///* the main pool initializes from the whole table, ignoring the `read` table within it
+///* each pool serves connections, and connections from both can be held at once
+///* read acquisitions fall back to the main pool when no `read` table is configured
+///* pools refuse connections once closed
+pub async fn run<P>(figment: &Figment) where P: Pool, P::Error: From<rocket::figment::Error>, P::Connection: Send {
+    assert!(figment.contains("read"), "conformance config must have a `read` table");
+    let pool = init::<P>(figment, "with a read pool").await;
+    let main = pool.get().await.unwrap_or_else(|e| panic!("main pool failed to serve a connection: {}", e));
+    let (role, read) = ReadCapablePool::<P::Connection>::get_read(&pool).await;
+    assert_eq!(role, PoolRole::Read, "read acquisition wasn't routed to the read pool");
+    let read = read.unwrap_or_else(|e| panic!("read pool failed to serve a connection: {}", e));
+    drop((main, read));
+    if figment.contains(crate::keys::DELAYED) {
+        match ReadCapablePool::<P::Connection>::get_delayed(&pool).await {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => panic!("delayed pool failed to serve a connection: {}", e),
+            None => panic!("`read.delayed` is configured but no delayed pool was created"),
+        }
+    }
+    assert_closes(pool, "with a read pool").await;
+
+    let mut main_only: Dict = figment.extract().unwrap_or_else(|e| panic!("invalid conformance config: {}", e));
+    main_only.remove("read");
+    let pool = init::<P>(&Figment::from(Serialized::defaults(main_only)), "without a read pool").await;
+    let (role, read) = ReadCapablePool::<P::Connection>::get_read(&pool).await;
+    assert_eq!(role, PoolRole::Main, "read acquisition didn't fall back to the main pool");
+    if let Err(e) = read {
+        panic!("main pool failed to serve a fallback read connection: {}", e);
+    }
+    assert!(ReadCapablePool::<P::Connection>::get_delayed(&pool).await.is_none(), "delayed pool created without `read.delayed`");
+    assert_closes(pool, "without a read pool").await;
+}
+
+async fn init<P>(figment: &Figment, case: &str) -> ReadPool<P> where P: Pool, P::Error: From<rocket::figment::Error>, P::Connection: Send {
+    ReadPool::<P>::init(figment).await
+        .unwrap_or_else(|e| panic!("`ReadPool` failed to initialize {}: {}", case, e))
+}
+
+async fn assert_closes<P>(pool: ReadPool<P>, case: &str) where P: Pool, P::Error: From<rocket::figment::Error>, P::Connection: Send {
+    pool.close().await;
+    assert!(pool.get().await.is_err(), "main pool served a connection after close ({})", case);
+    let (_, read) = ReadCapablePool::<P::Connection>::get_read(&pool).await;
+    assert!(read.is_err(), "read acquisition succeeded after close ({})", case);
+}
@@ -0,0 +1,181 @@
+//!A primary and streaming replica Postgres pair in Docker containers, with the `testcontainers`
+//!feature
+use std::io;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use rocket::figment::Figment;
+use super::{pair_figment, pair_rocket_figment};
+
+///The image [`PostgresPair::start`] runs
+pub const DEFAULT_IMAGE: &str = "postgres:16";
+
+///How long [`PostgresPair::start`] waits for the replica to stream from the primary
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+///Run by the replica's container: clones the primary, retrying until it accepts replication
+///connections, and starts a standby streaming from it
+const REPLICA_SCRIPT: &str = r#"until pg_basebackup -h primary -U postgres -D "$PGDATA" -R -X stream; do rm -rf "$PGDATA"/*; sleep 1; done
+chmod 700 "$PGDATA"
+exec postgres"#;
+
+///A primary Postgres server and a streaming replica of it, each in a Docker container, for
+///end-to-end tests of replica lag and failover.
+///
+///The containers are started with the `docker` command, so it must be on the `PATH` and able to
+///reach a daemon which can pull the image. They share a new Docker network, publish their ports
+///on the host and trust every connection as the `postgres` user. Both containers and the network
+///are removed when the pair is dropped.
+///```rust,no_run
+/// # fn _inner() -> std::io::Result<()> {
+/// use rocket_read_db_pools::testing::PostgresPair;
+///
+/// let pair = PostgresPair::start()?;
+/// let rocket = rocket::custom(pair.rocket_figment("db"));
+/// // ...attach the database, write to the primary, then pause replay to test reads from a
+/// // lagging replica...
+/// pair.pause_replay()?;
+/// # Ok(()) }
+///```
+pub struct PostgresPair {
+    network: String,
+    primary: String,
+    replica: String,
+    main_url: String,
+    read_url: String,
+}
+impl PostgresPair {
+    ///Starts the pair from the [`DEFAULT_IMAGE`], returning once the replica is streaming from
+    ///the primary and accepting connections
+    pub fn start() -> io::Result<Self> {
+        Self::start_image(DEFAULT_IMAGE)
+    }
+
+    ///Starts the pair from the Postgres `image`, which must be one of the official images or
+    ///derived from them
+    pub fn start_image(image: &str) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let network = format!("read_db_pools_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        docker(&["network", "create", &network])?;
+        let mut pair = PostgresPair{
+            primary: format!("{}_primary", network),
+            replica: format!("{}_replica", network),
+            network,
+            main_url: String::new(),
+            read_url: String::new(),
+        };
+        pair.main_url = pair.start_primary(image)?;
+        pair.read_url = pair.start_replica(image)?;
+        Ok(pair)
+    }
+
+    fn start_primary(&self, image: &str) -> io::Result<String> {
+        docker(&[
+            "run", "-d", "--name", &self.primary, "--network", &self.network, "--network-alias", "primary",
+            "-e", "POSTGRES_HOST_AUTH_METHOD=trust", "-P", image,
+        ])?;
+        wait_for(&format!("primary `{}` to accept connections", self.primary), || {
+            Ok(psql(&self.primary, "SELECT 1").is_ok_and(|out| out == "1"))
+        })?;
+        docker(&["exec", "-u", "postgres", &self.primary, "sh", "-c", r#"echo "host replication all all trust" >> "$PGDATA/pg_hba.conf""#])?;
+        psql(&self.primary, "SELECT pg_reload_conf()")?;
+        Ok(url(&published_port(&self.primary)?))
+    }
+
+    fn start_replica(&self, image: &str) -> io::Result<String> {
+        docker(&[
+            "run", "-d", "--name", &self.replica, "--network", &self.network, "-u", "postgres",
+            "-P", "--entrypoint", "sh", image, "-c", REPLICA_SCRIPT,
+        ])?;
+        wait_for(&format!("replica `{}` to stream from the primary", self.replica), || {
+            let streaming = psql(&self.primary, "SELECT count(*) FROM pg_stat_replication WHERE state = 'streaming'")
+                .is_ok_and(|out| out == "1");
+            Ok(streaming && psql(&self.replica, "SELECT pg_is_in_recovery()").is_ok_and(|out| out == "t"))
+        })?;
+        Ok(url(&published_port(&self.replica)?))
+    }
+
+    ///URL of the primary, from the host
+    pub fn main_url(&self) -> &str {
+        &self.main_url
+    }
+
+    ///URL of the replica, from the host
+    pub fn read_url(&self) -> &str {
+        &self.read_url
+    }
+
+    ///Database config for the pair, as passed to `Pool::init`
+    pub fn figment(&self) -> Figment {
+        pair_figment(&self.main_url, &self.read_url)
+    }
+
+    ///Rocket config with the pair configured as the database `name`
+    pub fn rocket_figment(&self, name: &str) -> Figment {
+        pair_rocket_figment(name, &self.main_url, &self.read_url)
+    }
+
+    ///Pauses the replica's replay of the primary's WAL, so it lags behind writes until
+    ///[`PostgresPair::resume_replay`]
+    pub fn pause_replay(&self) -> io::Result<()> {
+        psql(&self.replica, "SELECT pg_wal_replay_pause()").map(drop)
+    }
+
+    ///Resumes the replica's replay of the primary's WAL
+    pub fn resume_replay(&self) -> io::Result<()> {
+        psql(&self.replica, "SELECT pg_wal_replay_resume()").map(drop)
+    }
+
+    ///Stops the primary's container, leaving the replica running but unable to replicate, to
+    ///test how reads and writes behave while the primary is down
+    pub fn stop_primary(&self) -> io::Result<()> {
+        docker(&["stop", &self.primary]).map(drop)
+    }
+}
+impl Drop for PostgresPair {
+    fn drop(&mut self) {
+        let _ = docker(&["rm", "-f", "-v", &self.primary, &self.replica]);
+        let _ = docker(&["network", "rm", &self.network]);
+    }
+}
+
+///Runs `docker` with `args`, returning its trimmed output, or its error output if it fails
+fn docker(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("docker").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("`docker {}` failed: {}", args.first().unwrap_or(&""), stderr.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+///Runs `sql` with `psql` in `container` over TCP, so the server started by the image's
+///entrypoint is reached rather than its temporary initialization server
+fn psql(container: &str, sql: &str) -> io::Result<String> {
+    docker(&["exec", container, "psql", "-h", "127.0.0.1", "-U", "postgres", "-tAc", sql])
+}
+
+///The host port `container` publishes Postgres on
+fn published_port(container: &str) -> io::Result<String> {
+    let mappings = docker(&["port", container, "5432/tcp"])?;
+    mappings.lines()
+        .find_map(|mapping| mapping.rsplit_once(':').map(|(_, port)| port.to_string()))
+        .ok_or_else(|| io::Error::other(format!("`{}` doesn't publish port 5432", container)))
+}
+
+fn url(port: &str) -> String {
+    format!("postgres://postgres@127.0.0.1:{}/postgres", port)
+}
+
+///Polls `ready` every 500ms until it returns true, failing after [`READY_TIMEOUT`]
+fn wait_for(what: &str, mut ready: impl FnMut() -> io::Result<bool>) -> io::Result<()> {
+    let start = Instant::now();
+    while !ready()? {
+        if start.elapsed() > READY_TIMEOUT {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("timed out waiting for {}", what)));
+        }
+        sleep(Duration::from_millis(500));
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//!A mock pool recording how guards acquire connections
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use rocket::figment::Figment;
+use rocket_db_pools::Pool;
+use crate::{keys, FallbackReason, PoolRole, ReadCapablePool};
+use super::lock;
+
+///An acquisition made from a [`MockReadPool`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockCall {
+    ///`Pool::get`, as by `RwConnection`
+    Get,
+    ///`ReadCapablePool::get_read`, as by `ReadConnection`
+    GetRead,
+    ///`ReadCapablePool::get_read_main`, for reads kept off the replicas, e.g. by
+    ///`ReadYourWrites`
+    GetReadMain,
+}
+
+///A connection from a [`MockReadPool`], telling which pool served it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockConnection {
+    pub role: PoolRole,
+}
+
+///The error of an acquisition made to fail with [`MockReadPool::fail_next`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockPoolError(pub MockCall);
+impl fmt::Display for MockPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "injected failure of {:?}", self.0)
+    }
+}
+impl std::error::Error for MockPoolError {}
+
+///A [`ReadCapablePool`] which connects to nothing, for unit-testing routing without databases.
+///
+///It records every acquisition, which tests check with [`MockReadPool::calls`], and fails those
+///chosen with [`MockReadPool::fail_next`]. Reads are served by the read pool if a `read` table is
+///configured (any `read.url` will do), else by the main pool. Clones share their records.
+///```rust
+/// # async fn _inner() {
+/// use rocket::local::asynchronous::Client;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{ReadConnection, RwConnection};
+/// use rocket_read_db_pools::testing::{pair_rocket_figment, MockCall, MockReadPool};
+///
+/// #[derive(Database)]
+/// #[database("db")]
+/// struct Db(MockReadPool);
+///
+/// #[rocket::get("/")]
+/// fn read(conn: ReadConnection<Db>) -> String {
+///     format!("{:?}", conn.role)
+/// }
+///
+/// let rocket = rocket::custom(pair_rocket_figment("db", "mock://main", "mock://read"))
+///     .attach(Db::init())
+///     .mount("/", rocket::routes![read]);
+/// let client = Client::tracked(rocket).await.unwrap();
+/// assert_eq!(client.get("/").dispatch().await.into_string().await.as_deref(), Some("Read"));
+/// let db = Db::fetch(client.rocket()).unwrap();
+/// assert_eq!(db.calls(), [MockCall::GetRead]);
+///
+/// db.fail_next(MockCall::GetRead, 1);
+/// assert_eq!(client.get("/").dispatch().await.status().code, 503);
+/// # }
+///```
+#[derive(Debug, Clone, Default)]
+pub struct MockReadPool {
+    has_read: bool,
+    calls: Arc<Mutex<Vec<MockCall>>>,
+    failures: Arc<Mutex<HashMap<MockCall, usize>>>,
+}
+impl MockReadPool {
+    ///A pool with a read pool if `has_read`, without going through `Pool::init`
+    pub fn new(has_read: bool) -> Self {
+        MockReadPool{has_read, ..Default::default()}
+    }
+
+    ///The acquisitions made so far, in order
+    pub fn calls(&self) -> Vec<MockCall> {
+        lock(&self.calls).clone()
+    }
+
+    ///Forgets the acquisitions made so far
+    pub fn clear_calls(&self) {
+        lock(&self.calls).clear();
+    }
+
+    ///Makes the next `count` acquisitions of kind `call` fail with [`MockPoolError`]. They're
+    ///still recorded.
+    pub fn fail_next(&self, call: MockCall, count: usize) {
+        lock(&self.failures).insert(call, count);
+    }
+
+    fn acquire(&self, call: MockCall, role: PoolRole) -> Result<MockConnection, MockPoolError> {
+        lock(&self.calls).push(call);
+        match lock(&self.failures).get_mut(&call) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                Err(MockPoolError(call))
+            }
+            _ => Ok(MockConnection{role}),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Pool for MockReadPool {
+    type Connection = MockConnection;
+    type Error = MockPoolError;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        Ok(MockReadPool::new(figment.contains(keys::READ) || figment.contains(keys::READ_URLS)))
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        self.acquire(MockCall::Get, PoolRole::Main)
+    }
+
+    async fn close(&self) {}
+}
+
+impl ReadCapablePool for MockReadPool {
+    async fn get_read(&self) -> (PoolRole, Result<MockConnection, MockPoolError>) {
+        let role = if self.has_read {PoolRole::Read} else {PoolRole::Main};
+        (role, self.acquire(MockCall::GetRead, role))
+    }
+
+    async fn get_read_explained(&self) -> (PoolRole, Option<FallbackReason>, Result<MockConnection, MockPoolError>) {
+        let (role, result) = self.get_read().await;
+        (role, (!self.has_read).then_some(FallbackReason::NoReadPool), result)
+    }
+
+    async fn get_read_main(&self) -> Option<Result<MockConnection, MockPoolError>> {
+        Some(self.acquire(MockCall::GetReadMain, PoolRole::Main))
+    }
+}
@@ -0,0 +1,455 @@
+//!A mock pool standing in for a database driver's pool
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rocket::figment::{self, Figment};
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket_db_pools::Pool;
+use crate::{
+    keys, BoxError, CancelQuery, CancelToken, HealthProbe, PoolUsage, RefreshView, ReplicationPosition, ResetStrategy,
+    ResizePool, ServerProcess, SessionReset, SessionRole, SessionVariables, StatementTimeout, Transactional, TryAcquire,
+};
+use super::lock;
+
+///`max_connections` of a [`MockPool`] whose config doesn't set it
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+///An error of a [`MockPool`] or its connections
+#[derive(Debug)]
+pub enum MockError {
+    ///The pool's config is invalid, e.g. it has no `url`
+    Config(Box<figment::Error>),
+    ///The pool is down, see [`MockPool::set_down`], or the acquisition was made to fail with
+    ///[`MockPool::fail_next`]
+    Unavailable(String),
+    ///All of the pool's `max_connections` are in use
+    Exhausted(String),
+    ///A query fetched a value which wasn't given with [`MockPool::respond`], or couldn't be
+    ///parsed
+    NoResult(String),
+}
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MockError::Config(e) => write!(f, "invalid mock pool config: {}", e),
+            MockError::Unavailable(url) => write!(f, "`{}` is unavailable", url),
+            MockError::Exhausted(url) => write!(f, "all connections to `{}` are in use", url),
+            MockError::NoResult(sql) => write!(f, "no result for `{}`", sql),
+        }
+    }
+}
+impl std::error::Error for MockError {}
+impl From<figment::Error> for MockError {
+    fn from(e: figment::Error) -> Self {
+        MockError::Config(Box::new(e))
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    statements: Vec<String>,
+    results: HashMap<String, String>,
+    failing: usize,
+    down: bool,
+    in_use: u32,
+    max_connections: u32,
+}
+
+///A [`Pool`] which connects to nothing, standing in for a driver's pool in tests and examples,
+///e.g. as `ReadPool<MockPool>`.
+///
+///Its connections record the statements they run, which tests check with
+///[`MockPool::statements`], and queries fetch the values given with [`MockPool::respond`]. It
+///implements this crate's driver traits, such as [`HealthProbe`] and [`SessionVariables`], by
+///running the statements Postgres would, so the features using them can be tested too.
+///Acquisitions fail while the pool is down or more than `max_connections` are in use. Clones
+///share their state.
+///```rust
+/// # rocket::execute(async {
+/// use rocket::local::asynchronous::Client;
+/// use rocket_db_pools::Database;
+/// use rocket_read_db_pools::{ReadConnection, ReadPool};
+/// use rocket_read_db_pools::testing::{pair_rocket_figment, MockPool};
+///
+/// #[derive(Database)]
+/// #[database("db")]
+/// struct Db(ReadPool<MockPool>);
+///
+/// #[rocket::get("/")]
+/// async fn count(mut conn: ReadConnection<Db>) -> String {
+///     conn.query("SELECT count(*) FROM items").fetch_one::<i64>().await.unwrap().to_string()
+/// }
+///
+/// let rocket = rocket::custom(pair_rocket_figment("db", "mock://main", "mock://read"))
+///     .attach(Db::init())
+///     .mount("/", rocket::routes![count]);
+/// let client = Client::tracked(rocket).await.unwrap();
+/// let db = Db::fetch(client.rocket()).unwrap();
+/// let replica = db.replica("0").unwrap();
+/// replica.respond("SELECT count(*) FROM items", 3);
+/// assert_eq!(client.get("/").dispatch().await.into_string().await.as_deref(), Some("3"));
+/// assert_eq!(replica.statements(), ["SELECT count(*) FROM items"]);
+/// # });
+///```
+#[derive(Debug, Clone)]
+pub struct MockPool {
+    url: Arc<str>,
+    state: Arc<Mutex<MockState>>,
+}
+impl MockPool {
+    ///A pool for `url`, without going through `Pool::init`
+    pub fn new(url: &str) -> Self {
+        MockPool{
+            url: url.into(),
+            state: Arc::new(Mutex::new(MockState{max_connections: DEFAULT_MAX_CONNECTIONS, ..Default::default()})),
+        }
+    }
+
+    ///The `url` the pool was configured with
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    ///Has queries of `sql` fetch `value`. `sql` is matched exactly, either with its placeholders
+    ///replaced by the bound parameters, as in [`MockPool::statements`], or as written.
+    pub fn respond(&self, sql: &str, value: impl ToString) {
+        lock(&self.state).results.insert(sql.to_string(), value.to_string());
+    }
+
+    ///The statements run by the pool's connections so far, in order, with their placeholders
+    ///replaced by the bound parameters
+    pub fn statements(&self) -> Vec<String> {
+        lock(&self.state).statements.clone()
+    }
+
+    ///Forgets the statements run so far
+    pub fn clear_statements(&self) {
+        lock(&self.state).statements.clear();
+    }
+
+    ///Makes the next `count` acquisitions fail with [`MockError::Unavailable`]
+    pub fn fail_next(&self, count: usize) {
+        lock(&self.state).failing = count;
+    }
+
+    ///Makes acquisitions and statements fail with [`MockError::Unavailable`] until set back,
+    ///as while the database is down
+    pub fn set_down(&self, down: bool) {
+        lock(&self.state).down = down;
+    }
+
+    fn acquire(&self) -> Result<MockPoolConnection, MockError> {
+        let mut state = lock(&self.state);
+        if state.down || state.failing > 0 {
+            state.failing = state.failing.saturating_sub(1);
+            return Err(MockError::Unavailable(self.url.to_string()));
+        }
+        if state.in_use >= state.max_connections {
+            return Err(MockError::Exhausted(self.url.to_string()));
+        }
+        state.in_use += 1;
+        Ok(MockPoolConnection{pool: self.clone()})
+    }
+}
+
+#[rocket::async_trait]
+impl Pool for MockPool {
+    type Connection = MockPoolConnection;
+    type Error = MockError;
+
+    async fn init(figment: &Figment) -> Result<Self, Self::Error> {
+        let pool = MockPool::new(&figment.extract_inner::<String>(keys::URL)?);
+        lock(&pool.state).max_connections = figment.extract_inner(keys::MAX_CONNECTIONS).unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        Ok(pool)
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        self.acquire()
+    }
+
+    async fn close(&self) {}
+}
+impl TryAcquire for MockPool {
+    fn try_get(&self) -> Option<Self::Connection> {
+        self.acquire().ok()
+    }
+}
+impl PoolUsage for MockPool {
+    fn in_use(&self) -> u32 {
+        lock(&self.state).in_use
+    }
+
+    fn max_connections(&self) -> u32 {
+        lock(&self.state).max_connections
+    }
+}
+impl ResizePool for MockPool {
+    fn resize(&self, max_connections: u32) -> bool {
+        lock(&self.state).max_connections = max_connections;
+        true
+    }
+}
+
+///A connection from a [`MockPool`], returned to it when dropped
+#[derive(Debug)]
+pub struct MockPoolConnection {
+    pool: MockPool,
+}
+impl MockPoolConnection {
+    ///The `url` of the pool the connection came from
+    pub fn url(&self) -> &str {
+        self.pool.url()
+    }
+
+    ///A query of `sql`, with parameters bound to its `$1`, `$2`, ... placeholders as with
+    ///drivers' query builders
+    pub fn query(&mut self, sql: &str) -> MockQuery<'_> {
+        MockQuery{conn: self, sql: sql.to_string(), params: Vec::new()}
+    }
+
+    ///Records `sql` as run, with its placeholders replaced by `params`, and returns the value
+    ///given for it with [`MockPool::respond`], if any
+    fn run(&mut self, sql: &str, params: &[String]) -> Result<Option<String>, MockError> {
+        let mut state = lock(&self.pool.state);
+        if state.down {
+            return Err(MockError::Unavailable(self.pool.url.to_string()));
+        }
+        let statement = substitute(sql, params);
+        let result = state.results.get(&statement).or_else(|| state.results.get(sql)).cloned();
+        state.statements.push(statement);
+        Ok(result)
+    }
+}
+
+///`sql` with each `$n` placeholder replaced by the `n`th of `params`, if there are as many
+fn substitute(sql: &str, params: &[String]) -> String {
+    let mut statement = String::with_capacity(sql.len());
+    let mut rest = sql;
+    while let Some(at) = rest.find('$') {
+        statement.push_str(&rest[..at]);
+        let digits = rest[at + 1..].bytes().take_while(u8::is_ascii_digit).count();
+        let placeholder = &rest[at..at + 1 + digits];
+        match placeholder[1..].parse::<usize>().ok().and_then(|n| params.get(n.checked_sub(1)?)) {
+            Some(param) => statement.push_str(param),
+            None => statement.push_str(placeholder),
+        }
+        rest = &rest[at + 1 + digits..];
+    }
+    statement.push_str(rest);
+    statement
+}
+
+///A query being built by [`MockPoolConnection::query`]
+pub struct MockQuery<'c> {
+    conn: &'c mut MockPoolConnection,
+    sql: String,
+    params: Vec<String>,
+}
+impl<'c> MockQuery<'c> {
+    ///Binds the next placeholder to `value`, recorded in its `Debug` format, e.g. quoted for
+    ///strings
+    pub fn bind(mut self, value: impl fmt::Debug) -> Self {
+        self.params.push(format!("{:?}", value));
+        self
+    }
+
+    ///Runs the query, ignoring any result
+    pub async fn execute(self) -> Result<(), MockError> {
+        self.conn.run(&self.sql, &self.params).map(drop)
+    }
+
+    ///Runs the query and parses the value given for it, failing without one
+    pub async fn fetch_one<T: FromStr>(self) -> Result<T, MockError> {
+        let sql = self.sql.clone();
+        self.fetch_optional().await?.ok_or(MockError::NoResult(sql))
+    }
+
+    ///Runs the query and parses the value given for it, if any
+    pub async fn fetch_optional<T: FromStr>(self) -> Result<Option<T>, MockError> {
+        match self.conn.run(&self.sql, &self.params)? {
+            Some(value) => value.parse().map(Some).map_err(|_| MockError::NoResult(self.sql)),
+            None => Ok(None),
+        }
+    }
+
+    ///Runs the query and parses each line of the value given for it, or returns no rows
+    ///without one
+    pub async fn fetch_all<T: FromStr>(self) -> Result<Vec<T>, MockError> {
+        let value = self.conn.run(&self.sql, &self.params)?.unwrap_or_default();
+        value.lines().map(|line| line.parse().map_err(|_| MockError::NoResult(self.sql.clone()))).collect()
+    }
+
+    ///Runs the query once the stream is first polled, streaming its rows as
+    ///[`MockQuery::fetch_all`] returns them
+    pub fn fetch<T: FromStr + Send + 'c>(self) -> BoxStream<'c, Result<T, MockError>> {
+        Box::pin(stream::once(self.fetch_all()).flat_map(|rows| stream::iter(match rows {
+            Ok(rows) => rows.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        })))
+    }
+}
+impl Drop for MockPoolConnection {
+    fn drop(&mut self) {
+        let mut state = lock(&self.pool.state);
+        state.in_use = state.in_use.saturating_sub(1);
+    }
+}
+
+#[rocket::async_trait]
+impl HealthProbe for MockPoolConnection {
+    async fn probe(&mut self) -> Result<(), BoxError> {
+        Ok(self.query("SELECT 1").execute().await?)
+    }
+
+    async fn lag(&mut self) -> Result<Duration, BoxError> {
+        let seconds: f64 = self.query("SELECT EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp())").fetch_one().await?;
+        Ok(Duration::try_from_secs_f64(seconds)?)
+    }
+
+    async fn can_write(&mut self) -> Result<bool, BoxError> {
+        Ok(self.query("SELECT NOT pg_is_in_recovery()").fetch_one().await?)
+    }
+}
+#[rocket::async_trait]
+impl ReplicationPosition for MockPoolConnection {
+    async fn write_position(&mut self) -> Result<String, BoxError> {
+        Ok(self.query("SELECT pg_current_wal_lsn()").fetch_one().await?)
+    }
+
+    async fn has_replayed(&mut self, position: &str) -> Result<bool, BoxError> {
+        Ok(self.query("SELECT pg_last_wal_replay_lsn() >= $1").bind(position).fetch_one().await?)
+    }
+}
+#[rocket::async_trait]
+impl ServerProcess for MockPoolConnection {
+    async fn server_pid(&mut self) -> Result<u64, BoxError> {
+        Ok(self.query("SELECT pg_backend_pid()").fetch_one().await?)
+    }
+}
+impl CancelQuery for MockPoolConnection {
+    fn cancel_token(&self) -> Option<CancelToken> {
+        let pool = self.pool.clone();
+        Some(CancelToken::new(move || async move {
+            lock(&pool.state).statements.push("SELECT pg_cancel_backend(pg_backend_pid())".to_string());
+            Ok(())
+        }))
+    }
+}
+#[rocket::async_trait]
+impl StatementTimeout for MockPoolConnection {
+    async fn set_statement_timeout(&mut self, timeout: Duration) -> Result<(), BoxError> {
+        Ok(self.query(&format!("SET statement_timeout = {}", timeout.as_millis())).execute().await?)
+    }
+}
+#[rocket::async_trait]
+impl SessionVariables for MockPoolConnection {
+    async fn set_variable(&mut self, name: &str, value: &str) -> Result<(), BoxError> {
+        Ok(self.query("SELECT set_config($1, $2, false)").bind(name).bind(value).execute().await?)
+    }
+}
+#[rocket::async_trait]
+impl SessionRole for MockPoolConnection {
+    async fn set_role(&mut self, role: &str) -> Result<(), BoxError> {
+        Ok(self.query(&format!("SET ROLE {}", role)).execute().await?)
+    }
+
+    async fn reset_role(&mut self) -> Result<(), BoxError> {
+        Ok(self.query("RESET ROLE").execute().await?)
+    }
+}
+#[rocket::async_trait]
+impl SessionReset for MockPoolConnection {
+    async fn reset_session(&mut self, strategy: ResetStrategy) -> Result<(), BoxError> {
+        match strategy {
+            ResetStrategy::DiscardAll => Ok(self.query("DISCARD ALL").execute().await?),
+            ResetStrategy::Reset => Ok(self.query("RESET ALL").execute().await?),
+            ResetStrategy::None => Ok(()),
+        }
+    }
+}
+#[rocket::async_trait]
+impl Transactional for MockPoolConnection {
+    async fn begin(&mut self, read_only: bool) -> Result<(), BoxError> {
+        Ok(self.query(if read_only {"BEGIN READ ONLY"} else {"BEGIN"}).execute().await?)
+    }
+
+    async fn commit(&mut self) -> Result<(), BoxError> {
+        Ok(self.query("COMMIT").execute().await?)
+    }
+
+    async fn rollback(&mut self) -> Result<(), BoxError> {
+        Ok(self.query("ROLLBACK").execute().await?)
+    }
+}
+#[rocket::async_trait]
+impl RefreshView for MockPoolConnection {
+    async fn refresh_view(&mut self, view: &str) -> Result<(), BoxError> {
+        Ok(self.query(&format!("REFRESH MATERIALIZED VIEW {}", view)).execute().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::providers::Serialized;
+    use super::*;
+
+    #[rocket::async_test]
+    async fn records_statements_with_parameters() {
+        let pool = MockPool::new("mock://db");
+        let mut conn = pool.get().await.unwrap();
+        conn.query("UPDATE t SET a = $1 WHERE b = $2 OR c = $10").bind("x").bind(2).execute().await.unwrap();
+        assert_eq!(pool.statements(), ["UPDATE t SET a = \"x\" WHERE b = 2 OR c = $10"]);
+        pool.clear_statements();
+        assert!(pool.statements().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn fetches_responses() {
+        let pool = MockPool::new("mock://db");
+        pool.respond("SELECT name FROM t", "a\nb");
+        pool.respond("SELECT n FROM t WHERE id = 1", 5);
+        let mut conn = pool.get().await.unwrap();
+        assert_eq!(conn.query("SELECT name FROM t").fetch_all::<String>().await.unwrap(), ["a", "b"]);
+        assert_eq!(conn.query("SELECT n FROM t WHERE id = $1").bind(1).fetch_one::<u32>().await.unwrap(), 5);
+        assert_eq!(conn.query("SELECT n FROM t WHERE id = $1").bind(2).fetch_optional::<u32>().await.unwrap(), None);
+        assert!(matches!(conn.query("SELECT n FROM t WHERE id = $1").bind(2).fetch_one::<u32>().await, Err(MockError::NoResult(_))));
+        assert!(matches!(conn.query("SELECT name FROM t").fetch_one::<u32>().await, Err(MockError::NoResult(_))));
+        let streamed: Vec<_> = conn.query("SELECT name FROM t").fetch::<String>().collect().await;
+        assert_eq!(streamed.into_iter().collect::<Result<Vec<_>, _>>().unwrap(), ["a", "b"]);
+    }
+
+    #[rocket::async_test]
+    async fn fails_when_down_or_exhausted() {
+        let figment = Figment::new()
+            .merge(Serialized::default(keys::URL, "mock://db"))
+            .merge(Serialized::default(keys::MAX_CONNECTIONS, 1));
+        let pool = MockPool::init(&figment).await.unwrap();
+        pool.fail_next(1);
+        assert!(matches!(pool.get().await, Err(MockError::Unavailable(_))));
+        let mut conn = pool.get().await.unwrap();
+        assert_eq!(pool.in_use(), 1);
+        assert!(matches!(pool.get().await, Err(MockError::Exhausted(_))));
+        assert!(pool.try_get().is_none());
+        pool.set_down(true);
+        assert!(conn.query("SELECT 1").execute().await.is_err());
+        drop(conn);
+        assert_eq!(pool.in_use(), 0);
+        assert!(matches!(pool.get().await, Err(MockError::Unavailable(_))));
+        pool.set_down(false);
+        assert!(pool.resize(2));
+        let _both = (pool.get().await.unwrap(), pool.get().await.unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn implements_driver_traits_with_postgres_statements() {
+        let pool = MockPool::new("mock://db");
+        pool.respond("SELECT NOT pg_is_in_recovery()", false);
+        let mut conn = pool.get().await.unwrap();
+        conn.probe().await.unwrap();
+        assert!(!conn.can_write().await.unwrap());
+        assert!(conn.lag().await.is_err());
+        assert_eq!(pool.statements()[..2], ["SELECT 1", "SELECT NOT pg_is_in_recovery()"]);
+    }
+}
@@ -0,0 +1,212 @@
+//!Randomized checks of routing policies and replica balancers
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io;
+use rocket::config::LogLevel;
+use rocket::figment::Figment;
+use rocket::local::blocking::Client;
+use rocket_db_pools::Pool;
+use crate::balance::pick_admitted;
+use crate::{PoolUsage, Random, ReadBalancer, RoutingContext, RoutingFlags, RoutingPolicies};
+
+///A read replica in a [`StrategyCheck`] scenario, standing in for the pool type a
+///[`ReadBalancer`] picks between. It reports the scenario's load through [`PoolUsage`], but
+///can't be created from config or hand out connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioReplica {
+    healthy: bool,
+    in_use: u32,
+    max_connections: u32,
+}
+impl ScenarioReplica {
+    ///Whether health checks let reads use the replica
+    pub fn healthy(&self) -> bool {
+        self.healthy
+    }
+}
+#[rocket::async_trait]
+impl Pool for ScenarioReplica {
+    type Connection = ();
+    type Error = io::Error;
+
+    async fn init(_figment: &Figment) -> Result<Self, Self::Error> {
+        Err(io::Error::other("scenario replicas are only created by `StrategyCheck`"))
+    }
+
+    async fn get(&self) -> Result<Self::Connection, Self::Error> {
+        Err(io::Error::other("scenario replicas can't connect"))
+    }
+
+    async fn close(&self) {}
+}
+impl PoolUsage for ScenarioReplica {
+    fn in_use(&self) -> u32 {
+        self.in_use
+    }
+
+    fn max_connections(&self) -> u32 {
+        self.max_connections
+    }
+}
+
+///Totals of a passing [`StrategyCheck`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrategyReport {
+    pub scenarios: usize,
+    pub reads: u64,
+    ///Reads which the policies, or a lack of healthy replicas, sent to the main pool
+    pub main_reads: u64,
+}
+
+///Runs routing policies and a replica balancer through randomized scenarios, panicking if they
+///break the invariants every strategy must keep:
+///- no read is served by an unhealthy replica
+///- no healthy replica is starved: each serves at least `min_fair_share` (by default 0.1) of
+///  its share of the reads sent to the replicas, in proportion to its weight
+///
+///Each scenario has between 1 and `max_replicas` replicas of random health, weight and load,
+///and routes `reads` reads of a request for one of `paths` as [`ReadConnection`](crate::ReadConnection)
+///does: through the policies, then the balancer, skipping unhealthy replicas. Each read takes
+///a connection from the replica serving it, and one connection of a random replica is
+///released before each read, so load-aware balancers see load change.
+///
+///The scenarios are random, but fixed by the seed, which failures report so they can be
+///reproduced with [`StrategyCheck::seed`]. Balancers generic over the pool type can be checked
+///as they are; those for a particular pool type must be implemented for [`ScenarioReplica`] too.
+///
+///The requests are made with a blocking local client, so run it from a plain `#[test]`:
+///```rust
+/// use rocket_read_db_pools::{LeastConnections, RoutingPolicies};
+/// use rocket_read_db_pools::testing::StrategyCheck;
+///
+/// let report = StrategyCheck::new().scenarios(20).run(RoutingPolicies::new(), LeastConnections);
+/// assert_eq!(report.scenarios, 20);
+///```
+#[derive(Debug, Clone)]
+pub struct StrategyCheck {
+    seed: u64,
+    scenarios: usize,
+    reads: usize,
+    max_replicas: usize,
+    min_fair_share: f64,
+    paths: Vec<String>,
+}
+impl StrategyCheck {
+    ///100 scenarios of 1000 reads of `/` with up to 6 replicas, from a random seed
+    pub fn new() -> Self {
+        StrategyCheck{
+            seed: RandomState::new().hash_one(0u64),
+            scenarios: 100,
+            reads: 1000,
+            max_replicas: 6,
+            min_fair_share: 0.1,
+            paths: vec!["/".to_string()],
+        }
+    }
+
+    ///Fixes the scenarios, e.g. to reproduce a failure
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn scenarios(mut self, scenarios: usize) -> Self {
+        self.scenarios = scenarios;
+        self
+    }
+
+    ///Reads routed in each scenario
+    pub fn reads(mut self, reads: usize) -> Self {
+        self.reads = reads;
+        self
+    }
+
+    pub fn max_replicas(mut self, max_replicas: usize) -> Self {
+        self.max_replicas = max_replicas.max(1);
+        self
+    }
+
+    ///Least share of its fair share of reads each healthy replica must serve, or 0 to allow
+    ///balancers which deliberately starve replicas, such as [`Preferred`](crate::Preferred)
+    pub fn min_fair_share(mut self, min_fair_share: f64) -> Self {
+        self.min_fair_share = min_fair_share;
+        self
+    }
+
+    ///Paths of the requests reading, chosen at random for each scenario
+    pub fn paths<I: IntoIterator<Item = S>, S: Into<String>>(mut self, paths: I) -> Self {
+        self.paths = paths.into_iter().map(Into::into).collect();
+        if self.paths.is_empty() {
+            self.paths.push("/".to_string());
+        }
+        self
+    }
+
+    ///Runs the scenarios with `policies` managed and `balancer` choosing replicas
+    pub fn run<B: ReadBalancer<ScenarioReplica>>(&self, policies: RoutingPolicies, balancer: B) -> StrategyReport {
+        let config = rocket::Config{log_level: LogLevel::Off, ..rocket::Config::debug_default()};
+        let client = Client::untracked(rocket::custom(config).manage(policies)).expect("failed to build a local client");
+        let random = Random::seeded(self.seed);
+        let mut report = StrategyReport{scenarios: self.scenarios, reads: 0, main_reads: 0};
+        for scenario in 0..self.scenarios {
+            let below = |n: usize| random.next() as usize % n;
+            let mut replicas: Vec<ScenarioReplica> = (0..1 + below(self.max_replicas)).map(|_| {
+                let max_connections = 1 + below(20) as u32;
+                ScenarioReplica{healthy: below(4) != 0, in_use: below(max_connections as usize + 1) as u32, max_connections}
+            }).collect();
+            let weights: Vec<u32> = replicas.iter().map(|_| 1 + below(4) as u32).collect();
+            let request = client.get(self.paths[below(self.paths.len())].as_str());
+            let cx = RoutingContext{
+                database: "check_strategy",
+                flags: RoutingFlags::default(),
+                replicas: Some(replicas.len()),
+                available_replicas: Some(replicas.iter().filter(|r| r.healthy).count()),
+                pin_after_write: false,
+            };
+            let mut served = vec![0u64; replicas.len()];
+            for _ in 0..self.reads {
+                let released = below(replicas.len());
+                replicas[released].in_use = replicas[released].in_use.saturating_sub(1);
+                report.reads += 1;
+                let picked = match RoutingPolicies::route(request.inner(), &cx) {
+                    Some(_) => None,
+                    None => pick_admitted(&balancer, &replicas, &weights, |i| replicas[i].healthy),
+                };
+                let Some(i) = picked else {
+                    report.main_reads += 1;
+                    continue;
+                };
+                if !replicas[i].healthy {
+                    self.fail(scenario, &replicas, &weights, format!("a read was served by unhealthy replica {}", i));
+                }
+                served[i] += 1;
+                replicas[i].in_use = (replicas[i].in_use + 1).min(replicas[i].max_connections);
+            }
+            let to_replicas: u64 = served.iter().sum();
+            let healthy_weight: u64 = replicas.iter().zip(&weights).filter(|(r, _)| r.healthy).map(|(_, &w)| w as u64).sum();
+            for i in (0..replicas.len()).filter(|&i| replicas[i].healthy) {
+                let fair = to_replicas as f64 * weights[i] as f64 / healthy_weight as f64;
+                if (served[i] as f64) < self.min_fair_share * fair {
+                    self.fail(scenario, &replicas, &weights, format!("healthy replica {} served {} of {} reads, under {} of its fair share of {:.0}",
+                        i, served[i], to_replicas, self.min_fair_share, fair));
+                }
+            }
+        }
+        report
+    }
+
+    fn fail(&self, scenario: usize, replicas: &[ScenarioReplica], weights: &[u32], problem: String) -> ! {
+        panic!("routing strategy check failed in scenario {} of seed {}: {}\nreplicas: {:?}\nweights: {:?}", scenario, self.seed, problem, replicas, weights)
+    }
+}
+impl Default for StrategyCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///Checks `policies` and `balancer` with the default [`StrategyCheck`], panicking if they break
+///its invariants
+pub fn check_strategy<B: ReadBalancer<ScenarioReplica>>(policies: RoutingPolicies, balancer: B) -> StrategyReport {
+    StrategyCheck::new().run(policies, balancer)
+}
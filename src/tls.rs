@@ -0,0 +1,113 @@
+//!Per-pool TLS settings, translated into the driver's url parameters
+use rocket::figment::Figment;
+use rocket::figment::providers::Serialized;
+use rocket::serde::{Deserialize, Serialize};
+
+///How a pool verifies the server's certificate, from the `verify` option of its `tls` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum TlsVerify {
+    ///Require TLS, and verify the certificate and that it names the server's host
+    #[default]
+    Full,
+    ///Require TLS, and verify the certificate but not the host it names
+    Ca,
+    ///Require TLS without verifying the certificate
+    None,
+}
+
+///TLS settings of one pool: the `tls` table of the main pool, `read`, a `read.replicas` entry
+///or `read.delayed`.
+///
+///The settings are translated into parameters of the pool's `url`: `sslmode`, `sslrootcert`,
+///`sslcert` and `sslkey` for `postgres://` and `postgresql://` urls, and `ssl-mode`, `ssl-ca`,
+///`ssl-cert` and `ssl-key` for `mysql://` and `mariadb://` urls. They're ignored with a warning
+///for other schemes. Replicas inherit the `read` table's settings, so only those in a different
+///network segment need their own:
+///```toml
+///[default.databases.main.read]
+///tls = {ca_cert = "/etc/ssl/db/internal-ca.pem"}
+///
+///[default.databases.main.read.replicas.dr]
+///url = "postgresql://user@replica.dr.example/dbname"
+///tls = {ca_cert = "/etc/ssl/db/dr-ca.pem", client_cert = "/etc/ssl/db/dr.crt", client_key = "/etc/ssl/db/dr.key"}
+///```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TlsConfig {
+    ///`ca_cert`: path of the certificate authority the server's certificate is verified against.
+    ///Defaults to the driver's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<String>,
+    ///`client_cert`: path of the certificate presented to the server, if it requires one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    ///`client_key`: path of the key of `client_cert`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    ///`verify`: `"full"`, `"ca"` or `"none"`. Defaults to `"full"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<TlsVerify>,
+}
+impl TlsConfig {
+    ///The url parameters for `scheme`, if it's a known one
+    fn params(&self, scheme: &str) -> Option<Vec<(&'static str, String)>> {
+        let verify = self.verify.unwrap_or_default();
+        let (names, mode) = match scheme {
+            "postgres" | "postgresql" => (["sslrootcert", "sslcert", "sslkey", "sslmode"], match verify {
+                TlsVerify::Full => "verify-full",
+                TlsVerify::Ca => "verify-ca",
+                TlsVerify::None => "require",
+            }),
+            "mysql" | "mariadb" => (["ssl-ca", "ssl-cert", "ssl-key", "ssl-mode"], match verify {
+                TlsVerify::Full => "VERIFY_IDENTITY",
+                TlsVerify::Ca => "VERIFY_CA",
+                TlsVerify::None => "REQUIRED",
+            }),
+            _ => return None,
+        };
+        let paths = [&self.ca_cert, &self.client_cert, &self.client_key];
+        let mut params: Vec<_> = names.into_iter().zip(paths)
+            .filter_map(|(name, path)| Some((name, path.clone()?)))
+            .collect();
+        params.push((names[3], mode.to_string()));
+        Some(params)
+    }
+}
+
+///Applies the `tls` option of a pool's figment, appending its parameters to the pool's `url`
+pub(crate) fn apply(figment: Figment) -> Figment {
+    let Ok(tls) = figment.extract_inner::<TlsConfig>("tls") else {return figment};
+    let Ok(url) = figment.extract_inner::<String>("url") else {return figment};
+    let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+    let Some(params) = tls.params(scheme) else {
+        rocket::warn!("`tls` ignored: unknown url scheme `{}`", scheme);
+        return figment;
+    };
+    with_params(figment, &url, params)
+}
+
+///Appends `params` to `url`, the `url` of `figment`
+pub(crate) fn with_params(figment: Figment, url: &str, params: Vec<(&str, String)>) -> Figment {
+    let (base, fragment) = url.split_once('#').map_or((url, None), |(base, fragment)| (base, Some(fragment)));
+    let mut url = base.to_string();
+    for (name, value) in params {
+        url.push(if url.contains('?') {'&'} else {'?'});
+        url += name;
+        url.push('=');
+        url += &encode(&value);
+    }
+    if let Some(fragment) = fragment {
+        url.push('#');
+        url += fragment;
+    }
+    figment.merge(Serialized::global("url", url))
+}
+
+///Percent-encodes the characters of `value` which would end a url parameter
+fn encode(value: &str) -> String {
+    value.chars().map(|c| match c {
+        '%' | '&' | '#' | '+' | '=' | '?' | ' ' => format!("%{:02X}", c as u32),
+        c => c.to_string(),
+    }).collect()
+}
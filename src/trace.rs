@@ -0,0 +1,66 @@
+//!`tracing` spans around connection acquisition, with the `tracing` feature
+use std::future::Future;
+use std::time::Instant;
+use crate::{FallbackReason, PoolRole};
+
+///A span covering one connection acquisition, inert without the `tracing` feature.
+///
+///Its `pool` and `label` fields name the pool which served the connection, `latency_us` the
+///time taken and `fallback` the reason a read was served by the main pool, if it was.
+pub(crate) struct Checkout {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+impl Checkout {
+    ///A span for the acquisition `op` of the database or pool named `database`
+    #[cfg_attr(not(feature = "tracing"), inline(always))]
+    pub fn new(op: &'static str, database: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        return Checkout{span: tracing::info_span!(
+            "read_db_pool.checkout",
+            op,
+            database,
+            pool = tracing::field::Empty,
+            label = tracing::field::Empty,
+            latency_us = tracing::field::Empty,
+            fallback = tracing::field::Empty,
+        )};
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = (op, database);
+            Checkout{}
+        }
+    }
+
+    ///Runs `acquire` within the span
+    pub async fn run<F: Future>(&self, acquire: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(acquire, self.span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        acquire.await
+    }
+
+    ///Records which pool served the acquisition begun at `start`, and whether it fell back
+    #[cfg_attr(not(feature = "tracing"), inline(always))]
+    pub fn served(&self, role: PoolRole, label: Option<&str>, start: Instant, ok: bool, fallback: Option<FallbackReason>) {
+        #[cfg(feature = "tracing")]
+        {
+            let latency_us = start.elapsed().as_micros() as u64;
+            self.span.record("pool", tracing::field::debug(role));
+            if let Some(label) = label {
+                self.span.record("label", label);
+            }
+            self.span.record("latency_us", latency_us);
+            if let Some(reason) = fallback {
+                self.span.record("fallback", reason.name());
+                tracing::info!(parent: &self.span, reason = reason.name(), "read served by the main pool");
+            }
+            match ok {
+                true => tracing::debug!(parent: &self.span, pool = ?role, label, latency_us, "connection acquired"),
+                false => tracing::warn!(parent: &self.span, pool = ?role, label, latency_us, "connection acquisition failed"),
+            }
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = (role, label, start, ok, fallback);
+    }
+}
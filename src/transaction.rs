@@ -0,0 +1,291 @@
+//!Request guards holding a transaction open for the handler
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use rocket::{Ignite, Rocket, Sentinel};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::http::Status;
+use rocket::tokio::runtime::Handle;
+use rocket_db_pools::{Database, Pool};
+use crate::hooks::Release;
+use crate::{BoxError, ReadCapablePool, ReadConnection, ReadPoolError, RwConnection};
+
+///Begins and ends transactions on a connection, for [`ReadTransaction`] and [`RwTransaction`].
+///
+///Implement it for your connection type, e.g. with `BEGIN READ ONLY`, `BEGIN`, `COMMIT` and
+///`ROLLBACK` on Postgres.
+#[rocket::async_trait]
+pub trait Transactional: Send {
+    ///Begins a transaction, which may only read if `read_only`
+    async fn begin(&mut self, read_only: bool) -> Result<(), BoxError>;
+    ///Commits the open transaction
+    async fn commit(&mut self) -> Result<(), BoxError>;
+    ///Rolls back the open transaction
+    async fn rollback(&mut self) -> Result<(), BoxError>;
+}
+
+///Error of the [`ReadTransaction`] and [`RwTransaction`] guards
+#[derive(Debug)]
+pub enum TransactionError<E> {
+    ///The connection guard failed
+    Guard(E),
+    ///Beginning the transaction failed
+    Begin(BoxError),
+}
+impl<E: fmt::Display> fmt::Display for TransactionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Guard(e) => e.fmt(f),
+            TransactionError::Begin(e) => write!(f, "failed to begin transaction: {}", e),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display> std::error::Error for TransactionError<E> {}
+
+///A connection with an open transaction, rolled back in the background if dropped before it's
+///committed or rolled back, along with the release ticket of the guard it was acquired by, so
+///the connection's release is observed once the transaction ends
+struct Transaction<C: Transactional + 'static>(Option<C>, Option<Release>);
+impl<C: Transactional + 'static> Transaction<C> {
+    async fn begin<E>(mut conn: C, read_only: bool, release: Option<Release>) -> Result<Self, TransactionError<E>> {
+        conn.begin(read_only).await.map_err(TransactionError::Begin)?;
+        Ok(Transaction(Some(conn), release))
+    }
+
+    ///Commits the transaction, rolling it back if that fails so the connection doesn't return
+    ///to the pool with it open
+    async fn commit(mut self) -> Result<(), BoxError> {
+        let Some(mut conn) = self.0.take() else {return Ok(())};
+        if let Err(e) = conn.commit().await {
+            if let Err(rollback) = conn.rollback().await {
+                rocket::warn!("failed to roll back transaction after its commit failed: {}", rollback);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn rollback(mut self) -> Result<(), BoxError> {
+        match self.0.take() {
+            Some(mut conn) => conn.rollback().await,
+            None => Ok(()),
+        }
+    }
+}
+impl<C: Transactional + 'static> Drop for Transaction<C> {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.0.take() else {return};
+        let release = self.1.take();
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = conn.rollback().await {
+                        rocket::warn!("failed to roll back dropped transaction: {}", e);
+                    }
+                    drop(conn);
+                    drop(release);
+                });
+            }
+            Err(_) => rocket::warn!("transaction dropped outside the runtime, leaving it to the pool to end"),
+        }
+    }
+}
+impl<C: Transactional + 'static> Deref for Transaction<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.0.as_ref().expect("transaction used after it ended")
+    }
+}
+impl<C: Transactional + 'static> DerefMut for Transaction<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.0.as_mut().expect("transaction used after it ended")
+    }
+}
+
+///A request guard which gets a connection as [`ReadConnection`] does and begins a read-only
+///transaction on it, so the handler's reads see one snapshot.
+///
+///End it with [`ReadTransaction::commit`]. If it's dropped first, including when the handler
+///returns early with an error, it's rolled back in the background. The connection's type must
+///implement [`Transactional`].
+pub struct ReadTransaction<D: Database>(Transaction<<D::Pool as Pool>::Connection>, PhantomData<fn() -> D>)
+    where <D::Pool as Pool>::Connection: Transactional;
+impl<D: Database> ReadTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    ///Commits the transaction, returning the connection to the pool. If the commit fails, the
+    ///transaction is rolled back first.
+    pub async fn commit(self) -> Result<(), BoxError> {
+        self.0.commit().await
+    }
+
+    ///Rolls back the transaction, returning the connection to the pool
+    pub async fn rollback(self) -> Result<(), BoxError> {
+        self.0.rollback().await
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for ReadTransaction<D>
+    where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Transactional
+{
+    type Error = TransactionError<ReadPoolError<<D::Pool as Pool>::Error>>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (conn, release) = match ReadConnection::<D>::from_request(req).await {
+            Outcome::Success(conn) => conn.into_parts(),
+            Outcome::Error((status, e)) => return Outcome::Error((status, TransactionError::Guard(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        match Transaction::begin(conn, true, release).await {
+            Ok(transaction) => Outcome::Success(ReadTransaction(transaction, PhantomData)),
+            Err(e) => Outcome::Error((Status::ServiceUnavailable, e)),
+        }
+    }
+}
+impl<D: Database> Sentinel for ReadTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for ReadTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<D: Database> DerefMut for ReadTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+///A request guard which gets a connection as [`RwConnection`] does and begins a transaction on
+///it.
+///
+///End it with [`RwTransaction::commit`]. If it's dropped first, including when the handler
+///returns early with an error, it's rolled back in the background, so none of its writes are
+///kept. The connection's type must implement [`Transactional`].
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{BoxError, ReadPool};
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket::response::Debug;
+/// use rocket_read_db_pools::RwTransaction;
+///
+/// #[rocket::post("/transfer/<from>/<to>/<amount>")]
+/// async fn transfer(mut tx: RwTransaction<Db>, from: i64, to: i64, amount: i64) -> Result<(), Debug<BoxError>> {
+///     tx.query("UPDATE accounts SET balance = balance - $1 WHERE id = $2")
+///         .bind(amount).bind(from).execute().await.map_err(BoxError::from)?;
+///     tx.query("UPDATE accounts SET balance = balance + $1 WHERE id = $2")
+///         .bind(amount).bind(to).execute().await.map_err(BoxError::from)?;
+///     Ok(tx.commit().await?)
+/// }
+///```
+pub struct RwTransaction<D: Database>(Transaction<<D::Pool as Pool>::Connection>, PhantomData<fn() -> D>)
+    where <D::Pool as Pool>::Connection: Transactional;
+impl<D: Database> RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    ///Commits the transaction, returning the connection to the pool. If the commit fails, the
+    ///transaction is rolled back first.
+    pub async fn commit(self) -> Result<(), BoxError> {
+        self.0.commit().await
+    }
+
+    ///Rolls back the transaction, returning the connection to the pool
+    pub async fn rollback(self) -> Result<(), BoxError> {
+        self.0.rollback().await
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    type Error = TransactionError<ReadPoolError<<D::Pool as Pool>::Error>>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (conn, release) = match RwConnection::<D>::from_request(req).await {
+            Outcome::Success(conn) => conn.into_parts(),
+            Outcome::Error((status, e)) => return Outcome::Error((status, TransactionError::Guard(e))),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        match Transaction::begin(conn, false, release).await {
+            Ok(transaction) => Outcome::Success(RwTransaction(transaction, PhantomData)),
+            Err(e) => Outcome::Error((Status::ServiceUnavailable, e)),
+        }
+    }
+}
+impl<D: Database> Sentinel for RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<D: Database> DerefMut for RwTransaction<D> where <D::Pool as Pool>::Connection: Transactional {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::sync::mpsc;
+    use rocket::tokio::time::timeout;
+    use crate::testing::{pair_rocket_figment, MockPool};
+    use crate::ReadPool;
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    ///A connection recording its statements, whose commits fail
+    struct Uncommittable(Arc<Mutex<Vec<&'static str>>>);
+    #[rocket::async_trait]
+    impl Transactional for Uncommittable {
+        async fn begin(&mut self, _read_only: bool) -> Result<(), BoxError> {
+            self.0.lock().unwrap().push("BEGIN");
+            Ok(())
+        }
+
+        async fn commit(&mut self) -> Result<(), BoxError> {
+            self.0.lock().unwrap().push("COMMIT");
+            Err("serialization failure".into())
+        }
+
+        async fn rollback(&mut self) -> Result<(), BoxError> {
+            self.0.lock().unwrap().push("ROLLBACK");
+            Ok(())
+        }
+    }
+
+    #[rocket::async_test]
+    async fn a_failed_commit_rolls_back() {
+        let statements = Arc::new(Mutex::new(Vec::new()));
+        let transaction = Transaction::begin::<()>(Uncommittable(statements.clone()), false, None).await.unwrap();
+        assert!(transaction.commit().await.is_err());
+        assert_eq!(*statements.lock().unwrap(), ["BEGIN", "COMMIT", "ROLLBACK"]);
+    }
+
+    #[rocket::async_test]
+    async fn the_connection_is_released_when_the_transaction_ends() {
+        let rocket = rocket::custom(pair_rocket_figment("db", "main", "read")).attach(Db::init());
+        let client = Client::tracked(rocket).await.unwrap();
+        let (send, mut released) = mpsc::unbounded_channel();
+        Db::fetch(client.rocket()).unwrap().on_release(move |event| {
+            let _ = send.send(event.label);
+            Box::pin(async {})
+        });
+        let (conn, release) = ReadConnection::<Db>::from_rocket(client.rocket()).await.unwrap().into_parts();
+        let transaction = Transaction::begin::<()>(conn, true, release).await.unwrap();
+        assert!(timeout(Duration::from_millis(20), released.recv()).await.is_err());
+        transaction.commit().await.unwrap();
+        assert!(timeout(Duration::from_secs(1), released.recv()).await.unwrap().is_some());
+    }
+}
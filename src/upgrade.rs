@@ -0,0 +1,131 @@
+//!Request guard starting on a read connection which can be upgraded to the main pool
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use rocket::{Ignite, Orbit, Rocket, Sentinel};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::{Database, Pool};
+use crate::hooks::Release;
+use crate::{ReadCapablePool, ReadConnection, ReadPoolError, RequestRouting, RwConnection};
+
+/// A request guard which retrieves a read connection as [`ReadConnection`] does, and can swap
+/// it for a main pool connection with [`ReadOrRw::upgrade`] once the handler finds it needs to
+/// write, without acquiring both up front.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::{BoxError, ReadPool};
+/// use rocket::response::Debug;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use rocket_read_db_pools::ReadOrRw;
+///
+/// #[rocket::post("/items/<id>/touch")]
+/// async fn touch(mut conn: ReadOrRw<'_, Db>, id: i64) -> Result<(), Debug<BoxError>> {
+///     let stale: bool = conn.query("SELECT updated_at < now() - interval '1 day' FROM items WHERE id = $1")
+///         .bind(id).fetch_one().await.map_err(BoxError::from)?;
+///     if stale {
+///         conn.upgrade().await.map_err(BoxError::from)?;
+///         conn.query("UPDATE items SET updated_at = now() WHERE id = $1").bind(id).execute().await.map_err(BoxError::from)?;
+///     }
+///     Ok(())
+/// }
+///```
+pub struct ReadOrRw<'r, D: Database> {
+    conn: <D::Pool as Pool>::Connection,
+    release: Option<Release>,
+    upgraded: bool,
+    rocket: &'r Rocket<Orbit>,
+    routing: &'r RequestRouting,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D: Database> ReadOrRw<'_, D> {
+    ///Swaps the read connection for a main pool connection, as
+    ///[`ReadConnection::upgrade`] does. Does nothing if already upgraded.
+    ///
+    ///The read connection is only given up once the main pool connection is acquired, so if
+    ///that fails the guard keeps it.
+    pub async fn upgrade(&mut self) -> Result<(), ReadPoolError<<D::Pool as Pool>::Error>> {
+        if self.upgraded {
+            return Ok(());
+        }
+        let conn = RwConnection::<D>::acquire(self.rocket, self.routing).await.map_err(|(_, e)| e)?;
+        (self.conn, self.release) = conn.into_parts();
+        self.upgraded = true;
+        Ok(())
+    }
+
+    ///Whether the connection is from the main pool after [`ReadOrRw::upgrade`]. A read may
+    ///also have been served by the main pool without it.
+    pub fn is_upgraded(&self) -> bool {
+        self.upgraded
+    }
+
+    ///Gets the internal connection value
+    pub fn into_inner(self) -> <D::Pool as Pool>::Connection {
+        drop(self.release);
+        self.conn
+    }
+}
+#[rocket::async_trait]
+impl<'r, D: Database> FromRequest<'r> for ReadOrRw<'r, D> where D::Pool: ReadCapablePool, <D::Pool as Pool>::Connection: Send {
+    type Error = ReadPoolError<<D::Pool as Pool>::Error>;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        ReadConnection::<D>::from_request(req).await.map(|conn| {
+            let (conn, release) = conn.into_parts();
+            ReadOrRw{
+                conn,
+                release,
+                upgraded: false,
+                rocket: req.rocket(),
+                routing: RequestRouting::routed(req),
+                _db: PhantomData,
+            }
+        })
+    }
+}
+impl<D: Database> Sentinel for ReadOrRw<'_, D> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        D::fetch(rocket).is_none()
+    }
+}
+impl<D: Database> Deref for ReadOrRw<'_, D> {
+    type Target = <D::Pool as Pool>::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl<D: Database> DerefMut for ReadOrRw<'_, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::asynchronous::Client;
+    use crate::testing::{pair_rocket_figment, FailureInjector, MockPool};
+    use crate::{PoolRole, ReadPool};
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    #[rocket::async_test]
+    async fn a_failed_upgrade_keeps_the_read_connection() {
+        let rocket = rocket::custom(pair_rocket_figment("db", "main", "read")).attach(Db::init()).manage(FailureInjector::default());
+        let client = Client::tracked(rocket).await.unwrap();
+        let req = client.get("/");
+        let db = Db::fetch(client.rocket()).unwrap();
+        let read = ReadCapablePool::get_read(&**db).await.1.unwrap();
+        let mut conn = ReadOrRw::<Db>{conn: read, release: None, upgraded: false, rocket: client.rocket(), routing: RequestRouting::routed(&req), _db: PhantomData};
+        client.rocket().state::<FailureInjector>().unwrap().fail_next::<Db>(PoolRole::Main, 1);
+        assert!(matches!(conn.upgrade().await, Err(ReadPoolError::Injected(PoolRole::Main))));
+        assert!(!conn.is_upgraded());
+        assert_eq!(conn.url(), "read");
+        conn.upgrade().await.unwrap();
+        assert!(conn.is_upgraded());
+        assert_eq!(conn.into_inner().url(), "main");
+    }
+}
@@ -0,0 +1,392 @@
+//!Per-process totals of pool usage, reported at shutdown
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rocket::{Orbit, Request, Response, Rocket};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::time::{interval, MissedTickBehavior};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{FallbackReason, PoolRole, PoolUsage, ReadPool};
+
+///Acquisition wait histogram buckets: bucket `i` counts waits under 2^i microseconds
+pub(crate) const BUCKETS: usize = 32;
+
+///Usage counters of one pool role
+#[derive(Debug)]
+pub(crate) struct Counters {
+    acquisitions: AtomicU64,
+    errors: AtomicU64,
+    fallbacks: AtomicU64,
+    reasons: [AtomicU64; FallbackReason::ALL.len()],
+    unreplicated: AtomicU64,
+    peak_in_use: AtomicU32,
+    waits: [AtomicU64; BUCKETS],
+    wait_micros: AtomicU64,
+}
+impl Default for Counters {
+    fn default() -> Self {
+        Counters{
+            acquisitions: Default::default(),
+            errors: Default::default(),
+            fallbacks: Default::default(),
+            reasons: Default::default(),
+            unreplicated: Default::default(),
+            peak_in_use: Default::default(),
+            waits: std::array::from_fn(|_| Default::default()),
+            wait_micros: Default::default(),
+        }
+    }
+}
+impl Counters {
+    pub fn acquired(&self, start: Instant, ok: bool) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let micros = start.elapsed().as_micros().min(u64::MAX as u128) as u64;
+        self.wait_micros.fetch_add(micros, Ordering::Relaxed);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.waits[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    ///Counts a read served by the main pool instead, for `reason` if known
+    pub fn fell_back(&self, reason: Option<FallbackReason>) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+        if let Some(reason) = reason {
+            self.reasons[reason as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    ///The number of fallbacks counted for each reason
+    #[cfg(feature = "metrics-basic")]
+    pub fn fallback_reasons(&self) -> Vec<(FallbackReason, u64)> {
+        FallbackReason::ALL.iter().map(|&reason| (reason, self.reasons[reason as usize].load(Ordering::Relaxed))).collect()
+    }
+
+    ///Counts a read served by this pool because there's no read pool
+    pub fn unreplicated(&self) {
+        self.unreplicated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn in_use(&self, in_use: u32) {
+        self.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
+    }
+
+    ///The number of acquisitions attempted, and of those which failed
+    #[cfg(any(feature = "metrics-basic", feature = "json"))]
+    pub fn totals(&self) -> (u64, u64) {
+        (self.acquisitions.load(Ordering::Relaxed), self.errors.load(Ordering::Relaxed))
+    }
+
+    ///The number of waits in each bucket, and the total of all waits
+    #[cfg(feature = "metrics-basic")]
+    pub fn waits(&self) -> ([u64; BUCKETS], Duration) {
+        (std::array::from_fn(|i| self.waits[i].load(Ordering::Relaxed)),
+            Duration::from_micros(self.wait_micros.load(Ordering::Relaxed)))
+    }
+
+    ///The wait at `percentile`, rounded up to a power of two microseconds
+    fn wait_percentile(&self, percentile: u64) -> Option<Duration> {
+        let counts: Vec<u64> = self.waits.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let mut seen = 0;
+        let bucket = counts.iter().position(|&count| {
+            seen += count;
+            seen * 100 >= total * percentile
+        })?;
+        Some(Duration::from_micros(1 << bucket))
+    }
+}
+
+///Totals for one pool role over the life of a [`ReadPool`], from [`ReadPool::usage_summary`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolSummary {
+    pub role: PoolRole,
+    pub label: String,
+    ///Connection acquisitions attempted
+    pub acquisitions: u64,
+    ///Acquisitions which failed
+    pub errors: u64,
+    ///Reads served by the main pool instead, through `read.on_error`, brownout or routing
+    ///overrides. Only counted for the read role.
+    pub fallbacks: u64,
+    ///Reads served because no read pool is configured, which would move to one once it is.
+    ///Only counted for the main role.
+    #[serde(default)]
+    pub unreplicated_reads: u64,
+    ///Most connections seen in use, if sampled by [`UsageReport::sample_in_use`]
+    pub peak_in_use: Option<u32>,
+    ///Median acquisition wait, rounded up to a power of two microseconds
+    #[serde(default)]
+    pub p50_wait: Option<Duration>,
+    ///99th percentile acquisition wait, rounded up to a power of two microseconds
+    pub p99_wait: Option<Duration>,
+}
+
+impl<P: Pool, R: Pool> ReadPool<P, R> {
+    ///Totals of the configured pools since the pool was created, read replicas counted together.
+    ///Clones of the pool share their totals.
+    pub fn usage_summary(&self) -> Vec<PoolSummary> {
+        self.roles().into_iter().map(|role| {
+            let counters = self.usage.get(role);
+            let peak = counters.peak_in_use.load(Ordering::Relaxed);
+            PoolSummary{
+                role,
+                label: self.labels.get(role).to_string(),
+                acquisitions: counters.acquisitions.load(Ordering::Relaxed),
+                errors: counters.errors.load(Ordering::Relaxed),
+                fallbacks: counters.fallbacks.load(Ordering::Relaxed),
+                unreplicated_reads: counters.unreplicated.load(Ordering::Relaxed),
+                peak_in_use: self.usage_sampled.load(Ordering::Relaxed).then_some(peak),
+                p50_wait: counters.wait_percentile(50),
+                p99_wait: counters.wait_percentile(99),
+            }
+        }).collect()
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///The roles of the configured pools
+    pub(crate) fn roles(&self) -> Vec<PoolRole> {
+        let mut roles = vec![PoolRole::Main];
+        if !self.replica_set().is_empty() {
+            roles.push(PoolRole::Read);
+        }
+        if self.delayed.is_some() {
+            roles.push(PoolRole::Delayed);
+        }
+        roles
+    }
+}
+
+impl<P: PoolUsage, R: PoolUsage> ReadPool<P, R> {
+    ///Records the number of connections currently in use by each pool towards
+    ///[`PoolSummary::peak_in_use`]
+    pub fn sample_in_use(&self) {
+        self.usage_sampled.store(true, Ordering::Relaxed);
+        self.usage.main.in_use(self.primary().in_use());
+        self.usage.read.in_use(self.replica_set().pools.iter().map(PoolUsage::in_use).sum());
+        if let Some(ref delayed) = self.delayed {
+            self.usage.delayed.in_use(delayed.in_use());
+        }
+    }
+}
+
+///A fairing which logs [`ReadPool::usage_summary`] for the database `D` at shutdown, and
+///optionally appends it to a file as tab separated lines of
+///`unix_secs database role label acquisitions errors fallbacks peak_in_use p99_wait_us p50_wait_us`,
+///with `-` for absent values. With [`UsageReport::every`] the summary is also logged while
+///running, for deployments without a metrics scraper.
+///
+///Peak connections in use are only reported when sampled; with pools implementing
+///[`PoolUsage`], [`UsageReport::sample_in_use`] samples them after every response.
+///```rust
+/// # use rocket_db_pools::Database;
+/// # use rocket_read_db_pools::testing::MockPool;
+/// # use rocket_read_db_pools::ReadPool;
+/// # #[derive(Database)] #[database("main")] struct Db(ReadPool<MockPool>);
+/// use std::time::Duration;
+/// use rocket_read_db_pools::UsageReport;
+///
+/// # fn _rocket() -> rocket::Rocket<rocket::Build> {
+/// rocket::build()
+///     .attach(Db::init())
+///     .attach(UsageReport::<Db>::new().every(Duration::from_secs(3600)))
+/// # }
+///```
+pub struct UsageReport<D: Database> {
+    path: Option<PathBuf>,
+    sample: Option<fn(&D::Pool)>,
+    periodic: Option<(Duration, SpawnPeriodic)>,
+    _db: PhantomData<fn() -> D>,
+}
+impl<D: Database> UsageReport<D> {
+    pub fn new() -> Self {
+        UsageReport{path: None, sample: None, periodic: None, _db: PhantomData}
+    }
+
+    ///Also appends the summary to the file at `path`, creating it if necessary
+    pub fn to_file<T: Into<PathBuf>>(mut self, path: T) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    fn write(&self, summary: &[PoolSummary]) -> io::Result<()> {
+        let Some(ref path) = self.path else {return Ok(())};
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut out = String::new();
+        for pool in summary {
+            out += &format!("{}\t{}\t{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n", now, crate::record::escape(D::NAME), pool.role,
+                crate::record::escape(&pool.label), pool.acquisitions, pool.errors, pool.fallbacks,
+                pool.peak_in_use.map_or("-".to_string(), |p| p.to_string()),
+                pool.p99_wait.map_or("-".to_string(), |w| w.as_micros().to_string()),
+                pool.p50_wait.map_or("-".to_string(), |w| w.as_micros().to_string()));
+        }
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(out.as_bytes())
+    }
+}
+impl<D, P, R> UsageReport<D> where D: Database<Pool = ReadPool<P, R>>, P: PoolUsage, R: PoolUsage {
+    ///Samples connections in use after every response, for [`PoolSummary::peak_in_use`]
+    pub fn sample_in_use(mut self) -> Self {
+        self.sample = Some(ReadPool::sample_in_use);
+        self
+    }
+}
+impl<D, P, R> UsageReport<D> where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone {
+    ///Also logs the summary every `interval` from launch until shutdown. Totals are since the
+    ///pool was created, not since the last summary.
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.periodic = Some((interval, spawn_periodic::<D, P, R>));
+        self
+    }
+}
+
+///Starts logging a database's summary periodically, see [`spawn_periodic`]
+type SpawnPeriodic = fn(&Rocket<Orbit>, Duration);
+
+///Logs the summary of the database `D` every `every` until shutdown
+fn spawn_periodic<D, P, R>(rocket: &Rocket<Orbit>, every: Duration)
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool + Clone, R: Pool + Clone
+{
+    let Some(db) = D::fetch(rocket) else {return};
+    let pool: ReadPool<P, R> = (**db).clone();
+    let mut shutdown = rocket.shutdown();
+    rocket::tokio::spawn(async move {
+        let mut ticks = interval(every.max(Duration::from_millis(1)));
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        //The first tick completes at once
+        ticks.tick().await;
+        loop {
+            rocket::tokio::select! {
+                _ = ticks.tick() => {}
+                _ = &mut shutdown => break,
+            }
+            log_summary(D::NAME, &pool, &pool.usage_summary());
+        }
+    });
+}
+
+///Logs `summary`, the usage summary of the database `database`
+fn log_summary<P, R>(database: &str, db: &ReadPool<P, R>, summary: &[PoolSummary]) {
+    let wait = |w: Option<Duration>| w.map_or("unknown".to_string(), |w| format!("{:?}", w));
+    for pool in summary {
+        db_log!(db.log, General, Info, "database `{}`: `{}` pool: {} acquisitions, {} errors, {} fallbacks, peak in use {}, p50 wait {}, p99 wait {}",
+            database, pool.label, pool.acquisitions, pool.errors, pool.fallbacks,
+            pool.peak_in_use.map_or("unknown".to_string(), |p| p.to_string()),
+            wait(pool.p50_wait), wait(pool.p99_wait));
+        if pool.unreplicated_reads > 0 {
+            db_log!(db.log, General, Info, "database `{}`: {} reads served by `{}` with no read pool configured",
+                database, pool.unreplicated_reads, pool.label);
+        }
+    }
+}
+impl<D: Database> Default for UsageReport<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for UsageReport<D> where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool {
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Pool Usage Report",
+            kind: Kind::Liftoff | Kind::Response | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if let Some((every, spawn)) = self.periodic {
+            spawn(rocket, every);
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, _res: &mut Response<'r>) {
+        if let (Some(sample), Some(db)) = (self.sample, D::fetch(req.rocket())) {
+            sample(db);
+        }
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        let Some(db) = D::fetch(rocket) else {return};
+        let summary = db.usage_summary();
+        log_summary(D::NAME, db, &summary);
+        if let Err(e) = self.write(&summary) {
+            rocket::error!("failed to write usage report for `{}`: {}", D::NAME, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::Figment;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::local::asynchronous::Client;
+    use crate::testing::{pair_rocket_figment, MockPool, MockPoolConnection};
+    use crate::ReadCapablePool;
+    use super::*;
+
+    #[derive(Database)]
+    #[database("db")]
+    struct Db(ReadPool<MockPool>);
+
+    async fn read(pool: &ReadPool<MockPool>) -> (PoolRole, Option<MockPoolConnection>) {
+        let (role, conn) = ReadCapablePool::<MockPoolConnection>::get_read(pool).await;
+        (role, conn.ok())
+    }
+
+    #[rocket::async_test]
+    async fn summary_counts_acquisitions_errors_and_fallbacks() {
+        let toml = "url = \"main\"\nread = {url = \"read\", on_error = \"fallback\"}";
+        let pool: ReadPool<MockPool> = Pool::init(&Figment::from(Toml::string(toml))).await.unwrap();
+        let (_, held) = read(&pool).await;
+        pool.replica_set().pools[0].fail_next(1);
+        assert_eq!(read(&pool).await.0, PoolRole::Main);
+        drop(pool.get().await.unwrap());
+        assert!(pool.usage_summary().iter().all(|s| s.peak_in_use.is_none()));
+        pool.sample_in_use();
+        drop(held);
+
+        let summary = pool.usage_summary();
+        let roles: Vec<_> = summary.iter().map(|s| (s.role, s.acquisitions, s.errors, s.fallbacks, s.peak_in_use)).collect();
+        assert_eq!(roles, [(PoolRole::Main, 2, 0, 0, Some(0)), (PoolRole::Read, 2, 1, 1, Some(1))]);
+        assert!(summary.iter().all(|s| s.p99_wait.is_some()));
+    }
+
+    #[test]
+    fn wait_percentiles_round_up_to_their_bucket() {
+        let counters = Counters::default();
+        assert_eq!(counters.wait_percentile(50), None);
+        counters.waits[3].store(98, Ordering::Relaxed);
+        counters.waits[10].store(2, Ordering::Relaxed);
+        assert_eq!(counters.wait_percentile(50), Some(Duration::from_micros(8)));
+        assert_eq!(counters.wait_percentile(99), Some(Duration::from_micros(1024)));
+    }
+
+    #[rocket::async_test]
+    async fn report_is_appended_to_the_file_at_shutdown() {
+        let path = std::env::temp_dir().join(format!("read_db_pools_usage_{}.tsv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for _ in 0..2 {
+            let rocket = rocket::custom(pair_rocket_figment("db", "main", "read"))
+                .attach(Db::init())
+                .attach(UsageReport::<Db>::new().to_file(&path));
+            let client = Client::tracked(rocket).await.unwrap();
+            drop(Db::fetch(client.rocket()).unwrap().get().await.unwrap());
+            client.terminate().await;
+        }
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Vec<&str>> = report.lines().map(|line| line.split('\t').skip(1).take(7).collect()).collect();
+        let run = [vec!["db", "Main", "main", "1", "0", "0", "-"], vec!["db", "Read", "read", "0", "0", "0", "-"]];
+        assert_eq!(lines, [&run[..], &run[..]].concat());
+    }
+}
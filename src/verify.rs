@@ -0,0 +1,194 @@
+//!Experimental verification of replica results against the main pool, and shadow reads
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rocket_db_pools::Pool;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::futures::future::join;
+use crate::brownout::spread;
+use crate::logging::db_log;
+use crate::ReadPool;
+
+///The result of comparing a query across the main and read pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Verification {
+    ///Both pools returned the same result
+    Match,
+    ///The pools returned different results, identified by their checksums
+    Diverged{main: u64, read: u64},
+    ///A connection couldn't be acquired from one of the pools
+    Unavailable,
+}
+
+fn checksum<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool {
+    ///Runs `query` on a connection from each of the main and read pools concurrently and
+    ///compares checksums of the results, logging and counting any divergence.
+    ///
+    ///Returns `None` without running anything unless `read.verify = true` is configured and a
+    ///read pool exists, so calls can be left in place and toggled from config. Both
+    ///connections are converted into `C`, as with `ReadConnection<D, C>`.
+    ///
+    ///This is experimental and intended for validating a new replica; it doubles the load of
+    ///every verified query.
+    pub async fn verify<C, F, Fut, T>(&self, name: &str, query: F) -> Option<Verification>
+        where P::Connection: Into<C>, R::Connection: Into<C>, F: Fn(C) -> Fut, Fut: Future<Output = T>, T: Hash
+    {
+        let set = self.replica_set();
+        let read = &set.pools[self.next_replica(&set).filter(|_| self.verify)?];
+        let run = |conn: C| async { checksum(&query(conn).await) };
+        let (main, read) = join(
+            async { Some(run(self.primary().get().await.ok()?.into()).await) },
+            async { Some(run(read.get().await.ok()?.into()).await) },
+        ).await;
+        let result = match (main, read) {
+            (Some(main), Some(read)) if main == read => Verification::Match,
+            (Some(main), Some(read)) => {
+                self.divergences.fetch_add(1, Ordering::Relaxed);
+                db_log!(self.log, General, Warn, "`{}` result for `{}` diverged from `{}` (checksums {:x} / {:x})",
+                    self.labels.read, name, self.labels.main, read, main);
+                Verification::Diverged{main, read}
+            }
+            _ => Verification::Unavailable,
+        };
+        Some(result)
+    }
+
+    ///Number of diverging results found by [`ReadPool::verify`] since the pool was created
+    pub fn divergences(&self) -> u64 {
+        self.divergences.load(Ordering::Relaxed)
+    }
+}
+
+///Results of the shadow reads of [`ReadPool::shadow`], from [`ReadPool::shadow_stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ShadowStats {
+    ///Reads run on both pools and compared
+    pub compared: u64,
+    ///Compared reads whose results differed
+    pub mismatches: u64,
+    ///Shadow reads not compared as no replica connection could be acquired
+    pub unavailable: u64,
+    ///Average time compared reads took on the main pool
+    pub mean_main_latency: Duration,
+    ///Average time compared reads took on the replicas
+    pub mean_read_latency: Duration,
+}
+
+///Sampling and counters of shadow reads
+#[derive(Debug)]
+pub(crate) struct Shadow {
+    ratio: f64,
+    ///Accumulated fraction of reads owed a shadow read, in millionths
+    debt: AtomicU64,
+    compared: AtomicU64,
+    mismatches: AtomicU64,
+    unavailable: AtomicU64,
+    main_us: AtomicU64,
+    read_us: AtomicU64,
+}
+impl Shadow {
+    ///Shadows `ratio` of reads, or none if it's 0 or less
+    pub fn new(ratio: f64) -> Option<Self> {
+        (ratio > 0.0).then(|| Shadow{
+            ratio: ratio.min(1.0),
+            debt: AtomicU64::new(0),
+            compared: AtomicU64::new(0),
+            mismatches: AtomicU64::new(0),
+            unavailable: AtomicU64::new(0),
+            main_us: AtomicU64::new(0),
+            read_us: AtomicU64::new(0),
+        })
+    }
+
+    fn stats(&self) -> ShadowStats {
+        let compared = self.compared.load(Ordering::Relaxed);
+        let mean = |total: &AtomicU64| Duration::from_micros(total.load(Ordering::Relaxed).checked_div(compared).unwrap_or(0));
+        ShadowStats{
+            compared,
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+            unavailable: self.unavailable.load(Ordering::Relaxed),
+            mean_main_latency: mean(&self.main_us),
+            mean_read_latency: mean(&self.read_us),
+        }
+    }
+}
+
+///Runs `query` on `conn`, returning its result along with how long it took
+async fn timed<C, F, Fut, T>(query: &F, conn: C) -> (T, Duration)
+    where F: Fn(C) -> Fut, Fut: Future<Output = T>
+{
+    let start = Instant::now();
+    let value = query(conn).await;
+    (value, start.elapsed())
+}
+
+impl<P, R> ReadPool<P, R> where P: Pool, R: Pool, P::Connection: Send + 'static {
+    ///Runs the read `query` on a connection from the main pool and returns its result, also
+    ///running it on a replica for the fraction of calls set by `read.shadow_ratio`. The
+    ///replica's result is only compared with the main pool's by checksum, logging and counting
+    ///mismatches, and the time each took is reported in [`ReadPool::shadow_stats`].
+    ///
+    ///Intended for validating replicas before reads are moved to them: calls run the query on
+    ///the main pool alone without `read.shadow_ratio`, so they can be left in place. Shadowed
+    ///calls wait for both pools. Both connections are converted into `C`, as with
+    ///`ReadConnection<D, C>`.
+    ///```rust
+    /// # use rocket_read_db_pools::ReadPool;
+    /// # use rocket_read_db_pools::testing::{MockError, MockPool, MockPoolConnection};
+    /// async fn count_users(db: &ReadPool<MockPool>) -> Result<i64, MockError> {
+    ///     db.shadow("count_users", |mut conn: MockPoolConnection| async move {
+    ///         conn.query("SELECT count(*) FROM users").fetch_one().await.ok()
+    ///     }).await?.ok_or(MockError::NoResult("count_users".to_string()))
+    /// }
+    ///```
+    pub async fn shadow<C, F, Fut, T>(&self, name: &str, query: F) -> Result<T, P::Error>
+        where P::Connection: Into<C>, R::Connection: Into<C>, F: Fn(C) -> Fut, Fut: Future<Output = T>, T: Hash
+    {
+        let set = self.replica_set();
+        let shadowed = self.shadow.as_ref()
+            .filter(|shadow| spread(&shadow.debt, shadow.ratio))
+            .and_then(|shadow| Some((shadow, self.next_replica(&set)?)));
+        let Some((shadow, i)) = shadowed else {
+            return Ok(query(self.get_primary().await?.into()).await);
+        };
+        let (main, read) = join(
+            async { Ok::<_, P::Error>(timed(&query, self.get_primary().await?.into()).await) },
+            async { Some(timed(&query, set.pools[i].get().await.ok()?.into()).await) },
+        ).await;
+        let (value, main_latency) = main?;
+        let label = set.label(&self.labels.read, i);
+        let Some((read, read_latency)) = read else {
+            shadow.unavailable.fetch_add(1, Ordering::Relaxed);
+            db_log!(self.log, General, Debug, "shadow read `{}` skipped: no connection from `{}`", name, label);
+            return Ok(value);
+        };
+        shadow.compared.fetch_add(1, Ordering::Relaxed);
+        shadow.main_us.fetch_add(main_latency.as_micros() as u64, Ordering::Relaxed);
+        shadow.read_us.fetch_add(read_latency.as_micros() as u64, Ordering::Relaxed);
+        let (main, read) = (checksum(&value), checksum(&read));
+        if main != read {
+            shadow.mismatches.fetch_add(1, Ordering::Relaxed);
+            db_log!(self.log, General, Warn, "shadow read `{}` on `{}` diverged from `{}` (checksums {:x} / {:x})",
+                name, label, self.labels.main, read, main);
+        }
+        db_log!(self.log, General, Debug, "shadow read `{}` took {:?} on `{}` and {:?} on `{}`",
+            name, read_latency, label, main_latency, self.labels.main);
+        Ok(value)
+    }
+
+    ///Counts of the shadow reads made by [`ReadPool::shadow`] since the pool was created, or
+    ///`None` without `read.shadow_ratio`
+    pub fn shadow_stats(&self) -> Option<ShadowStats> {
+        self.shadow.as_ref().map(|shadow| shadow.stats())
+    }
+}
@@ -0,0 +1,136 @@
+//!Opening the pools' connections ahead of traffic
+use std::marker::PhantomData;
+use rocket::{Build, Rocket};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::futures::stream::{self, StreamExt};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::{Database, Pool};
+use crate::logging::db_log;
+use crate::{LogConfig, PoolRole, ReadPool};
+
+///Default number of connections opened at once while warming up
+pub(crate) const DEFAULT_CONCURRENCY: usize = 4;
+
+///What [`ReadWarmup`] does when `min_connections` can't be opened, from the database's `warmup`
+///option. With either, the fairing opens `min_connections` on the main pool and each replica,
+///so the first requests after a deploy don't wait for connections:
+///```toml
+///[default.databases.main]
+///min_connections = 5
+///warmup = "fail"
+///
+///[default.databases.main.read]
+///min_connections = 10
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum WarmupPolicy {
+    ///Log the failures and launch anyway
+    #[default]
+    Log,
+    ///Fail launch
+    Fail,
+}
+
+///The `warmup` policy and the main pool's `min_connections`, for
+///[`ReadPool::warm_up_min_connections`]
+#[derive(Debug, Clone)]
+pub(crate) struct MinConnections {
+    pub(crate) policy: WarmupPolicy,
+    pub(crate) main: u32,
+}
+
+///Opens `connections` connections on `pool`, at most `concurrency` at a time, holding them all
+///until the last is open. Describes the failures if any.
+async fn open<T: Pool>(pool: &T, label: &str, connections: u32, concurrency: usize, log: &LogConfig) -> Result<(), String> {
+    let results: Vec<_> = stream::iter(0..connections)
+        .map(|_| pool.get())
+        .buffer_unordered(concurrency.max(1))
+        .collect().await;
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    match results.iter().find_map(|r| r.as_ref().err()) {
+        Some(e) => Err(format!("`{}` pool warm-up: {} of {} connections failed, e.g.: {}", label, failed, connections, e)),
+        None => {
+            db_log!(log, General, Info, "`{}` pool warmed up with {} connections", label, connections);
+            Ok(())
+        }
+    }
+}
+
+impl<P, R: Pool> ReadPool<P, R> {
+    ///Opens the `read.warmup` configured number of connections on each read replica, at most
+    ///`read.warmup_concurrency` at a time. They are all held until the last is open, so each
+    ///needs a new connection, then returned to the pool. Failures are logged.
+    pub async fn warm_up(&self) {
+        let Some((connections, concurrency)) = self.warmup else {return};
+        let label = self.label(PoolRole::Read);
+        for read in &self.replica_set().pools {
+            if let Err(e) = open(read, label, connections, concurrency, &self.log).await {
+                db_log!(self.log, General, Warn, "{}", e);
+            }
+        }
+    }
+}
+
+impl<P: Pool, R: Pool> ReadPool<P, R> {
+    ///If the `warmup` option is set, opens `min_connections` on the main pool and each read
+    ///replica as [`ReadPool::warm_up`] does, describing the first pool which failed
+    pub async fn warm_up_min_connections(&self) -> Result<(), String> {
+        let Some(ref min) = self.min_connections else {return Ok(())};
+        let concurrency = self.warmup.map_or(DEFAULT_CONCURRENCY, |(_, concurrency)| concurrency);
+        let mut result = open(self.primary(), self.label(PoolRole::Main), min.main, concurrency, &self.log).await;
+        let set = self.replica_set();
+        for (read, replica) in set.pools.iter().zip(&set.replicas) {
+            let opened = open(read, self.label(PoolRole::Read), replica.min_connections, concurrency, &self.log).await;
+            result = result.and(opened);
+        }
+        result
+    }
+}
+
+///A fairing which warms up the pools of the database `D` at ignite: the read pool with
+///[`ReadPool::warm_up`], and every pool's `min_connections` with
+///[`ReadPool::warm_up_min_connections`] if the `warmup` option is set. With `warmup = "fail"`,
+///launch fails if they can't all be opened. Attach it after `D::init()`.
+pub struct ReadWarmup<D>(PhantomData<fn() -> D>);
+impl<D> ReadWarmup<D> {
+    pub fn new() -> Self {
+        ReadWarmup(PhantomData)
+    }
+}
+impl<D> Default for ReadWarmup<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[rocket::async_trait]
+impl<D, P, R> Fairing for ReadWarmup<D>
+    where D: Database<Pool = ReadPool<P, R>>, P: Pool, R: Pool,
+        P::Connection: Send, P::Error: Send, R::Connection: Send, R::Error: Send
+{
+    fn info(&self) -> Info {
+        Info {
+            name: "Read Pool Warm-up",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let Some(db) = D::fetch(&rocket) else {
+            rocket::error!("`ReadWarmup` must be attached after `{}::init()`", std::any::type_name::<D>());
+            return Err(rocket);
+        };
+        db.warm_up().await;
+        match db.warm_up_min_connections().await {
+            Err(e) if db.min_connections.as_ref().is_some_and(|m| m.policy == WarmupPolicy::Fail) => {
+                db_log!(db.log, General, Error, "database `{}`: {}", D::NAME, e);
+                Err(rocket)
+            }
+            Err(e) => {
+                db_log!(db.log, General, Warn, "database `{}`: {}", D::NAME, e);
+                Ok(rocket)
+            }
+            Ok(()) => Ok(rocket),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//!Preference for read replicas in the application's own zone
+use std::sync::Arc;
+use rocket_db_pools::Pool;
+use crate::config::ReplicaConfig;
+use crate::replicas::ReplicaSet;
+use crate::{PoolUsage, ReadPool};
+
+///The saturation from which reads leave the preferred zone, and how it's measured
+pub(crate) type Spill<R> = (f32, fn(&R) -> f32);
+
+///The zone reads prefer, from `read.prefer_zone` or the variable named by
+///`read.prefer_zone_env`
+pub(crate) fn preferred(read: Option<&ReplicaConfig>) -> Option<Arc<str>> {
+    let read = read?;
+    if let Some(ref zone) = read.prefer_zone {
+        return Some(zone.as_str().into());
+    }
+    let var = read.prefer_zone_env.as_deref()?;
+    match std::env::var(var) {
+        Ok(zone) if !zone.is_empty() => Some(zone.into()),
+        _ => {
+            rocket::warn!("`read.prefer_zone_env` variable `{}` is unset, so reads don't prefer a zone", var);
+            None
+        }
+    }
+}
+
+impl<P, R> ReadPool<P, R> {
+    ///The index of the replica of `set` to use next with replicas in `zone` preferred. The
+    ///balancer chooses among the local replicas, whose weights are the only ones it's given,
+    ///and the next local one is used if it picks a remote one. Remote replicas are only used
+    ///while none of the local ones is available or below the saturation set by
+    ///[`ReadPool::spill_at`], and a saturated local replica is used over none.
+    pub(crate) fn next_local_replica(&self, set: &ReplicaSet<R>, zone: &str, admits: impl Fn(usize) -> bool) -> Option<usize> {
+        let n = set.len();
+        let local = |i: usize| set.replicas[i].zone.as_deref() == Some(zone);
+        let locals: Vec<usize> = (0..n).filter(|&i| local(i)).collect();
+        let weights: Vec<u32> = set.weights.iter().enumerate().map(|(i, &w)| if local(i) {w} else {0}).collect();
+        let pick = match n {
+            1 => 0,
+            n => self.balancer.read().unwrap_or_else(|e| e.into_inner()).pick(&set.pools, &weights) % n,
+        };
+        let start = if local(pick) {pick} else {locals[pick % locals.len()]};
+        let spill = *self.spill.read().unwrap_or_else(|e| e.into_inner());
+        let saturated = |i: usize| spill.is_some_and(|(threshold, usage)| usage(&set.pools[i]) >= threshold);
+        let local_order = || (0..n).map(|i| (start + i) % n).filter(|&i| local(i));
+        local_order().find(|&i| !saturated(i) && admits(i))
+            .or_else(|| (0..n).map(|i| (pick + i) % n).filter(|&i| !local(i)).find(|&i| admits(i)))
+            .or_else(|| local_order().find(|&i| admits(i)))
+    }
+}
+
+impl<P: Pool, R: PoolUsage> ReadPool<P, R> {
+    ///Makes reads spill over to replicas outside `read.prefer_zone` once the local replicas
+    ///have at least `saturation` of their connections in use, from 0 to 1. Without it, reads
+    ///only leave the zone while no local replica is available. Clones of the pool share the
+    ///change.
+    pub fn spill_at(&self, saturation: f32) {
+        *self.spill.write().unwrap_or_else(|e| e.into_inner()) = Some((saturation, usage::<R>));
+    }
+}
+
+///Fraction of `pool` in use, as [`ReadPool::saturation`] measures it
+fn usage<R: PoolUsage>(pool: &R) -> f32 {
+    match pool.max_connections() {
+        0 => 1.0,
+        max => (pool.in_use() as f32 / max as f32).min(1.0),
+    }
+}